@@ -0,0 +1,223 @@
+use std::{
+    fs::{File, OpenOptions},
+    io::{self, Read, Write},
+    net::{IpAddr, SocketAddr},
+    path::Path,
+    sync::Mutex,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use serde_json::json;
+
+use crate::{args::Args, listing, parse_request_line, stream::Stream};
+
+// A response head longer than this isn't one of ours; stop looking for its end
+const MAX_RESPONSE_HEAD: usize = 64 * 1024;
+
+const MONTHS: [&str; 12] = ["Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec"];
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum LogFormat {
+    // Apache's common format with the duration in milliseconds appended,
+    // which log analysers that take the combined format's extras accept:
+    //
+    //     192.0.2.7 - alice [14/Oct/2026:04:54:51 +0000] "GET /a.txt HTTP/1.1" 200 1234 3
+    Common,
+    // One object per line:
+    //
+    //     {"bytes":1234,"client":"192.0.2.7","duration_ms":3,"method":"GET",...}
+    Json,
+}
+
+// `--access-log FILE`, or `-` for stdout, records every request that got a
+// response. Bytes are the response body's as sent, so chunk framing counts
+// and HEAD responses are 0.
+pub struct AccessLog {
+    format: LogFormat,
+    out: Mutex<Box<dyn Write + Send>>,
+}
+
+impl AccessLog {
+    pub fn new(args: &Args) -> io::Result<Option<AccessLog>> {
+        let out: Box<dyn Write + Send> = match args.access_log.as_deref() {
+            None => return Ok(None),
+            Some(path) if path == Path::new("-") => Box::new(io::stdout()),
+            Some(path) => Box::new(OpenOptions::new().create(true).append(true).open(path)?),
+        };
+        Ok(Some(AccessLog { format: args.log_format, out: Mutex::new(out) }))
+    }
+
+    pub fn record(&self, client: IpAddr, request_line: &str, user: Option<&str>, response: &Recorder, duration: Duration) {
+        let status = match response.status {
+            Some(status) => status,
+            // The client left before anything was sent
+            None => return,
+        };
+        let now = SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |since| since.as_secs());
+        let (method, path, version) = parse_request_line(request_line);
+        let millis = duration.as_millis();
+
+        let line = match self.format {
+            LogFormat::Common => format!(
+                "{} - {} [{}] \"{}\" {} {} {}\n",
+                client,
+                // Names are the login's, so keep them from splitting the line
+                user.map_or("-".to_string(), |user| user.replace(|c: char| !c.is_ascii_graphic(), "?")),
+                common_date(now),
+                escape(request_line),
+                status,
+                response.body_bytes,
+                millis
+            ),
+            LogFormat::Json => {
+                let body = json!({
+                    "time": listing::format_iso8601(now),
+                    "client": client.to_string(),
+                    "user": user,
+                    "method": method,
+                    "path": path,
+                    "protocol": version,
+                    "status": status,
+                    "bytes": response.body_bytes,
+                    "duration_ms": millis as u64,
+                });
+                body.to_string() + "\n"
+            }
+        };
+
+        // One write per line, so lines from other threads and processes never interleave
+        let mut out = self.out.lock().unwrap_or_else(|e| e.into_inner());
+        if let Err(e) = out.write_all(line.as_bytes()).and_then(|_| out.flush()) {
+            eprintln!("Error writing access log: {:?}", e);
+        }
+    }
+}
+
+// Passes a connection through, noting the status and body size of the
+// response written to it. Interim 100 Continue heads are skipped over.
+pub struct Recorder<'a> {
+    inner: &'a mut dyn Stream,
+    head: Vec<u8>,
+    status: Option<u16>,
+    body_bytes: u64,
+}
+
+impl<'a> Recorder<'a> {
+    pub fn new(inner: &'a mut dyn Stream) -> Recorder<'a> {
+        Recorder { inner, head: Vec::new(), status: None, body_bytes: 0 }
+    }
+
+    // None until a final response head has been written
+    pub fn status(&self) -> Option<u16> {
+        self.status
+    }
+
+    pub fn body_bytes(&self) -> u64 {
+        self.body_bytes
+    }
+
+    fn observe(&mut self, data: &[u8]) {
+        if self.status.is_some() {
+            self.body_bytes += data.len() as u64;
+            return;
+        }
+        self.head.extend_from_slice(data);
+        while let Some(end) = self.head.windows(4).position(|window| window == b"\r\n\r\n") {
+            let rest = self.head.split_off(end + 4);
+            // `HTTP/1.1 200 OK`
+            let status = String::from_utf8_lossy(&self.head)
+                .split(' ')
+                .nth(1)
+                .and_then(|status| status.parse::<u16>().ok())
+                .unwrap_or(0);
+            self.head = rest;
+            if !(100..200).contains(&status) {
+                self.status = Some(status);
+                self.body_bytes = self.head.len() as u64;
+                self.head = Vec::new();
+                return;
+            }
+        }
+        if self.head.len() > MAX_RESPONSE_HEAD {
+            self.status = Some(0);
+            self.head = Vec::new();
+        }
+    }
+}
+
+impl Read for Recorder<'_> {
+    fn read(&mut self, buffer: &mut [u8]) -> io::Result<usize> {
+        self.inner.read(buffer)
+    }
+}
+
+impl Write for Recorder<'_> {
+    fn write(&mut self, data: &[u8]) -> io::Result<usize> {
+        let written = self.inner.write(data)?;
+        self.observe(&data[..written]);
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+impl Stream for Recorder<'_> {
+    fn peer_addr(&self) -> io::Result<SocketAddr> {
+        self.inner.peer_addr()
+    }
+
+    fn set_read_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        self.inner.set_read_timeout(timeout)
+    }
+
+    fn set_write_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        self.inner.set_write_timeout(timeout)
+    }
+
+    fn shutdown_write(&mut self) -> io::Result<()> {
+        self.inner.shutdown_write()
+    }
+
+    fn client_names(&self) -> Vec<String> {
+        self.inner.client_names()
+    }
+
+    fn send_file(&mut self, file: &File, offset: u64, length: u64) -> Option<io::Result<u64>> {
+        let result = self.inner.send_file(file, offset, length);
+        if let Some(Ok(sent)) = result {
+            self.body_bytes += sent;
+        }
+        result
+    }
+}
+
+// `14/Oct/2026:04:54:51 +0000`
+fn common_date(seconds: u64) -> String {
+    let (year, month, day) = listing::civil_from_days((seconds / 86_400) as i64);
+    let time = seconds % 86_400;
+    format!(
+        "{:02}/{}/{}:{:02}:{:02}:{:02} +0000",
+        day,
+        MONTHS[month as usize - 1],
+        year,
+        time / 3600,
+        time % 3600 / 60,
+        time % 60
+    )
+}
+
+// The request line is the client's; quotes and control characters in it
+// mustn't be able to forge fields or lines
+fn escape(value: &str) -> String {
+    let mut escaped = String::new();
+    for c in value.chars() {
+        match c {
+            '"' | '\\' => escaped.push_str(&format!("\\{}", c)),
+            c if c.is_control() => escaped.push_str(&format!("\\x{:02x}", c as u32)),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
@@ -0,0 +1,48 @@
+use std::{io, net::TcpListener};
+
+use crate::args::Args;
+
+// systemd passes sockets from descriptor 3 up
+#[cfg(unix)]
+const FIRST_LISTEN_FD: i32 = 3;
+
+// Listening sockets someone else bound: `--fd N`, or systemd socket activation
+// (LISTEN_FDS, meant for this process when LISTEN_PID says so). Empty means
+// bind the --bind addresses as usual. The variables are cleared once taken,
+// so processes started later don't claim the same descriptors.
+#[cfg(unix)]
+pub fn inherited_listeners(args: &Args) -> io::Result<Vec<TcpListener>> {
+    use std::{env, os::unix::io::FromRawFd, process};
+
+    let mut fds = args.fds.clone();
+    let for_us = env::var("LISTEN_PID").ok().and_then(|pid| pid.parse::<u32>().ok()) == Some(process::id());
+    if let Some(count) = env::var("LISTEN_FDS").ok().and_then(|count| count.parse::<i32>().ok()).filter(|_| for_us) {
+        fds.extend(FIRST_LISTEN_FD..FIRST_LISTEN_FD + count);
+        env::remove_var("LISTEN_PID");
+        env::remove_var("LISTEN_FDS");
+        env::remove_var("LISTEN_FDNAMES");
+    }
+
+    fds.iter()
+        .map(|&fd| {
+            // Ownership passes to the listener; nothing else here uses the descriptor
+            let listener = unsafe { TcpListener::from_raw_fd(fd) };
+            match listener.local_addr() {
+                Ok(_) => Ok(listener),
+                Err(e) => {
+                    // Not ours to close if it isn't a socket
+                    std::mem::forget(listener);
+                    Err(io::Error::new(e.kind(), format!("descriptor {} isn't a listening TCP socket: {}", fd, e)))
+                }
+            }
+        })
+        .collect()
+}
+
+#[cfg(not(unix))]
+pub fn inherited_listeners(args: &Args) -> io::Result<Vec<TcpListener>> {
+    match args.fds.is_empty() {
+        true => Ok(Vec::new()),
+        false => Err(io::Error::new(io::ErrorKind::Unsupported, "--fd needs Unix file descriptors")),
+    }
+}
@@ -0,0 +1,274 @@
+use std::{
+    fs,
+    io,
+    path::{Component, Path, PathBuf},
+    process,
+    time::UNIX_EPOCH,
+};
+
+use serde::Deserialize;
+use serde_json::{json, Value};
+
+use crate::{
+    cache::FileInfo, hidden::Hidden, is_path_within, listing,
+    metrics::{Metrics, Snapshot},
+    mime::{self, MimeTypes},
+    read_body, request_header,
+    send_error,
+    shutdown::{ShutdownHandle, Totals},
+    stats,
+    Exchange,
+    users::{self, User},
+    write_response,
+};
+
+// Far more than any UI sends at once; the batch is parsed in memory
+const MAX_BATCH_BODY: u64 = 1024 * 1024;
+
+#[derive(Deserialize)]
+#[serde(tag = "op", rename_all = "lowercase")]
+enum Operation {
+    Delete { path: String },
+    Move { from: String, to: String },
+    Copy { from: String, to: String },
+    Mkdir { path: String },
+}
+
+// `GET /_api/stat/<path>`: what a sync or monitoring tool needs to decide
+// whether to fetch a file, without transferring it
+pub fn send_stat(stream: &mut Exchange, root: &Path, resource_path: &str, mime_types: &MimeTypes) -> io::Result<()> {
+    let path = root.join(resource_path);
+
+    // Uncached on purpose: pollers want to see a change as soon as it happens
+    let metadata = match fs::metadata(&path) {
+        Ok(metadata) => metadata,
+        Err(_) => return send_error(stream, "404 Not Found", "Not Found"),
+    };
+    if !is_path_within(&path, root)? {
+        return send_error(stream, "403 Forbidden", "Forbidden");
+    }
+
+    let info = FileInfo {
+        is_dir: metadata.is_dir(),
+        is_file: metadata.is_file(),
+        size: metadata.len(),
+        modified: metadata.modified().ok(),
+    };
+    let kind = if info.is_dir {
+        "directory"
+    } else if info.is_file {
+        "file"
+    } else {
+        "other"
+    };
+    let mtime = info.modified.and_then(|modified| modified.duration_since(UNIX_EPOCH).ok()).map(|since| since.as_secs());
+
+    // The type a download would be served with
+    let mime = info.is_file.then(|| mime_types.by_extension(&path).unwrap_or_else(|| mime::sniff_file(&path)));
+
+    let body = json!({
+        "path": format!("/{}", resource_path),
+        "type": kind,
+        "size": info.size,
+        "mtime": mtime.map(listing::format_iso8601),
+        "permissions": permissions(&metadata),
+        "etag": info.etag(),
+        "mime": mime,
+    });
+    send_json(stream, "200 OK", &body)
+}
+
+// `GET /_api/usage`: how much of their quota the logged-in account has used
+pub fn send_usage(stream: &mut Exchange, user: &User) -> io::Result<()> {
+    let used = users::disk_usage(&user.root);
+    let body = json!({
+        "user": user.name,
+        "used": used,
+        "quota": user.quota,
+        "available": user.quota.map(|quota| quota.saturating_sub(used)),
+        "writable": user.can_write,
+    });
+    send_json(stream, "200 OK", &body)
+}
+
+// `GET <asset prefix>health`: for orchestrators' probes, which should stop
+// sending traffic once a shutdown has begun
+pub fn send_health(stream: &mut Exchange, totals: &Totals, shutdown: &ShutdownHandle) -> io::Result<()> {
+    let (status, state) = if shutdown.requested() { ("503 Service Unavailable", "draining") } else { ("200 OK", "ok") };
+    let body = json!({
+        "status": state,
+        "version": env!("CARGO_PKG_VERSION"),
+        "uptime_seconds": totals.uptime().as_secs(),
+    });
+    send_json(stream, status, &body)
+}
+
+// `GET <asset prefix>stats`: the server's totals, with responses by status
+// when --metrics is counting them
+pub fn send_stats(stream: &mut Exchange, totals: &Totals, metrics: Option<&Metrics>, worker_state: Option<&Path>) -> io::Result<()> {
+    let snapshot = Snapshot::gather(totals, metrics, worker_state);
+    let body = json!({
+        "uptime_seconds": totals.uptime().as_secs(),
+        "requests": snapshot.requests,
+        "connections": snapshot.connections,
+        "open_connections": snapshot.open,
+        "processes": snapshot.processes,
+        "responses": metrics.map(|_| snapshot.statuses),
+    });
+    send_json(stream, "200 OK", &body)
+}
+
+// `POST /_api/batch` with a JSON array of operations. Each operation either
+// happens completely or not at all, but the batch as a whole isn't a
+// transaction: the response reports every operation's outcome in order.
+// The caller has already checked the token.
+pub fn handle_batch(
+    stream: &mut Exchange,
+    method: &str,
+    request: &str,
+    received: &[u8],
+    root: &Path,
+    hidden: &Hidden,
+) -> io::Result<()> {
+    if method != "POST" {
+        return send_error(stream, "405 Method Not Allowed", "Method Not Allowed");
+    }
+
+    let length = match request_header(request, "Content-Length").and_then(|length| length.parse::<u64>().ok()) {
+        Some(length) if length > MAX_BATCH_BODY => {
+            return send_error(stream, "413 Payload Too Large", "Payload Too Large")
+        }
+        Some(length) => length,
+        None => return send_error(stream, "411 Length Required", "Length Required"),
+    };
+    let body = match read_body(stream, request, received, length) {
+        Ok(body) => body,
+        Err(e) => {
+            eprintln!("Error reading batch body: {:?}", e);
+            return send_error(stream, "400 Bad Request", "Bad Request");
+        }
+    };
+
+    let operations: Vec<Operation> = match serde_json::from_slice(&body) {
+        Ok(operations) => operations,
+        Err(e) => return send_json(stream, "400 Bad Request", &json!({ "error": e.to_string() })),
+    };
+
+    let results: Vec<Value> = operations
+        .iter()
+        .map(|operation| match run_operation(operation, root, hidden) {
+            Ok(()) => json!({ "ok": true }),
+            Err(error) => json!({ "ok": false, "error": error }),
+        })
+        .collect();
+    send_json(stream, "200 OK", &Value::Array(results))
+}
+
+fn run_operation(operation: &Operation, root: &Path, hidden: &Hidden) -> Result<(), String> {
+    // Reported like any other path that can't be used, so as not to say what's there
+    let paths = match operation {
+        Operation::Delete { path } | Operation::Mkdir { path } => vec![path],
+        Operation::Move { from, to } | Operation::Copy { from, to } => vec![from, to],
+    };
+    if let Some(path) = paths.into_iter().find(|path| hidden.hides(path)) {
+        return Err(format!("invalid path {:?}", path));
+    }
+    match operation {
+        Operation::Delete { path } => {
+            let path = resolve(path, root)?;
+            let metadata = fs::symlink_metadata(&path).map_err(describe)?;
+            if metadata.is_dir() {
+                // Removing a tree takes many steps, so move it out of sight in one first
+                let doomed = hidden_sibling(&path);
+                fs::rename(&path, &doomed).map_err(describe)?;
+                fs::remove_dir_all(&doomed).map_err(describe)
+            } else {
+                fs::remove_file(&path).map_err(describe)
+            }
+        }
+        Operation::Move { from, to } => {
+            let (from, to) = (resolve(from, root)?, resolve(to, root)?);
+            fs::symlink_metadata(&from).map_err(describe)?;
+            refuse_existing(&to)?;
+            fs::rename(&from, &to).map_err(describe)
+        }
+        Operation::Copy { from, to } => {
+            let (from, to) = (resolve(from, root)?, resolve(to, root)?);
+            // Copying reads through symlinks, so the target has to be inside too
+            if !is_path_within(&from, root).map_err(describe)? {
+                return Err("source is outside the served directory".to_string());
+            }
+            if !from.is_file() {
+                return Err("only files can be copied".to_string());
+            }
+            refuse_existing(&to)?;
+
+            // Copy beside the destination and rename, so it never appears half written
+            let partial = hidden_sibling(&to);
+            if let Err(e) = fs::copy(&from, &partial).and_then(|_| fs::rename(&partial, &to)) {
+                let _ = fs::remove_file(&partial);
+                return Err(describe(e));
+            }
+            Ok(())
+        }
+        Operation::Mkdir { path } => fs::create_dir(resolve(path, root)?).map_err(describe),
+    }
+}
+
+// Paths are relative to the root and may only name things inside an existing
+// directory of it; the final component itself is never followed
+pub fn resolve(path: &str, root: &Path) -> Result<PathBuf, String> {
+    let relative = Path::new(path.trim_start_matches('/'));
+    let is_plain = relative.components().all(|component| matches!(component, Component::Normal(_)));
+    let file_name = match relative.file_name() {
+        Some(file_name) if is_plain => file_name,
+        _ => return Err(format!("invalid path {:?}", path)),
+    };
+
+    let parent = root.join(relative).parent().map(Path::to_path_buf).unwrap_or(root.to_path_buf());
+    if !is_path_within(&parent, root).map_err(describe)? {
+        return Err(format!("{:?} is outside the served directory", path));
+    }
+    Ok(parent.join(file_name))
+}
+
+// rename() replaces an existing file silently, so check first
+fn refuse_existing(path: &Path) -> Result<(), String> {
+    match fs::symlink_metadata(path) {
+        Ok(_) => Err("destination already exists".to_string()),
+        Err(_) => Ok(()),
+    }
+}
+
+fn hidden_sibling(path: &Path) -> PathBuf {
+    let name = path.file_name().unwrap_or_default().to_string_lossy();
+    path.with_file_name(format!(".{}.bounty-{}", name, process::id()))
+}
+
+fn describe(error: io::Error) -> String {
+    error.to_string()
+}
+
+#[cfg(unix)]
+fn permissions(metadata: &fs::Metadata) -> String {
+    use std::os::unix::fs::PermissionsExt;
+    format!("{:04o}", metadata.permissions().mode() & 0o7777)
+}
+
+// Windows only knows the read-only flag
+#[cfg(not(unix))]
+fn permissions(metadata: &fs::Metadata) -> String {
+    if metadata.permissions().readonly() { "readonly" } else { "readwrite" }.to_string()
+}
+
+// `GET /_api/stats/top`: the downloads report behind `/_stats/top`
+pub fn send_top_downloads(stream: &mut Exchange, dir: &Path, query: &str) -> io::Result<()> {
+    let body = stats::top_json(dir, query)?;
+    send_json(stream, "200 OK", &body)
+}
+
+fn send_json(stream: &mut Exchange, status: &str, body: &Value) -> io::Result<()> {
+    let body = body.to_string() + "\n";
+    let head = stream.response_head(status, "application/json", body.len(), "Cache-Control: no-store\r\n");
+    write_response(stream, &head, body.as_bytes())
+}
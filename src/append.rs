@@ -0,0 +1,120 @@
+use std::{
+    fs::{self, File, OpenOptions},
+    io::{self, Write},
+    path::{Component, Path, PathBuf},
+};
+
+use crate::{args::Args, is_path_within, read_body, request_header, send_error, send_response, Exchange, users};
+
+// The body is held in memory so it can go out in one write; bigger ones can
+// be split across requests
+const MAX_APPEND_BODY: u64 = 8 * 1024 * 1024;
+
+// `POST /_append/<path>` appends the request body to a file under `root`,
+// creating the file (but never directories) on first use. The caller has
+// already checked that the client may write there.
+#[allow(clippy::too_many_arguments)]
+pub fn handle(
+    stream: &mut Exchange,
+    method: &str,
+    request: &str,
+    received: &[u8],
+    target: &str,
+    root: &Path,
+    quota: Option<u64>,
+    args: &Args,
+) -> io::Result<()> {
+    if method != "POST" {
+        return send_error(stream, "405 Method Not Allowed", "Method Not Allowed");
+    }
+
+    // Chunked uploads would need their own size accounting; devices can send a length
+    let length = match request_header(request, "Content-Length").and_then(|length| length.parse::<u64>().ok()) {
+        Some(length) if length > MAX_APPEND_BODY => {
+            return send_error(stream, "413 Payload Too Large", "Payload Too Large")
+        }
+        Some(length) => length,
+        None => return send_error(stream, "411 Length Required", "Length Required"),
+    };
+
+    let path = match resolve(target, root) {
+        Ok(path) => path,
+        Err(status) => return send_error(stream, status, &status[4..]),
+    };
+
+    let existing_size = fs::metadata(&path).map_or(0, |metadata| metadata.len());
+    let over_quota = quota.is_some_and(|quota| users::disk_usage(root).saturating_add(length) > quota);
+    if over_quota || existing_size.saturating_add(length) > args.append_max_file_size {
+        return send_error(stream, "507 Insufficient Storage", "Insufficient Storage");
+    }
+
+    // Everything that could refuse the body has been checked, so let it come
+    let body = match read_body(stream, request, received, length) {
+        Ok(body) => body,
+        Err(e) => {
+            eprintln!("Error reading append body for {}: {:?}", path.display(), e);
+            return send_error(stream, "400 Bad Request", "Bad Request");
+        }
+    };
+
+    // One write of the whole body keeps concurrent appends from interleaving.
+    // The path may have become a symlink since it was resolved, so the open
+    // doesn't follow one, and the parent is checked again once it's open.
+    let created = fs::symlink_metadata(&path).is_err();
+    let mut file = match open_no_follow(&path) {
+        Ok(file) if is_path_within(path.parent().unwrap_or(root), root).unwrap_or(false) => file,
+        Ok(_) => return send_error(stream, "403 Forbidden", "Forbidden"),
+        Err(_) if fs::symlink_metadata(&path).is_ok_and(|metadata| metadata.file_type().is_symlink()) => {
+            return send_error(stream, "403 Forbidden", "Forbidden")
+        }
+        Err(e) => return Err(e),
+    };
+    file.write_all(&body)?;
+    if args.append_fsync {
+        file.sync_data()?;
+    }
+
+    if created {
+        send_response(stream, "201 Created", "text/html", "")
+    } else {
+        send_response(stream, "204 No Content", "text/html", "")
+    }
+}
+
+// Errors are the status line to answer with
+pub fn resolve(target: &str, root: &Path) -> Result<PathBuf, &'static str> {
+    let relative = Path::new(target);
+    let is_plain = relative.components().all(|component| matches!(component, Component::Normal(_)));
+    let file_name = match relative.file_name() {
+        Some(file_name) if is_plain && !target.ends_with('/') => file_name,
+        _ => return Err("403 Forbidden"),
+    };
+
+    let parent = match root.join(relative).parent() {
+        Some(parent) if parent.is_dir() => parent.to_path_buf(),
+        _ => return Err("404 Not Found"),
+    };
+    if !is_path_within(&parent, root).unwrap_or(false) {
+        return Err("403 Forbidden");
+    }
+
+    // An existing entry might be a directory or a symlink pointing outside the
+    // root, or nowhere, which exists() would take for no entry at all
+    let path = parent.join(file_name);
+    if fs::symlink_metadata(&path).is_ok() && (!path.is_file() || !is_path_within(&path, root).unwrap_or(false)) {
+        return Err("403 Forbidden");
+    }
+    Ok(path)
+}
+
+#[cfg(unix)]
+fn open_no_follow(path: &Path) -> io::Result<File> {
+    use std::os::unix::fs::OpenOptionsExt;
+
+    OpenOptions::new().create(true).append(true).custom_flags(libc::O_NOFOLLOW).open(path)
+}
+
+#[cfg(not(unix))]
+fn open_no_follow(path: &Path) -> io::Result<File> {
+    OpenOptions::new().create(true).append(true).open(path)
+}
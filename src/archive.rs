@@ -0,0 +1,430 @@
+use std::{
+    fs::File,
+    io::{self, BufWriter, Read, Write},
+    path::{Path, PathBuf},
+};
+
+use flate2::CrcWriter;
+use walkdir::WalkDir;
+
+use crate::{
+    chunked::{self, ChunkedWriter},
+    content_disposition, hidden::Hidden, is_path_within, listing, Exchange,
+};
+
+const BLOCK: u64 = 512;
+// ustar's octal size and mtime fields top out here; bigger sizes go in a PAX header
+const TAR_MAX_OCTAL: u64 = 0o777_7777_7777;
+const TAR_MAX_NAME: usize = 100;
+
+// Past these a ZIP needs its ZIP64 extensions (APPNOTE 4.5)
+const ZIP32_MAX: u64 = 0xFFFF_FFFF;
+const ZIP32_MAX_ENTRIES: usize = 0xFFFF;
+// Sizes and CRC follow the data (bit 3), and names are UTF-8 (bit 11)
+const ZIP_FLAGS: u16 = 0x0808;
+
+#[derive(Clone, Copy)]
+pub enum Format {
+    Zip,
+    Tar,
+}
+
+impl Format {
+    pub fn parse(name: &str) -> Option<Format> {
+        match name {
+            "zip" => Some(Format::Zip),
+            "tar" => Some(Format::Tar),
+            _ => None,
+        }
+    }
+
+    fn extension(self) -> &'static str {
+        match self {
+            Format::Zip => "zip",
+            Format::Tar => "tar",
+        }
+    }
+
+    fn content_type(self) -> &'static str {
+        match self {
+            Format::Zip => "application/zip",
+            Format::Tar => "application/x-tar",
+        }
+    }
+}
+
+struct Entry {
+    // Inside a folder named after the directory, `/`-separated, with a
+    // trailing slash for directories
+    name: String,
+    path: PathBuf,
+    is_dir: bool,
+    size: u64,
+    modified: u64,
+}
+
+// `?download=zip` or `?download=tar` on a directory. Entries are stored, not
+// compressed, so every size is known before the first byte goes out: the
+// archive gets a Content-Length and streams straight from the files. With
+// `digest_trailer` it's chunked instead, to end with a Repr-Digest trailer.
+// `url_dir` is the directory's path in the URL, which `hidden` goes by
+pub fn send(
+    stream: &mut Exchange,
+    dir: &Path,
+    root: &Path,
+    format: Format,
+    hidden: &Hidden,
+    url_dir: &str,
+    digest_trailer: bool,
+) -> io::Result<()> {
+    let top = dir.file_name().map_or("download".to_string(), |name| name.to_string_lossy().to_string());
+    let entries = collect(dir, root, &top, hidden, url_dir)?;
+    let length = match format {
+        Format::Zip => zip_length(&entries),
+        Format::Tar => tar_length(&entries),
+    };
+
+    let headers = content_disposition(&format!("{}.{}", top, format.extension()));
+    let head = match digest_trailer {
+        true => stream.chunked_response_head("200 OK", format.content_type(), &(headers + chunked::DIGEST_TRAILER)),
+        false => stream.response_head("200 OK", format.content_type(), length as usize, &headers),
+    };
+    stream.write_all(head.as_bytes())?;
+    if stream.is_head_request() {
+        return stream.flush();
+    }
+
+    if digest_trailer {
+        let mut out = BufWriter::with_capacity(64 * 1024, ChunkedWriter::with_digest(stream));
+        write_archive(&mut out, format, &entries)?;
+        out.into_inner().map_err(io::IntoInnerError::into_error)?.finish()?;
+        return Ok(());
+    }
+    // Headers are small writes; batch them with the file data around them
+    let mut out = BufWriter::with_capacity(64 * 1024, stream);
+    write_archive(&mut out, format, &entries)?;
+    out.flush()
+}
+
+fn write_archive(out: &mut impl Write, format: Format, entries: &[Entry]) -> io::Result<()> {
+    match format {
+        Format::Zip => write_zip(out, entries),
+        Format::Tar => write_tar(out, entries),
+    }
+}
+
+// Symlinks are followed like everywhere else, but only to inside the root.
+// Whatever can't be read is left out rather than failing the whole archive.
+fn collect(dir: &Path, root: &Path, top: &str, hidden: &Hidden, url_dir: &str) -> io::Result<Vec<Entry>> {
+    let mut entries = Vec::new();
+    for entry in WalkDir::new(dir).follow_links(true).sort_by_file_name() {
+        let entry = match entry {
+            Ok(entry) => entry,
+            Err(e) => {
+                eprintln!("Leaving out of archive: {}", e);
+                continue;
+            }
+        };
+        if !is_path_within(entry.path(), root).unwrap_or(false) {
+            continue;
+        }
+        let metadata = match entry.metadata() {
+            Ok(metadata) if metadata.is_dir() || metadata.is_file() => metadata,
+            _ => continue,
+        };
+        let relative = match entry.path().strip_prefix(dir).ok().and_then(Path::to_str) {
+            Some(relative) => relative,
+            None => {
+                eprintln!("Leaving out of archive: {} isn't valid UTF-8", entry.path().display());
+                continue;
+            }
+        };
+        if !relative.is_empty() && hidden.hides_in(url_dir, relative) {
+            continue;
+        }
+
+        let mut name = if relative.is_empty() { top.to_string() } else { format!("{}/{}", top, relative) };
+        if metadata.is_dir() {
+            name.push('/');
+        }
+        entries.push(Entry {
+            name,
+            path: entry.path().to_path_buf(),
+            is_dir: metadata.is_dir(),
+            size: if metadata.is_dir() { 0 } else { metadata.len() },
+            modified: metadata.modified().ok().and_then(listing::unix_seconds).unwrap_or(0),
+        });
+    }
+    Ok(entries)
+}
+
+// Exactly the `size` the headers promised; a file that shrank since can only
+// end the response early
+fn copy_file(entry: &Entry, out: &mut impl Write) -> io::Result<()> {
+    let copied = io::copy(&mut File::open(&entry.path)?.take(entry.size), out)?;
+    if copied != entry.size {
+        return Err(io::Error::new(io::ErrorKind::UnexpectedEof, format!("{} shrank while archiving", entry.name)));
+    }
+    Ok(())
+}
+
+fn tar_length(entries: &[Entry]) -> u64 {
+    let archived: u64 = entries
+        .iter()
+        .map(|entry| {
+            let records = pax_records(entry).len() as u64;
+            let pax = if records > 0 { BLOCK + padded(records) } else { 0 };
+            pax + BLOCK + padded(entry.size)
+        })
+        .sum();
+    // Two zero blocks end the archive
+    archived + 2 * BLOCK
+}
+
+fn write_tar(out: &mut impl Write, entries: &[Entry]) -> io::Result<()> {
+    for entry in entries {
+        let records = pax_records(entry);
+        if !records.is_empty() {
+            out.write_all(&tar_header("././@PaxHeader", records.len() as u64, entry.modified, b'x'))?;
+            out.write_all(&records)?;
+            pad_block(out, records.len() as u64)?;
+        }
+
+        let kind = if entry.is_dir { b'5' } else { b'0' };
+        out.write_all(&tar_header(&entry.name, entry.size, entry.modified, kind))?;
+        if !entry.is_dir {
+            copy_file(entry, out)?;
+            pad_block(out, entry.size)?;
+        }
+    }
+    out.write_all(&[0; 2 * BLOCK as usize])
+}
+
+fn tar_header(name: &str, size: u64, modified: u64, kind: u8) -> [u8; BLOCK as usize] {
+    let mut header = [0; BLOCK as usize];
+    // Longer names are cut here and given whole in the PAX header before
+    let name = &name.as_bytes()[..name.len().min(TAR_MAX_NAME)];
+    header[..name.len()].copy_from_slice(name);
+    octal(&mut header[100..108], if kind == b'5' { 0o755 } else { 0o644 });
+    octal(&mut header[108..116], 0);
+    octal(&mut header[116..124], 0);
+    octal(&mut header[124..136], size.min(TAR_MAX_OCTAL));
+    octal(&mut header[136..148], modified.min(TAR_MAX_OCTAL));
+    header[156] = kind;
+    header[257..265].copy_from_slice(b"ustar\x0000");
+
+    // Summed with the checksum field itself as spaces
+    header[148..156].fill(b' ');
+    let checksum: u64 = header.iter().map(|&byte| byte as u64).sum();
+    octal(&mut header[148..155], checksum);
+    header
+}
+
+// Zero-padded, ending in a NUL
+fn octal(field: &mut [u8], value: u64) {
+    let digits = format!("{:0width$o}", value, width = field.len() - 1);
+    field[..digits.len()].copy_from_slice(digits.as_bytes());
+    field[digits.len()] = 0;
+}
+
+// What ustar's fields can't hold, as a PAX extended header's records
+fn pax_records(entry: &Entry) -> Vec<u8> {
+    let mut records = Vec::new();
+    if entry.name.len() > TAR_MAX_NAME {
+        pax_record(&mut records, "path", &entry.name);
+    }
+    if entry.size > TAR_MAX_OCTAL {
+        pax_record(&mut records, "size", &entry.size.to_string());
+    }
+    records
+}
+
+// `<length> <key>=<value>\n`, where the length counts its own digits
+fn pax_record(records: &mut Vec<u8>, key: &str, value: &str) {
+    let rest = key.len() + value.len() + 3;
+    let mut length = rest + rest.to_string().len();
+    while length != rest + length.to_string().len() {
+        length = rest + length.to_string().len();
+    }
+    records.extend_from_slice(format!("{} {}={}\n", length, key, value).as_bytes());
+}
+
+fn padded(length: u64) -> u64 {
+    length.div_ceil(BLOCK) * BLOCK
+}
+
+fn pad_block(out: &mut impl Write, length: u64) -> io::Result<()> {
+    out.write_all(&[0; BLOCK as usize][..(padded(length) - length) as usize])
+}
+
+// Every header's length depends only on names, sizes and offsets, never on
+// the CRCs, so laying them out with dummy ones gives the final length
+fn zip_length(entries: &[Entry]) -> u64 {
+    let (mut offset, mut central) = (0, 0);
+    for entry in entries {
+        central += central_header(entry, 0, offset).len() as u64;
+        offset += local_header(entry).len() as u64 + entry.size + data_descriptor(entry, 0).len() as u64;
+    }
+    offset + central + end_of_central_directory(entries.len(), central, offset).len() as u64
+}
+
+fn write_zip(out: &mut impl Write, entries: &[Entry]) -> io::Result<()> {
+    let mut central = Vec::new();
+    let mut offset = 0;
+    for entry in entries {
+        let local = local_header(entry);
+        out.write_all(&local)?;
+        let crc = if entry.is_dir {
+            0
+        } else {
+            let mut writer = CrcWriter::new(&mut *out);
+            copy_file(entry, &mut writer)?;
+            writer.crc().sum()
+        };
+        let descriptor = data_descriptor(entry, crc);
+        out.write_all(&descriptor)?;
+
+        central.extend_from_slice(&central_header(entry, crc, offset));
+        offset += local.len() as u64 + entry.size + descriptor.len() as u64;
+    }
+    out.write_all(&central)?;
+    out.write_all(&end_of_central_directory(entries.len(), central.len() as u64, offset))
+}
+
+fn local_header(entry: &Entry) -> Vec<u8> {
+    let zip64 = entry.size >= ZIP32_MAX;
+    let (time, date) = dos_date_time(entry.modified);
+    let mut header = Vec::new();
+    put32(&mut header, 0x0403_4b50);
+    put16(&mut header, if zip64 { 45 } else { 20 });
+    put16(&mut header, ZIP_FLAGS);
+    put16(&mut header, 0);
+    put16(&mut header, time);
+    put16(&mut header, date);
+    // CRC and sizes are in the data descriptor
+    put32(&mut header, 0);
+    put32(&mut header, if zip64 { ZIP32_MAX as u32 } else { 0 });
+    put32(&mut header, if zip64 { ZIP32_MAX as u32 } else { 0 });
+    put16(&mut header, entry.name.len() as u16);
+    put16(&mut header, if zip64 { 20 } else { 0 });
+    header.extend_from_slice(entry.name.as_bytes());
+    if zip64 {
+        put16(&mut header, 0x0001);
+        put16(&mut header, 16);
+        put64(&mut header, 0);
+        put64(&mut header, 0);
+    }
+    header
+}
+
+fn data_descriptor(entry: &Entry, crc: u32) -> Vec<u8> {
+    let mut descriptor = Vec::new();
+    put32(&mut descriptor, 0x0807_4b50);
+    put32(&mut descriptor, crc);
+    if entry.size >= ZIP32_MAX {
+        put64(&mut descriptor, entry.size);
+        put64(&mut descriptor, entry.size);
+    } else {
+        put32(&mut descriptor, entry.size as u32);
+        put32(&mut descriptor, entry.size as u32);
+    }
+    descriptor
+}
+
+fn central_header(entry: &Entry, crc: u32, offset: u64) -> Vec<u8> {
+    let (big, far) = (entry.size >= ZIP32_MAX, offset >= ZIP32_MAX);
+    let mut extra = Vec::new();
+    if big || far {
+        put16(&mut extra, 0x0001);
+        put16(&mut extra, if big { 16 } else { 0 } + if far { 8 } else { 0 });
+        if big {
+            put64(&mut extra, entry.size);
+            put64(&mut extra, entry.size);
+        }
+        if far {
+            put64(&mut extra, offset);
+        }
+    }
+    // Unix permissions in the high half; the low half's 0x10 is MS-DOS's directory bit
+    let attributes = if entry.is_dir { (0o40755 << 16) | 0x10 } else { 0o100644 << 16 };
+
+    let (time, date) = dos_date_time(entry.modified);
+    let mut header = Vec::new();
+    put32(&mut header, 0x0201_4b50);
+    // Made by Unix, APPNOTE 4.5
+    put16(&mut header, (3 << 8) | 45);
+    put16(&mut header, if big || far { 45 } else { 20 });
+    put16(&mut header, ZIP_FLAGS);
+    put16(&mut header, 0);
+    put16(&mut header, time);
+    put16(&mut header, date);
+    put32(&mut header, crc);
+    put32(&mut header, entry.size.min(ZIP32_MAX) as u32);
+    put32(&mut header, entry.size.min(ZIP32_MAX) as u32);
+    put16(&mut header, entry.name.len() as u16);
+    put16(&mut header, extra.len() as u16);
+    put16(&mut header, 0);
+    put16(&mut header, 0);
+    put16(&mut header, 0);
+    put32(&mut header, attributes);
+    put32(&mut header, offset.min(ZIP32_MAX) as u32);
+    header.extend_from_slice(entry.name.as_bytes());
+    header.extend_from_slice(&extra);
+    header
+}
+
+// `size` and `offset` are the central directory's
+fn end_of_central_directory(entries: usize, size: u64, offset: u64) -> Vec<u8> {
+    let mut end = Vec::new();
+    if entries >= ZIP32_MAX_ENTRIES || size >= ZIP32_MAX || offset >= ZIP32_MAX {
+        put32(&mut end, 0x0606_4b50);
+        put64(&mut end, 44);
+        put16(&mut end, (3 << 8) | 45);
+        put16(&mut end, 45);
+        put32(&mut end, 0);
+        put32(&mut end, 0);
+        put64(&mut end, entries as u64);
+        put64(&mut end, entries as u64);
+        put64(&mut end, size);
+        put64(&mut end, offset);
+        // The locator, pointing back at the record just written
+        put32(&mut end, 0x0706_4b50);
+        put32(&mut end, 0);
+        put64(&mut end, offset + size);
+        put32(&mut end, 1);
+    }
+    put32(&mut end, 0x0605_4b50);
+    put16(&mut end, 0);
+    put16(&mut end, 0);
+    put16(&mut end, entries.min(ZIP32_MAX_ENTRIES) as u16);
+    put16(&mut end, entries.min(ZIP32_MAX_ENTRIES) as u16);
+    put32(&mut end, size.min(ZIP32_MAX) as u32);
+    put32(&mut end, offset.min(ZIP32_MAX) as u32);
+    put16(&mut end, 0);
+    end
+}
+
+// MS-DOS time has two-second steps and begins in 1980; it's read as local
+// time, but UTC is all there is to give
+fn dos_date_time(seconds: u64) -> (u16, u16) {
+    let (year, month, day) = listing::civil_from_days((seconds / 86_400) as i64);
+    if year < 1980 {
+        return (0, (1 << 5) | 1);
+    }
+    let year = year.min(2107) as u16;
+    let time = seconds % 86_400;
+    let time = ((time / 3600) << 11) | ((time % 3600 / 60) << 5) | (time % 60 / 2);
+    (time as u16, ((year - 1980) << 9) | ((month as u16) << 5) | day as u16)
+}
+
+fn put16(out: &mut Vec<u8>, value: u16) {
+    out.extend_from_slice(&value.to_le_bytes());
+}
+
+fn put32(out: &mut Vec<u8>, value: u32) {
+    out.extend_from_slice(&value.to_le_bytes());
+}
+
+fn put64(out: &mut Vec<u8>, value: u64) {
+    out.extend_from_slice(&value.to_le_bytes());
+}
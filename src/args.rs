@@ -0,0 +1,928 @@
+use std::{
+    env, io,
+    net::{IpAddr, Ipv4Addr, SocketAddr},
+    path::{Component, Path, PathBuf},
+    time::Duration,
+};
+
+use crate::{
+    access_log::LogFormat,
+    assets,
+    cache::DigestAlgorithm,
+    config::{self, Config},
+    cors::Cors,
+    firewall::Cidr,
+    hidden::Hidden,
+    mime::MimeTypes,
+    proxy::Upstream,
+    tls::{self, TlsOptions},
+    users::{Credential, Users},
+};
+
+// Where Tor listens for controllers unless told otherwise
+const DEFAULT_TOR_CONTROL: &str = "127.0.0.1:9051";
+
+pub struct Args {
+    // The file settings were read from before the command line's
+    pub config: Option<PathBuf>,
+    // Directory served, canonical so containment checks can compare against it
+    pub root: PathBuf,
+    // Addresses to listen on, all at the same port; the first is the one URLs use
+    pub bind: Vec<IpAddr>,
+    pub port: u16,
+    // Already listening sockets to serve instead of binding (`--fd`), Unix only
+    pub fds: Vec<i32>,
+    // Print the first reachable URL as a QR code at startup
+    pub qr: bool,
+    // Advertise bounty.local and an HTTP service over multicast DNS
+    pub mdns: bool,
+    pub dlna: bool,
+    // Serve the root from this ref's tree instead of the working directory
+    pub git_ref: Option<String>,
+    // Answer `/_git/<ref>/` URLs with any commit's tree, as it was below the root
+    pub git_urls: bool,
+    // Directories layered over the root, highest first; the first one holding a path serves it
+    pub overlays: Vec<PathBuf>,
+    // URL prefixes like `/docs` served from their own canonical directories, longest first
+    pub mounts: Vec<(String, PathBuf)>,
+    // Lowercase hostnames served from their own canonical directories; other hosts get the root
+    pub vhosts: Vec<(String, PathBuf)>,
+    // URL prefixes forwarded to upstream servers, longest first
+    pub proxies: Vec<(String, Upstream)>,
+    // Show exact byte counts and ISO timestamps in listings unless `?exact=0`
+    pub exact: bool,
+    // URL prefix for embedded assets, in case the served tree has its own `_bounty` directory
+    pub asset_prefix: String,
+    // Language variant served when none matches Accept-Language
+    pub default_language: Option<String>,
+    // Largest request body accepted, in bytes
+    pub max_body_size: u64,
+    // Longest request head accepted, request line included, in bytes (431 past it)
+    pub max_header_size: usize,
+    // Most header lines a request may have (431 past it)
+    pub max_headers: usize,
+    // Longest request line accepted, in bytes (414 past it)
+    pub max_uri_length: usize,
+    // How long a request's head may take to arrive, however it trickles in (408 past it)
+    pub header_timeout: Duration,
+    // Longest any one read of a request body or write of a response may block
+    pub read_timeout: Duration,
+    pub write_timeout: Duration,
+    // Cache-Control value sent with every file
+    pub cache_control: Option<String>,
+    // Sent with every response, errors included: `--secure-headers` ones, then each `--header`
+    pub response_headers: Vec<(String, String)>,
+    // Pages served in place of the built-in one for these error statuses
+    pub error_pages: Vec<(u16, PathBuf)>,
+    // Content types by extension, with those from `--mime-types FILE` over the built-in ones
+    pub mime_types: MimeTypes,
+    // End streamed responses with a Repr-Digest trailer: listings, archives and
+    // files compressed on the fly. Files sent whole have `--file-digest`.
+    pub digest_trailers: bool,
+    // Gzip or deflate text files and listings for clients that accept it
+    pub compress: bool,
+    // Serve `<file>.br` or `<file>.gz` in place of a file to clients that accept them
+    pub precompressed: bool,
+    // Dotfiles and .bountyignore matches, left out of listings and answered with 404
+    pub hidden: Hidden,
+    // Reload open pages when anything under the root changes, for development
+    pub watch: bool,
+    // Serve Prometheus metrics at `<asset prefix>metrics`, which needs a login
+    // with --auth or accounts, unlike the prefix's embedded assets
+    pub metrics: bool,
+    // Serve `<asset prefix>health` and `<asset prefix>stats`, unless `--no-admin`.
+    // Like metrics they take a login, and count against --rate-limit.
+    pub admin: bool,
+    // Show .md files as pages unless `?render=0`, rather than only with `?render=1`
+    pub render_markdown: bool,
+    // Answer paths that don't exist with the root's index.html, for client-side routing
+    pub spa: bool,
+    // Accept `PUT` and multipart `POST` uploads anywhere under the root
+    pub allow_upload: bool,
+    // Answer OPTIONS and PROPFIND so the root can be mounted as a WebDAV share
+    pub webdav: bool,
+    // Also take WebDAV's MKCOL, MOVE, DELETE and PUT; implies --webdav
+    pub webdav_write: bool,
+    // How long a missing path keeps answering 404 without a fresh lookup; zero disables
+    pub not_found_ttl: Duration,
+    // How long a directory's listing may be reused while its mtime is unchanged; zero disables
+    pub listing_cache_ttl: Duration,
+    // How long file metadata is trusted before being looked up again; zero disables
+    pub stat_cache_ttl: Duration,
+    // Bytes of small files' contents kept in memory, shared by every handler
+    pub file_cache: Option<u64>,
+    // Send file bodies through io_uring, when built with the `io-uring` feature
+    pub io_uring: bool,
+    // How long open connections get to finish after Ctrl-C or SIGTERM
+    pub shutdown_timeout: Duration,
+    // Threads each accepting on their own SO_REUSEPORT socket
+    pub workers: usize,
+    // Threads handling accepted connections, shared by all the process's listeners
+    pub threads: usize,
+    // Worker processes run and restarted by a supervisor
+    pub processes: usize,
+    // Set by the supervisor on the processes it starts
+    pub supervised: bool,
+    // Where a supervised child publishes its numbers for its siblings to add up
+    pub worker_state: Option<PathBuf>,
+    // Bearer token enabling `POST /_append/<path>`; the endpoint doesn't exist without one
+    pub append_token: Option<String>,
+    // Sync each append to disk before answering
+    pub append_fsync: bool,
+    // Appends that would grow a file past this are refused
+    pub append_max_file_size: u64,
+    // Bearer token enabling `POST /_api/batch`, which can delete anything under the root
+    pub batch_token: Option<String>,
+    // Directory under the root that `/_paste` stores snippets in; no pastebin without it
+    pub paste_dir: Option<String>,
+    // File backing `/s/<code>` short links; they're off without one
+    pub short_links: Option<PathBuf>,
+    // Send a digest of the content with every file response
+    pub file_digest: Option<DigestAlgorithm>,
+    // Pattern like `/home/*/public_html` that `/~<user>/` URLs map into
+    pub user_dirs: Option<String>,
+    // Accounts from `--users FILE`; each then sees only its own root
+    pub users: Option<Users>,
+    // `--auth user:password` and `--auth-file` pairs, any of which gets in
+    pub auth: Vec<Credential>,
+    // PAM service that system accounts log in through, when built with the `pam` feature
+    pub pam_service: Option<String>,
+    // Pattern like `/home/*/public_html` giving each system account's root
+    pub pam_root: String,
+    // `host:port` of the directory that `--ldap` accounts bind to
+    pub ldap: Option<String>,
+    // The DN each binds as, with `*` standing for the user name
+    pub ldap_dn: String,
+    // Like --pam-root; without it every account gets the served root
+    pub ldap_root: Option<String>,
+    // Members of this group's DN can write
+    pub ldap_write_group: Option<String>,
+    // File that failed logins are appended to, in a format fail2ban can match
+    pub auth_log: Option<PathBuf>,
+    // Key that `<asset prefix>share` signs links with; unset, there are no share links
+    pub share_key: Option<PathBuf>,
+    // File every request is appended to, `-` meaning stdout, in --log-format
+    pub access_log: Option<PathBuf>,
+    pub log_format: LogFormat,
+    // Failed logins from one address within ten minutes before it's locked out
+    pub auth_lockout: Option<u32>,
+    pub auth_lockout_time: Duration,
+    // Connections from `--deny` networks are refused, and with any `--allow`
+    // so are those from networks it doesn't list
+    pub allow: Vec<Cidr>,
+    pub deny: Vec<Cidr>,
+    // Origins whose scripts may read responses, or any with `*`
+    pub cors: Option<Cors>,
+    // Requests a second each address may make on average, and in a burst
+    pub rate_limit: Option<f64>,
+    pub rate_burst: Option<f64>,
+    // Connections each address may have open at once; more are closed unanswered
+    pub max_connections_per_ip: Option<usize>,
+    // Stall requests for well-known exploit paths instead of answering them
+    pub tarpit: bool,
+    // Replaces the built-in list of paths that --tarpit catches
+    pub tarpit_patterns: Option<PathBuf>,
+    // Enable `/_debug/echo` for loopback clients
+    pub debug_echo: bool,
+    // Print the effective configuration and check it, then exit
+    pub dry_run: bool,
+    // Write a static copy of what would be served into this directory, then exit
+    pub export: Option<PathBuf>,
+    // Write a copy of this executable that serves the working directory from within itself, then exit
+    pub bundle: Option<PathBuf>,
+    // Tor control port to publish an onion service through; no onion service without it
+    pub tor_control: Option<String>,
+    // Control port password, when Tor is set up with HashedControlPassword
+    pub tor_password: Option<String>,
+    // Where the onion service's key is kept so its address stays the same across restarts
+    pub tor_key: Option<PathBuf>,
+    // PEM certificate chain and private key; with them the server speaks only HTTPS
+    pub tls_cert: Option<PathBuf>,
+    pub tls_key: Option<PathBuf>,
+    pub tls: TlsOptions,
+    // Directory that file downloads are counted in, enabling `/_stats/top`
+    pub download_stats: Option<PathBuf>,
+    // Answer `/_api/stat/<path>` with a file's metadata as JSON
+    pub stat_api: bool,
+}
+
+impl Default for Args {
+    fn default() -> Args {
+        Args {
+            config: None,
+            root: PathBuf::from("."),
+            bind: vec![IpAddr::V4(Ipv4Addr::LOCALHOST)],
+            fds: Vec::new(),
+            qr: false,
+            mdns: false,
+            dlna: false,
+            port: 8080,
+            git_ref: None,
+            git_urls: false,
+            overlays: Vec::new(),
+            mounts: Vec::new(),
+            vhosts: Vec::new(),
+            proxies: Vec::new(),
+            exact: false,
+            asset_prefix: assets::DEFAULT_PREFIX.to_string(),
+            default_language: None,
+            max_body_size: 1024 * 1024 * 1024,
+            max_header_size: 16 * 1024,
+            max_headers: 100,
+            max_uri_length: 8 * 1024,
+            header_timeout: Duration::from_secs(30),
+            read_timeout: Duration::from_secs(30),
+            write_timeout: Duration::from_secs(30),
+            cache_control: None,
+            response_headers: Vec::new(),
+            error_pages: Vec::new(),
+            mime_types: MimeTypes::new(),
+            digest_trailers: false,
+            compress: false,
+            precompressed: false,
+            hidden: Hidden::default(),
+            watch: false,
+            metrics: false,
+            admin: true,
+            render_markdown: false,
+            spa: false,
+            allow_upload: false,
+            webdav: false,
+            webdav_write: false,
+            not_found_ttl: Duration::from_secs(2),
+            listing_cache_ttl: Duration::from_secs(10),
+            stat_cache_ttl: Duration::from_secs(1),
+            file_cache: None,
+            io_uring: false,
+            shutdown_timeout: Duration::from_secs(10),
+            workers: 1,
+            threads: 16,
+            processes: 1,
+            supervised: false,
+            worker_state: None,
+            append_token: None,
+            append_fsync: true,
+            append_max_file_size: 1024 * 1024 * 1024,
+            batch_token: None,
+            paste_dir: None,
+            short_links: None,
+            file_digest: None,
+            user_dirs: None,
+            users: None,
+            auth: Vec::new(),
+            pam_service: None,
+            pam_root: "/home/*".to_string(),
+            ldap: None,
+            ldap_dn: String::new(),
+            ldap_root: None,
+            ldap_write_group: None,
+            auth_log: None,
+            share_key: None,
+            access_log: None,
+            log_format: LogFormat::Common,
+            auth_lockout: None,
+            auth_lockout_time: Duration::from_secs(600),
+            allow: Vec::new(),
+            deny: Vec::new(),
+            cors: None,
+            rate_limit: None,
+            rate_burst: None,
+            max_connections_per_ip: None,
+            tarpit: false,
+            tarpit_patterns: None,
+            debug_echo: false,
+            dry_run: false,
+            export: None,
+            bundle: None,
+            tor_control: None,
+            tor_password: None,
+            tor_key: None,
+            tls_cert: None,
+            tls_key: None,
+            tls: TlsOptions::default(),
+            download_stats: None,
+            stat_api: false,
+        }
+    }
+}
+
+impl Args {
+    pub fn from_env() -> io::Result<Args> {
+        let command_line: Vec<String> = env::args().skip(1).collect();
+        // Looked for ahead of parsing, since the file's flags go first for the command line's to override
+        let config_path = match command_line.iter().position(|arg| arg == "--config") {
+            Some(index) => {
+                let path = command_line.get(index + 1).ok_or_else(|| invalid("--config requires a value".to_string()))?;
+                Some(PathBuf::from(path))
+            }
+            None => Some(PathBuf::from(config::DEFAULT_PATH)).filter(|path| path.is_file()),
+        };
+        let (flags, config_root) = match &config_path {
+            Some(path) => {
+                let config = Config::load(path)?;
+                let base = path.parent().unwrap_or(Path::new(""));
+                (config.flags(base), config.root(base))
+            }
+            None => (Vec::new(), None),
+        };
+
+        let mut args = Args::parse(flags.into_iter().chain(command_line), config_root)?;
+        args.config = config_path;
+        Ok(args)
+    }
+
+    // A config file's settings alone, as `bounty check` judges them
+    pub(crate) fn from_config(config: &Config, path: &Path) -> io::Result<Args> {
+        let base = path.parent().unwrap_or(Path::new(""));
+        let mut args = Args::parse(config.flags(base).into_iter(), config.root(base))?;
+        args.config = Some(path.to_path_buf());
+        Ok(args)
+    }
+
+    // For embedding: the command line's flags, root included, without any config file
+    pub fn from_flags(flags: impl IntoIterator<Item = String>) -> io::Result<Args> {
+        Args::parse(flags.into_iter(), None)
+    }
+
+    fn parse(mut iter: impl Iterator<Item = String>, config_root: Option<PathBuf>) -> io::Result<Args> {
+        let mut args = Args::default();
+        let mut root = None;
+        let mut bound = false;
+        let (mut hide_dotfiles, mut bountyignore) = (false, false);
+        let mut secure_headers = false;
+
+        while let Some(arg) = iter.next() {
+            match arg.as_str() {
+                "--config" => {
+                    value(&mut iter, &arg)?;
+                }
+                "--port" => {
+                    args.port = value(&mut iter, &arg)?
+                        .parse()
+                        .map_err(|_| invalid(format!("{} must be a port number", arg)))?;
+                }
+                // Repeatable; the first one given replaces the default
+                "--bind" => {
+                    let address = value(&mut iter, &arg)?;
+                    let ip: IpAddr = address
+                        .trim_start_matches('[')
+                        .trim_end_matches(']')
+                        .parse()
+                        .map_err(|_| invalid(format!("{} must be an IP address, not {}", arg, address)))?;
+                    if !bound {
+                        args.bind.clear();
+                        bound = true;
+                    }
+                    if !args.bind.contains(&ip) {
+                        args.bind.push(ip);
+                    }
+                }
+                "--fd" => {
+                    let fd = value(&mut iter, &arg)?;
+                    // 0 to 2 are stdin, stdout and stderr
+                    let fd = fd.parse::<i32>().ok().filter(|fd| *fd > 2).ok_or_else(|| {
+                        invalid(format!("{} expects a file descriptor number above 2, not {}", arg, fd))
+                    })?;
+                    args.fds.push(fd);
+                }
+                "--git-ref" => args.git_ref = Some(value(&mut iter, &arg)?),
+                "--git-urls" => args.git_urls = true,
+                "--overlay" => {
+                    let dir = PathBuf::from(value(&mut iter, &arg)?);
+                    if !dir.is_dir() {
+                        return Err(invalid(format!("{} {} is not a directory", arg, dir.display())));
+                    }
+                    // Canonical, like the root containment checks compare against
+                    args.overlays.push(dir.canonicalize()?);
+                }
+                "--mount" => {
+                    let mount = value(&mut iter, &arg)?;
+                    let (prefix, dir) = mount
+                        .split_once('=')
+                        .map(|(prefix, dir)| (prefix.trim_end_matches('/'), PathBuf::from(dir)))
+                        .filter(|(prefix, _)| prefix.starts_with('/'))
+                        .ok_or_else(|| invalid(format!("{} expects /PREFIX=DIR, not {}", arg, mount)))?;
+                    if !dir.is_dir() {
+                        return Err(invalid(format!("{} {} is not a directory", arg, dir.display())));
+                    }
+                    args.mounts.retain(|(known, _)| known != prefix);
+                    args.mounts.push((prefix.to_string(), dir.canonicalize()?));
+                    // The most specific prefix wins, so /docs/api can sit inside /docs
+                    args.mounts.sort_by_key(|(prefix, _)| std::cmp::Reverse(prefix.len()));
+                }
+                "--vhost" => {
+                    let vhost = value(&mut iter, &arg)?;
+                    let (host, dir) = vhost
+                        .split_once('=')
+                        .map(|(host, dir)| (host.to_ascii_lowercase(), PathBuf::from(dir)))
+                        .filter(|(host, _)| !host.is_empty() && !host.contains(['/', ':']))
+                        .ok_or_else(|| invalid(format!("{} expects HOST=DIR, not {}", arg, vhost)))?;
+                    if !dir.is_dir() {
+                        return Err(invalid(format!("{} {} is not a directory", arg, dir.display())));
+                    }
+                    args.vhosts.retain(|(known, _)| *known != host);
+                    args.vhosts.push((host, dir.canonicalize()?));
+                }
+                "--proxy" => {
+                    let proxy = value(&mut iter, &arg)?;
+                    let (prefix, upstream) = proxy
+                        .split_once('=')
+                        .and_then(|(prefix, url)| Some((prefix.trim_end_matches('/'), Upstream::parse(url)?)))
+                        .filter(|(prefix, _)| prefix.starts_with('/'))
+                        .ok_or_else(|| invalid(format!("{} expects /PREFIX=http://HOST[:PORT][/PATH], not {}", arg, proxy)))?;
+                    args.proxies.retain(|(known, _)| known != prefix);
+                    args.proxies.push((prefix.to_string(), upstream));
+                    args.proxies.sort_by_key(|(prefix, _)| std::cmp::Reverse(prefix.len()));
+                }
+                "--exact" => args.exact = true,
+                "--default-language" => args.default_language = Some(value(&mut iter, &arg)?),
+                "--max-body-size" => args.max_body_size = parse_size(&value(&mut iter, &arg)?)?,
+                "--max-header-size" => args.max_header_size = parse_size(&value(&mut iter, &arg)?)? as usize,
+                "--max-headers" => args.max_headers = parse_count(&value(&mut iter, &arg)?)?,
+                "--max-uri-length" => args.max_uri_length = parse_size(&value(&mut iter, &arg)?)? as usize,
+                "--header-timeout" => args.header_timeout = parse_timeout(&arg, &value(&mut iter, &arg)?)?,
+                "--read-timeout" => args.read_timeout = parse_timeout(&arg, &value(&mut iter, &arg)?)?,
+                "--write-timeout" => args.write_timeout = parse_timeout(&arg, &value(&mut iter, &arg)?)?,
+                "--cache-control" => args.cache_control = Some(value(&mut iter, &arg)?),
+                "--header" => args.response_headers.push(parse_header(&arg, &value(&mut iter, &arg)?)?),
+                "--secure-headers" => secure_headers = true,
+                "--error-page" => {
+                    let page = value(&mut iter, &arg)?;
+                    let (code, path) = page
+                        .split_once('=')
+                        .and_then(|(code, path)| Some((code.parse::<u16>().ok()?, path)))
+                        .filter(|(code, path)| (400..600).contains(code) && !path.is_empty())
+                        .ok_or_else(|| invalid(format!("{} expects STATUS=FILE with a 4xx or 5xx status, not {}", arg, page)))?;
+                    args.error_pages.retain(|(known, _)| *known != code);
+                    args.error_pages.push((code, PathBuf::from(path)));
+                }
+                "--mime-types" => args.mime_types = MimeTypes::load(Path::new(&value(&mut iter, &arg)?))?,
+                "--digest-trailers" => args.digest_trailers = true,
+                "--compress" => args.compress = true,
+                "--precompressed" => args.precompressed = true,
+                "--spa" => args.spa = true,
+                "--render-markdown" => args.render_markdown = true,
+                "--watch" => args.watch = true,
+                "--metrics" => args.metrics = true,
+                "--qr" => args.qr = true,
+                "--mdns" => args.mdns = true,
+                "--dlna" => args.dlna = true,
+                "--no-admin" => args.admin = false,
+                "--hide-dotfiles" => hide_dotfiles = true,
+                "--bountyignore" => bountyignore = true,
+                "--allow-upload" => args.allow_upload = true,
+                "--webdav" => args.webdav = true,
+                "--webdav-write" => {
+                    args.webdav = true;
+                    args.webdav_write = true;
+                }
+                "--not-found-ttl" => args.not_found_ttl = parse_seconds(&value(&mut iter, &arg)?)?,
+                "--listing-cache-ttl" => args.listing_cache_ttl = parse_seconds(&value(&mut iter, &arg)?)?,
+                "--stat-cache-ttl" => args.stat_cache_ttl = parse_seconds(&value(&mut iter, &arg)?)?,
+                "--file-cache" => args.file_cache = Some(parse_size(&value(&mut iter, &arg)?)?),
+                "--io-uring" if cfg!(feature = "io-uring") => args.io_uring = true,
+                "--io-uring" => return Err(invalid("--io-uring needs a build with the io-uring feature".to_string())),
+                "--shutdown-timeout" => args.shutdown_timeout = parse_seconds(&value(&mut iter, &arg)?)?,
+                "--workers" => args.workers = parse_count(&value(&mut iter, &arg)?)?,
+                "--threads" => args.threads = parse_count(&value(&mut iter, &arg)?)?,
+                "--processes" => args.processes = parse_count(&value(&mut iter, &arg)?)?,
+                "--supervised" => args.supervised = true,
+                "--worker-state" => args.worker_state = Some(PathBuf::from(value(&mut iter, &arg)?)),
+                "--append-token" => args.append_token = Some(value(&mut iter, &arg)?),
+                "--append-fsync" => {
+                    args.append_fsync = match value(&mut iter, &arg)?.as_str() {
+                        "always" => true,
+                        "never" => false,
+                        other => return Err(invalid(format!("{} must be always or never, not {}", arg, other))),
+                    };
+                }
+                "--append-max-file-size" => args.append_max_file_size = parse_size(&value(&mut iter, &arg)?)?,
+                "--batch-token" => args.batch_token = Some(value(&mut iter, &arg)?),
+                "--paste-dir" => {
+                    let dir = value(&mut iter, &arg)?.trim_matches('/').to_string();
+                    let is_plain = Path::new(&dir).components().all(|component| matches!(component, Component::Normal(_)));
+                    if dir.is_empty() || !is_plain {
+                        return Err(invalid(format!("{} must be a directory inside the root, not {}", arg, dir)));
+                    }
+                    args.paste_dir = Some(dir);
+                }
+                "--short-links" => args.short_links = Some(PathBuf::from(value(&mut iter, &arg)?)),
+                "--user-dirs" => {
+                    let pattern = value(&mut iter, &arg)?;
+                    if pattern.matches('*').count() != 1 {
+                        return Err(invalid(format!("{} needs exactly one * standing for the user name", arg)));
+                    }
+                    args.user_dirs = Some(pattern);
+                }
+                "--users" => args.users = Some(Users::load(Path::new(&value(&mut iter, &arg)?))?),
+                "--auth" => {
+                    let credential = Credential::parse(&value(&mut iter, &arg)?);
+                    args.auth.push(credential.map_err(|message| invalid(format!("{}: {}", arg, message)))?);
+                }
+                "--auth-file" => args.auth.extend(Credential::load(Path::new(&value(&mut iter, &arg)?))?),
+                "--pam" if cfg!(feature = "pam") => args.pam_service = Some(value(&mut iter, &arg)?),
+                "--pam" => return Err(invalid("--pam needs a build with the pam feature".to_string())),
+                "--pam-root" => {
+                    let pattern = value(&mut iter, &arg)?;
+                    if pattern.matches('*').count() != 1 {
+                        return Err(invalid(format!("{} needs exactly one * standing for the user name", arg)));
+                    }
+                    args.pam_root = pattern;
+                }
+                "--ldap" => args.ldap = Some(parse_ldap_url(&value(&mut iter, &arg)?)?),
+                "--ldap-dn" | "--ldap-root" => {
+                    let pattern = value(&mut iter, &arg)?;
+                    if pattern.matches('*').count() != 1 {
+                        return Err(invalid(format!("{} needs exactly one * standing for the user name", arg)));
+                    }
+                    match arg.as_str() {
+                        "--ldap-dn" => args.ldap_dn = pattern,
+                        _ => args.ldap_root = Some(pattern),
+                    }
+                }
+                "--ldap-write-group" => args.ldap_write_group = Some(value(&mut iter, &arg)?),
+                "--auth-log" => args.auth_log = Some(PathBuf::from(value(&mut iter, &arg)?)),
+                "--share-key" => args.share_key = Some(PathBuf::from(value(&mut iter, &arg)?)),
+                "--allow" => args.allow.extend(parse_cidrs(&arg, &value(&mut iter, &arg)?)?),
+                "--deny" => args.deny.extend(parse_cidrs(&arg, &value(&mut iter, &arg)?)?),
+                "--cors" => {
+                    let cors = Cors::parse(&value(&mut iter, &arg)?);
+                    args.cors = Some(cors.map_err(|message| invalid(format!("{}: {}", arg, message)))?);
+                }
+                "--rate-limit" => args.rate_limit = Some(parse_rate(&arg, &value(&mut iter, &arg)?)?),
+                "--rate-burst" => args.rate_burst = Some(parse_rate(&arg, &value(&mut iter, &arg)?)?),
+                "--max-connections-per-ip" => args.max_connections_per_ip = Some(parse_count(&value(&mut iter, &arg)?)?),
+                "--access-log" => args.access_log = Some(PathBuf::from(value(&mut iter, &arg)?)),
+                "--log-format" => {
+                    args.log_format = match value(&mut iter, &arg)?.as_str() {
+                        "common" => LogFormat::Common,
+                        "json" => LogFormat::Json,
+                        other => return Err(invalid(format!("{} must be common or json, not {}", arg, other))),
+                    };
+                }
+                "--auth-lockout" => args.auth_lockout = Some(parse_count(&value(&mut iter, &arg)?)? as u32),
+                "--auth-lockout-time" => args.auth_lockout_time = parse_seconds(&value(&mut iter, &arg)?)?,
+                "--tarpit" => args.tarpit = true,
+                "--tarpit-patterns" => {
+                    args.tarpit = true;
+                    args.tarpit_patterns = Some(PathBuf::from(value(&mut iter, &arg)?));
+                }
+                "--debug-echo" => args.debug_echo = true,
+                "--dry-run" => args.dry_run = true,
+                "--bundle" => args.bundle = Some(PathBuf::from(value(&mut iter, &arg)?)),
+                "--export" => args.export = Some(PathBuf::from(value(&mut iter, &arg)?)),
+                "--download-stats" => args.download_stats = Some(PathBuf::from(value(&mut iter, &arg)?)),
+                "--stat-api" => args.stat_api = true,
+                "--tor" => args.tor_control = Some(DEFAULT_TOR_CONTROL.to_string()),
+                "--tor-control" => args.tor_control = Some(value(&mut iter, &arg)?),
+                "--tor-password" => args.tor_password = Some(value(&mut iter, &arg)?),
+                "--tor-key" => args.tor_key = Some(PathBuf::from(value(&mut iter, &arg)?)),
+                "--tls-cert" => args.tls_cert = Some(PathBuf::from(value(&mut iter, &arg)?)),
+                "--tls-key" => args.tls_key = Some(PathBuf::from(value(&mut iter, &arg)?)),
+                "--tls-min-version" => {
+                    args.tls.tls13_only = match value(&mut iter, &arg)?.as_str() {
+                        "1.2" => false,
+                        "1.3" => true,
+                        other => return Err(invalid(format!("{} must be 1.2 or 1.3, not {}", arg, other))),
+                    };
+                }
+                "--tls-ciphers" => {
+                    let known = tls::cipher_suite_names();
+                    let ciphers: Vec<String> = value(&mut iter, &arg)?.split(',').map(|name| name.trim().to_string()).collect();
+                    if let Some(unknown) = ciphers.iter().find(|name| !known.contains(&name.as_str())) {
+                        return Err(invalid(format!("{}: unknown suite {}; known ones are {}", arg, unknown, known.join(", "))));
+                    }
+                    args.tls.ciphers = ciphers;
+                }
+                // Empty offers nothing, for clients that choke on ALPN
+                "--tls-alpn" => {
+                    let protocols = value(&mut iter, &arg)?;
+                    let protocols: Vec<String> =
+                        protocols.split(',').map(str::trim).filter(|protocol| !protocol.is_empty()).map(String::from).collect();
+                    if let Some(other) = protocols.iter().find(|protocol| !tls::ALPN_PROTOCOLS.contains(&protocol.as_str())) {
+                        return Err(invalid(format!("{} can only offer {}, not {}", arg, tls::ALPN_PROTOCOLS.join(" or "), other)));
+                    }
+                    args.tls.alpn = Some(protocols);
+                }
+                "--tls-session-tickets" => args.tls.session_tickets = true,
+                "--no-tls-resumption" => args.tls.no_resumption = true,
+                "--tls-client-ca" => args.tls.client_ca = Some(PathBuf::from(value(&mut iter, &arg)?)),
+                "--tls-ocsp" => args.tls.ocsp = true,
+                "--tls-ocsp-responder" => {
+                    let responder = value(&mut iter, &arg)?;
+                    if Upstream::parse(&responder).is_none() {
+                        return Err(invalid(format!("{} must be an http:// URL, not {}", arg, responder)));
+                    }
+                    args.tls.ocsp = true;
+                    args.tls.ocsp_responder = Some(responder);
+                }
+                "--file-digest" => {
+                    args.file_digest = match value(&mut iter, &arg)?.as_str() {
+                        "sha-256" => Some(DigestAlgorithm::Sha256),
+                        "md5" => Some(DigestAlgorithm::Md5),
+                        other => return Err(invalid(format!("{} must be sha-256 or md5, not {}", arg, other))),
+                    };
+                }
+                "--asset-prefix" => {
+                    let prefix = value(&mut iter, &arg)?;
+                    let prefix = prefix.trim_matches('/');
+                    if prefix.is_empty() {
+                        return Err(invalid(format!("{} can't be empty", arg)));
+                    }
+                    args.asset_prefix = format!("/{}/", prefix);
+                }
+                // Undo a switch an earlier flag, likely the config file's, turned on
+                "--no-exact" => args.exact = false,
+                "--no-git-urls" => args.git_urls = false,
+                "--no-spa" => args.spa = false,
+                "--no-render-markdown" => args.render_markdown = false,
+                "--no-digest-trailers" => args.digest_trailers = false,
+                "--no-secure-headers" => secure_headers = false,
+                "--no-compress" => args.compress = false,
+                "--no-precompressed" => args.precompressed = false,
+                "--no-io-uring" => args.io_uring = false,
+                "--no-allow-upload" => args.allow_upload = false,
+                "--no-webdav" => {
+                    args.webdav = false;
+                    args.webdav_write = false;
+                }
+                "--no-webdav-write" => args.webdav_write = false,
+                "--no-tarpit" => args.tarpit = false,
+                "--no-tor" => args.tor_control = None,
+                "--no-debug-echo" => args.debug_echo = false,
+                "--no-stat-api" => args.stat_api = false,
+                "--no-watch" => args.watch = false,
+                "--no-metrics" => args.metrics = false,
+                "--admin" => args.admin = true,
+                "--no-qr" => args.qr = false,
+                "--no-mdns" => args.mdns = false,
+                "--no-dlna" => args.dlna = false,
+                "--no-hide-dotfiles" => hide_dotfiles = false,
+                "--no-bountyignore" => bountyignore = false,
+                "--no-tls-session-tickets" => args.tls.session_tickets = false,
+                "--tls-resumption" => args.tls.no_resumption = false,
+                "--no-tls-ocsp" => args.tls.ocsp = false,
+                _ if arg.starts_with('-') => return Err(invalid(format!("unknown argument: {}", arg))),
+                _ if root.is_some() => return Err(invalid(format!("only one root directory, not also {}", arg))),
+                _ => root = Some(PathBuf::from(arg)),
+            }
+        }
+
+        if let Some(root) = root.or(config_root) {
+            if !root.is_dir() {
+                return Err(invalid(format!("{} is not a directory", root.display())));
+            }
+            args.root = root;
+        }
+        args.root = args.root.canonicalize()?;
+        args.hidden = Hidden::new(&args.root, hide_dotfiles, bountyignore)?;
+        // A --header of the same name replaces the preset's
+        if secure_headers {
+            let preset = SECURE_HEADERS.iter().filter(|(name, _)| {
+                !args.response_headers.iter().any(|(given, _)| given.eq_ignore_ascii_case(name))
+            });
+            let preset: Vec<_> = preset.map(|(name, value)| (name.to_string(), value.to_string())).collect();
+            args.response_headers.splice(0..0, preset);
+        }
+
+        // These serve or change the working directory no matter who logged in
+        if args.has_accounts() {
+            let unscoped = [
+                ("--git-ref", args.git_ref.is_some()),
+                ("--git-urls", args.git_urls),
+                ("--overlay", !args.overlays.is_empty()),
+                ("--mount", !args.mounts.is_empty()),
+                ("--vhost", !args.vhosts.is_empty()),
+                ("--append-token", args.append_token.is_some()),
+                ("--batch-token", args.batch_token.is_some()),
+                ("--paste-dir", args.paste_dir.is_some()),
+                ("--short-links", args.short_links.is_some()),
+                ("--user-dirs", args.user_dirs.is_some()),
+                ("--download-stats", args.download_stats.is_some()),
+            ];
+            if let Some((flag, _)) = unscoped.iter().find(|(_, given)| *given) {
+                return Err(invalid(format!("--users, --pam and --ldap can't be combined with {}", flag)));
+            }
+        }
+
+        // Each of these wants the Authorization header for itself
+        if !args.auth.is_empty() {
+            let conflicting = [
+                ("--users, --pam or --ldap", args.has_accounts()),
+                ("--append-token", args.append_token.is_some()),
+                ("--batch-token", args.batch_token.is_some()),
+            ];
+            if let Some((flag, _)) = conflicting.iter().find(|(_, given)| *given) {
+                return Err(invalid(format!("--auth can't be combined with {}", flag)));
+            }
+        }
+
+        // The export is of the working directory as it stands
+        if args.export.is_some() {
+            let unsupported = [
+                ("--git-ref", args.git_ref.is_some()),
+                ("--overlay", !args.overlays.is_empty()),
+                ("--mount", !args.mounts.is_empty()),
+                ("--vhost", !args.vhosts.is_empty()),
+                ("--users, --pam or --ldap", args.has_accounts()),
+            ];
+            if let Some((flag, _)) = unsupported.iter().find(|(_, given)| *given) {
+                return Err(invalid(format!("--export can't be combined with {}", flag)));
+            }
+        }
+
+        if args.tor_control.is_none() && (args.tor_password.is_some() || args.tor_key.is_some()) {
+            return Err(invalid("--tor-password and --tor-key need --tor or --tor-control".to_string()));
+        }
+        // Uploads would land in the working tree, which a ref doesn't show
+        if args.allow_upload && args.git_ref.is_some() {
+            return Err(invalid("--allow-upload can't be combined with --git-ref".to_string()));
+        }
+        // WebDAV describes and changes the root's own tree, which these don't serve as it is
+        if args.webdav {
+            let unsupported = [
+                ("--git-ref", args.git_ref.is_some()),
+                ("--overlay", !args.overlays.is_empty()),
+                ("--mount", !args.mounts.is_empty()),
+                ("--user-dirs", args.user_dirs.is_some()),
+            ];
+            if let Some((flag, _)) = unsupported.iter().find(|(_, given)| *given) {
+                return Err(invalid(format!("--webdav can't be combined with {}", flag)));
+            }
+        }
+        // Both serve the root's own tree whichever host was asked for
+        if !args.vhosts.is_empty() {
+            let unsupported = [("--git-ref", args.git_ref.is_some()), ("--overlay", !args.overlays.is_empty())];
+            if let Some((flag, _)) = unsupported.iter().find(|(_, given)| *given) {
+                return Err(invalid(format!("--vhost can't be combined with {}", flag)));
+            }
+        }
+        // Players can't log in or speak TLS, and are shown the root's own tree
+        if args.dlna {
+            let unsupported = [
+                ("--tls-cert", args.tls_cert.is_some()),
+                ("--auth", !args.auth.is_empty()),
+                ("--users, --pam or --ldap", args.has_accounts()),
+                ("--git-ref", args.git_ref.is_some()),
+                ("--overlay", !args.overlays.is_empty()),
+            ];
+            if let Some((flag, _)) = unsupported.iter().find(|(_, given)| *given) {
+                return Err(invalid(format!("--dlna can't be combined with {}", flag)));
+            }
+        }
+        if args.rate_burst.is_some() && args.rate_limit.is_none() {
+            return Err(invalid("--rate-burst needs --rate-limit".to_string()));
+        }
+        if args.ldap.is_some() && args.ldap_dn.is_empty() {
+            return Err(invalid("--ldap needs --ldap-dn".to_string()));
+        }
+        // A bucket that can't hold a whole token would refuse everything
+        if args.rate_burst.is_some_and(|burst| burst < 1.0) {
+            return Err(invalid("--rate-burst must be at least 1".to_string()));
+        }
+        if args.tls_cert.is_some() != args.tls_key.is_some() {
+            return Err(invalid("--tls-cert and --tls-key go together".to_string()));
+        }
+        let tls_options = [
+            ("--tls-min-version", args.tls.tls13_only),
+            ("--tls-ciphers", !args.tls.ciphers.is_empty()),
+            ("--tls-alpn", args.tls.alpn.is_some()),
+            ("--tls-session-tickets", args.tls.session_tickets),
+            ("--no-tls-resumption", args.tls.no_resumption),
+            ("--tls-client-ca", args.tls.client_ca.is_some()),
+            ("--tls-ocsp", args.tls.ocsp),
+        ];
+        if let Some((flag, _)) = tls_options.iter().find(|(_, given)| *given).filter(|_| args.tls_cert.is_none()) {
+            return Err(invalid(format!("{} needs --tls-cert and --tls-key", flag)));
+        }
+        if args.tls.session_tickets && args.tls.no_resumption {
+            return Err(invalid("--tls-session-tickets can't be used with --no-tls-resumption".to_string()));
+        }
+
+        Ok(args)
+    }
+
+    pub fn address(&self) -> SocketAddr {
+        SocketAddr::new(self.bind[0], self.port)
+    }
+
+    pub fn addresses(&self) -> Vec<SocketAddr> {
+        self.bind.iter().map(|ip| SocketAddr::new(*ip, self.port)).collect()
+    }
+
+    // For URLs pointing back at this server
+    pub fn scheme(&self) -> &'static str {
+        if self.tls_cert.is_some() { "https" } else { "http" }
+    }
+
+    // Logins are required, and each request is confined to the account's root
+    pub fn has_accounts(&self) -> bool {
+        self.users.is_some() || self.pam_service.is_some() || self.ldap.is_some()
+    }
+}
+
+fn value(iter: &mut impl Iterator<Item = String>, flag: &str) -> io::Result<String> {
+    iter.next()
+        .ok_or_else(|| invalid(format!("{} requires a value", flag)))
+}
+
+// Plain bytes, or with a K/M/G suffix (powers of 1024)
+pub fn parse_size(value: &str) -> io::Result<u64> {
+    let (digits, multiplier) = match value.to_ascii_uppercase().chars().last() {
+        Some('K') => (&value[..value.len() - 1], 1024),
+        Some('M') => (&value[..value.len() - 1], 1024 * 1024),
+        Some('G') => (&value[..value.len() - 1], 1024 * 1024 * 1024),
+        _ => (value, 1),
+    };
+    digits
+        .parse::<u64>()
+        .ok()
+        .and_then(|size| size.checked_mul(multiplier))
+        .ok_or_else(|| invalid(format!("invalid size: {}", value)))
+}
+
+fn parse_count(value: &str) -> io::Result<usize> {
+    match value.parse() {
+        Ok(count) if count > 0 => Ok(count),
+        _ => Err(invalid(format!("expected a positive count: {}", value))),
+    }
+}
+
+// Repeatable, and each value may list several networks separated by commas
+fn parse_cidrs(flag: &str, value: &str) -> io::Result<Vec<Cidr>> {
+    value
+        .split(',')
+        .map(|cidr| Cidr::parse(cidr.trim()).map_err(|message| invalid(format!("{}: {}", flag, message))))
+        .collect()
+}
+
+// `ldap://host[:port]` as the `host:port` to connect to. Passwords go over it
+// in the clear, so ldaps:// directories need a local TLS tunnel.
+fn parse_ldap_url(url: &str) -> io::Result<String> {
+    let authority = match url.strip_prefix("ldap://") {
+        Some(rest) => rest.trim_end_matches('/'),
+        None => return Err(invalid(format!("--ldap expects an ldap:// URL, not {}", url))),
+    };
+    if authority.is_empty() || authority.contains('/') {
+        return Err(invalid(format!("--ldap expects ldap://host[:port], not {}", url)));
+    }
+    // A port, unless the colon is part of a bracketed IPv6 address
+    match authority.rsplit_once(':') {
+        Some((_, port)) if !port.ends_with(']') => Ok(authority.to_string()),
+        _ => Ok(format!("{}:389", authority)),
+    }
+}
+
+fn parse_rate(flag: &str, value: &str) -> io::Result<f64> {
+    match value.parse::<f64>() {
+        Ok(rate) if rate.is_finite() && rate > 0.0 => Ok(rate),
+        _ => Err(invalid(format!("{} expects a positive number, not {}", flag, value))),
+    }
+}
+
+fn parse_seconds(value: &str) -> io::Result<Duration> {
+    value
+        .parse()
+        .map(Duration::from_secs)
+        .map_err(|_| invalid(format!("invalid number of seconds: {}", value)))
+}
+
+// `--secure-headers`. Inline styles are allowed for the built-in error page,
+// and pages may still be framed by their own origin.
+const SECURE_HEADERS: [(&str, &str); 3] = [
+    ("X-Content-Type-Options", "nosniff"),
+    ("Referrer-Policy", "strict-origin-when-cross-origin"),
+    ("Content-Security-Policy", "default-src 'self'; style-src 'self' 'unsafe-inline'; frame-ancestors 'self'"),
+];
+
+// The server sets these itself, and a second copy would garble the response
+const RESERVED_HEADERS: [&str; 4] = ["Content-Length", "Content-Type", "Transfer-Encoding", "Connection"];
+
+// `Name: value`, as it appears in a response
+fn parse_header(flag: &str, header: &str) -> io::Result<(String, String)> {
+    let (name, value) = header
+        .split_once(':')
+        .map(|(name, value)| (name.trim(), value.trim()))
+        .filter(|(name, value)| {
+            !name.is_empty()
+                && name.bytes().all(|byte| byte.is_ascii_alphanumeric() || b"!#$%&'*+-.^_`|~".contains(&byte))
+                && !value.bytes().any(|byte| byte.is_ascii_control() && byte != b'\t')
+        })
+        .ok_or_else(|| invalid(format!("{} expects NAME: VALUE, not {:?}", flag, header)))?;
+    if let Some(reserved) = RESERVED_HEADERS.iter().find(|reserved| reserved.eq_ignore_ascii_case(name)) {
+        return Err(invalid(format!("{} can't set {}, which the server sends itself", flag, reserved)));
+    }
+    Ok((name.to_string(), value.to_string()))
+}
+
+// Sockets take no zero timeout, and one would mean giving up at once anyway
+fn parse_timeout(flag: &str, value: &str) -> io::Result<Duration> {
+    match parse_seconds(value)? {
+        timeout if timeout.is_zero() => Err(invalid(format!("{} expects a positive number of seconds", flag))),
+        timeout => Ok(timeout),
+    }
+}
+
+fn invalid(message: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidInput, message)
+}
@@ -0,0 +1,101 @@
+use std::{
+    fs,
+    io,
+    path::Path,
+};
+
+use crate::{send_error, Exchange, write_response};
+
+pub const DEFAULT_PREFIX: &str = "/_bounty/";
+
+struct Asset {
+    name: &'static str,
+    extension: &'static str,
+    content_type: &'static str,
+    content: &'static [u8],
+    hash: u64,
+}
+
+macro_rules! asset {
+    ($name:literal, $extension:literal, $content_type:literal) => {{
+        const CONTENT: &[u8] = include_bytes!(concat!("assets/", $name, ".", $extension));
+        Asset {
+            name: $name,
+            extension: $extension,
+            content_type: $content_type,
+            content: CONTENT,
+            hash: fnv1a(CONTENT),
+        }
+    }};
+}
+
+const ASSETS: &[Asset] = &[
+    asset!("favicon", "ico", "image/vnd.microsoft.icon"),
+    asset!("listing", "css", "text/css; charset=utf-8"),
+    asset!("listing", "js", "text/javascript; charset=utf-8"),
+    asset!("markdown", "css", "text/css; charset=utf-8"),
+    asset!("reload", "js", "text/javascript; charset=utf-8"),
+    asset!("source", "css", "text/css; charset=utf-8"),
+];
+
+pub const FAVICON: &[u8] = include_bytes!("assets/favicon.ico");
+
+impl Asset {
+    fn hashed_name(&self) -> String {
+        format!("{}.{:016x}.{}", self.name, self.hash, self.extension)
+    }
+
+    fn plain_name(&self) -> String {
+        format!("{}.{}", self.name, self.extension)
+    }
+}
+
+// Content-addressed so it can be cached forever; changes to an asset change its URL
+pub fn url(prefix: &str, name: &str, extension: &str) -> String {
+    let asset = ASSETS
+        .iter()
+        .find(|asset| asset.name == name && asset.extension == extension)
+        .expect("unknown embedded asset");
+    format!("{}{}", prefix, asset.hashed_name())
+}
+
+pub fn send_asset(stream: &mut Exchange, file: &str) -> io::Result<()> {
+    // Plain names stay reachable for hand-written links, but must be revalidated
+    let found = ASSETS.iter().find_map(|asset| {
+        if file == asset.hashed_name() {
+            Some((asset, "public, max-age=31536000, immutable"))
+        } else if file == asset.plain_name() {
+            Some((asset, "no-cache"))
+        } else {
+            None
+        }
+    });
+
+    let (asset, cache_control) = match found {
+        Some(found) => found,
+        None => return send_error(stream, "404 Not Found", "Not Found"),
+    };
+
+    let headers = format!("Cache-Control: {}\r\n", cache_control);
+    let head = stream.response_head("200 OK", asset.content_type, asset.content.len(), &headers);
+    write_response(stream, &head, asset.content)
+}
+
+// For `--export`: each asset under the name its URL uses
+pub fn write_to(dir: &Path) -> io::Result<()> {
+    for asset in ASSETS {
+        fs::write(dir.join(asset.hashed_name()), asset.content)?;
+    }
+    Ok(())
+}
+
+const fn fnv1a(bytes: &[u8]) -> u64 {
+    let mut hash = 0xcbf2_9ce4_8422_2325u64;
+    let mut i = 0;
+    while i < bytes.len() {
+        hash ^= bytes[i] as u64;
+        hash = hash.wrapping_mul(0x0100_0000_01b3);
+        i += 1;
+    }
+    hash
+}
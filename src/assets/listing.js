@@ -0,0 +1,75 @@
+// `/` focuses the filter, arrows move between visible rows, backspace goes up a
+// directory. Enter opens the first match, or searches subdirectories where the
+// filter is a form.
+const filter = document.getElementById('filter');
+const links = () => [...document.querySelectorAll('tbody tr:not([hidden]) a')];
+
+filter.addEventListener('input', () => {
+  const query = filter.value.toLowerCase();
+  for (const row of document.querySelectorAll('tbody tr')) {
+    row.hidden = !row.querySelector('.name').textContent.toLowerCase().includes(query);
+  }
+});
+
+document.addEventListener('keydown', (e) => {
+  if (e.ctrlKey || e.metaKey || e.altKey) return;
+
+  if (e.target === filter) {
+    if (e.key === 'ArrowDown' || e.key === 'Enter' && !filter.form) {
+      const first = links()[0];
+      if (first) {
+        e.preventDefault();
+        e.key === 'Enter' ? first.click() : first.focus();
+      }
+    } else if (e.key === 'Escape') {
+      filter.value = '';
+      filter.dispatchEvent(new Event('input'));
+      filter.blur();
+    }
+    return;
+  }
+
+  const visible = links();
+  const index = visible.indexOf(document.activeElement);
+  if (e.key === '/') {
+    e.preventDefault();
+    filter.focus();
+  } else if (e.key === 'ArrowDown') {
+    e.preventDefault();
+    (visible[index + 1] || visible[index] || visible[0])?.focus();
+  } else if (e.key === 'ArrowUp') {
+    e.preventDefault();
+    index > 0 ? visible[index - 1].focus() : filter.focus();
+  } else if (e.key === 'Backspace') {
+    e.preventDefault();
+    location.href = '../';
+  }
+});
+
+// Listings that take uploads have Rename and Delete buttons on each row, sent
+// as MOVE and DELETE requests for the row's link
+document.querySelector('tbody').addEventListener('click', async (e) => {
+  const button = e.target.closest('button[data-action]');
+  if (!button) return;
+  const link = button.closest('tr').querySelector('.name a');
+  const url = new URL(link.getAttribute('href'), location.href);
+  const isDir = url.pathname.endsWith('/');
+  const name = link.textContent.replace(/\/$/, '');
+
+  let response;
+  if (button.dataset.action === 'delete') {
+    if (!confirm(`Delete ${name}${isDir ? ' and everything in it' : ''}?`)) return;
+    response = await fetch(url, { method: 'DELETE' });
+  } else {
+    const renamed = prompt(`Rename ${name} to`, name);
+    if (!renamed || renamed === name) return;
+    if (renamed.includes('/')) return alert('Names can\'t contain /');
+    const destination = new URL(encodeURIComponent(renamed) + (isDir ? '/' : ''), location.href);
+    response = await fetch(url, { method: 'MOVE', headers: { Destination: destination.href, Overwrite: 'F' } });
+  }
+  if (response.ok) {
+    location.reload();
+  } else {
+    alert(`${name}: ${response.status === 412 ? 'that name is taken' : response.statusText || response.status}`);
+  }
+});
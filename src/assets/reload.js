@@ -0,0 +1,10 @@
+// --watch reloads the page whenever the served tree changes; the events
+// endpoint sits next to this script, under the asset prefix. In a block so
+// it can't clash with the page's own globals.
+{
+  const events = new EventSource(document.currentScript.src.replace(/[^/]*$/, 'events'));
+  events.addEventListener('change', () => {
+    events.close();
+    location.reload();
+  });
+}
@@ -0,0 +1,114 @@
+use std::{
+    collections::HashMap,
+    fs::{File, OpenOptions},
+    io::{self, Write},
+    net::IpAddr,
+    sync::Mutex,
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+use crate::{args::Args, listing};
+
+// Failures further apart than this don't add up to a lockout
+const FAILURE_WINDOW: Duration = Duration::from_secs(600);
+
+const MAX_TRACKED_ADDRESSES: usize = 10_000;
+
+// Failed logins and bearer tokens, shared by all workers of a process. Each one
+// is appended to the `--auth-log` file as a single line:
+//
+//     2026-10-14T04:54:51Z auth failure from 192.0.2.7 user=alice path=/private/
+//
+// The timestamp is UTC, the user is `-` for token failures, and characters
+// other than printable ASCII (spaces included) in the user and path become `?`
+// so the line always splits on spaces. A fail2ban filter only needs
+// `auth failure from <HOST> `.
+pub struct AuthFailures {
+    log: Option<Mutex<File>>,
+    // Lock an address out after this many failures within FAILURE_WINDOW
+    limit: Option<u32>,
+    lockout: Duration,
+    addresses: Mutex<HashMap<IpAddr, Failures>>,
+}
+
+struct Failures {
+    count: u32,
+    first: Instant,
+    locked_until: Option<Instant>,
+}
+
+impl AuthFailures {
+    pub fn new(args: &Args) -> io::Result<AuthFailures> {
+        let log = match &args.auth_log {
+            Some(path) => Some(Mutex::new(OpenOptions::new().create(true).append(true).open(path)?)),
+            None => None,
+        };
+        Ok(AuthFailures {
+            log,
+            limit: args.auth_lockout,
+            lockout: args.auth_lockout_time,
+            addresses: Mutex::new(HashMap::new()),
+        })
+    }
+
+    // How much longer the address stays locked out, if it is
+    pub fn locked_out(&self, ip: IpAddr) -> Option<Duration> {
+        let addresses = self.addresses.lock().unwrap_or_else(|e| e.into_inner());
+        let locked_until = addresses.get(&ip)?.locked_until?;
+        locked_until.checked_duration_since(Instant::now())
+    }
+
+    pub fn record(&self, ip: IpAddr, user: Option<&str>, path: &str) {
+        if let Some(log) = &self.log {
+            let now = SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |since| since.as_secs());
+            let line = format!(
+                "{} auth failure from {} user={} path={}\n",
+                listing::format_iso8601(now),
+                ip,
+                user.map_or("-".to_string(), sanitize),
+                sanitize(path)
+            );
+            let mut file = log.lock().unwrap_or_else(|e| e.into_inner());
+            if let Err(e) = file.write_all(line.as_bytes()) {
+                eprintln!("Error writing auth log: {:?}", e);
+            }
+        }
+
+        let limit = match self.limit {
+            Some(limit) => limit,
+            None => return,
+        };
+        let now = Instant::now();
+        let mut addresses = self.addresses.lock().unwrap_or_else(|e| e.into_inner());
+        if addresses.len() >= MAX_TRACKED_ADDRESSES {
+            addresses.retain(|_, failures| {
+                failures.locked_until.is_some_and(|until| until > now) || now - failures.first < FAILURE_WINDOW
+            });
+            if addresses.len() >= MAX_TRACKED_ADDRESSES {
+                addresses.clear();
+            }
+        }
+
+        let failures = addresses.entry(ip).or_insert(Failures { count: 0, first: now, locked_until: None });
+        let lockout_over = failures.locked_until.is_some_and(|until| until <= now);
+        if lockout_over || now - failures.first >= FAILURE_WINDOW {
+            *failures = Failures { count: 0, first: now, locked_until: None };
+        }
+        failures.count += 1;
+        if failures.count >= limit {
+            failures.locked_until = Some(now + self.lockout);
+            eprintln!("Locking out {} for {}s after {} failed logins", ip, self.lockout.as_secs(), failures.count);
+        }
+    }
+
+    // A successful login forgives earlier mistakes
+    pub fn clear(&self, ip: IpAddr) {
+        if self.limit.is_some() {
+            self.addresses.lock().unwrap_or_else(|e| e.into_inner()).remove(&ip);
+        }
+    }
+}
+
+fn sanitize(value: &str) -> String {
+    value.chars().map(|c| if c.is_ascii_graphic() { c } else { '?' }).collect()
+}
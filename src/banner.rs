@@ -0,0 +1,150 @@
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+
+use crate::{args::Args, qr::Code};
+
+// What people type into a phone: a URL per address on the LAN when listening
+// on all interfaces, and with `--qr` the first of them as a QR code
+pub fn print(args: &Args, addresses: &[SocketAddr]) {
+    let reachable = reachable(addresses);
+    let urls: Vec<String> = reachable.iter().map(|address| url(args, *address)).collect();
+    // Otherwise the listening addresses already said it all
+    let everywhere = addresses.iter().any(|address| address.ip().is_unspecified());
+    if urls.is_empty() || !everywhere && !args.qr {
+        return;
+    }
+
+    println!("Reachable at:");
+    for url in &urls {
+        println!("  {}", url);
+    }
+    if args.qr {
+        match Code::encode(urls[0].as_bytes()) {
+            Some(code) => print!("{}", render(&code)),
+            None => eprintln!("{} is too long for a QR code", urls[0]),
+        }
+    }
+}
+
+// The listening addresses, with unspecified ones replaced by the interface
+// addresses they take connections on
+pub fn reachable(addresses: &[SocketAddr]) -> Vec<SocketAddr> {
+    let mut reachable = Vec::new();
+    for address in addresses {
+        if !address.ip().is_unspecified() {
+            reachable.push(*address);
+            continue;
+        }
+        // `::` also takes IPv4 connections unless it sits next to other addresses
+        let ipv4 = address.is_ipv4() || addresses.len() == 1;
+        for ip in interface_addresses() {
+            if ip.is_ipv4() && ipv4 || ip.is_ipv6() && address.is_ipv6() {
+                reachable.push(SocketAddr::new(ip, address.port()));
+            }
+        }
+    }
+    reachable.dedup();
+    // A phone can't reach loopback, so those only stand in when there's nothing else
+    reachable.sort_by_key(|address| address.ip().is_loopback());
+    reachable
+}
+
+fn url(args: &Args, address: SocketAddr) -> String {
+    format!("{}://{}/", args.scheme(), address)
+}
+
+// Two modules per character cell using half blocks, light ones drawn, for
+// terminals with dark backgrounds; with the quiet zone scanners need around it
+fn render(code: &Code) -> String {
+    const QUIET: isize = 2;
+    let size = code.size() as isize;
+    let mut out = String::new();
+    let mut y = -QUIET;
+    while y < size + QUIET {
+        for x in -QUIET..size + QUIET {
+            out.push(match (code.is_dark(x, y), code.is_dark(x, y + 1) || y + 1 >= size + QUIET) {
+                (false, false) => '█',
+                (false, true) => '▀',
+                (true, false) => '▄',
+                (true, true) => ' ',
+            });
+        }
+        out.push('\n');
+        y += 2;
+    }
+    out
+}
+
+// Non-loopback addresses of interfaces that are up, IPv4 first. IPv6
+// link-local ones are left out, since their URLs would need a zone.
+#[cfg(any(target_os = "linux", target_os = "android", target_os = "macos", target_os = "ios", target_os = "freebsd"))]
+fn interface_addresses() -> Vec<IpAddr> {
+    use std::ffi::{c_char, c_int, c_uint};
+
+    #[cfg(any(target_os = "linux", target_os = "android"))]
+    const AF_INET6: u16 = 10;
+    #[cfg(any(target_os = "macos", target_os = "ios"))]
+    const AF_INET6: u16 = 30;
+    #[cfg(target_os = "freebsd")]
+    const AF_INET6: u16 = 28;
+    const AF_INET: u16 = 2;
+    const IFF_UP: c_uint = 0x1;
+    const IFF_LOOPBACK: c_uint = 0x8;
+
+    // The fields every platform's struct ifaddrs starts with, up to the one needed
+    #[repr(C)]
+    struct IfAddrs {
+        next: *mut IfAddrs,
+        _name: *mut c_char,
+        flags: c_uint,
+        address: *mut u8,
+    }
+
+    extern "C" {
+        fn getifaddrs(list: *mut *mut IfAddrs) -> c_int;
+        fn freeifaddrs(list: *mut IfAddrs);
+    }
+
+    let mut list = std::ptr::null_mut();
+    if unsafe { getifaddrs(&mut list) } != 0 {
+        return Vec::new();
+    }
+    let mut addresses = Vec::new();
+    let mut entry = list;
+    while !entry.is_null() {
+        let interface = unsafe { &*entry };
+        entry = interface.next;
+        if interface.address.is_null() || interface.flags & IFF_UP == 0 || interface.flags & IFF_LOOPBACK != 0 {
+            continue;
+        }
+        // A 16-bit family on Linux; a length byte and then the family on the BSDs
+        let family = if cfg!(any(target_os = "linux", target_os = "android")) {
+            unsafe { (interface.address as *const u16).read_unaligned() }
+        } else {
+            unsafe { *interface.address.add(1) as u16 }
+        };
+        // Both sockaddr_in and sockaddr_in6 keep the port in bytes 2 and 3
+        let ip = match family {
+            AF_INET => {
+                let octets: [u8; 4] = unsafe { *(interface.address.add(4) as *const [u8; 4]) };
+                IpAddr::V4(Ipv4Addr::from(octets))
+            }
+            AF_INET6 => {
+                let octets: [u8; 16] = unsafe { *(interface.address.add(8) as *const [u8; 16]) };
+                IpAddr::V6(Ipv6Addr::from(octets))
+            }
+            _ => continue,
+        };
+        let link_local = matches!(ip, IpAddr::V6(ip) if ip.segments()[0] & 0xffc0 == 0xfe80);
+        if !ip.is_loopback() && !link_local && !addresses.contains(&ip) {
+            addresses.push(ip);
+        }
+    }
+    unsafe { freeifaddrs(list) };
+    addresses.sort_by_key(|ip| ip.is_ipv6());
+    addresses
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "android", target_os = "macos", target_os = "ios", target_os = "freebsd")))]
+fn interface_addresses() -> Vec<IpAddr> {
+    Vec::new()
+}
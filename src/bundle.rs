@@ -0,0 +1,159 @@
+use std::{
+    env,
+    fs::{self, File},
+    io::{self, BufReader, BufWriter, Read, Seek, SeekFrom, Write},
+    path::{Component, Path, PathBuf},
+    time::UNIX_EPOCH,
+};
+
+use walkdir::WalkDir;
+
+use crate::{args::Args, is_path_within};
+
+// Ends an executable that has a tree appended, preceded by the tree's length:
+// `<executable> <archive> <archive length, u64 LE> <MAGIC>`
+const MAGIC: &[u8; 8] = b"BOUNTYB1";
+const TRAILER_LEN: u64 = 16;
+
+const DIR: u8 = 0;
+const FILE: u8 = 1;
+
+// `--bundle OUT`: writes a copy of this executable with the root appended, which serves that tree when run. Entries are `<kind> <path length,
+// u32 LE> <path>` and, for files, `<size, u64 LE> <content>`.
+pub fn create(args: &Args, out: &Path) -> io::Result<()> {
+    let root = &args.root;
+    let exe = env::current_exe()?;
+    let (exe_len, _) = executable_len(&exe)?;
+
+    let mut writer = BufWriter::new(File::create(out)?);
+    io::copy(&mut File::open(&exe)?.take(exe_len), &mut writer)?;
+    // The output itself may well be inside the tree being bundled
+    let out = out.canonicalize()?;
+
+    let mut archive_len = 0u64;
+    let mut files = 0;
+    let walker = WalkDir::new(root).min_depth(1).follow_links(true).into_iter().filter_entry(|entry| entry.path() != out);
+    for entry in walker {
+        let entry = entry?;
+        if !is_path_within(entry.path(), root)? {
+            eprintln!("Skipping {}: outside the root", entry.path().display());
+            continue;
+        }
+        let relative = entry.path().strip_prefix(root).map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+        let name = relative
+            .to_str()
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, format!("{} isn't valid UTF-8", relative.display())))?;
+
+        let kind = if entry.file_type().is_dir() { DIR } else if entry.file_type().is_file() { FILE } else { continue };
+        writer.write_all(&[kind])?;
+        writer.write_all(&(name.len() as u32).to_le_bytes())?;
+        writer.write_all(name.as_bytes())?;
+        archive_len += 1 + 4 + name.len() as u64;
+
+        if kind == FILE {
+            let size = entry.metadata()?.len();
+            writer.write_all(&size.to_le_bytes())?;
+            // Exactly `size` bytes, even if the file grows while being copied
+            let copied = io::copy(&mut File::open(entry.path())?.take(size), &mut writer)?;
+            if copied != size {
+                return Err(io::Error::new(io::ErrorKind::UnexpectedEof, format!("{} shrank while bundling", name)));
+            }
+            archive_len += 8 + size;
+            files += 1;
+        }
+    }
+
+    writer.write_all(&archive_len.to_le_bytes())?;
+    writer.write_all(MAGIC)?;
+    writer.flush()?;
+    drop(writer);
+    fs::set_permissions(&out, fs::metadata(&exe)?.permissions())?;
+
+    println!("Bundled {} files from {} into {}", files, root.display(), out.display());
+    Ok(())
+}
+
+// When this executable carries a tree, unpacks it into the temporary directory
+// (once per distinct tree, so restarts and sibling processes reuse it) and
+// returns where
+pub fn unpack() -> io::Result<Option<PathBuf>> {
+    let exe = env::current_exe()?;
+    let (exe_len, archive_len) = executable_len(&exe)?;
+    if archive_len == 0 {
+        return Ok(None);
+    }
+
+    let mut file = File::open(&exe)?;
+    let mut tail = [0; 16];
+    file.seek(SeekFrom::Start(exe_len + archive_len.saturating_sub(tail.len() as u64)))?;
+    file.read_exact(&mut tail)?;
+    // Tells bundles apart well enough without hashing the whole tree on every start
+    let modified = file.metadata()?.modified()?.duration_since(UNIX_EPOCH).map_or(0, |since| since.as_nanos());
+    let id = tail
+        .iter()
+        .chain(&archive_len.to_le_bytes())
+        .chain(&modified.to_le_bytes())
+        .fold(0xcbf2_9ce4_8422_2325u64, |hash, byte| (hash ^ *byte as u64).wrapping_mul(0x0100_0000_01b3));
+    let dir = env::temp_dir().join(format!("bounty-bundle-{:016x}", id));
+    if dir.is_dir() {
+        return Ok(Some(dir));
+    }
+
+    // Unpacked beside the final name and renamed, so a half-written tree is never served
+    let partial = env::temp_dir().join(format!("bounty-bundle-{:016x}.{}", id, std::process::id()));
+    fs::create_dir_all(&partial)?;
+    file.seek(SeekFrom::Start(exe_len))?;
+    let mut archive = BufReader::new(file.take(archive_len));
+    let mut kind = [0; 1];
+    while archive.read(&mut kind)? == 1 {
+        let name_len = u32::from_le_bytes(read_array(&mut archive)?) as usize;
+        let mut name = vec![0; name_len];
+        archive.read_exact(&mut name)?;
+        let name = String::from_utf8(name).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+        if !Path::new(&name).components().all(|component| matches!(component, Component::Normal(_))) {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, format!("bundled path {} leaves the tree", name)));
+        }
+
+        let target = partial.join(&name);
+        if kind[0] == DIR {
+            fs::create_dir_all(&target)?;
+        } else {
+            let size = u64::from_le_bytes(read_array(&mut archive)?);
+            let copied = io::copy(&mut (&mut archive).take(size), &mut File::create(&target)?)?;
+            if copied != size {
+                return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "bundle truncated"));
+            }
+        }
+    }
+
+    match fs::rename(&partial, &dir) {
+        Ok(()) => {}
+        // Another process unpacked the same tree first
+        Err(_) if dir.is_dir() => fs::remove_dir_all(&partial)?,
+        Err(e) => return Err(e),
+    }
+    Ok(Some(dir))
+}
+
+// The executable's own length and that of the tree appended to it, if any
+fn executable_len(exe: &Path) -> io::Result<(u64, u64)> {
+    let mut file = File::open(exe)?;
+    let len = file.metadata()?.len();
+    if len < TRAILER_LEN {
+        return Ok((len, 0));
+    }
+
+    file.seek(SeekFrom::End(-(TRAILER_LEN as i64)))?;
+    let archive_len = u64::from_le_bytes(read_array(&mut file)?);
+    let magic: [u8; 8] = read_array(&mut file)?;
+    if &magic != MAGIC || archive_len > len - TRAILER_LEN {
+        return Ok((len, 0));
+    }
+    Ok((len - TRAILER_LEN - archive_len, archive_len))
+}
+
+fn read_array<const N: usize>(reader: &mut impl Read) -> io::Result<[u8; N]> {
+    let mut bytes = [0; N];
+    reader.read_exact(&mut bytes)?;
+    Ok(bytes)
+}
@@ -0,0 +1,400 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    fs::{self, File},
+    io::{self, Read},
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex, MutexGuard},
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+use base64::{engine::general_purpose::STANDARD, Engine};
+use md5::Md5;
+use sha2::{Digest, Sha256};
+
+use crate::{args::Args, listing::ListingEntry, watch::Watcher};
+
+// Caps memory when a scanner walks through endless distinct URLs
+const MAX_NOT_FOUND_ENTRIES: usize = 10_000;
+
+// Total rows across all cached directories
+const MAX_LISTING_ENTRIES: usize = 200_000;
+
+const MAX_STAT_ENTRIES: usize = 100_000;
+
+const MAX_CHECKSUM_ENTRIES: usize = 10_000;
+
+// Bigger files gain little from skipping a read and would crowd out many small ones
+pub const MAX_CACHED_FILE_SIZE: u64 = 1024 * 1024;
+
+pub struct Caches {
+    pub not_found: NotFoundCache,
+    pub listings: ListingCache,
+    pub stats: StatCache,
+    pub checksums: ChecksumCache,
+    // The --watch generation the caches were last emptied for
+    generation: u64,
+}
+
+impl Caches {
+    // `stats` is the process's, shared with every other thread's Caches
+    pub fn new(args: &Args, stats: &StatCache) -> Caches {
+        Caches {
+            not_found: NotFoundCache::new(args.not_found_ttl),
+            listings: ListingCache::new(args.listing_cache_ttl),
+            stats: stats.clone(),
+            checksums: ChecksumCache::new(),
+            generation: 0,
+        }
+    }
+
+    // With --watch, anything changing under the root empties the caches, so
+    // the change shows up at once rather than once the TTLs run out. Checksums
+    // stay: they're checked against the file's size and mtime anyway.
+    pub fn follow(&mut self, watcher: Option<&Watcher>) {
+        let generation = match watcher {
+            Some(watcher) => watcher.generation(),
+            None => return,
+        };
+        if generation != self.generation {
+            self.generation = generation;
+            self.not_found.entries.clear();
+            self.listings.listings.clear();
+            self.listings.total_entries = 0;
+            self.stats.clear();
+        }
+    }
+}
+
+// Remembers paths that recently didn't exist so repeated misses (bots probing
+// wp-login.php and friends) are answered without touching the disk. Entries
+// simply expire, so a file created meanwhile shows up after at most one TTL.
+pub struct NotFoundCache {
+    ttl: Duration,
+    entries: HashMap<PathBuf, Instant>,
+}
+
+impl NotFoundCache {
+    pub fn new(ttl: Duration) -> NotFoundCache {
+        NotFoundCache { ttl, entries: HashMap::new() }
+    }
+
+    pub fn contains(&mut self, path: &Path) -> bool {
+        match self.entries.get(path) {
+            Some(expires) if *expires > Instant::now() => true,
+            Some(_) => {
+                self.entries.remove(path);
+                false
+            }
+            None => false,
+        }
+    }
+
+    pub fn insert(&mut self, path: PathBuf) {
+        if self.ttl.is_zero() {
+            return;
+        }
+
+        if self.entries.len() >= MAX_NOT_FOUND_ENTRIES {
+            let now = Instant::now();
+            self.entries.retain(|_, expires| *expires > now);
+            // Still full of live entries: start over rather than track recency
+            if self.entries.len() >= MAX_NOT_FOUND_ENTRIES {
+                self.entries.clear();
+            }
+        }
+
+        self.entries.insert(path, Instant::now() + self.ttl);
+    }
+}
+
+// Keeps the collected rows of recently listed directories, not rendered pages,
+// so relative times and per-request display options stay current. A listing is
+// reused while the directory's own mtime is unchanged, which catches entries
+// being added, removed or renamed; the TTL bounds how stale child sizes and
+// times can get, since editing a file doesn't touch its directory.
+pub struct ListingCache {
+    ttl: Duration,
+    listings: HashMap<PathBuf, CachedListing>,
+    total_entries: usize,
+}
+
+struct CachedListing {
+    modified: SystemTime,
+    expires: Instant,
+    entries: Vec<ListingEntry>,
+}
+
+impl ListingCache {
+    pub fn new(ttl: Duration) -> ListingCache {
+        ListingCache { ttl, listings: HashMap::new(), total_entries: 0 }
+    }
+
+    pub fn get(&self, path: &Path, modified: SystemTime) -> Option<&[ListingEntry]> {
+        self.listings
+            .get(path)
+            .filter(|listing| listing.modified == modified && listing.expires > Instant::now())
+            .map(|listing| listing.entries.as_slice())
+    }
+
+    pub fn insert(&mut self, path: PathBuf, modified: SystemTime, entries: Vec<ListingEntry>) {
+        if self.ttl.is_zero() || entries.len() > MAX_LISTING_ENTRIES {
+            return;
+        }
+
+        if let Some(old) = self.listings.remove(&path) {
+            self.total_entries -= old.entries.len();
+        }
+
+        if self.total_entries + entries.len() > MAX_LISTING_ENTRIES {
+            let now = Instant::now();
+            self.listings.retain(|_, listing| listing.expires > now);
+            self.total_entries = self.listings.values().map(|listing| listing.entries.len()).sum();
+            if self.total_entries + entries.len() > MAX_LISTING_ENTRIES {
+                self.listings.clear();
+                self.total_entries = 0;
+            }
+        }
+
+        self.total_entries += entries.len();
+        self.listings.insert(path, CachedListing { modified, expires: Instant::now() + self.ttl, entries });
+    }
+}
+
+#[derive(Clone, Copy)]
+pub struct FileInfo {
+    pub is_dir: bool,
+    pub is_file: bool,
+    pub size: u64,
+    pub modified: Option<SystemTime>,
+}
+
+impl FileInfo {
+    // Strong validator from size and mtime, so it costs nothing beyond the stat
+    pub fn etag(&self) -> Option<String> {
+        let since = self.modified?.duration_since(UNIX_EPOCH).ok()?;
+        Some(format!("\"{:x}-{:x}-{:x}\"", since.as_secs(), since.subsec_nanos(), self.size))
+    }
+}
+
+// Metadata of recently seen paths, shared by request dispatch and listings so a
+// request doesn't stat the same file several times over. Only existing paths are
+// kept; misses are the NotFoundCache's job. One per process: clones share entries,
+// so every handler thread sees the same metadata.
+#[derive(Clone)]
+pub struct StatCache {
+    ttl: Duration,
+    entries: Arc<Mutex<HashMap<PathBuf, (FileInfo, Instant)>>>,
+}
+
+impl StatCache {
+    pub fn new(ttl: Duration) -> StatCache {
+        StatCache { ttl, entries: Arc::new(Mutex::new(HashMap::new())) }
+    }
+
+    fn entries(&self) -> MutexGuard<'_, HashMap<PathBuf, (FileInfo, Instant)>> {
+        self.entries.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    // Follows symlinks; None when the path doesn't exist or can't be read.
+    // The lock isn't held while the disk is asked.
+    pub fn metadata(&self, path: &Path) -> Option<FileInfo> {
+        if let Some((info, expires)) = self.entries().get(path) {
+            if *expires > Instant::now() {
+                return Some(*info);
+            }
+        }
+
+        let info = match stat(path) {
+            Some(info) => info,
+            None => {
+                self.entries().remove(path);
+                return None;
+            }
+        };
+        self.insert(path.to_path_buf(), info);
+        Some(info)
+    }
+
+    // For metadata looked up elsewhere, such as by parallel listing workers
+    pub fn insert(&self, path: PathBuf, info: FileInfo) {
+        if self.ttl.is_zero() {
+            return;
+        }
+
+        let now = Instant::now();
+        let mut entries = self.entries();
+        if entries.len() >= MAX_STAT_ENTRIES {
+            entries.retain(|_, (_, expires)| *expires > now);
+            if entries.len() >= MAX_STAT_ENTRIES {
+                entries.clear();
+            }
+        }
+        entries.insert(path, (info, now + self.ttl));
+    }
+
+    fn clear(&self) {
+        self.entries().clear();
+    }
+}
+
+// Uncached lookup; follows symlinks
+pub fn stat(path: &Path) -> Option<FileInfo> {
+    let metadata = fs::metadata(path).ok()?;
+    Some(FileInfo {
+        is_dir: metadata.is_dir(),
+        is_file: metadata.is_file(),
+        size: metadata.len(),
+        modified: metadata.modified().ok(),
+    })
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+pub enum DigestAlgorithm {
+    // RFC 9530 Repr-Digest
+    Sha256,
+    // The obsolete Content-MD5, for tooling that predates Repr-Digest
+    Md5,
+}
+
+// File digests keyed on path, reused while the size and mtime still match, so a
+// popular download is hashed once rather than on every request
+pub struct ChecksumCache {
+    entries: HashMap<(PathBuf, DigestAlgorithm), (u64, SystemTime, String)>,
+}
+
+impl ChecksumCache {
+    pub fn new() -> ChecksumCache {
+        ChecksumCache { entries: HashMap::new() }
+    }
+
+    // The CRLF-terminated header line carrying the file's digest
+    pub fn header(&mut self, path: &Path, info: FileInfo, algorithm: DigestAlgorithm) -> io::Result<String> {
+        let digest = self.digest(path, info, algorithm)?;
+        Ok(match algorithm {
+            DigestAlgorithm::Sha256 => format!("Repr-Digest: sha-256=:{}:\r\n", digest),
+            DigestAlgorithm::Md5 => format!("Content-MD5: {}\r\n", digest),
+        })
+    }
+
+    fn digest(&mut self, path: &Path, info: FileInfo, algorithm: DigestAlgorithm) -> io::Result<String> {
+        let key = (path.to_path_buf(), algorithm);
+        if let (Some((size, modified, digest)), Some(current)) = (self.entries.get(&key), info.modified) {
+            if *size == info.size && *modified == current {
+                return Ok(digest.clone());
+            }
+        }
+
+        let digest = match algorithm {
+            DigestAlgorithm::Sha256 => hash_file::<Sha256>(path)?,
+            DigestAlgorithm::Md5 => hash_file::<Md5>(path)?,
+        };
+
+        // Without an mtime there'd be no telling when the digest goes stale
+        if let Some(modified) = info.modified {
+            if self.entries.len() >= MAX_CHECKSUM_ENTRIES {
+                self.entries.clear();
+            }
+            self.entries.insert(key, (info.size, modified, digest.clone()));
+        }
+        Ok(digest)
+    }
+}
+
+fn hash_file<D: Digest>(path: &Path) -> io::Result<String> {
+    let mut file = File::open(path)?;
+    let mut hasher = D::new();
+    let mut buffer = [0; 65536];
+    loop {
+        match file.read(&mut buffer)? {
+            0 => break,
+            n => hasher.update(&buffer[..n]),
+        }
+    }
+    Ok(STANDARD.encode(hasher.finalize()))
+}
+
+// `--file-cache SIZE`: the contents of recently served small files, up to SIZE
+// bytes in all, shared by every handler. Entries are checked against a fresh
+// stat when used, so an edited file is read again at once; past the budget,
+// the least recently used go first.
+pub struct FileCache {
+    budget: u64,
+    inner: Mutex<FileCacheEntries>,
+}
+
+struct FileCacheEntries {
+    files: HashMap<PathBuf, CachedFile>,
+    // Last use of each entry, oldest first
+    uses: BTreeMap<u64, PathBuf>,
+    clock: u64,
+    total: u64,
+}
+
+struct CachedFile {
+    modified: SystemTime,
+    contents: Arc<[u8]>,
+    used: u64,
+}
+
+impl FileCache {
+    pub fn new(args: &Args) -> Option<FileCache> {
+        let entries = FileCacheEntries { files: HashMap::new(), uses: BTreeMap::new(), clock: 0, total: 0 };
+        Some(FileCache { budget: args.file_cache?, inner: Mutex::new(entries) })
+    }
+
+    pub fn admits(&self, size: u64) -> bool {
+        size <= MAX_CACHED_FILE_SIZE.min(self.budget)
+    }
+
+    pub fn get(&self, path: &Path) -> Option<Arc<[u8]>> {
+        // Looked up before taking the lock, so handlers don't queue behind a slow disk
+        let current = fs::metadata(path).ok();
+        let mut inner = self.inner.lock().unwrap_or_else(|e| e.into_inner());
+        let inner = &mut *inner;
+        let cached = inner.files.get_mut(path)?;
+        let fresh = current.is_some_and(|current| {
+            current.len() == cached.contents.len() as u64 && current.modified().ok() == Some(cached.modified)
+        });
+        if !fresh {
+            inner.remove(path);
+            return None;
+        }
+
+        inner.clock += 1;
+        inner.uses.remove(&cached.used);
+        inner.uses.insert(inner.clock, path.to_path_buf());
+        cached.used = inner.clock;
+        Some(Arc::clone(&cached.contents))
+    }
+
+    // `modified` must come from the same open file the contents were read from
+    pub fn insert(&self, path: PathBuf, modified: SystemTime, contents: Arc<[u8]>) {
+        let size = contents.len() as u64;
+        if !self.admits(size) {
+            return;
+        }
+        let mut inner = self.inner.lock().unwrap_or_else(|e| e.into_inner());
+        inner.remove(&path);
+        while inner.total + size > self.budget {
+            match inner.uses.pop_first() {
+                Some((_, oldest)) => inner.remove(&oldest),
+                None => break,
+            }
+        }
+
+        inner.clock += 1;
+        let used = inner.clock;
+        inner.total += size;
+        inner.uses.insert(used, path.clone());
+        inner.files.insert(path, CachedFile { modified, contents, used });
+    }
+}
+
+impl FileCacheEntries {
+    fn remove(&mut self, path: &Path) {
+        if let Some(cached) = self.files.remove(path) {
+            self.uses.remove(&cached.used);
+            self.total -= cached.contents.len() as u64;
+        }
+    }
+}
@@ -0,0 +1,149 @@
+use std::{
+    fs, io,
+    ops::Range,
+    path::{Path, PathBuf},
+};
+
+use crate::{
+    args::Args,
+    config::{self, Config},
+    dry_run,
+};
+
+// `bounty check [FILE]`: judges a config file, bounty.toml by default, the way
+// the server would read it, then checks the files it names like --dry-run
+// does, and fails if anything is wrong. Problems point at their line and
+// column; a misspelt key gets the known one it's closest to.
+pub fn run(mut command_line: impl Iterator<Item = String>) -> io::Result<()> {
+    let path = PathBuf::from(command_line.next().unwrap_or_else(|| config::DEFAULT_PATH.to_string()));
+    if let Some(extra) = command_line.next() {
+        return Err(io::Error::new(io::ErrorKind::InvalidInput, format!("check takes one config file, not also {}", extra)));
+    }
+    let text = fs::read_to_string(&path).map_err(|e| io::Error::new(e.kind(), format!("{}: {}", path.display(), e)))?;
+
+    let problems = match Config::parse(&text) {
+        Ok(config) => judge(&config, &path, &text)?,
+        Err(e) => {
+            let message = suggest(e.message());
+            vec![locate(&path, &text, e.span().unwrap_or(0..0), &message)]
+        }
+    };
+    if problems.is_empty() {
+        println!("{}: OK", path.display());
+        return Ok(());
+    }
+    for problem in &problems {
+        eprintln!("{}", problem);
+    }
+    Err(io::Error::new(io::ErrorKind::InvalidInput, format!("{} configuration problem(s)", problems.len())))
+}
+
+// The settings are checked as the flags they stand for, so an error names the
+// flag; it's pointed at the key that set it
+fn judge(config: &Config, path: &Path, text: &str) -> io::Result<Vec<String>> {
+    let args = match Args::from_config(config, path) {
+        Ok(args) => args,
+        Err(e) => {
+            let message = e.to_string();
+            let at = message.split_whitespace().find(|word| word.starts_with("--")).and_then(|flag| key_span(text, flag));
+            return Ok(vec![match at {
+                Some(at) => locate(path, text, at, &message),
+                None => format!("{}: {}", path.display(), message),
+            }]);
+        }
+    };
+    let problems = dry_run::report(&args, &mut io::sink())?;
+    Ok(problems.into_iter().map(|problem| format!("{}: {}", path.display(), problem)).collect())
+}
+
+// `file:line:column: message`, then the line itself with the span underlined
+fn locate(path: &Path, text: &str, span: Range<usize>, message: &str) -> String {
+    let offset = span.start.min(text.len());
+    let line_start = text[..offset].rfind('\n').map_or(0, |newline| newline + 1);
+    let line_number = text[..offset].matches('\n').count() + 1;
+    let column = text[line_start..offset].chars().count() + 1;
+    let line = text[line_start..].lines().next().unwrap_or("");
+    let end = span.end.clamp(offset, line_start + line.len());
+    let width = text[offset..end].chars().count().max(1);
+    format!(
+        "{}:{}:{}: {}\n  {} | {}\n  {} | {}{}",
+        path.display(),
+        line_number,
+        column,
+        message,
+        line_number,
+        line,
+        " ".repeat(line_number.to_string().len()),
+        " ".repeat(column - 1),
+        "^".repeat(width)
+    )
+}
+
+// Where the key a flag's value came from is set, as `key = ...` or a `[key]` table
+fn key_span(text: &str, flag: &str) -> Option<Range<usize>> {
+    let flag = flag.trim_end_matches(|c: char| !c.is_ascii_alphanumeric());
+    let key = match flag {
+        "--tls-cert" => "cert",
+        "--tls-key" => "key",
+        "--tls-min-version" => "min-version",
+        "--tls-ciphers" => "ciphers",
+        "--tls-alpn" => "alpn",
+        "--tls-client-ca" => "client-ca",
+        "--tls-ocsp-responder" => "ocsp-responder",
+        "--header" => "custom",
+        "--error-page" => "error-pages",
+        "--mount" => "mounts",
+        "--vhost" => "vhosts",
+        "--proxy" => "proxies",
+        flag => flag.trim_start_matches('-'),
+    };
+    let mut offset = 0;
+    for line in text.split_inclusive('\n') {
+        let trimmed = line.trim_start();
+        let indent = line.len() - trimmed.len();
+        let assigned = trimmed.strip_prefix(key).is_some_and(|rest| rest.trim_start().starts_with('='));
+        let table = trimmed.trim_end().strip_prefix('[').and_then(|rest| rest.strip_suffix(']'));
+        if assigned {
+            return Some(offset + indent..offset + indent + key.len());
+        }
+        if table.is_some_and(|table| table.rsplit('.').next() == Some(key)) {
+            return Some(offset + indent..offset + indent + trimmed.trim_end().len());
+        }
+        offset += line.len();
+    }
+    None
+}
+
+// serde lists every field an unknown one could have been; only the likeliest is worth showing
+fn suggest(message: &str) -> String {
+    let unknown = match message.strip_prefix("unknown field `").and_then(|rest| rest.split_once('`')) {
+        Some((unknown, _)) => unknown,
+        None => return message.to_string(),
+    };
+    let closest = message
+        .split('`')
+        .skip(3)
+        .step_by(2)
+        .map(|known| (distance(unknown, known), known))
+        .min()
+        .filter(|(distance, _)| *distance <= unknown.len().max(3) / 3);
+    match closest {
+        Some((_, known)) => format!("unknown key `{}`; did you mean `{}`?", unknown, known),
+        None => message.replacen("unknown field", "unknown key", 1),
+    }
+}
+
+// Levenshtein: the fewest single-character edits from one to the other
+fn distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut previous: Vec<usize> = (0..=b.len()).collect();
+    for (i, a) in a.chars().enumerate() {
+        let mut current = vec![i + 1];
+        for (j, b) in b.iter().enumerate() {
+            let substitution = previous[j] + usize::from(a != *b);
+            current.push(substitution.min(previous[j + 1] + 1).min(current[j] + 1));
+        }
+        previous = current;
+    }
+    previous[b.len()]
+}
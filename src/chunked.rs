@@ -0,0 +1,135 @@
+use std::io::{self, BufRead, Read, Write};
+
+use base64::{engine::general_purpose::STANDARD, Engine};
+use sha2::{Digest, Sha256};
+
+const CHUNK_SIZE: usize = 8192;
+// Plenty for a chunk size with extensions, or a trailer
+const MAX_LINE: u64 = 8192;
+
+// Announces the trailer in the response head; required before sending it
+pub const DIGEST_TRAILER: &str = "Trailer: Repr-Digest\r\n";
+
+#[derive(Clone, Copy)]
+pub enum Transfer {
+    // Whole body up front with a Content-Length, for clients that can't take chunks
+    Buffered,
+    Chunked,
+    // Chunked, ending with an RFC 9530 Repr-Digest trailer computed as the body streams
+    ChunkedWithDigest,
+}
+
+// Buffers writes into chunks of up to CHUNK_SIZE so callers can write small
+// pieces (one listing row at a time) without producing tiny chunks
+pub struct ChunkedWriter<W: Write> {
+    inner: W,
+    buffer: Vec<u8>,
+    digest: Option<Sha256>,
+}
+
+impl<W: Write> ChunkedWriter<W> {
+    pub fn new(inner: W) -> ChunkedWriter<W> {
+        ChunkedWriter { inner, buffer: Vec::with_capacity(CHUNK_SIZE), digest: None }
+    }
+
+    pub fn with_digest(inner: W) -> ChunkedWriter<W> {
+        ChunkedWriter { digest: Some(Sha256::new()), ..ChunkedWriter::new(inner) }
+    }
+
+    // The zero-length chunk marks the end of the body; without it the client
+    // treats the response as truncated
+    pub fn finish(mut self) -> io::Result<W> {
+        self.write_chunk()?;
+        self.inner.write_all(b"0\r\n")?;
+        if let Some(digest) = self.digest.take() {
+            write!(self.inner, "Repr-Digest: sha-256=:{}:\r\n", STANDARD.encode(digest.finalize()))?;
+        }
+        self.inner.write_all(b"\r\n")?;
+        self.inner.flush()?;
+        Ok(self.inner)
+    }
+
+    fn write_chunk(&mut self) -> io::Result<()> {
+        if self.buffer.is_empty() {
+            return Ok(());
+        }
+
+        if let Some(digest) = &mut self.digest {
+            digest.update(&self.buffer);
+        }
+
+        write!(self.inner, "{:x}\r\n", self.buffer.len())?;
+        self.inner.write_all(&self.buffer)?;
+        self.inner.write_all(b"\r\n")?;
+        self.buffer.clear();
+        Ok(())
+    }
+}
+
+impl<W: Write> Write for ChunkedWriter<W> {
+    fn write(&mut self, data: &[u8]) -> io::Result<usize> {
+        self.buffer.extend_from_slice(data);
+        if self.buffer.len() >= CHUNK_SIZE {
+            self.write_chunk()?;
+        }
+        Ok(data.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.write_chunk()?;
+        self.inner.flush()
+    }
+}
+
+// Undoes the chunked coding of a body being passed on to a client that can't
+// take it. Trailers are read past and dropped.
+pub struct ChunkedReader<R: BufRead> {
+    inner: R,
+    remaining: u64,
+    done: bool,
+}
+
+impl<R: BufRead> ChunkedReader<R> {
+    pub fn new(inner: R) -> ChunkedReader<R> {
+        ChunkedReader { inner, remaining: 0, done: false }
+    }
+
+    fn read_line(&mut self) -> io::Result<String> {
+        let mut line = String::new();
+        (&mut self.inner).take(MAX_LINE).read_line(&mut line)?;
+        if !line.ends_with('\n') {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "chunk line cut short or too long"));
+        }
+        Ok(line.trim_end().to_string())
+    }
+}
+
+impl<R: BufRead> Read for ChunkedReader<R> {
+    fn read(&mut self, buffer: &mut [u8]) -> io::Result<usize> {
+        if self.remaining == 0 {
+            if self.done || buffer.is_empty() {
+                return Ok(0);
+            }
+            let line = self.read_line()?;
+            let size = line.split(';').next().unwrap_or("").trim();
+            self.remaining = u64::from_str_radix(size, 16)
+                .map_err(|_| io::Error::new(io::ErrorKind::InvalidData, "bad chunk size"))?;
+            if self.remaining == 0 {
+                while !self.read_line()?.is_empty() {}
+                self.done = true;
+                return Ok(0);
+            }
+        }
+
+        let limit = buffer.len().min(usize::try_from(self.remaining).unwrap_or(usize::MAX));
+        let read = self.inner.read(&mut buffer[..limit])?;
+        if read == 0 {
+            return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "body ended inside a chunk"));
+        }
+        self.remaining -= read as u64;
+        if self.remaining == 0 && !self.read_line()?.is_empty() {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "chunk longer than its size"));
+        }
+        Ok(read)
+    }
+}
@@ -0,0 +1,128 @@
+use std::{
+    io::{self, Write},
+    path::Path,
+};
+
+use flate2::{
+    write::{GzEncoder, ZlibEncoder},
+    Compression,
+};
+
+// Below this a compressed body barely shrinks, and chunking it costs more than it saves
+pub const MIN_SIZE: u64 = 1024;
+
+// Going by extension, since sniffing only recognises binary formats and every
+// text file comes out as application/octet-stream
+const COMPRESSIBLE_EXTENSIONS: &[&str] = &[
+    "css", "csv", "htm", "html", "js", "json", "map", "md", "mjs", "svg", "txt", "wasm", "xml",
+];
+
+#[derive(Clone, Copy)]
+pub enum Encoding {
+    Gzip,
+    // HTTP's "deflate" is the zlib format, not a raw deflate stream (RFC 9110 section 8.4.1.2)
+    Deflate,
+}
+
+impl Encoding {
+    pub fn name(self) -> &'static str {
+        match self {
+            Encoding::Gzip => "gzip",
+            Encoding::Deflate => "deflate",
+        }
+    }
+}
+
+pub fn is_compressible(path: &Path) -> bool {
+    path.extension()
+        .and_then(|extension| extension.to_str())
+        .is_some_and(|extension| COMPRESSIBLE_EXTENSIONS.iter().any(|known| extension.eq_ignore_ascii_case(known)))
+}
+
+// Siblings a build may have compressed ahead of time, as (coding, extension),
+// in order of preference when the client likes them equally
+pub const PRECOMPRESSED: &[(&str, &str)] = &[("br", "br"), ("gzip", "gz")];
+
+// The coding we can produce that the client prefers, or None for identity
+pub fn negotiate(accept_encoding: Option<&str>) -> Option<Encoding> {
+    let candidates = [("gzip", Encoding::Gzip), ("deflate", Encoding::Deflate)];
+    preferred(accept_encoding, candidates).map(|(_, encoding)| encoding)
+}
+
+// The candidate whose coding the client prefers, the earliest of equals;
+// none when it accepts none of them
+pub fn preferred<T>(
+    accept_encoding: Option<&str>,
+    candidates: impl IntoIterator<Item = (&'static str, T)>,
+) -> Option<(&'static str, T)> {
+    let accept_encoding = accept_encoding?;
+    let mut best: Option<(f32, (&'static str, T))> = None;
+    for (coding, candidate) in candidates {
+        let quality = quality(accept_encoding, coding);
+        if quality > 0.0 && best.as_ref().is_none_or(|(best, _)| quality > *best) {
+            best = Some((quality, (coding, candidate)));
+        }
+    }
+    best.map(|(_, candidate)| candidate)
+}
+
+// How much Accept-Encoding wants `coding`: `*` stands for any coding not
+// listed, and `q=0` refuses one
+fn quality(accept_encoding: &str, coding: &str) -> f32 {
+    let mut wildcard = None;
+    for item in accept_encoding.split(',') {
+        let mut params = item.split(';');
+        let name = params.next().unwrap_or("").trim();
+        let quality = params
+            .filter_map(|param| param.split_once('='))
+            .find(|(name, _)| name.trim().eq_ignore_ascii_case("q"))
+            .map_or(1.0, |(_, value)| value.trim().parse::<f32>().unwrap_or(0.0));
+        if name.eq_ignore_ascii_case(coding) || (coding == "gzip" && name.eq_ignore_ascii_case("x-gzip")) {
+            return quality;
+        }
+        if name == "*" {
+            wildcard = Some(quality);
+        }
+    }
+    wildcard.unwrap_or(0.0)
+}
+
+// Compresses whatever is written through it into `inner`
+pub enum Encoder<W: Write> {
+    Gzip(GzEncoder<W>),
+    Deflate(ZlibEncoder<W>),
+}
+
+impl<W: Write> Encoder<W> {
+    pub fn new(encoding: Encoding, inner: W) -> Encoder<W> {
+        match encoding {
+            Encoding::Gzip => Encoder::Gzip(GzEncoder::new(inner, Compression::default())),
+            Encoding::Deflate => Encoder::Deflate(ZlibEncoder::new(inner, Compression::default())),
+        }
+    }
+
+    // Writes out the end of the compressed stream, which the client needs to
+    // tell it from a truncated one
+    pub fn finish(self) -> io::Result<W> {
+        match self {
+            Encoder::Gzip(encoder) => encoder.finish(),
+            Encoder::Deflate(encoder) => encoder.finish(),
+        }
+    }
+}
+
+impl<W: Write> Write for Encoder<W> {
+    fn write(&mut self, data: &[u8]) -> io::Result<usize> {
+        match self {
+            Encoder::Gzip(encoder) => encoder.write(data),
+            Encoder::Deflate(encoder) => encoder.write(data),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            Encoder::Gzip(encoder) => encoder.flush(),
+            Encoder::Deflate(encoder) => encoder.flush(),
+        }
+    }
+}
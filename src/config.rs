@@ -0,0 +1,383 @@
+use std::{
+    collections::BTreeMap,
+    fs, io,
+    net::IpAddr,
+    path::{Path, PathBuf},
+};
+
+use serde::Deserialize;
+
+// Read from the working directory when there's no --config
+pub const DEFAULT_PATH: &str = "bounty.toml";
+
+// `--config FILE`: the same settings as flags, keyed by the flag's name and
+// grouped into tables. Flags given on the command line win over the file;
+// values of repeatable flags like --allow or --auth add up, and a switch the
+// file turns on is turned off again by its `--no-` flag (`--no-compress`,
+// or `--admin` for `no-admin`). Relative paths are relative to the file.
+// Only the one-off modes and what's particular to one run have no key:
+// --config, --fd, --dry-run, --bundle, --export, and the supervisor's own
+// --supervised and --worker-state.
+//
+//     root = "public"
+//     port = 8080
+//
+//     [listing]
+//     exact = true
+//
+//     [headers]
+//     cache-control = "max-age=60"
+//     cors = "*"
+//
+//     [features]
+//     compress = true
+#[derive(Deserialize, Default)]
+#[serde(default, deny_unknown_fields, rename_all = "kebab-case")]
+pub struct Config {
+    root: Option<PathBuf>,
+    bind: Binds,
+    port: Option<u16>,
+    workers: Option<usize>,
+    threads: Option<usize>,
+    processes: Option<usize>,
+    shutdown_timeout: Option<u64>,
+    // Status codes to the page served for them
+    error_pages: BTreeMap<u16, PathBuf>,
+    // URL prefixes to the directories served under them
+    mounts: BTreeMap<String, PathBuf>,
+    // Hostnames to the directories served for them
+    vhosts: BTreeMap<String, PathBuf>,
+    // URL prefixes to the upstream servers they're forwarded to
+    proxies: BTreeMap<String, String>,
+    listing: Listing,
+    headers: Headers,
+    features: Features,
+    limits: Limits,
+    cache: Cache,
+    auth: Auth,
+    log: Log,
+    tls: Tls,
+}
+
+// `bind = "::"` or `bind = ["0.0.0.0", "::"]`
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum Binds {
+    One(IpAddr),
+    Many(Vec<IpAddr>),
+}
+
+impl Default for Binds {
+    fn default() -> Binds {
+        Binds::Many(Vec::new())
+    }
+}
+
+#[derive(Deserialize, Default)]
+#[serde(default, deny_unknown_fields, rename_all = "kebab-case")]
+struct Listing {
+    exact: bool,
+    asset_prefix: Option<String>,
+    default_language: Option<String>,
+    overlay: Vec<PathBuf>,
+    git_ref: Option<String>,
+    git_urls: bool,
+    spa: bool,
+    render_markdown: bool,
+}
+
+#[derive(Deserialize, Default)]
+#[serde(default, deny_unknown_fields, rename_all = "kebab-case")]
+struct Headers {
+    cache_control: Option<String>,
+    // Header names to values sent with every response
+    custom: BTreeMap<String, String>,
+    secure_headers: bool,
+    cors: Option<String>,
+    file_digest: Option<String>,
+    digest_trailers: bool,
+    mime_types: Option<PathBuf>,
+}
+
+#[derive(Deserialize, Default)]
+#[serde(default, deny_unknown_fields, rename_all = "kebab-case")]
+struct Features {
+    compress: bool,
+    precompressed: bool,
+    allow_upload: bool,
+    webdav: bool,
+    webdav_write: bool,
+    paste_dir: Option<String>,
+    short_links: Option<PathBuf>,
+    user_dirs: Option<String>,
+    download_stats: Option<PathBuf>,
+    stat_api: bool,
+    // With a K/M/G suffix, like the flag
+    file_cache: Option<String>,
+    io_uring: bool,
+    tarpit: bool,
+    tarpit_patterns: Option<PathBuf>,
+    append_token: Option<String>,
+    // always or never, like the flag
+    append_fsync: Option<String>,
+    append_max_file_size: Option<String>,
+    batch_token: Option<String>,
+    tor: bool,
+    tor_control: Option<String>,
+    tor_password: Option<String>,
+    tor_key: Option<PathBuf>,
+    debug_echo: bool,
+    watch: bool,
+    metrics: bool,
+    no_admin: bool,
+    qr: bool,
+    mdns: bool,
+    dlna: bool,
+    hide_dotfiles: bool,
+    bountyignore: bool,
+}
+
+#[derive(Deserialize, Default)]
+#[serde(default, deny_unknown_fields, rename_all = "kebab-case")]
+struct Limits {
+    // With a K/M/G suffix, like the flag
+    max_body_size: Option<String>,
+    max_header_size: Option<String>,
+    max_headers: Option<usize>,
+    max_uri_length: Option<String>,
+    // In seconds, like the flags
+    header_timeout: Option<u64>,
+    read_timeout: Option<u64>,
+    write_timeout: Option<u64>,
+    max_connections_per_ip: Option<usize>,
+    allow: Vec<String>,
+    deny: Vec<String>,
+    rate_limit: Option<f64>,
+    rate_burst: Option<f64>,
+}
+
+// In seconds, like the flags
+#[derive(Deserialize, Default)]
+#[serde(default, deny_unknown_fields, rename_all = "kebab-case")]
+struct Cache {
+    not_found_ttl: Option<u64>,
+    listing_cache_ttl: Option<u64>,
+    stat_cache_ttl: Option<u64>,
+}
+
+#[derive(Deserialize, Default)]
+#[serde(default, deny_unknown_fields, rename_all = "kebab-case")]
+struct Auth {
+    auth: Vec<String>,
+    auth_file: Option<PathBuf>,
+    users: Option<PathBuf>,
+    auth_log: Option<PathBuf>,
+    share_key: Option<PathBuf>,
+    auth_lockout: Option<u32>,
+    auth_lockout_time: Option<u64>,
+    pam: Option<String>,
+    pam_root: Option<String>,
+    ldap: Option<String>,
+    ldap_dn: Option<String>,
+    ldap_root: Option<String>,
+    ldap_write_group: Option<String>,
+}
+
+#[derive(Deserialize, Default)]
+#[serde(default, deny_unknown_fields, rename_all = "kebab-case")]
+struct Log {
+    access_log: Option<PathBuf>,
+    log_format: Option<String>,
+}
+
+#[derive(Deserialize, Default)]
+#[serde(default, deny_unknown_fields, rename_all = "kebab-case")]
+struct Tls {
+    cert: Option<PathBuf>,
+    key: Option<PathBuf>,
+    // 1.2 or 1.3, as a string like the flag takes
+    min_version: Option<String>,
+    ciphers: Vec<String>,
+    alpn: Option<Vec<String>>,
+    session_tickets: bool,
+    no_resumption: bool,
+    client_ca: Option<PathBuf>,
+    ocsp: bool,
+    ocsp_responder: Option<String>,
+}
+
+impl Config {
+    pub fn load(path: &Path) -> io::Result<Config> {
+        let text = fs::read_to_string(path)?;
+        Config::parse(&text)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, format!("{}: {}", path.display(), e.to_string().trim_end())))
+    }
+
+    pub fn parse(text: &str) -> Result<Config, toml::de::Error> {
+        toml::from_str(text)
+    }
+
+    pub fn root(&self, base: &Path) -> Option<PathBuf> {
+        self.root.as_ref().map(|root| base.join(root))
+    }
+
+    // The file's settings as the flags that would set them, so they're checked
+    // exactly like the command line is. `base` is the file's directory.
+    pub fn flags(&self, base: &Path) -> Vec<String> {
+        let mut flags = Vec::new();
+        let mut value = |flag: &str, value: Option<String>| {
+            if let Some(value) = value {
+                flags.push(flag.to_string());
+                flags.push(value);
+            }
+        };
+        let relative = |path: &PathBuf| base.join(path).to_string_lossy().to_string();
+        let path = |path: &Option<PathBuf>| path.as_ref().map(relative);
+
+        let binds = match &self.bind {
+            Binds::One(bind) => vec![*bind],
+            Binds::Many(binds) => binds.clone(),
+        };
+        for bind in binds {
+            value("--bind", Some(bind.to_string()));
+        }
+        value("--port", self.port.map(|port| port.to_string()));
+        value("--workers", self.workers.map(|workers| workers.to_string()));
+        value("--threads", self.threads.map(|threads| threads.to_string()));
+        value("--processes", self.processes.map(|processes| processes.to_string()));
+        value("--shutdown-timeout", self.shutdown_timeout.map(|timeout| timeout.to_string()));
+
+        let listing = &self.listing;
+        value("--asset-prefix", listing.asset_prefix.clone());
+        value("--default-language", listing.default_language.clone());
+        for overlay in &listing.overlay {
+            value("--overlay", Some(relative(overlay)));
+        }
+        value("--git-ref", listing.git_ref.clone());
+
+        for (code, page) in &self.error_pages {
+            value("--error-page", Some(format!("{}={}", code, relative(page))));
+        }
+        for (prefix, dir) in &self.mounts {
+            value("--mount", Some(format!("{}={}", prefix, relative(dir))));
+        }
+        for (host, dir) in &self.vhosts {
+            value("--vhost", Some(format!("{}={}", host, relative(dir))));
+        }
+        for (prefix, url) in &self.proxies {
+            value("--proxy", Some(format!("{}={}", prefix, url)));
+        }
+
+        let headers = &self.headers;
+        value("--cache-control", headers.cache_control.clone());
+        for (name, header) in &headers.custom {
+            value("--header", Some(format!("{}: {}", name, header)));
+        }
+        value("--cors", headers.cors.clone());
+        value("--file-digest", headers.file_digest.clone());
+        value("--mime-types", path(&headers.mime_types));
+
+        let features = &self.features;
+        value("--paste-dir", features.paste_dir.clone());
+        value("--short-links", path(&features.short_links));
+        value("--user-dirs", features.user_dirs.clone());
+        value("--download-stats", path(&features.download_stats));
+        value("--file-cache", features.file_cache.clone());
+        value("--tarpit-patterns", path(&features.tarpit_patterns));
+        value("--append-token", features.append_token.clone());
+        value("--append-fsync", features.append_fsync.clone());
+        value("--append-max-file-size", features.append_max_file_size.clone());
+        value("--batch-token", features.batch_token.clone());
+        value("--tor-control", features.tor_control.clone());
+        value("--tor-password", features.tor_password.clone());
+        value("--tor-key", path(&features.tor_key));
+
+        let cache = &self.cache;
+        value("--not-found-ttl", cache.not_found_ttl.map(|ttl| ttl.to_string()));
+        value("--listing-cache-ttl", cache.listing_cache_ttl.map(|ttl| ttl.to_string()));
+        value("--stat-cache-ttl", cache.stat_cache_ttl.map(|ttl| ttl.to_string()));
+
+        let limits = &self.limits;
+        value("--max-body-size", limits.max_body_size.clone());
+        value("--max-header-size", limits.max_header_size.clone());
+        value("--max-headers", limits.max_headers.map(|count| count.to_string()));
+        value("--max-uri-length", limits.max_uri_length.clone());
+        value("--header-timeout", limits.header_timeout.map(|timeout| timeout.to_string()));
+        value("--read-timeout", limits.read_timeout.map(|timeout| timeout.to_string()));
+        value("--write-timeout", limits.write_timeout.map(|timeout| timeout.to_string()));
+        value("--max-connections-per-ip", limits.max_connections_per_ip.map(|count| count.to_string()));
+        for cidr in &limits.allow {
+            value("--allow", Some(cidr.clone()));
+        }
+        for cidr in &limits.deny {
+            value("--deny", Some(cidr.clone()));
+        }
+        value("--rate-limit", limits.rate_limit.map(|rate| rate.to_string()));
+        value("--rate-burst", limits.rate_burst.map(|burst| burst.to_string()));
+
+        let auth = &self.auth;
+        for pair in &auth.auth {
+            value("--auth", Some(pair.clone()));
+        }
+        value("--auth-file", path(&auth.auth_file));
+        value("--users", path(&auth.users));
+        value("--auth-log", path(&auth.auth_log));
+        value("--share-key", path(&auth.share_key));
+        value("--auth-lockout", auth.auth_lockout.map(|lockout| lockout.to_string()));
+        value("--auth-lockout-time", auth.auth_lockout_time.map(|time| time.to_string()));
+        value("--pam", auth.pam.clone());
+        value("--pam-root", auth.pam_root.clone());
+        value("--ldap", auth.ldap.clone());
+        value("--ldap-dn", auth.ldap_dn.clone());
+        value("--ldap-root", auth.ldap_root.clone());
+        value("--ldap-write-group", auth.ldap_write_group.clone());
+
+        // `-` is stdout, not a file next to the config
+        let access_log = self.log.access_log.as_ref().map(|log| match log.to_str() {
+            Some("-") => "-".to_string(),
+            _ => relative(log),
+        });
+        value("--access-log", access_log);
+        value("--log-format", self.log.log_format.clone());
+
+        value("--tls-cert", path(&self.tls.cert));
+        value("--tls-key", path(&self.tls.key));
+        value("--tls-min-version", self.tls.min_version.clone());
+        value("--tls-ciphers", Some(self.tls.ciphers.join(",")).filter(|ciphers| !ciphers.is_empty()));
+        value("--tls-alpn", self.tls.alpn.as_ref().map(|protocols| protocols.join(",")));
+        value("--tls-client-ca", path(&self.tls.client_ca));
+        value("--tls-ocsp-responder", self.tls.ocsp_responder.clone());
+
+        let switches = [
+            ("--exact", listing.exact),
+            ("--git-urls", listing.git_urls),
+            ("--spa", listing.spa),
+            ("--render-markdown", listing.render_markdown),
+            ("--digest-trailers", headers.digest_trailers),
+            ("--secure-headers", headers.secure_headers),
+            ("--compress", features.compress),
+            ("--precompressed", features.precompressed),
+            ("--allow-upload", features.allow_upload),
+            ("--webdav", features.webdav),
+            ("--webdav-write", features.webdav_write),
+            ("--io-uring", features.io_uring),
+            ("--tarpit", features.tarpit),
+            ("--tor", features.tor),
+            ("--debug-echo", features.debug_echo),
+            ("--stat-api", features.stat_api),
+            ("--watch", features.watch),
+            ("--metrics", features.metrics),
+            ("--no-admin", features.no_admin),
+            ("--qr", features.qr),
+            ("--mdns", features.mdns),
+            ("--dlna", features.dlna),
+            ("--hide-dotfiles", features.hide_dotfiles),
+            ("--bountyignore", features.bountyignore),
+            ("--tls-session-tickets", self.tls.session_tickets),
+            ("--no-tls-resumption", self.tls.no_resumption),
+            ("--tls-ocsp", self.tls.ocsp),
+        ];
+        flags.extend(switches.iter().filter(|(_, on)| *on).map(|(flag, _)| flag.to_string()));
+        flags
+    }
+}
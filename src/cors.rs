@@ -0,0 +1,62 @@
+use std::io;
+
+use crate::{request_header, Exchange, write_response};
+
+// Response headers a script may read beyond the CORS-safelisted ones
+const EXPOSED: &str = "Content-Range, Content-Disposition, Accept-Ranges, ETag, Repr-Digest, Content-MD5";
+// How long browsers may reuse a preflight answer; Chromium caps it at two hours
+const PREFLIGHT_MAX_AGE: u64 = 7200;
+
+// `--cors '*'` lets any origin read responses; a comma-separated list of
+// origins lets only those, and lets them send credentials too
+pub enum Cors {
+    Any,
+    Origins(Vec<String>),
+}
+
+impl Cors {
+    pub fn parse(value: &str) -> Result<Cors, String> {
+        if value.trim() == "*" {
+            return Ok(Cors::Any);
+        }
+        let origins: Vec<String> =
+            value.split(',').map(|origin| origin.trim().trim_end_matches('/').to_string()).collect();
+        match origins.iter().find(|origin| !origin.starts_with("http://") && !origin.starts_with("https://")) {
+            Some(origin) => Err(format!("{:?} is not an origin like https://example.com", origin)),
+            None => Ok(Cors::Origins(origins)),
+        }
+    }
+
+    // Added to every response to the request; empty when its origin isn't allowed
+    pub fn headers(&self, request: &str) -> String {
+        let origin = request_header(request, "Origin");
+        match self {
+            Cors::Any => format!("Access-Control-Allow-Origin: *\r\nAccess-Control-Expose-Headers: {}\r\n", EXPOSED),
+            // Caches must tell apart the answers for different origins, allowed or not
+            Cors::Origins(origins) => match origin.filter(|origin| origins.iter().any(|allowed| allowed == origin)) {
+                Some(origin) => format!(
+                    "Access-Control-Allow-Origin: {}\r\nAccess-Control-Allow-Credentials: true\r\n\
+                     Access-Control-Expose-Headers: {}\r\nVary: Origin\r\n",
+                    origin, EXPOSED
+                ),
+                None => "Vary: Origin\r\n".to_string(),
+            },
+        }
+    }
+}
+
+pub fn is_preflight(method: &str, request: &str) -> bool {
+    method == "OPTIONS" && request_header(request, "Access-Control-Request-Method").is_some()
+}
+
+// Preflights never carry credentials, so they're answered before any login.
+// Whatever is asked for is allowed here; the real request still meets every
+// check a same-origin one would.
+pub fn send_preflight(stream: &mut Exchange, request: &str) -> io::Result<()> {
+    let method = request_header(request, "Access-Control-Request-Method").unwrap_or("GET");
+    let mut headers = format!("Access-Control-Allow-Methods: {}\r\nAccess-Control-Max-Age: {}\r\n", method, PREFLIGHT_MAX_AGE);
+    if let Some(requested) = request_header(request, "Access-Control-Request-Headers") {
+        headers.push_str(&format!("Access-Control-Allow-Headers: {}\r\n", requested));
+    }
+    write_response(stream, &stream.response_head("200 OK", "text/plain", 0, &headers), b"")
+}
@@ -0,0 +1,113 @@
+use std::{
+    io,
+    net::IpAddr,
+    path::Path,
+};
+
+use serde_json::json;
+
+use crate::{
+    args::Args, decode_url_encoded, is_path_within, by_prefix, overlay_layers, parse_request_line, user_dir, users::User,
+    Exchange, write_response,
+};
+
+// `/_debug/echo[/<path>]` describes the request as the server parsed it, and
+// how `<path>` would have been routed and resolved had it been requested
+pub fn send_echo(
+    stream: &mut Exchange,
+    args: &Args,
+    request: &str,
+    ip: IpAddr,
+    user: Option<&User>,
+    root: &Path,
+    rest: &str,
+) -> io::Result<()> {
+    let mut lines = request.lines();
+    let (method, target, version) = parse_request_line(lines.next().unwrap_or(""));
+    let headers: Vec<_> = lines
+        .take_while(|line| !line.is_empty())
+        .filter_map(|line| line.split_once(':'))
+        .map(|(name, value)| json!([name, value.trim()]))
+        .collect();
+
+    let (_, query) = target.split_once('?').unwrap_or((target, ""));
+    let probed_path = if rest.is_empty() { "/" } else { rest };
+    let decoded = decode_url_encoded(probed_path);
+
+    let user_root = args.user_dirs.as_deref().zip(decoded.strip_prefix("/~"));
+    let (root, resource_path) = match (by_prefix(&args.mounts, &decoded), user_root) {
+        (Some((root, resource_path)), _) => (Some(root.clone()), resource_path.to_string()),
+        (None, Some((pattern, rest))) => match user_dir(pattern, rest) {
+            Some((root, resource_path)) => (Some(root), resource_path.to_string()),
+            None => (None, String::new()),
+        },
+        (None, None) => {
+            let resource_path = decoded.trim_start_matches('/');
+            let layers = overlay_layers(args, root.to_path_buf());
+            let layer = layers.iter().find(|layer| layer.join(resource_path).exists()).unwrap_or(&layers[layers.len() - 1]);
+            (Some(layer.clone()), resource_path.to_string())
+        }
+    };
+    let filesystem_path = root.as_ref().map(|root| root.join(&resource_path));
+    let within_root = filesystem_path
+        .as_ref()
+        .zip(root.as_ref())
+        .and_then(|(path, root)| is_path_within(path, root).ok());
+
+    let body = json!({
+        "client": ip.to_string(),
+        "user": user.map(|user| &user.name),
+        "method": method,
+        "target": target,
+        "version": version,
+        "query": query,
+        "headers": headers,
+        "probe": {
+            "path": probed_path,
+            "decoded": decoded,
+            "route": route(args, probed_path, &decoded, user.is_some()),
+            "root": root.as_ref().map(|root| root.display().to_string()),
+            "filesystem_path": filesystem_path.as_ref().map(|path| path.display().to_string()),
+            "exists": filesystem_path.as_ref().is_some_and(|path| path.exists()),
+            // null when the path doesn't exist, since containment is checked on the resolved path
+            "within_root": within_root,
+        },
+    });
+
+    let body = body.to_string() + "\n";
+    let head = stream.response_head("200 OK", "application/json", body.len(), "Cache-Control: no-store\r\n");
+    write_response(stream, &head, body.as_bytes())
+}
+
+// Mirrors the order handle_connection dispatches in
+fn route(args: &Args, path: &str, decoded: &str, logged_in: bool) -> &'static str {
+    if path.starts_with(args.asset_prefix.as_str()) {
+        "embedded asset"
+    } else if path.starts_with("/_append/") && (logged_in || args.append_token.is_some()) {
+        "append"
+    } else if path == "/_paste" && args.paste_dir.is_some() {
+        "paste"
+    } else if (path == "/s" || path.starts_with("/s/")) && args.short_links.is_some() {
+        "short link"
+    } else if path == "/_api/batch" && args.batch_token.is_some() {
+        "batch"
+    } else if path == "/_api/usage" && logged_in {
+        "usage"
+    } else if (path == "/_stats/top" || path == "/_api/stats/top") && args.download_stats.is_some() {
+        "download stats"
+    } else if path.starts_with("/_api/stat/") && args.stat_api {
+        "stat"
+    } else if path.starts_with("/_git/") && args.git_urls {
+        "git tree"
+    } else if by_prefix(&args.proxies, path).is_some() {
+        "proxy"
+    } else if by_prefix(&args.mounts, decoded).is_some() {
+        "mount"
+    } else if args.git_ref.is_some() {
+        "git ref"
+    } else if decoded.starts_with("/~") && args.user_dirs.is_some() {
+        "user directory"
+    } else {
+        "file"
+    }
+}
@@ -0,0 +1,84 @@
+// Just enough DER to pick certificates apart and speak LDAP: tags are single
+// bytes and lengths definite, which is all X.509 and LDAP's messages use.
+
+pub const BOOLEAN: u8 = 0x01;
+pub const SEQUENCE: u8 = 0x30;
+pub const SET: u8 = 0x31;
+pub const OID: u8 = 0x06;
+pub const OCTET_STRING: u8 = 0x04;
+pub const INTEGER: u8 = 0x02;
+pub const ENUMERATED: u8 = 0x0a;
+pub const BIT_STRING: u8 = 0x03;
+pub const GENERALIZED_TIME: u8 = 0x18;
+
+// 2.5.4.3
+pub const COMMON_NAME: &[u8] = &[0x55, 0x04, 0x03];
+// 2.5.29.17
+pub const SUBJECT_ALT_NAME: &[u8] = &[0x55, 0x1d, 0x11];
+
+#[derive(Clone, Copy)]
+pub struct Element<'a> {
+    pub tag: u8,
+    pub contents: &'a [u8],
+}
+
+// Takes one element off the front of `input`
+pub fn read<'a>(input: &mut &'a [u8]) -> Option<Element<'a>> {
+    let (&tag, rest) = input.split_first()?;
+    let (&first, mut rest) = rest.split_first()?;
+    let length = if first < 0x80 {
+        first as usize
+    } else {
+        let count = (first & 0x7f) as usize;
+        if count == 0 || count > 4 || rest.len() < count {
+            return None;
+        }
+        let length = rest[..count].iter().fold(0, |length, &byte| length << 8 | byte as usize);
+        rest = &rest[count..];
+        length
+    };
+    if rest.len() < length {
+        return None;
+    }
+    let element = Element { tag, contents: &rest[..length] };
+    *input = &rest[length..];
+    Some(element)
+}
+
+// What a SEQUENCE or SET holds, stopping at the first malformed element
+pub fn elements(mut contents: &[u8]) -> impl Iterator<Item = Element<'_>> {
+    std::iter::from_fn(move || read(&mut contents))
+}
+
+pub fn encode(tag: u8, contents: &[u8]) -> Vec<u8> {
+    let mut encoded = vec![tag];
+    let length = contents.len();
+    if length < 0x80 {
+        encoded.push(length as u8);
+    } else {
+        let bytes: Vec<u8> = length.to_be_bytes().into_iter().skip_while(|&byte| byte == 0).collect();
+        encoded.push(0x80 | bytes.len() as u8);
+        encoded.extend(bytes);
+    }
+    encoded.extend_from_slice(contents);
+    encoded
+}
+
+// The fields of a certificate's tbsCertificate, with the optional version
+// dropped so the serial always comes first
+pub fn certificate_fields(certificate: &[u8]) -> Option<Vec<Element<'_>>> {
+    let certificate = read(&mut &certificate[..]).filter(|element| element.tag == SEQUENCE)?;
+    let tbs = read(&mut &certificate.contents[..]).filter(|element| element.tag == SEQUENCE)?;
+    Some(elements(tbs.contents).skip_while(|element| element.tag == 0xa0).collect())
+}
+
+// The value of the extension `oid`, unwrapped from its OCTET STRING
+pub fn extension<'a>(fields: &[Element<'a>], oid: &[u8]) -> Option<&'a [u8]> {
+    let extensions = fields.iter().find(|field| field.tag == 0xa3)?;
+    let list = read(&mut &extensions.contents[..])?;
+    elements(list.contents).find_map(|extension| {
+        let mut parts = elements(extension.contents);
+        parts.next().filter(|id| id.tag == OID && id.contents == oid)?;
+        parts.find(|part| part.tag == OCTET_STRING).map(|value| value.contents)
+    })
+}
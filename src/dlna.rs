@@ -0,0 +1,479 @@
+use std::{
+    fs, io,
+    net::{Ipv4Addr, SocketAddr, SocketAddrV4, UdpSocket},
+    path::Path,
+    thread,
+    time::Duration,
+};
+
+use sha2::{Digest, Sha256};
+use socket2::{Domain, Protocol, Socket, Type};
+
+use crate::{
+    args::Args, banner, encode_url_path, escape_html, is_path_within, read_body, request_header, send_error, Exchange,
+    write_response,
+};
+
+const GROUP: Ipv4Addr = Ipv4Addr::new(239, 255, 255, 250);
+const PORT: u16 = 1900;
+const MAX_AGE: u64 = 1800;
+// SOAP requests are a few hundred bytes of XML
+const MAX_BODY: u64 = 64 * 1024;
+
+const MEDIA_SERVER: &str = "urn:schemas-upnp-org:device:MediaServer:1";
+const CONTENT_DIRECTORY: &str = "urn:schemas-upnp-org:service:ContentDirectory:1";
+const CONNECTION_MANAGER: &str = "urn:schemas-upnp-org:service:ConnectionManager:1";
+
+// `--dlna` advertises the root as a UPnP MediaServer over SSDP, with a
+// ContentDirectory to browse it by, so smart TVs and media players list it.
+// Only folders and audio, video and image files show up, and the files are
+// played from their usual URLs, ranges and all. Advertised over IPv4, at the
+// first address that isn't loopback; withdrawn when the returned value is dropped.
+pub struct Advertisement {
+    socket: UdpSocket,
+    notices: Vec<String>,
+}
+
+pub fn advertise(args: &Args, addresses: &[SocketAddr]) -> io::Result<Advertisement> {
+    let address = match banner::reachable(addresses).into_iter().find(|address| address.is_ipv4() && !address.ip().is_loopback()) {
+        Some(address) => address,
+        None => {
+            let message = "--dlna has nothing to advertise without an IPv4 address off loopback; try --bind 0.0.0.0";
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, message));
+        }
+    };
+    let location = format!("http://{}{}dlna/device.xml", address, args.asset_prefix);
+    let uuid = device_uuid(args);
+
+    // Other control points and servers on the machine share the port
+    let socket = Socket::new(Domain::IPV4, Type::DGRAM, Some(Protocol::UDP))?;
+    socket.set_reuse_address(true)?;
+    #[cfg(unix)]
+    socket.set_reuse_port(true)?;
+    socket.bind(&SocketAddr::from((Ipv4Addr::UNSPECIFIED, PORT)).into())?;
+    socket.join_multicast_v4(&GROUP, &Ipv4Addr::UNSPECIFIED)?;
+    socket.set_multicast_ttl_v4(4)?;
+    let socket: UdpSocket = socket.into();
+
+    let notices: Vec<String> = targets(&uuid).into_iter().map(|(target, usn)| notice(&target, &usn, &location)).collect();
+    let announcer = socket.try_clone()?;
+    let alive = notices.clone();
+    thread::spawn(move || loop {
+        // Well before they expire, resent in case one is lost
+        for _ in 0..2 {
+            for notice in &alive {
+                let _ = announcer.send_to(notice.replace("@NTS@", "ssdp:alive").as_bytes(), SocketAddrV4::new(GROUP, PORT));
+            }
+            thread::sleep(Duration::from_secs(1));
+        }
+        thread::sleep(Duration::from_secs(MAX_AGE / 3));
+    });
+    let responder = socket.try_clone()?;
+    thread::spawn(move || respond(&responder, &uuid, &location));
+    println!("Advertising {} over DLNA", friendly_name(args));
+    Ok(Advertisement { socket, notices })
+}
+
+impl Drop for Advertisement {
+    fn drop(&mut self) {
+        for notice in &self.notices {
+            let _ = self.socket.send_to(notice.replace("@NTS@", "ssdp:byebye").as_bytes(), SocketAddrV4::new(GROUP, PORT));
+        }
+    }
+}
+
+// The same for the same root and port, so players remember it across restarts
+fn device_uuid(args: &Args) -> String {
+    let digest = Sha256::digest(format!("{}:{}", args.root.display(), args.port));
+    let hex: String = digest[..16].iter().map(|byte| format!("{:02x}", byte)).collect();
+    format!("uuid:{}-{}-{}-{}-{}", &hex[..8], &hex[8..12], &hex[12..16], &hex[16..20], &hex[20..])
+}
+
+fn friendly_name(args: &Args) -> String {
+    let name = args.root.file_name().map_or("/".into(), |name| name.to_string_lossy());
+    format!("Bounty: {}", name)
+}
+
+// What's advertised, each with the unique service name it goes by
+fn targets(uuid: &str) -> Vec<(String, String)> {
+    let mut targets = vec![(uuid.to_string(), uuid.to_string())];
+    for target in ["upnp:rootdevice", MEDIA_SERVER, CONTENT_DIRECTORY, CONNECTION_MANAGER] {
+        targets.push((target.to_string(), format!("{}::{}", uuid, target)));
+    }
+    targets
+}
+
+// With @NTS@ for ssdp:alive or ssdp:byebye
+fn notice(target: &str, usn: &str, location: &str) -> String {
+    format!(
+        "NOTIFY * HTTP/1.1\r\nHOST: {}:{}\r\nCACHE-CONTROL: max-age={}\r\nLOCATION: {}\r\nNT: {}\r\nNTS: @NTS@\r\nSERVER: {}\r\nUSN: {}\r\n\r\n",
+        GROUP, PORT, MAX_AGE, location, target, server_header(), usn
+    )
+}
+
+fn server_header() -> String {
+    format!("{}/1.0 UPnP/1.0 Bounty/{}", std::env::consts::OS, env!("CARGO_PKG_VERSION"))
+}
+
+// Answers M-SEARCH requests for anything advertised, straight to whoever asked
+fn respond(socket: &UdpSocket, uuid: &str, location: &str) {
+    let mut buffer = [0; 2048];
+    loop {
+        let (length, from) = match socket.recv_from(&mut buffer) {
+            Ok(received) => received,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(e) => {
+                eprintln!("Error reading SSDP searches, no longer answering them: {}", e);
+                return;
+            }
+        };
+        let search = String::from_utf8_lossy(&buffer[..length]);
+        if !search.starts_with("M-SEARCH * HTTP/1.1\r\n") || request_header(&search, "MAN") != Some("\"ssdp:discover\"") {
+            continue;
+        }
+        let wanted = match request_header(&search, "ST") {
+            Some(wanted) => wanted,
+            None => continue,
+        };
+        for (target, usn) in targets(uuid).into_iter().filter(|(target, _)| wanted == "ssdp:all" || wanted == target) {
+            let reply = format!(
+                "HTTP/1.1 200 OK\r\nCACHE-CONTROL: max-age={}\r\nEXT:\r\nLOCATION: {}\r\nSERVER: {}\r\nST: {}\r\nUSN: {}\r\n\r\n",
+                MAX_AGE,
+                location,
+                server_header(),
+                target,
+                usn
+            );
+            let _ = socket.send_to(reply.as_bytes(), from);
+        }
+    }
+}
+
+// The device description, the two services' descriptions, and their control
+// URLs, as `rest` of `<prefix>dlna/`
+pub fn handle(
+    stream: &mut Exchange,
+    method: &str,
+    request: &str,
+    received: &[u8],
+    rest: &str,
+    root: &Path,
+    args: &Args,
+) -> io::Result<()> {
+    let description = match rest {
+        "device.xml" => Some(device_description(args)),
+        "content-directory.xml" => Some(service_description(CONTENT_DIRECTORY_SCPD)),
+        "connection-manager.xml" => Some(service_description(CONNECTION_MANAGER_SCPD)),
+        _ => None,
+    };
+    if let Some(description) = description {
+        if method != "GET" && method != "HEAD" {
+            return send_error(stream, "405 Method Not Allowed", "Method Not Allowed");
+        }
+        let head = stream.response_head("200 OK", "text/xml; charset=\"utf-8\"", description.len(), "");
+        return write_response(stream, &head, description.as_bytes());
+    }
+    let service = match rest {
+        "content-directory" => CONTENT_DIRECTORY,
+        "connection-manager" => CONNECTION_MANAGER,
+        _ => return send_error(stream, "404 Not Found", "Not Found"),
+    };
+    if method != "POST" {
+        return send_error(stream, "405 Method Not Allowed", "Method Not Allowed");
+    }
+
+    let length = request_header(request, "Content-Length").and_then(|length| length.parse::<u64>().ok()).unwrap_or(0);
+    if length > MAX_BODY {
+        return send_error(stream, "413 Payload Too Large", "Payload Too Large");
+    }
+    let body = match read_body(stream, request, received, length) {
+        Ok(body) => String::from_utf8_lossy(&body).into_owned(),
+        Err(e) => {
+            eprintln!("Error reading SOAP request: {:?}", e);
+            return send_error(stream, "400 Bad Request", "Bad Request");
+        }
+    };
+    // `"urn:schemas-upnp-org:service:ContentDirectory:1#Browse"`
+    let action = request_header(request, "SOAPAction")
+        .map(|action| action.trim_matches('"'))
+        .and_then(|action| action.strip_prefix(service))
+        .and_then(|action| action.strip_prefix('#'))
+        .unwrap_or("");
+
+    let host = request_header(request, "Host").unwrap_or("localhost");
+    let result = match (service, action) {
+        (CONTENT_DIRECTORY, "Browse") => browse(&body, root, host, args),
+        (CONTENT_DIRECTORY, "GetSystemUpdateID") => Ok(vec![("Id", "0".to_string())]),
+        (CONTENT_DIRECTORY, "GetSearchCapabilities") => Ok(vec![("SearchCaps", String::new())]),
+        (CONTENT_DIRECTORY, "GetSortCapabilities") => Ok(vec![("SortCaps", String::new())]),
+        (CONNECTION_MANAGER, "GetProtocolInfo") => {
+            Ok(vec![("Source", "http-get:*:video/*:*,http-get:*:audio/*:*,http-get:*:image/*:*".to_string()), ("Sink", String::new())])
+        }
+        (CONNECTION_MANAGER, "GetCurrentConnectionIDs") => Ok(vec![("ConnectionIDs", "0".to_string())]),
+        (CONNECTION_MANAGER, "GetCurrentConnectionInfo") => Ok(vec![
+            ("RcsID", "-1".to_string()),
+            ("AVTransportID", "-1".to_string()),
+            ("ProtocolInfo", String::new()),
+            ("PeerConnectionManager", String::new()),
+            ("PeerConnectionID", "-1".to_string()),
+            ("Direction", "Output".to_string()),
+            ("Status", "OK".to_string()),
+        ]),
+        _ => Err((401, "Invalid Action")),
+    };
+
+    let (status, body) = match result {
+        Ok(values) => {
+            let values: String =
+                values.iter().map(|(name, value)| format!("<{0}>{1}</{0}>", name, escape_html(value))).collect();
+            ("200 OK", envelope(&format!("<u:{0}Response xmlns:u=\"{1}\">{2}</u:{0}Response>", action, service, values)))
+        }
+        Err((code, description)) => ("500 Internal Server Error", envelope(&format!(
+            "<s:Fault><faultcode>s:Client</faultcode><faultstring>UPnPError</faultstring><detail>\
+             <UPnPError xmlns=\"urn:schemas-upnp-org:control-1-0\"><errorCode>{}</errorCode>\
+             <errorDescription>{}</errorDescription></UPnPError></detail></s:Fault>",
+            code, description
+        ))),
+    };
+    let head = stream.response_head(status, "text/xml; charset=\"utf-8\"", body.len(), "");
+    write_response(stream, &head, body.as_bytes())
+}
+
+fn envelope(body: &str) -> String {
+    format!(
+        "<?xml version=\"1.0\" encoding=\"utf-8\"?>\
+         <s:Envelope xmlns:s=\"http://schemas.xmlsoap.org/soap/envelope/\" \
+         s:encodingStyle=\"http://schemas.xmlsoap.org/soap/encoding/\"><s:Body>{}</s:Body></s:Envelope>",
+        body
+    )
+}
+
+// Object IDs are paths below the root, `/Films/Heat.mkv`, with `0` for the
+// root itself as players expect. The result is DIDL-Lite, sent escaped.
+fn browse(body: &str, root: &Path, host: &str, args: &Args) -> Result<Vec<(&'static str, String)>, (u16, &'static str)> {
+    const NO_SUCH_OBJECT: (u16, &str) = (701, "No such object");
+    let id = argument(body, "ObjectID").ok_or((402, "Invalid Args"))?;
+    let path = if id == "0" { "/".to_string() } else { id.clone() };
+    if !path.starts_with('/') || path.split('/').any(|segment| segment == "..") || args.hidden.hides(&path) {
+        return Err(NO_SUCH_OBJECT);
+    }
+    let full = root.join(path.trim_start_matches('/'));
+    let metadata = fs::metadata(&full).map_err(|_| NO_SUCH_OBJECT)?;
+    if !is_path_within(&full, root).unwrap_or(false) {
+        return Err(NO_SUCH_OBJECT);
+    }
+
+    let (objects, total) = match argument(body, "BrowseFlag").as_deref() {
+        Some("BrowseMetadata") => {
+            let name = if id == "0" { friendly_name(args) } else { full.file_name().map_or(String::new(), |name| name.to_string_lossy().into_owned()) };
+            (vec![object(&path, &name, &metadata, host, args).ok_or(NO_SUCH_OBJECT)?], 1)
+        }
+        Some("BrowseDirectChildren") if metadata.is_dir() => {
+            let mut entries: Vec<(String, fs::Metadata)> = fs::read_dir(&full)
+                .map_err(|_| NO_SUCH_OBJECT)?
+                .filter_map(Result::ok)
+                .map(|entry| entry.file_name().to_string_lossy().into_owned())
+                .filter(|name| !args.hidden.hides_in(&path, name))
+                // Followed like everywhere else; broken links are left out
+                .filter_map(|name| fs::metadata(full.join(&name)).ok().map(|metadata| (name, metadata)))
+                .collect();
+            entries.sort_by_key(|(name, _)| name.to_lowercase());
+            let children: Vec<String> = entries
+                .iter()
+                .filter_map(|(name, metadata)| object(&format!("{}/{}", path.trim_end_matches('/'), name), name, metadata, host, args))
+                .collect();
+            let start = argument(body, "StartingIndex").and_then(|start| start.parse().ok()).unwrap_or(0);
+            // Zero asks for everything
+            let count = match argument(body, "RequestedCount").and_then(|count| count.parse().ok()).unwrap_or(0) {
+                0 => usize::MAX,
+                count => count,
+            };
+            let total = children.len();
+            (children.into_iter().skip(start).take(count).collect(), total)
+        }
+        Some("BrowseDirectChildren") => (Vec::new(), 0),
+        _ => return Err((402, "Invalid Args")),
+    };
+    let didl = format!(
+        "<DIDL-Lite xmlns=\"urn:schemas-upnp-org:metadata-1-0/DIDL-Lite/\" xmlns:dc=\"http://purl.org/dc/elements/1.1/\" \
+         xmlns:upnp=\"urn:schemas-upnp-org:metadata-1-0/upnp/\">{}</DIDL-Lite>",
+        objects.concat()
+    );
+    Ok(vec![
+        ("Result", didl),
+        ("NumberReturned", objects.len().to_string()),
+        ("TotalMatches", total.to_string()),
+        ("UpdateID", "0".to_string()),
+    ])
+}
+
+// A container for a directory or an item for a media file; anything else is
+// left out, since players couldn't play it
+fn object(path: &str, name: &str, metadata: &fs::Metadata, host: &str, args: &Args) -> Option<String> {
+    let id = if path == "/" { "0".to_string() } else { escape_html(path) };
+    let parent = match path.rsplit_once('/') {
+        _ if path == "/" => "-1".to_string(),
+        Some(("", _)) => "0".to_string(),
+        Some((parent, _)) => escape_html(parent),
+        None => return None,
+    };
+    let title = escape_html(name);
+    if metadata.is_dir() {
+        return Some(format!(
+            "<container id=\"{}\" parentID=\"{}\" restricted=\"1\"><dc:title>{}</dc:title>\
+             <upnp:class>object.container.storageFolder</upnp:class></container>",
+            id, parent, title
+        ));
+    }
+    let content_type = args.mime_types.by_extension(Path::new(name))?;
+    let essence = content_type.split(';').next().unwrap_or("").trim();
+    let class = match essence.split('/').next() {
+        Some("video") => "object.item.videoItem",
+        Some("audio") => "object.item.audioItem.musicTrack",
+        Some("image") => "object.item.imageItem.photo",
+        _ => return None,
+    };
+    // DLNA.ORG_OP=01 says byte ranges work, so players can seek
+    Some(format!(
+        "<item id=\"{}\" parentID=\"{}\" restricted=\"1\"><dc:title>{}</dc:title><upnp:class>{}</upnp:class>\
+         <res protocolInfo=\"http-get:*:{}:DLNA.ORG_OP=01\" size=\"{}\">http://{}{}</res></item>",
+        id,
+        parent,
+        title,
+        class,
+        essence,
+        metadata.len(),
+        escape_html(host),
+        escape_html(&encode_url_path(path))
+    ))
+}
+
+// The text of the first `<name>` element, unescaped. SOAP arguments are
+// unqualified and never nest, so there's no need for a real XML parser.
+fn argument(body: &str, name: &str) -> Option<String> {
+    let start = body.find(&format!("<{}>", name))? + name.len() + 2;
+    let length = body[start..].find(&format!("</{}>", name))?;
+    let value = &body[start..start + length];
+    Some(value.replace("&lt;", "<").replace("&gt;", ">").replace("&quot;", "\"").replace("&apos;", "'").replace("&amp;", "&"))
+}
+
+fn device_description(args: &Args) -> String {
+    let service = |kind: &str, id: &str, file: &str| {
+        format!(
+            "<service><serviceType>{}</serviceType><serviceId>urn:upnp-org:serviceId:{}</serviceId>\
+             <SCPDURL>{prefix}dlna/{file}.xml</SCPDURL><controlURL>{prefix}dlna/{file}</controlURL>\
+             <eventSubURL>{prefix}dlna/{file}/events</eventSubURL></service>",
+            kind,
+            id,
+            prefix = args.asset_prefix,
+            file = file
+        )
+    };
+    format!(
+        "<?xml version=\"1.0\" encoding=\"utf-8\"?>\
+         <root xmlns=\"urn:schemas-upnp-org:device-1-0\"><specVersion><major>1</major><minor>0</minor></specVersion>\
+         <device><deviceType>{}</deviceType><friendlyName>{}</friendlyName><manufacturer>Bounty</manufacturer>\
+         <modelName>Bounty</modelName><modelNumber>{}</modelNumber><UDN>{}</UDN><serviceList>{}{}</serviceList></device></root>",
+        MEDIA_SERVER,
+        escape_html(&friendly_name(args)),
+        env!("CARGO_PKG_VERSION"),
+        device_uuid(args),
+        service(CONTENT_DIRECTORY, "ContentDirectory", "content-directory"),
+        service(CONNECTION_MANAGER, "ConnectionManager", "connection-manager")
+    )
+}
+
+// Actions as (name, [(argument, direction, state variable)]), then the state
+// variables as (name, type)
+type Scpd = (&'static [(&'static str, &'static [(&'static str, &'static str, &'static str)])], &'static [(&'static str, &'static str)]);
+
+const CONTENT_DIRECTORY_SCPD: Scpd = (
+    &[
+        (
+            "Browse",
+            &[
+                ("ObjectID", "in", "A_ARG_TYPE_ObjectID"),
+                ("BrowseFlag", "in", "A_ARG_TYPE_BrowseFlag"),
+                ("Filter", "in", "A_ARG_TYPE_Filter"),
+                ("StartingIndex", "in", "A_ARG_TYPE_Index"),
+                ("RequestedCount", "in", "A_ARG_TYPE_Count"),
+                ("SortCriteria", "in", "A_ARG_TYPE_SortCriteria"),
+                ("Result", "out", "A_ARG_TYPE_Result"),
+                ("NumberReturned", "out", "A_ARG_TYPE_Count"),
+                ("TotalMatches", "out", "A_ARG_TYPE_Count"),
+                ("UpdateID", "out", "A_ARG_TYPE_UpdateID"),
+            ],
+        ),
+        ("GetSearchCapabilities", &[("SearchCaps", "out", "SearchCapabilities")]),
+        ("GetSortCapabilities", &[("SortCaps", "out", "SortCapabilities")]),
+        ("GetSystemUpdateID", &[("Id", "out", "SystemUpdateID")]),
+    ],
+    &[
+        ("A_ARG_TYPE_ObjectID", "string"),
+        ("A_ARG_TYPE_BrowseFlag", "string"),
+        ("A_ARG_TYPE_Filter", "string"),
+        ("A_ARG_TYPE_Index", "ui4"),
+        ("A_ARG_TYPE_Count", "ui4"),
+        ("A_ARG_TYPE_SortCriteria", "string"),
+        ("A_ARG_TYPE_Result", "string"),
+        ("A_ARG_TYPE_UpdateID", "ui4"),
+        ("SearchCapabilities", "string"),
+        ("SortCapabilities", "string"),
+        ("SystemUpdateID", "ui4"),
+    ],
+);
+
+const CONNECTION_MANAGER_SCPD: Scpd = (
+    &[
+        ("GetProtocolInfo", &[("Source", "out", "SourceProtocolInfo"), ("Sink", "out", "SinkProtocolInfo")]),
+        ("GetCurrentConnectionIDs", &[("ConnectionIDs", "out", "CurrentConnectionIDs")]),
+        (
+            "GetCurrentConnectionInfo",
+            &[
+                ("ConnectionID", "in", "A_ARG_TYPE_ConnectionID"),
+                ("RcsID", "out", "A_ARG_TYPE_RcsID"),
+                ("AVTransportID", "out", "A_ARG_TYPE_AVTransportID"),
+                ("ProtocolInfo", "out", "A_ARG_TYPE_ProtocolInfo"),
+                ("PeerConnectionManager", "out", "A_ARG_TYPE_ConnectionManager"),
+                ("PeerConnectionID", "out", "A_ARG_TYPE_ConnectionID"),
+                ("Direction", "out", "A_ARG_TYPE_Direction"),
+                ("Status", "out", "A_ARG_TYPE_ConnectionStatus"),
+            ],
+        ),
+    ],
+    &[
+        ("SourceProtocolInfo", "string"),
+        ("SinkProtocolInfo", "string"),
+        ("CurrentConnectionIDs", "string"),
+        ("A_ARG_TYPE_ConnectionID", "i4"),
+        ("A_ARG_TYPE_RcsID", "i4"),
+        ("A_ARG_TYPE_AVTransportID", "i4"),
+        ("A_ARG_TYPE_ProtocolInfo", "string"),
+        ("A_ARG_TYPE_ConnectionManager", "string"),
+        ("A_ARG_TYPE_Direction", "string"),
+        ("A_ARG_TYPE_ConnectionStatus", "string"),
+    ],
+);
+
+fn service_description((actions, variables): Scpd) -> String {
+    let mut xml = String::from(
+        "<?xml version=\"1.0\" encoding=\"utf-8\"?><scpd xmlns=\"urn:schemas-upnp-org:service-1-0\">\
+         <specVersion><major>1</major><minor>0</minor></specVersion><actionList>",
+    );
+    for (name, arguments) in actions {
+        xml.push_str(&format!("<action><name>{}</name><argumentList>", name));
+        for (argument, direction, variable) in arguments.iter() {
+            xml.push_str(&format!(
+                "<argument><name>{}</name><direction>{}</direction><relatedStateVariable>{}</relatedStateVariable></argument>",
+                argument, direction, variable
+            ));
+        }
+        xml.push_str("</argumentList></action>");
+    }
+    xml.push_str("</actionList><serviceStateTable>");
+    for (name, kind) in variables {
+        xml.push_str(&format!("<stateVariable sendEvents=\"no\"><name>{}</name><dataType>{}</dataType></stateVariable>", name, kind));
+    }
+    xml.push_str("</serviceStateTable></scpd>");
+    xml
+}
@@ -0,0 +1,359 @@
+use std::{
+    fs,
+    io::{self, Write},
+    path::Path,
+};
+
+use crate::{access_log::LogFormat, args::Args, cache::{DigestAlgorithm, MAX_CACHED_FILE_SIZE}, cors::Cors, share::ShareKey, tarpit::Tarpit, tls::{self, TlsOptions}};
+
+// `--dry-run`: prints what the server would do with these arguments, checks
+// that the files it needs are usable, and exits without listening. Problems
+// make it fail, so CI can run it before a rollout.
+pub fn run(args: &Args) -> io::Result<()> {
+    let problems = report(args, &mut io::stdout())?;
+    if problems.is_empty() {
+        println!("Configuration OK");
+        return Ok(());
+    }
+    for problem in &problems {
+        eprintln!("Problem: {}", problem);
+    }
+    Err(io::Error::new(io::ErrorKind::InvalidInput, format!("{} configuration problem(s)", problems.len())))
+}
+
+// The settings, to `out`, and the problems with them
+pub fn report(args: &Args, out: &mut dyn Write) -> io::Result<Vec<String>> {
+    let mut problems = Vec::new();
+    let root = &args.root;
+
+    if let Some(config) = &args.config {
+        writeln!(out, "Config")?;
+        writeln!(out, "  file           {}, overridden by flags", config.display())?;
+    }
+    writeln!(out, "Listen")?;
+    if args.fds.is_empty() {
+        for address in args.addresses() {
+            writeln!(out, "  address        {}://{}", args.scheme(), address)?;
+        }
+    }
+    for fd in &args.fds {
+        writeln!(out, "  inherited fd   {}, instead of binding", fd)?;
+    }
+    if let (Some(cert), Some(key)) = (&args.tls_cert, &args.tls_key) {
+        writeln!(out, "  tls            certificate {}, key {}", cert.display(), key.display())?;
+        let versions = if args.tls.tls13_only { "1.3" } else { "1.2 and 1.3" };
+        let ciphers = if args.tls.ciphers.is_empty() { "default ciphers".to_string() } else { args.tls.ciphers.join(", ") };
+        writeln!(out, "  tls policy     TLS {}, {}", versions, ciphers)?;
+        let alpn = args.tls.alpn.as_ref().map_or("http/1.1".to_string(), |protocols| protocols.join(", "));
+        let resumption = match (args.tls.no_resumption, args.tls.session_tickets) {
+            (true, _) => "off",
+            (false, true) => "session tickets",
+            (false, false) => "session cache",
+        };
+        writeln!(out, "  tls alpn       {}; resumption {}", if alpn.is_empty() { "none" } else { &alpn }, resumption)?;
+        if let Some(ca) = &args.tls.client_ca {
+            writeln!(out, "  tls clients    certificates signed by {}", ca.display())?;
+        }
+        if args.tls.ocsp {
+            match tls::ocsp_responder(cert, &args.tls) {
+                Ok(responder) => writeln!(out, "  tls ocsp       stapled from {}", responder)?,
+                Err(e) => problems.push(format!("OCSP stapling: {}", e)),
+            }
+        }
+        // Without stapling, which would start asking the responder
+        let options = TlsOptions { ocsp: false, ..args.tls.clone() };
+        if let Err(e) = tls::load_config(cert, key, &options) {
+            problems.push(format!("TLS setup failed: {}", e));
+        }
+    }
+    if args.mdns {
+        writeln!(out, "  mdns           bounty.local, _{}._tcp service", args.scheme())?;
+        if args.fds.is_empty() && args.bind.iter().all(|ip| ip.is_loopback()) {
+            problems.push("--mdns has nothing to advertise when listening on loopback only".to_string());
+        }
+    }
+    if args.dlna {
+        writeln!(out, "  dlna           UPnP MediaServer over SSDP, browsed at {}dlna/", args.asset_prefix)?;
+        if args.fds.is_empty() && args.bind.iter().all(|ip| ip.is_loopback()) {
+            problems.push("--dlna has nothing to advertise when listening on loopback only".to_string());
+        }
+    }
+    writeln!(out, "  workers        {} listener(s) x {} process(es)", args.workers, args.processes)?;
+    writeln!(out, "  threads        {} per process", args.threads)?;
+    writeln!(out, "  shutdown       drains for up to {}s", args.shutdown_timeout.as_secs())?;
+    if !args.allow.is_empty() || !args.deny.is_empty() {
+        writeln!(out, "  clients        {} allowed, {} denied network(s)", args.allow.len(), args.deny.len())?;
+    }
+    match &args.cors {
+        Some(Cors::Any) => writeln!(out, "  cors           any origin")?,
+        Some(Cors::Origins(origins)) => writeln!(out, "  cors           {}, with credentials", origins.join(", "))?,
+        None => {}
+    }
+    if let Some(rate) = args.rate_limit {
+        writeln!(out, "  rate limit     {} request(s)/s per address, bursts of {}", rate, args.rate_burst.unwrap_or(rate.max(1.0)))?;
+    }
+    writeln!(out, 
+        "  timeouts       head {}s, reads {}s, writes {}s",
+        args.header_timeout.as_secs(), args.read_timeout.as_secs(), args.write_timeout.as_secs()
+    )?;
+    if let Some(max) = args.max_connections_per_ip {
+        writeln!(out, "  connections    {} open per address", max)?;
+    }
+    if let Some(log) = &args.access_log {
+        let format = if args.log_format == LogFormat::Json { "JSON lines" } else { "common log format" };
+        writeln!(out, "  access log     {}, {}", log.display(), format)?;
+        if log != Path::new("-") {
+            check_writable_file(log, "--access-log", &mut problems);
+        }
+    }
+
+    writeln!(out, "Root")?;
+    writeln!(out, "  directory      {}", root.display())?;
+    if let Err(e) = fs::read_dir(root) {
+        problems.push(format!("root {} can't be listed: {}", root.display(), e));
+    }
+    for overlay in &args.overlays {
+        writeln!(out, "  overlay        {}", overlay.display())?;
+    }
+    for (host, dir) in &args.vhosts {
+        writeln!(out, "  vhost          {} -> {}", host, dir.display())?;
+    }
+    for (prefix, dir) in &args.mounts {
+        writeln!(out, "  mount          {} -> {}", prefix, dir.display())?;
+    }
+    for (prefix, upstream) in &args.proxies {
+        writeln!(out, "  proxy          {} -> {}", prefix, upstream.url())?;
+    }
+    if let Some(git_ref) = &args.git_ref {
+        writeln!(out, "  git ref        {}", git_ref)?;
+    }
+    if args.spa {
+        let index = root.join("index.html");
+        writeln!(out, "  spa fallback   {}", index.display())?;
+        if !index.is_file() {
+            problems.push(format!("--spa needs {}, which doesn't exist", index.display()));
+        }
+    }
+    if let Some(pattern) = &args.user_dirs {
+        writeln!(out, "  user dirs      /~<user>/ -> {}", pattern)?;
+        check_pattern(pattern, "--user-dirs", &mut problems);
+    }
+
+    writeln!(out, "Responses")?;
+    writeln!(out, "  exact sizes    {}", args.exact)?;
+    if args.hidden.is_active() {
+        writeln!(out, "  hidden         {} pattern(s), dotfiles {}", args.hidden.pattern_count(), args.hidden.hides_dotfiles())?;
+    }
+    writeln!(out, "  markdown       {}", if args.render_markdown { "rendered unless ?render=0" } else { "rendered with ?render=1" })?;
+    writeln!(out, "  languages      default {}", args.default_language.as_deref().unwrap_or("none"))?;
+    writeln!(out, "  max body       {} bytes", args.max_body_size)?;
+    writeln!(out, 
+        "  max head       {} bytes, {} headers, request line {} bytes",
+        args.max_header_size, args.max_headers, args.max_uri_length
+    )?;
+    writeln!(out, "  cache control  {}", args.cache_control.as_deref().unwrap_or("none, validators only"))?;
+    for (name, value) in &args.response_headers {
+        writeln!(out, "  header         {}: {}", name, value)?;
+    }
+    writeln!(out, "  mime types     built-in, {} overridden", args.mime_types.override_count())?;
+    for (code, path) in &args.error_pages {
+        writeln!(out, "  error page     {} -> {}", code, path.display())?;
+        if let Err(e) = fs::File::open(path) {
+            problems.push(format!("--error-page {}={} can't be read: {}", code, path.display(), e));
+        }
+    }
+    let file_digest = match args.file_digest {
+        Some(DigestAlgorithm::Sha256) => "Repr-Digest",
+        Some(DigestAlgorithm::Md5) => "Content-MD5",
+        None => "off",
+    };
+    writeln!(out, "  digests        trailers {}, file headers {}", args.digest_trailers, file_digest)?;
+    writeln!(out, 
+        "  compression    on the fly {}, precompressed siblings {}",
+        if args.compress { "gzip, deflate" } else { "off" },
+        args.precompressed
+    )?;
+    writeln!(out, 
+        "  cache TTLs     not found {}s, listings {}s, stats {}s",
+        args.not_found_ttl.as_secs(),
+        args.listing_cache_ttl.as_secs(),
+        args.stat_cache_ttl.as_secs()
+    )?;
+    if let Some(size) = args.file_cache {
+        writeln!(out, "  file cache     {} bytes, of files up to {} bytes", size, MAX_CACHED_FILE_SIZE.min(size))?;
+    }
+    if args.io_uring {
+        writeln!(out, "  io_uring       file bodies over plain TCP, if the kernel allows it")?;
+    }
+
+    writeln!(out, "Routes")?;
+    writeln!(out, "  {:<20} embedded assets", args.asset_prefix)?;
+    if args.append_token.is_some() || args.has_accounts() {
+        let fsync = if args.append_fsync { "fsync" } else { "no fsync" };
+        writeln!(out, "  {:<20} append, {}, files up to {} bytes", "/_append/", fsync, args.append_max_file_size)?;
+    }
+    if let Some(dir) = &args.paste_dir {
+        writeln!(out, "  {:<20} pastebin into /{}", "/_paste", dir)?;
+        // Created on first use, along with any missing parents
+        if root.join(dir).exists() && !root.join(dir).is_dir() {
+            problems.push(format!("--paste-dir {} is not a directory", dir));
+        }
+    }
+    if let Some(store) = &args.short_links {
+        writeln!(out, "  {:<20} short links stored in {}", "/s/", store.display())?;
+        check_writable_file(store, "--short-links", &mut problems);
+    }
+    if args.batch_token.is_some() {
+        writeln!(out, "  {:<20} batch file operations", "/_api/batch")?;
+    }
+    writeln!(out, "  {:<20} text file with line numbers and highlighting", "?view=source")?;
+    if args.git_ref.is_none() {
+        writeln!(out, "  {:<20} names matching below a directory", "?q=")?;
+        let thumbnails = std::env::temp_dir().join("bounty-thumbnails");
+        writeln!(out, "  {:<20} image grid, thumbnails cached in {}", "?view=gallery", thumbnails.display())?;
+    }
+    if args.overlays.is_empty() && args.git_ref.is_none() {
+        writeln!(out, "  {:<20} ZIP or tar archive of a directory", "?download=zip|tar")?;
+    }
+    if args.allow_upload {
+        let who = if args.has_accounts() { "accounts that can write" } else { "anyone" };
+        writeln!(out, "  {:<20} uploads by {}, PUT or multipart POST, and DELETE and MOVE", "/", who)?;
+    }
+    if args.webdav_write {
+        let who = if args.has_accounts() { "accounts that can write" } else { "anyone" };
+        writeln!(out, "  {:<20} WebDAV, changes by {}", "/", who)?;
+    } else if args.webdav {
+        writeln!(out, "  {:<20} WebDAV, read-only", "/")?;
+    }
+    if args.has_accounts() {
+        writeln!(out, "  {:<20} storage usage", "/_api/usage")?;
+    }
+    if let Some(dir) = &args.download_stats {
+        writeln!(out, "  {:<20} top downloads, counted in {}", "/_stats/top", dir.display())?;
+        if dir.exists() && !dir.is_dir() {
+            problems.push(format!("--download-stats {} is not a directory", dir.display()));
+        }
+    }
+    if args.stat_api {
+        writeln!(out, "  {:<20} file metadata", "/_api/stat/")?;
+    }
+    if args.git_urls {
+        writeln!(out, "  {:<20} any commit's tree below the root", "/_git/<ref>/")?;
+    }
+    if args.debug_echo {
+        writeln!(out, "  {:<20} request inspector, loopback only", "/_debug/echo")?;
+    }
+    if args.watch {
+        writeln!(out, "  {:<20} live reload events, polling the root twice a second", format!("{}events", args.asset_prefix))?;
+    }
+    if args.admin {
+        writeln!(out, "  {:<20} health probe, 503 while draining", format!("{}health", args.asset_prefix))?;
+        writeln!(out, "  {:<20} request and connection totals", format!("{}stats", args.asset_prefix))?;
+    }
+    if args.metrics {
+        writeln!(out, "  {:<20} Prometheus metrics", format!("{}metrics", args.asset_prefix))?;
+    }
+    if args.share_key.is_some() {
+        writeln!(out, "  {:<20} share links, ?path=/x&expires=1d", format!("{}share", args.asset_prefix))?;
+    }
+
+    writeln!(out, "Authentication")?;
+    match &args.users {
+        Some(users) => {
+            for user in users.iter() {
+                let access = if user.can_write { "rw" } else { "ro" };
+                let quota = user.quota.map_or("no quota".to_string(), |quota| format!("quota {} bytes", quota));
+                writeln!(out, "  user {:<15} {} {}, {}", user.name, access, user.root.display(), quota)?;
+            }
+        }
+        None => writeln!(out, "  users file     none")?,
+    }
+    if !args.auth.is_empty() {
+        writeln!(out, "  password gate  {} credential(s)", args.auth.len())?;
+    }
+    if let Some(service) = &args.pam_service {
+        writeln!(out, "  PAM            service {}, roots {} (read-only)", service, args.pam_root)?;
+        check_pattern(&args.pam_root, "--pam-root", &mut problems);
+    }
+    if let Some(server) = &args.ldap {
+        let root = args.ldap_root.clone().unwrap_or_else(|| args.root.display().to_string());
+        let access = match &args.ldap_write_group {
+            Some(group) => format!("read-write for members of {}", group),
+            None => "read-only".to_string(),
+        };
+        writeln!(out, "  LDAP           {} as {}, roots {} ({})", server, args.ldap_dn, root, access)?;
+        if let Some(pattern) = &args.ldap_root {
+            check_pattern(pattern, "--ldap-root", &mut problems);
+        }
+    }
+    // Only whether tokens are set: the output may well end up in CI logs
+    writeln!(out, "  append token   {}", if args.append_token.is_some() { "set" } else { "none" })?;
+    writeln!(out, "  batch token    {}", if args.batch_token.is_some() { "set" } else { "none" })?;
+    if let Some(log) = &args.auth_log {
+        writeln!(out, "  failure log    {}", log.display())?;
+        check_writable_file(log, "--auth-log", &mut problems);
+    }
+    // Made when first needed, which a dry run shouldn't do
+    if let Some(key) = &args.share_key {
+        writeln!(out, "  share key      {}", key.display())?;
+        if key.exists() {
+            if let Err(e) = ShareKey::load(key) {
+                problems.push(format!("--share-key {}: {}", key.display(), e));
+            }
+        } else {
+            check_writable_file(key, "--share-key", &mut problems);
+        }
+    }
+    if let Some(limit) = args.auth_lockout {
+        writeln!(out, "  lockout        after {} failures for {}s", limit, args.auth_lockout_time.as_secs())?;
+    }
+
+    if args.tarpit {
+        match Tarpit::new(args) {
+            Ok(_) => writeln!(out, "Tarpit\n  scanner paths  stalled")?,
+            Err(e) => problems.push(format!("--tarpit-patterns can't be read: {}", e)),
+        }
+    }
+
+    if let Some(control) = &args.tor_control {
+        writeln!(out, "Tor")?;
+        writeln!(out, "  control port   {}", control)?;
+        match &args.tor_key {
+            Some(key) => {
+                writeln!(out, "  onion key      {}", key.display())?;
+                check_writable_file(key, "--tor-key", &mut problems);
+            }
+            None => writeln!(out, "  onion key      none, new address every start")?,
+        }
+    }
+
+    Ok(problems)
+}
+
+// The fixed directories before the `*` have to exist
+fn check_pattern(pattern: &str, flag: &str, problems: &mut Vec<String>) {
+    let prefix = pattern.split('*').next().unwrap_or("");
+    let fixed = match prefix.rfind('/') {
+        Some(0) | None => return,
+        Some(slash) => &prefix[..slash],
+    };
+    if !Path::new(fixed).is_dir() {
+        problems.push(format!("{} {}: {} is not a directory", flag, pattern, fixed));
+    }
+}
+
+// Existing files must be writable; new ones need an existing parent directory
+fn check_writable_file(path: &Path, flag: &str, problems: &mut Vec<String>) {
+    match fs::metadata(path) {
+        Ok(metadata) if metadata.permissions().readonly() || !metadata.is_file() => {
+            problems.push(format!("{} {} is not a writable file", flag, path.display()));
+        }
+        Ok(_) => {}
+        Err(_) => {
+            let parent = path.parent().filter(|parent| !parent.as_os_str().is_empty()).unwrap_or(Path::new("."));
+            if !parent.is_dir() {
+                problems.push(format!("{} {}: {} is not a directory", flag, path.display(), parent.display()));
+            }
+        }
+    }
+}
@@ -0,0 +1,47 @@
+use std::{collections::HashMap, fs, io};
+
+use crate::{args::Args, escape_html};
+
+// Read once, when a Server is made, and kept with it
+pub struct ErrorPages(HashMap<u16, Vec<u8>>);
+
+impl ErrorPages {
+    // `--error-page 404=errors/404.html` serves that file, as is, for every 404
+    pub fn load(args: &Args) -> io::Result<ErrorPages> {
+        let mut pages = HashMap::new();
+        for (code, path) in &args.error_pages {
+            let page = fs::read(path)
+                .map_err(|e| io::Error::new(e.kind(), format!("--error-page {}={}: {}", code, path.display(), e)))?;
+            pages.insert(*code, page);
+        }
+        Ok(ErrorPages(pages))
+    }
+
+    // `status` is a whole status line like `404 Not Found`; `message` says more
+    // when it's more than the status's own reason
+    pub fn body(&self, status: &str, message: &str) -> Vec<u8> {
+        let code = status.split(' ').next().and_then(|code| code.parse::<u16>().ok());
+        match code.and_then(|code| self.0.get(&code)) {
+            Some(page) => page.clone(),
+            None => default_body(status, message),
+        }
+    }
+}
+
+fn default_body(status: &str, message: &str) -> Vec<u8> {
+
+    let status = escape_html(status);
+    let detail = match message {
+        message if message.is_empty() || status.ends_with(message) => String::new(),
+        message => format!("<p>{}</p>", escape_html(message)),
+    };
+    format!(
+        "<!DOCTYPE html><html><head><meta charset=\"utf-8\">\
+         <meta name=\"viewport\" content=\"width=device-width, initial-scale=1\"><title>{}</title>\
+         <style>body{{font-family:system-ui,sans-serif;max-width:40em;margin:4em auto;padding:0 1em;color:#333}}\
+         h1{{font-weight:500}}a{{color:#0366d6}}</style></head>\
+         <body><h1>{}</h1>{}<p><a href=\"/\">Back to the top</a></p></body></html>\n",
+        status, status, detail
+    )
+    .into_bytes()
+}
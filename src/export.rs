@@ -0,0 +1,86 @@
+use std::{
+    fs::{self, File},
+    io::{self, BufWriter, Write},
+    path::Path,
+};
+
+use walkdir::WalkDir;
+
+use crate::{args::Args, assets, cache::{Caches, StatCache}, is_path_within, listing::ListingOptions, write_directory_listing};
+
+// `--export DIR`: writes what the server would serve as plain files, so the
+// tree can go on a host that only serves files. Every directory gets the
+// listing the server would render as its index.html, and the embedded assets
+// go under the asset prefix, so the result has to be served from a host's root.
+pub fn run(args: &Args, out: &Path) -> io::Result<()> {
+    let root = &args.root;
+    fs::create_dir_all(out)?;
+    let out = out.canonicalize()?;
+
+    // Static pages can't answer archive, sort, search or gallery queries
+    let options = ListingOptions {
+        exact: args.exact,
+        asset_prefix: &args.asset_prefix,
+        upload: false,
+        archive: false,
+        sortable: false,
+        sort: None,
+        json: false,
+        reload: false,
+        hidden: &args.hidden,
+        dir: "",
+        gallery: false,
+        searchable: false,
+        search: None,
+    };
+    let mut caches = Caches::new(args, &StatCache::new(args.stat_cache_ttl));
+    let (mut files, mut directories) = (0, 0);
+
+    // Symlinks are followed as the server does, as long as they stay within the root
+    // Hidden entries stay out of the copy, along with everything under them
+    let walker = WalkDir::new(root).follow_links(true).into_iter().filter_entry(|entry| {
+        let relative = entry.path().strip_prefix(root).map_or(String::new(), |relative| relative.to_string_lossy().to_string());
+        entry.path() != out && !args.hidden.hides(&relative)
+    });
+    for entry in walker {
+        let entry = match entry {
+            Ok(entry) => entry,
+            Err(e) => {
+                eprintln!("Skipping: {}", e);
+                continue;
+            }
+        };
+        if !is_path_within(entry.path(), root)? {
+            eprintln!("Skipping {}: outside the root", entry.path().display());
+            continue;
+        }
+
+        let relative = entry.path().strip_prefix(root).map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+        let target = out.join(relative);
+        if entry.file_type().is_dir() {
+            fs::create_dir_all(&target)?;
+            // The tree's own index.html is copied like any file instead
+            if !entry.path().join("index.html").exists() {
+                let mut index = BufWriter::new(File::create(target.join("index.html"))?);
+                let dir = relative.to_string_lossy();
+                write_directory_listing(&mut index, entry.path(), &[], &ListingOptions { dir: &dir, ..options }, &mut caches)?;
+                index.flush()?;
+            }
+            directories += 1;
+        } else if entry.file_type().is_file() {
+            fs::copy(entry.path(), &target)?;
+            files += 1;
+        }
+    }
+
+    let asset_dir = out.join(args.asset_prefix.trim_matches('/'));
+    fs::create_dir_all(&asset_dir)?;
+    assets::write_to(&asset_dir)?;
+    // The server answers /favicon.ico itself when the root has none
+    if !root.join("favicon.ico").is_file() {
+        fs::write(out.join("favicon.ico"), assets::FAVICON)?;
+    }
+
+    println!("Exported {} files and {} directory listings to {}", files, directories, out.display());
+    Ok(())
+}
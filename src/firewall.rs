@@ -0,0 +1,149 @@
+use std::{
+    collections::HashMap,
+    net::IpAddr,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+use crate::args::Args;
+
+const MAX_TRACKED_ADDRESSES: usize = 10_000;
+
+// `10.0.0.0/8`, `2001:db8::/32`, or a bare address for just that one
+#[derive(Clone, Copy)]
+pub struct Cidr {
+    network: IpAddr,
+    prefix: u32,
+}
+
+impl Cidr {
+    pub fn parse(value: &str) -> Result<Cidr, String> {
+        let (address, prefix) = match value.split_once('/') {
+            Some((address, prefix)) => (address, Some(prefix)),
+            None => (value, None),
+        };
+        let network: IpAddr = address.parse().map_err(|_| format!("{} is not an IP address", address))?;
+        let bits = if network.is_ipv4() { 32 } else { 128 };
+        let prefix = match prefix {
+            Some(prefix) => prefix.parse::<u32>().ok().filter(|prefix| *prefix <= bits),
+            None => Some(bits),
+        };
+        let prefix = prefix.ok_or_else(|| format!("{} has an invalid prefix length", value))?;
+        Ok(Cidr { network, prefix })
+    }
+
+    fn contains(&self, ip: IpAddr) -> bool {
+        // A dual-stack listener sees IPv4 clients as ::ffff:a.b.c.d
+        let ip = match ip {
+            IpAddr::V6(v6) => v6.to_ipv4_mapped().map_or(ip, IpAddr::V4),
+            ip => ip,
+        };
+        match (self.network, ip) {
+            (IpAddr::V4(network), IpAddr::V4(ip)) => {
+                let mask = u32::MAX.checked_shl(32 - self.prefix).unwrap_or(0);
+                u32::from(network) & mask == u32::from(ip) & mask
+            }
+            (IpAddr::V6(network), IpAddr::V6(ip)) => {
+                let mask = u128::MAX.checked_shl(128 - self.prefix).unwrap_or(0);
+                u128::from(network) & mask == u128::from(ip) & mask
+            }
+            _ => false,
+        }
+    }
+}
+
+// `--deny` wins over `--allow`; with any `--allow`, addresses it doesn't name
+// are refused too. Refused connections are closed before reading anything.
+pub fn permits(args: &Args, ip: IpAddr) -> bool {
+    if args.deny.iter().any(|cidr| cidr.contains(ip)) {
+        return false;
+    }
+    args.allow.is_empty() || args.allow.iter().any(|cidr| cidr.contains(ip))
+}
+
+// `--rate-limit N` lets each address make N requests a second on average, in
+// bursts of up to `--rate-burst`: a token bucket per address, refilled
+// continuously. Shared by all workers of a process.
+pub struct RateLimiter {
+    rate: f64,
+    burst: f64,
+    buckets: Mutex<HashMap<IpAddr, Bucket>>,
+}
+
+struct Bucket {
+    tokens: f64,
+    updated: Instant,
+}
+
+impl RateLimiter {
+    pub fn new(args: &Args) -> Option<RateLimiter> {
+        let rate = args.rate_limit?;
+        let burst = args.rate_burst.unwrap_or(rate.max(1.0));
+        Some(RateLimiter { rate, burst, buckets: Mutex::new(HashMap::new()) })
+    }
+
+    // Takes a token for the request, or says how long until there is one
+    pub fn check(&self, ip: IpAddr) -> Option<Duration> {
+        let now = Instant::now();
+        let mut buckets = self.buckets.lock().unwrap_or_else(|e| e.into_inner());
+        if buckets.len() >= MAX_TRACKED_ADDRESSES && !buckets.contains_key(&ip) {
+            // A full bucket is no different from a forgotten one
+            let (rate, burst) = (self.rate, self.burst);
+            buckets.retain(|_, bucket| bucket.tokens + (now - bucket.updated).as_secs_f64() * rate < burst);
+            if buckets.len() >= MAX_TRACKED_ADDRESSES {
+                buckets.clear();
+            }
+        }
+
+        let bucket = buckets.entry(ip).or_insert(Bucket { tokens: self.burst, updated: now });
+        bucket.tokens = (bucket.tokens + (now - bucket.updated).as_secs_f64() * self.rate).min(self.burst);
+        bucket.updated = now;
+        if bucket.tokens >= 1.0 {
+            bucket.tokens -= 1.0;
+            return None;
+        }
+        Some(Duration::from_secs_f64((1.0 - bucket.tokens) / self.rate))
+    }
+}
+
+// `--max-connections-per-ip N` counts each address's open connections, so a
+// client holding many of them open can't take every handler thread
+pub struct ConnectionLimiter {
+    max: usize,
+    open: Mutex<HashMap<IpAddr, usize>>,
+}
+
+// One connection's place in the count, given back when it's dropped
+pub struct ConnectionSlot<'a> {
+    limiter: &'a ConnectionLimiter,
+    ip: IpAddr,
+}
+
+impl ConnectionLimiter {
+    pub fn new(args: &Args) -> Option<ConnectionLimiter> {
+        Some(ConnectionLimiter { max: args.max_connections_per_ip?, open: Mutex::new(HashMap::new()) })
+    }
+
+    // None when the address already has its fill
+    pub fn admit(&self, ip: IpAddr) -> Option<ConnectionSlot<'_>> {
+        let mut open = self.open.lock().unwrap_or_else(|e| e.into_inner());
+        let count = open.entry(ip).or_insert(0);
+        if *count >= self.max {
+            return None;
+        }
+        *count += 1;
+        Some(ConnectionSlot { limiter: self, ip })
+    }
+}
+
+impl Drop for ConnectionSlot<'_> {
+    fn drop(&mut self) {
+        let mut open = self.limiter.open.lock().unwrap_or_else(|e| e.into_inner());
+        if let Some(count) = open.get_mut(&self.ip) {
+            *count -= 1;
+            if *count == 0 {
+                open.remove(&self.ip);
+            }
+        }
+    }
+}
@@ -0,0 +1,132 @@
+use std::{
+    collections::HashSet,
+    env, fs,
+    io,
+    path::{Path, PathBuf},
+    process,
+    sync::atomic::{AtomicU64, Ordering},
+    time::UNIX_EPOCH,
+};
+
+use sha2::{Digest, Sha256};
+use walkdir::WalkDir;
+
+use crate::{
+    assets, cache::FileInfo, encode_path, escape_html, image, listing::ListingOptions,
+    Exchange, watch, write_response,
+};
+
+// Twice the size tiles are shown at, for high-density screens
+const THUMBNAIL_SIZE: usize = 320;
+// Files bigger than this are read whole to scale them, so they're left to the browser
+const MAX_SOURCE_SIZE: u64 = 64 * 1024 * 1024;
+// Tells apart the partial files of thumbnails being made at once
+static PARTIALS: AtomicU64 = AtomicU64::new(0);
+
+const EXTENSIONS: [&str; 9] = ["jpg", "jpeg", "png", "gif", "webp", "avif", "bmp", "svg", "ico"];
+
+pub fn is_image(name: &str) -> bool {
+    name.rsplit_once('.').is_some_and(|(stem, extension)| {
+        !stem.is_empty() && EXTENSIONS.iter().any(|known| extension.eq_ignore_ascii_case(known))
+    })
+}
+
+// More than half of a listing's files are images, which makes it worth
+// offering the gallery
+pub fn is_mostly_images<'a>(names: impl Iterator<Item = &'a str>) -> bool {
+    let (mut images, mut files) = (0, 0);
+    for name in names {
+        files += 1;
+        images += is_image(name) as usize;
+    }
+    images * 2 > files
+}
+
+// `?view=gallery` on a directory: a grid of its images as thumbnails, with
+// its subdirectories' galleries first. `dirs` holds the directory in each
+// overlay layer, highest first, like a listing merges them.
+pub fn send_page(stream: &mut Exchange, title: &str, dirs: &[PathBuf], options: &ListingOptions, headers: &str) -> io::Result<()> {
+    let title = escape_html(title);
+    let mut seen = HashSet::new();
+    let mut entries = Vec::new();
+    for dir in dirs {
+        for entry in WalkDir::new(dir).min_depth(1).max_depth(1).into_iter().filter_map(Result::ok) {
+            let name = entry.file_name().to_string_lossy().into_owned();
+            if options.hidden.hides_in(options.dir, &name) || !seen.insert(name.clone()) {
+                continue;
+            }
+            entries.push((entry.path().is_dir(), name));
+        }
+    }
+    entries.sort_by(|(a_is_dir, a), (b_is_dir, b)| b_is_dir.cmp(a_is_dir).then_with(|| a.to_lowercase().cmp(&b.to_lowercase())));
+
+    let mut tiles = String::new();
+    let mut others = 0;
+    for (is_dir, name) in &entries {
+        let (href, label) = (encode_path(name), escape_html(name));
+        if *is_dir {
+            tiles.push_str(&format!("<a class=\"folder\" href=\"{}/?view=gallery\">{}/</a>", href, label));
+        } else if is_image(name) {
+            tiles.push_str(&format!(
+                "<a href=\"{}\" title=\"{}\"><img src=\"{}?thumbnail\" alt=\"{}\" loading=\"lazy\"></a>",
+                href, label, href, label
+            ));
+        } else {
+            others += 1;
+        }
+    }
+    let others = match others {
+        0 => String::new(),
+        1 => " · 1 other file".to_string(),
+        _ => format!(" · {} other files", others),
+    };
+
+    let body = format!(
+        "<!DOCTYPE html><html><head><meta charset=\"utf-8\">\
+         <meta name=\"viewport\" content=\"width=device-width, initial-scale=1\">\
+         <title>{}</title><link rel=\"icon\" href=\"{}\"><link rel=\"stylesheet\" href=\"{}\"></head>\
+         <body><h1>Gallery of {}</h1><p id=\"views\"><a href=\"./\">List view</a>{}</p>\
+         <div class=\"gallery\">{}</div>{}</body></html>",
+        title,
+        assets::url(options.asset_prefix, "favicon", "ico"),
+        assets::url(options.asset_prefix, "listing", "css"),
+        title,
+        others,
+        tiles,
+        if options.reload { watch::script_tag(options.asset_prefix) } else { String::new() }
+    );
+    write_response(stream, &stream.response_head("200 OK", "text/html", body.len(), headers), body.as_bytes())
+}
+
+// `?thumbnail` on an image: a PNG of it scaled down, made the first time
+// it's asked for and kept in the temporary directory. The name comes from
+// the image's path, size and mtime, so an edited image gets a new one. None
+// when the image can't be scaled here, or is small enough to send as it is.
+pub fn thumbnail(path: &Path, info: &FileInfo) -> Option<PathBuf> {
+    if info.size > MAX_SOURCE_SIZE {
+        return None;
+    }
+    let modified = info.modified?.duration_since(UNIX_EPOCH).ok()?;
+    let mut hasher = Sha256::new();
+    hasher.update(path.as_os_str().as_encoded_bytes());
+    hasher.update(format!("\0{}\0{}.{}", info.size, modified.as_secs(), modified.subsec_nanos()));
+    let key: String = hasher.finalize()[..16].iter().map(|byte| format!("{:02x}", byte)).collect();
+
+    let dir = env::temp_dir().join("bounty-thumbnails");
+    let thumbnail = dir.join(format!("{}.png", key));
+    if thumbnail.is_file() {
+        return Some(thumbnail);
+    }
+    let image = image::thumbnail(&fs::read(path).ok()?, THUMBNAIL_SIZE)?;
+    // Written aside and renamed, so a concurrent request never sees half of it
+    let partial = dir.join(format!("{}.png.{}-{}", key, process::id(), PARTIALS.fetch_add(1, Ordering::Relaxed)));
+    let written = fs::create_dir_all(&dir)
+        .and_then(|_| fs::write(&partial, image::encode_png(&image)))
+        .and_then(|_| fs::rename(&partial, &thumbnail));
+    if let Err(e) = written {
+        eprintln!("Error caching a thumbnail in {}: {}", dir.display(), e);
+        let _ = fs::remove_file(&partial);
+        return None;
+    }
+    Some(thumbnail)
+}
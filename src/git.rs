@@ -0,0 +1,129 @@
+use std::{io, path::Path, process::Command};
+
+use crate::{
+    encode_path,
+    listing::{self, ListingEntry, ListingOptions},
+    mime::MimeTypes,
+    send_content, send_error,
+    Exchange,
+    write_response, write_row,
+};
+
+enum GitObject {
+    Tree(Vec<TreeEntry>),
+    Blob(Vec<u8>),
+}
+
+struct TreeEntry {
+    name: String,
+    is_dir: bool,
+    size: Option<u64>,
+}
+
+// `repo` is the served root, whose repository the refs are looked up in. Paths
+// are below the root, even when it's a subdirectory of the repository.
+pub fn send_tree_path(
+    stream: &mut Exchange,
+    repo: &Path,
+    git_ref: &str,
+    path: &str,
+    options: &ListingOptions,
+    mime_types: &MimeTypes,
+) -> io::Result<()> {
+    let path = path.trim_matches('/');
+    // Trees are read-only, and archives, searches and thumbnails are made from the working tree
+    let options = &ListingOptions { upload: false, archive: false, gallery: false, searchable: false, dir: path, ..*options };
+
+    // A leading dash would make git read the ref as an option, and a colon
+    // would start a path of its own, from the top of the repository
+    if git_ref.is_empty() || git_ref.starts_with('-') || git_ref.contains(':') || path.split('/').any(|part| part == "..") {
+        return send_error(stream, "403 Forbidden", "Forbidden");
+    }
+    if options.hidden.hides(path) {
+        return send_error(stream, "404 Not Found", "Not Found");
+    }
+
+    // `./` resolves from the working directory git runs in, which is the root.
+    // Peeling to a commit keeps a tree's hash from standing in for the ref, as
+    // a tree from above the root could.
+    let object = format!("{}^{{commit}}:./{}", git_ref, path);
+    let title = format!("{}:/{}", git_ref, path);
+    match read_object(repo, &object) {
+        Ok(Some(GitObject::Tree(entries))) => send_tree_listing(stream, &title, &entries, options),
+        Ok(Some(GitObject::Blob(content))) => {
+            send_content(stream, &content, &mime_types.for_file(Path::new(path), &content), "")
+        }
+        Ok(None) => send_error(stream, "404 Not Found", "Not Found"),
+        Err(e) => {
+            eprintln!("Error reading git object {}: {:?}", object, e);
+            send_error(stream, "500 Internal Server Error", "Internal Server Error")
+        }
+    }
+}
+
+fn read_object(repo: &Path, object: &str) -> io::Result<Option<GitObject>> {
+    let kind = match git(repo, &["cat-file", "-t", object])? {
+        Some(kind) => kind,
+        None => return Ok(None),
+    };
+
+    match String::from_utf8_lossy(&kind).trim() {
+        "blob" => Ok(git(repo, &["cat-file", "blob", object])?.map(GitObject::Blob)),
+        // Otherwise ls-tree would only list what's below the root's own path in the tree
+        "tree" => Ok(git(repo, &["ls-tree", "--full-tree", "-l", "-z", object])?.map(|output| GitObject::Tree(parse_ls_tree(&output)))),
+        // Submodule entries point at commits we can't serve
+        _ => Ok(None),
+    }
+}
+
+// Each record is `<mode> <type> <hash> <size>\t<name>`, NUL-terminated under -z,
+// with a `-` size for trees
+fn parse_ls_tree(output: &[u8]) -> Vec<TreeEntry> {
+    output
+        .split(|&byte| byte == 0)
+        .filter_map(|record| {
+            let record = String::from_utf8_lossy(record);
+            let (meta, name) = record.split_once('\t')?;
+            let mut fields = meta.split_whitespace();
+            Some(TreeEntry {
+                name: name.to_string(),
+                is_dir: fields.nth(1) == Some("tree"),
+                size: fields.nth(1).and_then(|size| size.parse().ok()),
+            })
+        })
+        .collect()
+}
+
+// Trees don't record modification times, so only sizes are shown
+fn send_tree_listing(stream: &mut Exchange, title: &str, entries: &[TreeEntry], options: &ListingOptions) -> io::Result<()> {
+    let entries: Vec<_> = entries
+        .iter()
+        .map(|entry| ListingEntry {
+            href: encode_path(&entry.name),
+            name: entry.name.clone(),
+            is_dir: entry.is_dir,
+            size: entry.size,
+            modified: None,
+        })
+        .collect();
+    let mut rows: Vec<_> = entries.iter().collect();
+    if let Some(sort) = options.sort {
+        sort.apply(&mut rows);
+    }
+
+    let mut body = listing::header(title, options).into_bytes();
+    let mut written = 0;
+    for entry in rows {
+        write_row(&mut body, entry, options, &mut written)?;
+    }
+    body.extend_from_slice(listing::footer(options).as_bytes());
+
+    let content_type = if options.json { "application/json" } else { "text/html" };
+    write_response(stream, &stream.response_head("200 OK", content_type, body.len(), ""), &body)
+}
+
+// Returns None when git exits unsuccessfully (unknown ref, missing path, not a repository)
+fn git(repo: &Path, args: &[&str]) -> io::Result<Option<Vec<u8>>> {
+    let output = Command::new("git").args(args).current_dir(repo).output()?;
+    Ok(if output.status.success() { Some(output.stdout) } else { None })
+}
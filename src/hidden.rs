@@ -0,0 +1,104 @@
+use std::{fs, io, path::Path};
+
+pub const IGNORE_FILE: &str = ".bountyignore";
+
+// `--hide-dotfiles` keeps names starting with a dot out of listings and
+// answers requests for them, or for anything beneath them, with 404.
+// `--bountyignore` does the same for the root's .bountyignore patterns, read
+// once at startup:
+//
+//     *.log          any file or directory named like this, at any depth
+//     drafts/        a directory's trailing slash is optional
+//     build/**/*.map a pattern with a slash is matched from the root; ** spans directories
+#[derive(Default)]
+pub struct Hidden {
+    dotfiles: bool,
+    patterns: Vec<String>,
+}
+
+impl Hidden {
+    pub fn new(root: &Path, dotfiles: bool, ignore_file: bool) -> io::Result<Hidden> {
+        let mut patterns = Vec::new();
+        if ignore_file {
+            let path = root.join(IGNORE_FILE);
+            let text = match fs::read_to_string(&path) {
+                Ok(text) => text,
+                Err(e) if e.kind() == io::ErrorKind::NotFound => String::new(),
+                Err(e) => return Err(e),
+            };
+            patterns = text
+                .lines()
+                .map(str::trim)
+                .filter(|line| !line.is_empty() && !line.starts_with('#'))
+                .map(|line| line.trim_start_matches('/').trim_end_matches('/').to_string())
+                .filter(|pattern| !pattern.is_empty())
+                .collect();
+            // The rules themselves aren't for visitors
+            patterns.push(IGNORE_FILE.to_string());
+        }
+        Ok(Hidden { dotfiles, patterns })
+    }
+
+    pub fn is_active(&self) -> bool {
+        self.dotfiles || !self.patterns.is_empty()
+    }
+
+    pub fn hides_dotfiles(&self) -> bool {
+        self.dotfiles
+    }
+
+    pub fn pattern_count(&self) -> usize {
+        self.patterns.len()
+    }
+
+    // `path` is relative to the root, `/`-separated; a leading slash is ignored
+    pub fn hides(&self, path: &str) -> bool {
+        if !self.is_active() {
+            return false;
+        }
+        // Resolved first, or `pub/../secret` would slip past an anchored `secret`
+        let mut components: Vec<&str> = Vec::new();
+        for component in path.split('/') {
+            match component {
+                "" | "." => {}
+                ".." => {
+                    components.pop();
+                }
+                component => components.push(component),
+            }
+        }
+        if self.dotfiles && components.iter().any(|component| component.starts_with('.')) {
+            return true;
+        }
+        self.patterns.iter().any(|pattern| {
+            if pattern.contains('/') {
+                // Hiding a directory hides what's in it
+                (1..=components.len()).any(|end| glob_match(pattern.as_bytes(), components[..end].join("/").as_bytes()))
+            } else {
+                components.iter().any(|component| glob_match(pattern.as_bytes(), component.as_bytes()))
+            }
+        })
+    }
+
+    // An entry of the listed directory `dir`, which is also relative to the root
+    pub fn hides_in(&self, dir: &str, name: &str) -> bool {
+        self.is_active() && self.hides(&format!("{}/{}", dir, name))
+    }
+}
+
+// `*` and `?` stay within one path component; `**` crosses them
+fn glob_match(pattern: &[u8], text: &[u8]) -> bool {
+    match pattern {
+        [] => text.is_empty(),
+        [b'*', b'*', rest @ ..] => {
+            let rest = rest.strip_prefix(b"/").unwrap_or(rest);
+            (0..=text.len()).any(|skip| glob_match(rest, &text[skip..]))
+        }
+        [b'*', rest @ ..] => {
+            let component = text.iter().position(|&c| c == b'/').unwrap_or(text.len());
+            (0..=component).any(|skip| glob_match(rest, &text[skip..]))
+        }
+        [b'?', rest @ ..] => matches!(text, [c, tail @ ..] if *c != b'/' && glob_match(rest, tail)),
+        [c, rest @ ..] => matches!(text, [t, tail @ ..] if t == c && glob_match(rest, tail)),
+    }
+}
@@ -0,0 +1,570 @@
+use std::io::{Read, Write};
+
+use flate2::{read::ZlibDecoder, write::ZlibEncoder, Compression, Crc};
+
+// Just enough image decoding for thumbnails: baseline JPEG and non-interlaced
+// PNG, scaled down with a box filter. Anything else, progressive JPEGs
+// included, is None and left to the browser to scale.
+pub struct Image {
+    pub width: usize,
+    pub height: usize,
+    // RGBA, row by row
+    pub pixels: Vec<[u8; 4]>,
+}
+
+// Bigger than any photo, and slow to scale
+const MAX_DIMENSION: usize = 1 << 16;
+
+// Scaled to fit `size` by `size`. None for a PNG that already fits, and for
+// a JPEG whose eighth-size image is under half of `size`: such a thumbnail
+// would look worse than the original, which can't be very big then.
+pub fn thumbnail(data: &[u8], size: usize) -> Option<Image> {
+    if data.starts_with(b"\x89PNG\r\n\x1a\n") {
+        return decode_png(data, size);
+    }
+    if data.starts_with(&[0xff, 0xd8]) {
+        return decode_jpeg(data, size, size / 2);
+    }
+    None
+}
+
+pub fn encode_png(image: &Image) -> Vec<u8> {
+    let mut raw = Vec::with_capacity(image.height * (image.width * 4 + 1));
+    for row in image.pixels.chunks(image.width) {
+        raw.push(0);
+        raw.extend(row.iter().flatten());
+    }
+    let mut encoder = ZlibEncoder::new(Vec::new(), Compression::default());
+    let _ = encoder.write_all(&raw);
+    let compressed = encoder.finish().unwrap_or_default();
+
+    let mut header = Vec::new();
+    header.extend_from_slice(&(image.width as u32).to_be_bytes());
+    header.extend_from_slice(&(image.height as u32).to_be_bytes());
+    // 8 bits per channel, RGBA, no interlacing
+    header.extend_from_slice(&[8, 6, 0, 0, 0]);
+
+    let mut png = b"\x89PNG\r\n\x1a\n".to_vec();
+    for (kind, data) in [(b"IHDR", &header), (b"IDAT", &compressed), (b"IEND", &Vec::new())] {
+        png.extend_from_slice(&(data.len() as u32).to_be_bytes());
+        png.extend_from_slice(kind);
+        png.extend_from_slice(data);
+        let mut crc = Crc::new();
+        crc.update(kind);
+        crc.update(data);
+        png.extend_from_slice(&crc.sum().to_be_bytes());
+    }
+    png
+}
+
+// Averages every source pixel into the target one it lands on, taking rows
+// as they're decoded so the full-size image never has to be held
+struct Scaler {
+    width: usize,
+    height: usize,
+    target_width: usize,
+    target_height: usize,
+    sums: Vec<[u64; 4]>,
+    counts: Vec<u64>,
+}
+
+impl Scaler {
+    fn new(width: usize, height: usize, size: usize) -> Scaler {
+        let longer = width.max(height);
+        let (target_width, target_height) = match longer > size {
+            true => ((width * size / longer).max(1), (height * size / longer).max(1)),
+            false => (width, height),
+        };
+        let pixels = target_width * target_height;
+        Scaler { width, height, target_width, target_height, sums: vec![[0; 4]; pixels], counts: vec![0; pixels] }
+    }
+
+    fn add_row(&mut self, y: usize, row: impl Iterator<Item = [u8; 4]>) {
+        let target_row = y * self.target_height / self.height * self.target_width;
+        for (x, pixel) in row.take(self.width).enumerate() {
+            let target = target_row + x * self.target_width / self.width;
+            for (sum, channel) in self.sums[target].iter_mut().zip(pixel) {
+                *sum += channel as u64;
+            }
+            self.counts[target] += 1;
+        }
+    }
+
+    fn finish(self) -> Image {
+        let pixels = self
+            .sums
+            .iter()
+            .zip(&self.counts)
+            .map(|(sums, &count)| sums.map(|sum| (sum / count.max(1)) as u8))
+            .collect();
+        Image { width: self.target_width, height: self.target_height, pixels }
+    }
+}
+
+fn decode_png(data: &[u8], size: usize) -> Option<Image> {
+    let mut header = None;
+    let mut palette: Vec<[u8; 4]> = Vec::new();
+    let mut compressed = Vec::new();
+    let mut at = 8;
+    while at + 8 <= data.len() {
+        let length = u32::from_be_bytes(data[at..at + 4].try_into().ok()?) as usize;
+        let kind = &data[at + 4..at + 8];
+        let chunk = data.get(at + 8..at + 8 + length)?;
+        match kind {
+            b"IHDR" if chunk.len() == 13 => header = Some(chunk.to_vec()),
+            b"PLTE" => palette = chunk.chunks_exact(3).map(|rgb| [rgb[0], rgb[1], rgb[2], 255]).collect(),
+            b"tRNS" => {
+                for (entry, &alpha) in palette.iter_mut().zip(chunk) {
+                    entry[3] = alpha;
+                }
+            }
+            b"IDAT" => compressed.extend_from_slice(chunk),
+            b"IEND" => break,
+            _ => {}
+        }
+        at += length + 12;
+    }
+
+    let header = header?;
+    let width = u32::from_be_bytes(header[0..4].try_into().ok()?) as usize;
+    let height = u32::from_be_bytes(header[4..8].try_into().ok()?) as usize;
+    let (depth, color, interlace) = (header[8] as usize, header[9], header[12]);
+    let channels = match (color, depth) {
+        (0, 1 | 2 | 4 | 8 | 16) => 1,
+        (3, 1 | 2 | 4 | 8) => 1,
+        (2, 8 | 16) => 3,
+        (4, 8 | 16) => 2,
+        (6, 8 | 16) => 4,
+        _ => return None,
+    };
+    if interlace != 0 || width == 0 || height == 0 || width > MAX_DIMENSION || height > MAX_DIMENSION {
+        return None;
+    }
+    if width.max(height) <= size {
+        return None;
+    }
+
+    let bits = channels * depth;
+    let stride = (width * bits).div_ceil(8);
+    // Filters look back a whole pixel, or a byte for pixels smaller than one
+    let step = bits.div_ceil(8);
+    let mut decoder = ZlibDecoder::new(&compressed[..]);
+    let mut scaler = Scaler::new(width, height, size);
+    let mut previous = vec![0; stride];
+    let mut row = vec![0; stride];
+    let mut filter = [0];
+    for y in 0..height {
+        decoder.read_exact(&mut filter).ok()?;
+        decoder.read_exact(&mut row).ok()?;
+        unfilter(filter[0], &mut row, &previous, step)?;
+
+        // Samples of 16 bits keep their high byte; smaller ones are widened
+        let sample = |row: &[u8], index: usize| -> u8 {
+            match depth {
+                16 => row[index * 2],
+                8 => row[index],
+                _ => {
+                    let bit = index * depth;
+                    let value = (row[bit / 8] >> (8 - depth - bit % 8)) & ((1 << depth) - 1) as u8;
+                    if color == 3 { value } else { value * (255 / ((1 << depth) - 1)) as u8 }
+                }
+            }
+        };
+        let pixels = (0..width).map(|x| match color {
+            0 => {
+                let gray = sample(&row, x);
+                [gray, gray, gray, 255]
+            }
+            2 => [sample(&row, x * 3), sample(&row, x * 3 + 1), sample(&row, x * 3 + 2), 255],
+            3 => palette.get(sample(&row, x) as usize).copied().unwrap_or([0, 0, 0, 255]),
+            4 => {
+                let gray = sample(&row, x * 2);
+                [gray, gray, gray, sample(&row, x * 2 + 1)]
+            }
+            _ => [sample(&row, x * 4), sample(&row, x * 4 + 1), sample(&row, x * 4 + 2), sample(&row, x * 4 + 3)],
+        });
+        scaler.add_row(y, pixels);
+        std::mem::swap(&mut previous, &mut row);
+    }
+    Some(scaler.finish())
+}
+
+fn unfilter(filter: u8, row: &mut [u8], previous: &[u8], step: usize) -> Option<()> {
+    for i in 0..row.len() {
+        let left = if i >= step { row[i - step] } else { 0 };
+        let up = previous[i];
+        let upper_left = if i >= step { previous[i - step] } else { 0 };
+        row[i] = row[i].wrapping_add(match filter {
+            0 => 0,
+            1 => left,
+            2 => up,
+            3 => ((left as u16 + up as u16) / 2) as u8,
+            4 => paeth(left, up, upper_left),
+            _ => return None,
+        });
+    }
+    Some(())
+}
+
+fn paeth(left: u8, up: u8, upper_left: u8) -> u8 {
+    let estimate = left as i16 + up as i16 - upper_left as i16;
+    let (a, b, c) = ((estimate - left as i16).abs(), (estimate - up as i16).abs(), (estimate - upper_left as i16).abs());
+    if a <= b && a <= c {
+        left
+    } else if b <= c {
+        up
+    } else {
+        upper_left
+    }
+}
+
+struct Component {
+    id: u8,
+    horizontal: usize,
+    vertical: usize,
+    table: usize,
+    dc_table: usize,
+    ac_table: usize,
+    // One pixel per 8x8 block: its average, which is all the DC coefficient says
+    plane: Vec<u8>,
+    blocks_wide: usize,
+}
+
+#[derive(Clone, Default)]
+struct Huffman {
+    values: Vec<u8>,
+    min_code: [i32; 17],
+    max_code: [i32; 17],
+    offsets: [usize; 17],
+}
+
+impl Huffman {
+    fn new(counts: &[u8], values: &[u8]) -> Huffman {
+        let mut table = Huffman { values: values.to_vec(), max_code: [-1; 17], ..Default::default() };
+        let (mut code, mut index) = (0, 0);
+        for length in 1..=16 {
+            table.min_code[length] = code;
+            table.offsets[length] = index;
+            let count = counts[length - 1] as i32;
+            code += count;
+            index += count as usize;
+            if count > 0 {
+                table.max_code[length] = code - 1;
+            }
+            code <<= 1;
+        }
+        table
+    }
+
+    fn decode(&self, bits: &mut Bits) -> Option<u8> {
+        let mut code = bits.bit() as i32;
+        for length in 1..=16 {
+            if code <= self.max_code[length] {
+                return self.values.get(self.offsets[length] + (code - self.min_code[length]) as usize).copied();
+            }
+            code = code << 1 | bits.bit() as i32;
+        }
+        None
+    }
+}
+
+// The entropy-coded data, with stuffed zero bytes dropped; a marker reads as zeros
+struct Bits<'a> {
+    data: &'a [u8],
+    at: usize,
+    byte: u8,
+    left: u32,
+}
+
+impl Bits<'_> {
+    fn bit(&mut self) -> u32 {
+        if self.left == 0 {
+            self.byte = match (self.data.get(self.at), self.data.get(self.at + 1)) {
+                (Some(0xff), Some(0x00)) => {
+                    self.at += 2;
+                    0xff
+                }
+                // The end of the data or of a restart interval, which restart() skips
+                (Some(0xff), _) | (None, _) => 0,
+                (Some(&byte), _) => {
+                    self.at += 1;
+                    byte
+                }
+            };
+            self.left = 8;
+        }
+        self.left -= 1;
+        (self.byte >> self.left) as u32 & 1
+    }
+
+    fn bits(&mut self, count: u8) -> i32 {
+        (0..count).fold(0, |value, _| value << 1 | self.bit() as i32)
+    }
+
+    // Past the RSTn marker that ends a restart interval
+    fn restart(&mut self) {
+        self.left = 0;
+        while self.at + 1 < self.data.len() && !(self.data[self.at] == 0xff && (0xd0..=0xd7).contains(&self.data[self.at + 1])) {
+            self.at += 1;
+        }
+        self.at += 2;
+    }
+}
+
+// A signed coefficient from its category and the bits that follow
+fn extend(value: i32, category: u8) -> i32 {
+    if category == 0 {
+        0
+    } else if value < 1 << (category - 1) {
+        value - (1 << category) + 1
+    } else {
+        value
+    }
+}
+
+// Only the DC coefficients are kept, which gives the image at an eighth of
+// its size without any inverse DCTs; the AC ones are decoded just to skip them
+fn decode_jpeg(data: &[u8], size: usize, min_size: usize) -> Option<Image> {
+    let mut quantization = [0u16; 4];
+    let mut dc_tables = vec![Huffman::default(); 4];
+    let mut ac_tables = vec![Huffman::default(); 4];
+    let mut components: Vec<Component> = Vec::new();
+    let (mut width, mut height, mut restart_interval, mut orientation) = (0, 0, 0, 1);
+
+    let mut at = 2;
+    loop {
+        while data.get(at) == Some(&0xff) && data.get(at + 1) == Some(&0xff) {
+            at += 1;
+        }
+        if data.get(at) != Some(&0xff) {
+            return None;
+        }
+        let marker = *data.get(at + 1)?;
+        let length = u16::from_be_bytes([*data.get(at + 2)?, *data.get(at + 3)?]) as usize;
+        let segment = data.get(at + 4..at + 2 + length)?;
+        at += 2 + length;
+        match marker {
+            // Baseline and extended sequential; progressive and the rest aren't handled
+            0xc0 | 0xc1 => {
+                if segment.len() < 6 || segment[0] != 8 {
+                    return None;
+                }
+                height = u16::from_be_bytes([segment[1], segment[2]]) as usize;
+                width = u16::from_be_bytes([segment[3], segment[4]]) as usize;
+                for component in segment[6..].chunks_exact(3).take(segment[5] as usize) {
+                    components.push(Component {
+                        id: component[0],
+                        horizontal: (component[1] >> 4).max(1) as usize,
+                        vertical: (component[1] & 15).max(1) as usize,
+                        table: (component[2] & 3) as usize,
+                        dc_table: 0,
+                        ac_table: 0,
+                        plane: Vec::new(),
+                        blocks_wide: 0,
+                    });
+                }
+            }
+            0xc2..=0xcf if ![0xc4, 0xc8, 0xcc].contains(&marker) => return None,
+            0xc4 => {
+                let mut rest = segment;
+                while rest.len() >= 17 {
+                    let (class, id) = (rest[0] >> 4, (rest[0] & 3) as usize);
+                    let counts = &rest[1..17];
+                    let total: usize = counts.iter().map(|&count| count as usize).sum();
+                    let values = rest.get(17..17 + total)?;
+                    let table = Huffman::new(counts, values);
+                    if class == 0 { dc_tables[id] = table } else { ac_tables[id] = table }
+                    rest = &rest[17 + total..];
+                }
+            }
+            0xdb => {
+                let mut rest = segment;
+                while !rest.is_empty() {
+                    let (precision, id) = (rest[0] >> 4, (rest[0] & 3) as usize);
+                    // Only the first value, the DC one, matters here
+                    let (first, length) = match precision {
+                        0 => (*rest.get(1)? as u16, 65),
+                        _ => (u16::from_be_bytes([*rest.get(1)?, *rest.get(2)?]), 129),
+                    };
+                    quantization[id] = first;
+                    rest = rest.get(length..)?;
+                }
+            }
+            0xdd if segment.len() >= 2 => restart_interval = u16::from_be_bytes([segment[0], segment[1]]) as usize,
+            0xe1 if segment.starts_with(b"Exif\0\0") => orientation = exif_orientation(&segment[6..]).unwrap_or(1),
+            0xda => {
+                let scanned = *segment.first()? as usize;
+                // Gray or YCbCr, all in the one scan
+                if !matches!(components.len(), 1 | 3) || scanned != components.len() {
+                    return None;
+                }
+                for selector in segment[1..].chunks_exact(2).take(scanned) {
+                    let component = components.iter_mut().find(|component| component.id == selector[0])?;
+                    component.dc_table = (selector[1] >> 4 & 3) as usize;
+                    component.ac_table = (selector[1] & 3) as usize;
+                }
+                if width.div_ceil(8).max(height.div_ceil(8)) < min_size {
+                    return None;
+                }
+                let scan = &data[at..];
+                break decode_scan(scan, &mut components, &dc_tables, &ac_tables, &quantization, width, height, restart_interval)
+                    .and_then(|_| dc_image(&components, width, height, size))
+                    .map(|image| orient(image, orientation));
+            }
+            0xd9 => return None,
+            _ => {}
+        }
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
+fn decode_scan(
+    scan: &[u8],
+    components: &mut [Component],
+    dc_tables: &[Huffman],
+    ac_tables: &[Huffman],
+    quantization: &[u16; 4],
+    width: usize,
+    height: usize,
+    restart_interval: usize,
+) -> Option<()> {
+    if width == 0 || height == 0 {
+        return None;
+    }
+    let max_horizontal = components.iter().map(|component| component.horizontal).max()?;
+    let max_vertical = components.iter().map(|component| component.vertical).max()?;
+    // A lone component is coded block by block, not in MCUs of the sampling factors
+    let single = components.len() == 1;
+    if single {
+        components[0].horizontal = 1;
+        components[0].vertical = 1;
+    }
+    let (mcus_wide, mcus_high) = match single {
+        true => (width.div_ceil(8), height.div_ceil(8)),
+        false => (width.div_ceil(8 * max_horizontal), height.div_ceil(8 * max_vertical)),
+    };
+    for component in components.iter_mut() {
+        component.blocks_wide = mcus_wide * component.horizontal;
+        component.plane = vec![0; component.blocks_wide * mcus_high * component.vertical];
+    }
+
+    let mut bits = Bits { data: scan, at: 0, byte: 0, left: 0 };
+    let mut predictions = vec![0i32; components.len()];
+    for mcu in 0..mcus_wide * mcus_high {
+        if restart_interval > 0 && mcu > 0 && mcu % restart_interval == 0 {
+            bits.restart();
+            predictions.iter_mut().for_each(|prediction| *prediction = 0);
+        }
+        let (mcu_x, mcu_y) = (mcu % mcus_wide, mcu / mcus_wide);
+        for (component, prediction) in components.iter_mut().zip(&mut predictions) {
+            for block in 0..component.horizontal * component.vertical {
+                let category = dc_tables[component.dc_table].decode(&mut bits)?;
+                let value = bits.bits(category);
+                *prediction += extend(value, category);
+                let mut coefficient = 1;
+                while coefficient < 64 {
+                    let symbol = ac_tables[component.ac_table].decode(&mut bits)?;
+                    let (run, category) = (symbol >> 4, symbol & 15);
+                    if category == 0 {
+                        if run != 15 {
+                            break;
+                        }
+                        coefficient += 16;
+                        continue;
+                    }
+                    bits.bits(category);
+                    coefficient += run as usize + 1;
+                }
+                let x = mcu_x * component.horizontal + block % component.horizontal;
+                let y = mcu_y * component.vertical + block / component.horizontal;
+                let average = *prediction * quantization[component.table] as i32 / 8 + 128;
+                component.plane[y * component.blocks_wide + x] = average.clamp(0, 255) as u8;
+            }
+        }
+    }
+    if single {
+        components[0].horizontal = max_horizontal;
+        components[0].vertical = max_vertical;
+    }
+    Some(())
+}
+
+fn dc_image(components: &[Component], width: usize, height: usize, size: usize) -> Option<Image> {
+    let (wide, high) = (width.div_ceil(8), height.div_ceil(8));
+    let max_horizontal = components.iter().map(|component| component.horizontal).max()?;
+    let max_vertical = components.iter().map(|component| component.vertical).max()?;
+    let sample = |component: &Component, x: usize, y: usize| -> f32 {
+        let x = x * component.horizontal / max_horizontal;
+        let y = y * component.vertical / max_vertical;
+        component.plane.get(y * component.blocks_wide + x).copied().unwrap_or(0) as f32
+    };
+
+    let mut scaler = Scaler::new(wide, high, size);
+    for y in 0..high {
+        let row = (0..wide).map(|x| match components {
+            [gray] => {
+                let gray = sample(gray, x, y) as u8;
+                [gray, gray, gray, 255]
+            }
+            // YCbCr, as JFIF has it
+            [luma, blue, red] => {
+                let (luma, blue, red) = (sample(luma, x, y), sample(blue, x, y) - 128.0, sample(red, x, y) - 128.0);
+                let channel = |value: f32| value.round().clamp(0.0, 255.0) as u8;
+                [channel(luma + 1.402 * red), channel(luma - 0.344136 * blue - 0.714136 * red), channel(luma + 1.772 * blue), 255]
+            }
+            _ => [0, 0, 0, 255],
+        });
+        scaler.add_row(y, row);
+    }
+    Some(scaler.finish())
+}
+
+// The orientation tag from an Exif block's first directory: cameras store
+// pixels as the sensor saw them and say how to turn them upright
+fn exif_orientation(tiff: &[u8]) -> Option<u16> {
+    let little = match tiff.get(..2)? {
+        b"II" => true,
+        b"MM" => false,
+        _ => return None,
+    };
+    let read16 = |at: usize| -> Option<u16> {
+        let bytes = [*tiff.get(at)?, *tiff.get(at + 1)?];
+        Some(if little { u16::from_le_bytes(bytes) } else { u16::from_be_bytes(bytes) })
+    };
+    let read32 = |at: usize| -> Option<u32> {
+        let bytes: [u8; 4] = tiff.get(at..at + 4)?.try_into().ok()?;
+        Some(if little { u32::from_le_bytes(bytes) } else { u32::from_be_bytes(bytes) })
+    };
+    let directory = read32(4)? as usize;
+    for entry in 0..read16(directory)? as usize {
+        let at = directory + 2 + entry * 12;
+        if read16(at)? == 0x0112 {
+            return read16(at + 8).filter(|orientation| (1..=8).contains(orientation));
+        }
+    }
+    None
+}
+
+fn orient(image: Image, orientation: u16) -> Image {
+    if orientation == 1 {
+        return image;
+    }
+    let (width, height) = (image.width, image.height);
+    let (new_width, new_height) = if orientation >= 5 { (height, width) } else { (width, height) };
+    let mut pixels = Vec::with_capacity(image.pixels.len());
+    for y in 0..new_height {
+        for x in 0..new_width {
+            let (source_x, source_y) = match orientation {
+                2 => (width - 1 - x, y),
+                3 => (width - 1 - x, height - 1 - y),
+                4 => (x, height - 1 - y),
+                5 => (y, x),
+                6 => (y, height - 1 - x),
+                7 => (width - 1 - y, height - 1 - x),
+                _ => (width - 1 - y, x),
+            };
+            pixels.push(image.pixels[source_y * width + source_x]);
+        }
+    }
+    Image { width: new_width, height: new_height, pixels }
+}
@@ -0,0 +1,106 @@
+use std::{
+    fs::OpenOptions,
+    io::{self, Write},
+    path::PathBuf,
+};
+
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use ring::rand::{SecureRandom, SystemRandom};
+
+use crate::config;
+
+const TEMPLATE: &str = r#"# Settings for bounty, read from the working directory or with --config FILE.
+# Every key is the flag of the same name; flags given on the command line win
+# over the file, and --no-compress and the like turn off switches set here.
+# Relative paths are relative to this file. Uncomment to enable.
+
+root = "."
+# bind = ["127.0.0.1"]
+port = 8000
+# threads = 32
+# shutdown-timeout = 10
+
+[listing]
+# exact = true
+# render-markdown = true
+# spa = true
+
+[headers]
+# cache-control = "max-age=60"
+# cors = "*"
+# secure-headers = true
+
+[features]
+# compress = true
+@UPLOAD@# watch = true
+# metrics = true
+# hide-dotfiles = true
+# bountyignore = true
+
+[limits]
+# max-body-size = "100M"
+# rate-limit = 10.0
+# max-connections-per-ip = 16
+
+[auth]
+@AUTH@# auth-lockout = 5
+
+[log]
+# access-log = "access.log"
+# log-format = "json"
+
+[tls]
+@TLS@"#;
+
+// `bounty init [--with-tls] [--with-auth] [--with-uploads] [FILE]`: writes a
+// commented bounty.toml to start from, with those features switched on.
+// An existing file is left alone.
+pub fn run(command_line: impl Iterator<Item = String>) -> io::Result<()> {
+    let (mut tls, mut auth, mut uploads) = (false, false, false);
+    let mut path = None;
+    for arg in command_line {
+        match arg.as_str() {
+            "--with-tls" => tls = true,
+            "--with-auth" => auth = true,
+            "--with-uploads" => uploads = true,
+            flag if flag.starts_with("--") => {
+                return Err(io::Error::new(io::ErrorKind::InvalidInput, format!("init doesn't know {}", flag)));
+            }
+            _ if path.is_some() => {
+                return Err(io::Error::new(io::ErrorKind::InvalidInput, format!("init takes one file, not also {}", arg)));
+            }
+            _ => path = Some(PathBuf::from(arg)),
+        }
+    }
+    let path = path.unwrap_or_else(|| PathBuf::from(config::DEFAULT_PATH));
+
+    let password = auth.then(generate_password).transpose()?;
+    let text = TEMPLATE
+        .replace("@UPLOAD@", if uploads { "allow-upload = true\n" } else { "# allow-upload = true\n" })
+        .replace("@AUTH@", &match &password {
+            Some(password) => format!("auth = [\"admin:{}\"]\n", password),
+            None => "# auth = [\"admin:change-me\"]\n".to_string(),
+        })
+        .replace("@TLS@", if tls { "cert = \"cert.pem\"\nkey = \"key.pem\"\n" } else { "# cert = \"cert.pem\"\n# key = \"key.pem\"\n" });
+
+    let mut file = OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .open(&path)
+        .map_err(|e| io::Error::new(e.kind(), format!("{}: {}", path.display(), e)))?;
+    file.write_all(text.as_bytes())?;
+    println!("Wrote {}", path.display());
+    if let Some(password) = &password {
+        println!("Log in as admin with password {}", password);
+    }
+    if tls {
+        println!("Put the certificate and key beside it as cert.pem and key.pem");
+    }
+    Ok(())
+}
+
+fn generate_password() -> io::Result<String> {
+    let mut bytes = [0; 12];
+    SystemRandom::new().fill(&mut bytes).map_err(|_| io::Error::other("no randomness for a password"))?;
+    Ok(URL_SAFE_NO_PAD.encode(bytes))
+}
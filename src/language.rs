@@ -0,0 +1,93 @@
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+// Looks for `page.html.<lang>` siblings of a missing `page.html` and picks the one
+// best matching Accept-Language, then the default language, then the first by name
+pub fn find_variant(
+    path: &Path,
+    accept_language: Option<&str>,
+    default_language: Option<&str>,
+) -> Option<(PathBuf, String)> {
+    let file_name = path.file_name()?.to_str()?;
+    let mut variants: Vec<(PathBuf, String)> = fs::read_dir(path.parent()?)
+        .ok()?
+        .filter_map(|entry| {
+            let entry = entry.ok()?;
+            let name = entry.file_name().into_string().ok()?;
+            let language = name.strip_prefix(file_name)?.strip_prefix('.')?;
+            if is_language_tag(language) && entry.path().is_file() {
+                Some((entry.path(), language.to_ascii_lowercase()))
+            } else {
+                None
+            }
+        })
+        .collect();
+
+    if variants.is_empty() {
+        return None;
+    }
+    variants.sort();
+
+    let ranges = accept_language.map(parse_accept_language).unwrap_or_default();
+    let chosen = ranges
+        .iter()
+        .find_map(|range| best_match(&variants, range))
+        .or_else(|| default_language.and_then(|language| best_match(&variants, &language.to_ascii_lowercase())))
+        .unwrap_or(0);
+
+    Some(variants.swap_remove(chosen))
+}
+
+// Exact tag first, then `en` matching `en-gb`, then `en-us` falling back to `en`
+fn best_match(variants: &[(PathBuf, String)], range: &str) -> Option<usize> {
+    // `*` adds nothing over the default-then-first fallback
+    if range == "*" {
+        return None;
+    }
+
+    let position = |matches: &dyn Fn(&str) -> bool| variants.iter().position(|(_, language)| matches(language));
+    position(&|language| language == range)
+        .or_else(|| position(&|language| is_prefix_tag(range, language)))
+        .or_else(|| position(&|language| is_prefix_tag(language, range)))
+}
+
+fn is_prefix_tag(prefix: &str, tag: &str) -> bool {
+    tag.strip_prefix(prefix).is_some_and(|rest| rest.starts_with('-'))
+}
+
+// Ranges ordered by descending quality; q=0 means "not acceptable" and is dropped
+fn parse_accept_language(header: &str) -> Vec<String> {
+    let mut ranges: Vec<(String, f32)> = header
+        .split(',')
+        .filter_map(|item| {
+            let mut parts = item.split(';');
+            let range = parts.next()?.trim().to_ascii_lowercase();
+            let quality = parts
+                .filter_map(|param| param.trim().strip_prefix("q="))
+                .find_map(|q| q.parse().ok())
+                .unwrap_or(1.0);
+            (!range.is_empty() && quality > 0.0).then_some((range, quality))
+        })
+        .collect();
+
+    // Stable, so equal qualities keep the client's order
+    ranges.sort_by(|a, b| b.1.total_cmp(&a.1));
+    ranges.into_iter().map(|(range, _)| range).collect()
+}
+
+// Common suffixes that are syntactically valid tags but never mean a language here
+const NOT_LANGUAGES: &[&str] = &["gz", "br", "zst", "bak", "old", "orig", "tmp"];
+
+fn is_language_tag(tag: &str) -> bool {
+    if NOT_LANGUAGES.contains(&tag) {
+        return false;
+    }
+
+    let mut subtags = tag.split('-');
+    let primary = subtags.next().unwrap_or("");
+    (1..=8).contains(&primary.len())
+        && primary.chars().all(|c| c.is_ascii_alphabetic())
+        && subtags.all(|subtag| (1..=8).contains(&subtag.len()) && subtag.chars().all(|c| c.is_ascii_alphanumeric()))
+}
@@ -0,0 +1,138 @@
+use std::{
+    io::{self, Read, Write},
+    net::TcpStream,
+    path::PathBuf,
+};
+
+use crate::{
+    args::Args,
+    der::{self, BOOLEAN, ENUMERATED, INTEGER, OCTET_STRING, SEQUENCE},
+    is_account_name, proxy,
+    users::User,
+};
+
+const BIND_REQUEST: u8 = 0x60;
+const BIND_RESPONSE: u8 = 0x61;
+const UNBIND_REQUEST: u8 = 0x42;
+const SEARCH_REQUEST: u8 = 0x63;
+const SEARCH_RESULT_ENTRY: u8 = 0x64;
+const SEARCH_RESULT_DONE: u8 = 0x65;
+// Context tags: a simple bind's password, and an equalityMatch filter
+const SIMPLE: u8 = 0x80;
+const EQUALITY_MATCH: u8 = 0xa3;
+const SUCCESS: u8 = 0;
+// Far more than a bind response or a search entry without attributes needs
+const MAX_MESSAGE: usize = 64 * 1024;
+
+// `--ldap ldap://host[:port]`: accounts are checked by binding to the
+// directory as `--ldap-dn` with the user's name in it, so the directory's
+// own password policy applies. Members of `--ldap-write-group` (by its
+// `member` attribute, as groupOfNames and Active Directory have it) can
+// write; everyone else is read-only. Each gets the `--ldap-root` pattern's
+// directory, or the served root without one.
+pub fn login(args: &Args, server: &str, name: &str, password: &str) -> Option<User> {
+    // An empty password is an anonymous bind, which most directories allow
+    if !is_account_name(name) || password.is_empty() {
+        return None;
+    }
+    let root = match &args.ldap_root {
+        Some(pattern) => PathBuf::from(pattern.replacen('*', name, 1)).canonicalize().ok()?,
+        None => args.root.clone(),
+    };
+    if !root.is_dir() {
+        return None;
+    }
+    let dn = args.ldap_dn.replacen('*', name, 1);
+
+    let mut connection = proxy::connect(server).map_err(|e| eprintln!("LDAP server {}: {}", server, e)).ok()?;
+    let can_write = match check(&mut connection, &dn, password, args.ldap_write_group.as_deref()) {
+        Ok(Some(can_write)) => can_write,
+        Ok(None) => return None,
+        Err(e) => {
+            eprintln!("LDAP server {}: {}", server, e);
+            return None;
+        }
+    };
+    let _ = connection.write_all(&message(3, UNBIND_REQUEST, &[]));
+    Some(User { name: name.to_string(), root, can_write, quota: None })
+}
+
+// None if the bind fails, or else whether the account is in `write_group`
+fn check(connection: &mut TcpStream, dn: &str, password: &str, write_group: Option<&str>) -> io::Result<Option<bool>> {
+    let bind = [der::encode(INTEGER, &[3]), der::encode(OCTET_STRING, dn.as_bytes()), der::encode(SIMPLE, password.as_bytes())];
+    connection.write_all(&message(1, BIND_REQUEST, &bind.concat()))?;
+    let (tag, result) = read_response(connection)?;
+    if tag != BIND_RESPONSE || result != Some(SUCCESS) {
+        return Ok(None);
+    }
+    let group = match write_group {
+        Some(group) => group,
+        None => return Ok(Some(false)),
+    };
+
+    // The group itself, if it has the account as a member
+    let filter = [der::encode(OCTET_STRING, b"member"), der::encode(OCTET_STRING, dn.as_bytes())].concat();
+    let search = [
+        der::encode(OCTET_STRING, group.as_bytes()),
+        // baseObject, neverDerefAliases, no size limit, a 10 second time limit
+        der::encode(ENUMERATED, &[0]),
+        der::encode(ENUMERATED, &[0]),
+        der::encode(INTEGER, &[0]),
+        der::encode(INTEGER, &[10]),
+        der::encode(BOOLEAN, &[0]),
+        der::encode(EQUALITY_MATCH, &filter),
+        // No attributes, just whether there's an entry
+        der::encode(SEQUENCE, &der::encode(OCTET_STRING, b"1.1")),
+    ];
+    connection.write_all(&message(2, SEARCH_REQUEST, &search.concat()))?;
+    let mut found = false;
+    loop {
+        match read_response(connection)? {
+            (SEARCH_RESULT_ENTRY, _) => found = true,
+            (SEARCH_RESULT_DONE, result) => return Ok(Some(found && result == Some(SUCCESS))),
+            // Referrals, which aren't followed
+            _ => {}
+        }
+    }
+}
+
+fn message(id: u8, tag: u8, contents: &[u8]) -> Vec<u8> {
+    der::encode(SEQUENCE, &[der::encode(INTEGER, &[id]), der::encode(tag, contents)].concat())
+}
+
+// The protocol op's tag, and its result code if it has one
+fn read_response(connection: &mut TcpStream) -> io::Result<(u8, Option<u8>)> {
+    let message = read_message(connection)?;
+    let invalid = || io::Error::new(io::ErrorKind::InvalidData, "malformed response");
+    let envelope = der::read(&mut &message[..]).filter(|element| element.tag == SEQUENCE).ok_or_else(invalid)?;
+    let mut parts = der::elements(envelope.contents);
+    parts.next().filter(|id| id.tag == INTEGER).ok_or_else(invalid)?;
+    let op = parts.next().ok_or_else(invalid)?;
+    let result = der::elements(op.contents).next().filter(|code| code.tag == ENUMERATED && code.contents.len() == 1);
+    Ok((op.tag, result.map(|code| code.contents[0])))
+}
+
+// One whole LDAPMessage, header and all
+fn read_message(connection: &mut TcpStream) -> io::Result<Vec<u8>> {
+    let mut message = vec![0; 2];
+    connection.read_exact(&mut message)?;
+    let length = if message[1] < 0x80 {
+        message[1] as usize
+    } else {
+        let count = (message[1] & 0x7f) as usize;
+        if count == 0 || count > 4 {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "unsupported length"));
+        }
+        let mut bytes = vec![0; count];
+        connection.read_exact(&mut bytes)?;
+        message.extend_from_slice(&bytes);
+        bytes.iter().fold(0, |length, &byte| length << 8 | byte as usize)
+    };
+    if length > MAX_MESSAGE {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, "response too large"));
+    }
+    let start = message.len();
+    message.resize(start + length, 0);
+    connection.read_exact(&mut message[start..])?;
+    Ok(message)
+}
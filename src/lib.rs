@@ -0,0 +1,2038 @@
+use std::{
+    collections::HashMap,
+    fs,
+    io::{self, Cursor, Read, Seek, SeekFrom, Write},
+    mem,
+    net::{IpAddr, SocketAddr, TcpListener, TcpStream},
+    path::{Path, PathBuf},
+    str,
+    sync::{atomic::Ordering, mpsc, Arc, Mutex},
+    thread,
+    time::{Duration, Instant},
+};
+use walkdir::{DirEntry, WalkDir};
+use socket2::{Domain, Protocol, Socket, Type};
+use percent_encoding::{percent_decode, utf8_percent_encode, AsciiSet, NON_ALPHANUMERIC};
+
+mod access_log;
+mod activation;
+mod api;
+mod append;
+mod archive;
+pub mod args;
+mod assets;
+mod auth;
+mod banner;
+mod bundle;
+mod cache;
+mod check;
+mod chunked;
+mod compress;
+mod config;
+mod cors;
+mod debug;
+mod der;
+mod dlna;
+mod dry_run;
+mod error_page;
+mod export;
+mod firewall;
+mod gallery;
+mod git;
+mod hidden;
+mod image;
+mod init;
+mod language;
+mod ldap;
+mod listing;
+mod markdown;
+mod mdns;
+mod metrics;
+mod mime;
+mod ocsp;
+#[cfg(feature = "pam")]
+mod pam;
+mod paste;
+mod proxy;
+mod qr;
+mod search;
+mod share;
+mod shortlink;
+mod source;
+mod shutdown;
+mod stats;
+pub mod stream;
+mod supervisor;
+mod tarpit;
+mod tls;
+mod tor;
+mod upload;
+#[cfg(all(feature = "io-uring", target_os = "linux"))]
+mod uring;
+mod users;
+mod watch;
+mod webdav;
+
+use access_log::{AccessLog, Recorder};
+use args::Args;
+use auth::AuthFailures;
+use cache::{Caches, FileCache, FileInfo, StatCache};
+use chunked::{ChunkedWriter, Transfer};
+use firewall::{ConnectionLimiter, ConnectionSlot, RateLimiter};
+use compress::{Encoder, Encoding};
+use listing::{ListingEntry, ListingOptions};
+use metrics::Metrics;
+use rustls::ServerConfig;
+use stream::Stream;
+use share::{Share, ShareKey};
+use error_page::ErrorPages;
+use shutdown::{OpenConnections, ShutdownHandle, Totals};
+use tarpit::Tarpit;
+use watch::Watcher;
+
+// Everything the command line can ask for: the one-off modes, supervising
+// --processes children, or serving
+pub fn run(mut args: Args) -> io::Result<()> {
+    if let Some(out) = &args.bundle {
+        return bundle::create(&args, out);
+    }
+    // A bundled executable serves the tree it carries
+    if let Some(root) = bundle::unpack()? {
+        args.root = root.canonicalize()?;
+    }
+    if args.dry_run {
+        return dry_run::run(&args);
+    }
+    if let Some(out) = &args.export {
+        return export::run(&args, out);
+    }
+
+    // Published once per server, so by the supervisor rather than its children
+    let _onion_service = match (&args.tor_control, args.supervised) {
+        (Some(control), false) => Some(tor::publish(control, args.tor_password.as_deref(), args.tor_key.as_deref(), args.address())?),
+        _ => None,
+    };
+    shutdown::install();
+    if args.processes > 1 || args.supervised {
+        shutdown::install_reload();
+    }
+    let inherited = activation::inherited_listeners(&args)?;
+    // Children would each need a copy of the sockets, which they'd only get by accident
+    if !inherited.is_empty() && args.processes > 1 && !args.supervised {
+        return Err(io::Error::new(io::ErrorKind::InvalidInput, "inherited sockets can't be shared with --processes"));
+    }
+    let addresses = match inherited.is_empty() {
+        true => args.addresses(),
+        false => inherited.iter().map(TcpListener::local_addr).collect::<io::Result<Vec<_>>>()?,
+    };
+    // Like the onion service, advertised once by the supervisor
+    let _advertisement = match (args.mdns, args.supervised) {
+        (true, false) => Some(mdns::advertise(&args, &addresses)?),
+        _ => None,
+    };
+    let _media_server = match (args.dlna, args.supervised) {
+        (true, false) => Some(dlna::advertise(&args, &addresses)?),
+        _ => None,
+    };
+    // Children read the same config file, --processes and all
+    if args.processes > 1 && !args.supervised {
+        return supervisor::run(args.processes);
+    }
+
+    println!("Serving {:?}", args.root);
+    let mut server = Server::new(args)?;
+    server.shared.shutdown = ShutdownHandle::new(true);
+    let args = &server.args;
+
+    // Bound by systemd or whoever started us, so --bind, --port and --workers don't apply
+    if !inherited.is_empty() {
+        for address in &addresses {
+            println!("Listening on {}://{} (inherited)", args.scheme(), address);
+        }
+        println!("Serving with {} threads", args.threads);
+        banner::print(args, &addresses);
+        return server.serve_all(inherited);
+    }
+
+    // Each worker binds its own socket and the kernel spreads new connections
+    // across them, so there is no accept lock to contend on. Supervised
+    // children always share the port with their siblings.
+    let reuse_port = args.workers > 1 || args.supervised;
+    let workers = if reuse_port { args.workers } else { 1 };
+    // `[::]` alone takes IPv4 connections too, on systems that allow it; next
+    // to other addresses it mustn't, or it would clash with `0.0.0.0`
+    let only_v6 = addresses.len() > 1;
+    let mut listeners = Vec::new();
+    for address in &addresses {
+        for _ in 0..workers {
+            listeners.push(bind_listener(*address, reuse_port, only_v6)?);
+        }
+        let everywhere = if address.ip().is_unspecified() { " (all interfaces)" } else { "" };
+        println!("Listening on {}://{}{}", args.scheme(), address, everywhere);
+    }
+    match reuse_port {
+        true => println!("Serving with {} workers and {} threads", args.workers, args.threads),
+        false => println!("Serving with {} threads", args.threads),
+    }
+    banner::print(args, &addresses);
+    server.serve_all(listeners)
+}
+
+// `bounty check [FILE]`: whether a config file would serve, without serving it
+pub fn check(command_line: impl Iterator<Item = String>) -> io::Result<()> {
+    check::run(command_line)
+}
+
+// `bounty init [FILE]`: a commented config file to start from
+pub fn init(command_line: impl Iterator<Item = String>) -> io::Result<()> {
+    init::run(command_line)
+}
+
+// A server for embedding: it answers on whatever listeners or streams it's
+// given, and leaves signals, the one-off modes and binding to its caller.
+//
+//     let args = Args::from_flags(["./public".to_string(), "--compress".to_string()])?;
+//     Server::new(args)?.serve(TcpListener::bind("127.0.0.1:0")?)
+pub struct Server {
+    args: Args,
+    shared: Shared,
+}
+
+impl Server {
+    pub fn new(args: Args) -> io::Result<Server> {
+        let error_pages = ErrorPages::load(&args)?;
+        let tls = match (&args.tls_cert, &args.tls_key) {
+            (Some(cert), Some(key)) => Some(tls::load_config(cert, key, &args.tls)?),
+            _ => None,
+        };
+        let shared = Shared {
+            auth_failures: AuthFailures::new(&args)?,
+            access_log: AccessLog::new(&args)?,
+            rate_limiter: RateLimiter::new(&args),
+            connection_limiter: ConnectionLimiter::new(&args),
+            file_cache: FileCache::new(&args),
+            stats: StatCache::new(args.stat_cache_ttl),
+            share_key: args.share_key.as_deref().map(ShareKey::load).transpose()?,
+            tarpit: Tarpit::new(&args)?,
+            tls,
+            totals: Totals::new(),
+            shutdown: ShutdownHandle::new(false),
+            open_connections: OpenConnections::new(),
+            error_pages,
+            metrics: args.metrics.then(Metrics::new),
+            watcher: args.watch.then(|| Watcher::start(&args.root)),
+        };
+        if let Some(paste_dir) = &args.paste_dir {
+            paste::spawn_sweeper(args.root.join(paste_dir));
+        }
+        Ok(Server { args, shared })
+    }
+
+    pub fn args(&self) -> &Args {
+        &self.args
+    }
+
+    // Stops accepting and lets open connections finish, as Ctrl-C does for `run`
+    pub fn shutdown_handle(&self) -> ShutdownHandle {
+        self.shared.shutdown.clone()
+    }
+
+    // Returns once the listener stops accepting, failing if connections were
+    // still open at the --shutdown-timeout deadline
+    pub fn serve(&self, listener: TcpListener) -> io::Result<()> {
+        self.serve_all(vec![listener])
+    }
+
+    // One acceptor per listener, all feeding the same handler pool
+    pub fn serve_all(&self, listeners: Vec<TcpListener>) -> io::Result<()> {
+        serve(listeners, &self.args, &self.shared)
+    }
+
+    // Answers every request on one connection, over any transport, with caches
+    // of its own. TLS is the caller's to have terminated.
+    pub fn handle(&self, stream: Box<dyn Stream>) -> io::Result<()> {
+        let mut caches = Caches::new(&self.args, &self.shared.stats);
+        serve_connection(stream, &self.args, &mut caches, &self.shared)
+    }
+}
+
+// State all workers of a process share
+struct Shared {
+    auth_failures: AuthFailures,
+    access_log: Option<AccessLog>,
+    rate_limiter: Option<RateLimiter>,
+    connection_limiter: Option<ConnectionLimiter>,
+    // Small files' contents with --file-cache
+    file_cache: Option<FileCache>,
+    // Metadata every handler thread looks up and fills in
+    stats: StatCache,
+    // Set with --share-key, which enables share links
+    share_key: Option<ShareKey>,
+    tarpit: Option<Tarpit>,
+    // Set with --tls-cert, and then every connection is TLS
+    tls: Option<Arc<ServerConfig>>,
+    totals: Totals,
+    shutdown: ShutdownHandle,
+    open_connections: OpenConnections,
+    // --error-page's pages by status
+    error_pages: ErrorPages,
+    // Response counts and latencies with --metrics
+    metrics: Option<Metrics>,
+    // Bumped on every change under the root with --watch
+    watcher: Option<Arc<Watcher>>,
+}
+
+// How long an acceptor waits when the process has run out of descriptors,
+// for open connections to finish and give some back
+const ACCEPT_BACKOFF: Duration = Duration::from_millis(100);
+
+#[cfg(unix)]
+fn out_of_descriptors(error: &io::Error) -> bool {
+    matches!(error.raw_os_error(), Some(libc::EMFILE | libc::ENFILE))
+}
+
+#[cfg(not(unix))]
+fn out_of_descriptors(_error: &io::Error) -> bool {
+    false
+}
+
+// One thread accepts on each listener and hands connections to a pool of
+// `--threads` handlers, so a slow client only ties up its own thread. Caches
+// are per handler, but for file metadata: threads share only the arguments and
+// Shared, which holds that.
+fn serve(listeners: Vec<TcpListener>, args: &Args, shared: &Shared) -> io::Result<()> {
+    // Bounded, so once every handler is busy new connections wait in the kernel's backlog
+    let (sender, receiver) = mpsc::sync_channel::<(TcpStream, Option<ConnectionSlot>)>(args.threads);
+    let receiver = Mutex::new(receiver);
+
+    thread::scope(|scope| {
+        for _ in 0..args.threads {
+            let receiver = &receiver;
+            scope.spawn(move || {
+                let mut caches = Caches::new(args, &shared.stats);
+                loop {
+                    // The lock is only held while waiting, not while handling
+                    // The slot is held until the connection is done with
+                    let (stream, _slot) = match receiver.lock().unwrap_or_else(|e| e.into_inner()).recv() {
+                        Ok(connection) => connection,
+                        Err(_) => return,
+                    };
+                    // Registered until it's done with, so the shutdown deadline can cut it off.
+                    // A TLS handshake gets no longer than a request head would.
+                    let result = shared.open_connections.register(&stream).and_then(|_registration| {
+                        stream
+                            .set_read_timeout(Some(args.header_timeout))
+                            .and_then(|_| stream.set_write_timeout(Some(args.write_timeout)))
+                            .and_then(|_| match &shared.tls {
+                                Some(config) => tls::accept(config, stream)
+                                    .and_then(|stream| serve_connection(Box::new(stream), args, &mut caches, shared)),
+                                None => serve_connection(Box::new(stream), args, &mut caches, shared),
+                            })
+                    });
+                    // A client hanging up mid-response shouldn't take the whole server down
+                    if let Err(e) = result {
+                        eprintln!("Error handling connection: {:?}", e);
+                    }
+                    shared.totals.open.fetch_sub(1, Ordering::SeqCst);
+                }
+            });
+        }
+
+        let watched = listeners.iter().map(TcpListener::try_clone).collect::<io::Result<Vec<_>>>()?;
+        let watcher = scope.spawn(move || {
+            shutdown::watch(&watched, &shared.totals, &shared.open_connections, &shared.shutdown, args.shutdown_timeout)
+        });
+        // Read by whichever sibling is asked for the server's numbers
+        if let Some(file) = &args.worker_state {
+            scope.spawn(move || metrics::publish(file, &shared.totals, shared.metrics.as_ref()));
+        }
+
+        let acceptors: Vec<_> = listeners
+            .into_iter()
+            .map(|listener| {
+                let sender = sender.clone();
+                scope.spawn(move || -> io::Result<()> {
+                    for stream in listener.incoming() {
+                        // Shutting the listener down to stop accepting fails the pending accept
+                        if shared.shutdown.requested() {
+                            break;
+                        }
+                        // Out of descriptors, or a client gone before it was accepted:
+                        // this listener is fine, so neither stops it for good
+                        let stream = match stream {
+                            Ok(stream) => stream,
+                            Err(e) => {
+                                eprintln!("Error accepting a connection: {}", e);
+                                if out_of_descriptors(&e) {
+                                    thread::sleep(ACCEPT_BACKOFF);
+                                }
+                                continue;
+                            }
+                        };
+                        // Dropped unanswered, before TLS or a handler thread is spent on it
+                        let ip = match stream.peer_addr() {
+                            Ok(peer) if firewall::permits(args, peer.ip()) => peer.ip(),
+                            _ => continue,
+                        };
+                        // Likewise connections past their address's cap
+                        let slot = match &shared.connection_limiter {
+                            Some(limiter) => match limiter.admit(ip) {
+                                Some(slot) => Some(slot),
+                                None => continue,
+                            },
+                            None => None,
+                        };
+                        shared.totals.connections.fetch_add(1, Ordering::Relaxed);
+                        shared.totals.open.fetch_add(1, Ordering::SeqCst);
+                        if sender.send((stream, slot)).is_err() {
+                            break;
+                        }
+                    }
+                    Ok(())
+                })
+            })
+            .collect();
+        // Handlers stop once every acceptor, and with it every sender, is gone
+        drop(sender);
+        let results: Vec<_> = acceptors.into_iter().map(|acceptor| acceptor.join().expect("accept thread panicked")).collect();
+        shared.totals.serving.store(false, Ordering::SeqCst);
+        let drained = watcher.join().expect("shutdown thread panicked");
+        results.into_iter().collect::<io::Result<()>>()?;
+        Ok::<_, io::Error>(drained)
+    })
+    .and_then(|drained| {
+        shared.totals.print_summary();
+        drained
+    })
+}
+
+fn bind_listener(address: SocketAddr, reuse_port: bool, only_v6: bool) -> io::Result<TcpListener> {
+    let socket = Socket::new(Domain::for_address(address), Type::STREAM, Some(Protocol::TCP))?;
+    // Matches what std's TcpListener::bind sets on Unix
+    #[cfg(unix)]
+    socket.set_reuse_address(true)?;
+    if reuse_port {
+        set_reuse_port(&socket)?;
+    }
+    if address.is_ipv6() {
+        socket.set_only_v6(only_v6)?;
+    }
+    socket.bind(&address.into())
+        .map_err(|e| io::Error::new(e.kind(), format!("can't listen on {}: {}", address, e)))?;
+    socket.listen(128)?;
+    Ok(socket.into())
+}
+
+#[cfg(unix)]
+fn set_reuse_port(socket: &Socket) -> io::Result<()> {
+    socket.set_reuse_port(true)
+}
+
+#[cfg(not(unix))]
+fn set_reuse_port(_socket: &Socket) -> io::Result<()> {
+    Err(io::Error::new(io::ErrorKind::Unsupported, "--workers needs SO_REUSEPORT, which this platform lacks"))
+}
+
+// How long an idle kept-alive connection may hold on to a handler thread
+const KEEP_ALIVE_TIMEOUT: Duration = Duration::from_secs(5);
+// Past this many requests a connection is closed, so one client can't keep a thread forever
+const MAX_REQUESTS_PER_CONNECTION: usize = 100;
+
+// One request's side of its connection, which handlers answer on. It holds
+// what every response head depends on besides the handler's own headers.
+struct Exchange<'a> {
+    stream: &'a mut dyn Stream,
+    // Whether the connection stays open after this response, for the Connection header
+    keep_alive: bool,
+    // A HEAD request's response ends with its head
+    head_request: bool,
+    // --cors headers, and a share link's cookie, for this request
+    extra_headers: String,
+    // --header and --secure-headers ones, the same for the whole connection
+    response_headers: &'a str,
+    error_pages: &'a ErrorPages,
+    // The target of a request the tarpit should have; it takes the connection over
+    tarpitted: Option<String>,
+    // The account the request logged in as, for the access log
+    user: Option<String>,
+    // --io-uring
+    io_uring: bool,
+}
+
+impl<'a> Exchange<'a> {
+    // Closes after its response until told otherwise
+    fn new(stream: &'a mut dyn Stream, response_headers: &'a str, error_pages: &'a ErrorPages) -> Exchange<'a> {
+        Exchange {
+            stream,
+            keep_alive: false,
+            head_request: false,
+            extra_headers: String::new(),
+            response_headers,
+            error_pages,
+            tarpitted: None,
+            user: None,
+            io_uring: false,
+        }
+    }
+
+    fn close_after_response(&mut self) {
+        self.keep_alive = false;
+    }
+
+    fn is_head_request(&self) -> bool {
+        self.head_request
+    }
+
+    // HTTP/1.0 clients assume a connection closes after each response, and
+    // HTTP/1.1 ones that it stays open, so either way they're told which
+    fn response_head(&self, status: &str, content_type: &str, content_length: usize, headers: &str) -> String {
+        format!(
+            "HTTP/1.1 {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: {}\r\n{}{}\r\n",
+            status,
+            content_type,
+            content_length,
+            self.connection(),
+            self.common_headers(),
+            headers
+        )
+    }
+
+    fn chunked_response_head(&self, status: &str, content_type: &str, headers: &str) -> String {
+        format!(
+            "HTTP/1.1 {}\r\nContent-Type: {}\r\nTransfer-Encoding: chunked\r\nConnection: {}\r\n{}{}\r\n",
+            status,
+            content_type,
+            self.connection(),
+            self.common_headers(),
+            headers
+        )
+    }
+
+    // What every response head carries besides its own headers
+    fn common_headers(&self) -> String {
+        format!("{}{}", self.extra_headers, self.response_headers)
+    }
+
+    fn connection(&self) -> &'static str {
+        if self.keep_alive { "keep-alive" } else { "close" }
+    }
+}
+
+impl Read for Exchange<'_> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.stream.read(buf)
+    }
+}
+
+impl Write for Exchange<'_> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.stream.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.stream.flush()
+    }
+}
+
+impl Stream for Exchange<'_> {
+    fn peer_addr(&self) -> io::Result<SocketAddr> {
+        self.stream.peer_addr()
+    }
+
+    fn set_read_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        self.stream.set_read_timeout(timeout)
+    }
+
+    fn set_write_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        self.stream.set_write_timeout(timeout)
+    }
+
+    fn shutdown_write(&mut self) -> io::Result<()> {
+        self.stream.shutdown_write()
+    }
+
+    fn client_names(&self) -> Vec<String> {
+        self.stream.client_names()
+    }
+
+    fn send_file(&mut self, file: &fs::File, offset: u64, length: u64) -> Option<io::Result<u64>> {
+        match self.io_uring {
+            true => self.stream.send_file(file, offset, length),
+            false => None,
+        }
+    }
+}
+
+// Answers requests on one connection until the client or a response wants it closed
+fn serve_connection(mut stream: Box<dyn Stream>, args: &Args, caches: &mut Caches, shared: &Shared) -> io::Result<()> {
+    // Bytes read past the end of the previous request: the start of the next one
+    let mut pending = Vec::new();
+    stream.set_write_timeout(Some(args.write_timeout))?;
+    let response_headers: String =
+        args.response_headers.iter().map(|(name, value)| format!("{}: {}\r\n", name, value)).collect();
+    for served in 0..MAX_REQUESTS_PER_CONNECTION {
+        // A new connection's first request is due at once
+        let idle = if served == 0 { None } else { Some(KEEP_ALIVE_TIMEOUT) };
+        let head_length = match read_head(&mut *stream, &mut pending, args, &response_headers, &shared.error_pages, idle)? {
+            Some(length) => length,
+            None => return Ok(()),
+        };
+        // Bodies, and anything else handlers read, are timed per read
+        stream.set_read_timeout(Some(args.read_timeout))?;
+        caches.follow(shared.watcher.as_deref());
+        shared.totals.requests.fetch_add(1, Ordering::Relaxed);
+        // Draining connections finish the request they're on and no more
+        let keep_alive = served + 1 < MAX_REQUESTS_PER_CONNECTION
+            && wants_keep_alive(&pending[..head_length])
+            && !shared.shutdown.requested();
+
+        let received = mem::take(&mut pending);
+        // Handlers read bodies straight from the stream, so only body-less requests leave
+        // what follows their head for the next request; the rest close the connection
+        pending = received[head_length..].to_vec();
+        // Whether the connection stays open after all, and whether the tarpit wants it
+        let (keep_alive, tarpitted) = if shared.access_log.is_some() || shared.metrics.is_some() {
+            let started = Instant::now();
+            let mut recorder = Recorder::new(&mut *stream);
+            let mut exchange = Exchange::new(&mut recorder, &response_headers, &shared.error_pages);
+            exchange.keep_alive = keep_alive;
+            exchange.io_uring = args.io_uring;
+            let result = handle_connection(&mut exchange, &received, head_length, args, caches, shared);
+            let outcome = (exchange.keep_alive, exchange.tarpitted);
+            let user = exchange.user;
+            let duration = started.elapsed();
+            if let Some(metrics) = &shared.metrics {
+                metrics.record(&recorder, duration);
+            }
+            if let (Some(log), Ok(peer)) = (&shared.access_log, recorder.peer_addr()) {
+                let request_line = String::from_utf8_lossy(&received[..head_length]);
+                log.record(peer.ip(), request_line.lines().next().unwrap_or(""), user.as_deref(), &recorder, duration);
+            }
+            result?;
+            outcome
+        } else {
+            let mut exchange = Exchange::new(&mut *stream, &response_headers, &shared.error_pages);
+            exchange.keep_alive = keep_alive;
+            exchange.io_uring = args.io_uring;
+            handle_connection(&mut exchange, &received, head_length, args, caches, shared)?;
+            (exchange.keep_alive, exchange.tarpitted)
+        };
+        if let (Some(target), Some(tarpit)) = (tarpitted, &shared.tarpit) {
+            let ip = stream.peer_addr()?.ip();
+            tarpit.trap(stream, ip, &target);
+            return Ok(());
+        }
+        if !keep_alive {
+            break;
+        }
+    }
+    // Over TLS this sends close_notify, without which clients can't tell the
+    // end of a response from a cut connection. It's too late to matter if it fails.
+    let _ = stream.shutdown_write();
+    Ok(())
+}
+
+// Reads until `pending` holds a complete request head and returns its length,
+// or None once the client has gone (or idled out) between requests, or was
+// refused for exceeding a limit. Limits are judged as soon as the bytes
+// arrive, so an oversized head is never buffered whole. The client may wait
+// `idle` before starting a request; from then on the whole head must arrive
+// within --header-timeout, so trickling it a byte at a time earns a 408.
+// Refusals carry `response_headers` and --error-page like every other response.
+fn read_head(
+    stream: &mut dyn Stream,
+    pending: &mut Vec<u8>,
+    args: &Args,
+    response_headers: &str,
+    error_pages: &ErrorPages,
+    idle: Option<Duration>,
+) -> io::Result<Option<usize>> {
+    let mut buffer = [0; 4096];
+    let mut deadline = Instant::now() + idle.filter(|_| pending.is_empty()).unwrap_or(args.header_timeout);
+    let mut started = idle.is_none() || !pending.is_empty();
+    loop {
+        let line_end = pending.windows(2).position(|window| window == b"\r\n");
+        let end = pending.windows(4).position(|window| window == b"\r\n\r\n").map(|end| end + 4);
+        let refusal = if line_end.unwrap_or(pending.len()) > args.max_uri_length {
+            Some(("414 URI Too Long", "URI Too Long"))
+        } else if end.unwrap_or(pending.len()) > args.max_header_size {
+            Some(("431 Request Header Fields Too Large", "Request Header Fields Too Large"))
+        } else if end.is_some_and(|end| pending[..end].windows(2).filter(|window| *window == b"\r\n").count() - 2 > args.max_headers) {
+            Some(("431 Request Header Fields Too Large", "Too many header fields"))
+        } else {
+            None
+        };
+        if let Some((status, message)) = refusal {
+            send_error(&mut Exchange::new(stream, response_headers, error_pages), status, message)?;
+            return Ok(None);
+        }
+        if let Some(end) = end {
+            return Ok(Some(end));
+        }
+
+        let remaining = deadline.saturating_duration_since(Instant::now());
+        let read = match remaining.is_zero() {
+            true => Err(io::ErrorKind::TimedOut.into()),
+            false => stream.set_read_timeout(Some(remaining)).and_then(|_| stream.read(&mut buffer)),
+        };
+        let read = match read {
+            Ok(read) => read,
+            Err(e) if !pending.is_empty() && matches!(e.kind(), io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut) => {
+                send_error(&mut Exchange::new(stream, response_headers, error_pages), "408 Request Timeout", "Request Timeout")?;
+                return Ok(None);
+            }
+            // TLS clients are meant to say goodbye first, but many just hang up
+            Err(e) if pending.is_empty() && matches!(
+                e.kind(),
+                io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut | io::ErrorKind::UnexpectedEof
+            ) => 0,
+            Err(e) => return Err(e),
+        };
+        if read == 0 {
+            return Ok(None);
+        }
+        if !started {
+            started = true;
+            deadline = Instant::now() + args.header_timeout;
+        }
+        pending.extend_from_slice(&buffer[..read]);
+    }
+}
+
+// HTTP/1.1 connections persist unless asked not to and HTTP/1.0 ones only when
+// asked to. Requests with a body always close: handlers may refuse it unread.
+fn wants_keep_alive(head: &[u8]) -> bool {
+    let head = String::from_utf8_lossy(head);
+    let (_, _, version) = parse_request_line(head.lines().next().unwrap_or(""));
+    let has_body = request_header(&head, "Content-Length").is_some_and(|length| length != "0");
+    let connection = request_header(&head, "Connection").unwrap_or("").to_ascii_lowercase();
+    let tokens: Vec<&str> = connection.split(',').map(str::trim).collect();
+    match version {
+        _ if has_body || tokens.contains(&"close") => false,
+        "HTTP/1.1" => true,
+        _ => tokens.contains(&"keep-alive"),
+    }
+}
+
+// `received` is everything read for this request so far: its head, which is
+// the first `head_length` bytes, and maybe the start of its body
+fn handle_connection(
+    stream: &mut Exchange,
+    received: &[u8],
+    head_length: usize,
+    args: &Args,
+    caches: &mut Caches,
+    shared: &Shared,
+) -> io::Result<()> {
+    let failures = &shared.auth_failures;
+
+    let request = String::from_utf8_lossy(&received[..head_length]);
+    let request_line = request.lines().next().unwrap_or("");
+    let (method, target, version) = parse_request_line(request_line);
+    stream.head_request = method == "HEAD";
+    if let Some(cors) = &args.cors {
+        stream.extra_headers = cors.headers(&request);
+    }
+    if version != "HTTP/1.0" && version != "HTTP/1.1" {
+        stream.close_after_response();
+        return send_error(stream, "505 HTTP Version Not Supported", "HTTP Version Not Supported");
+    }
+    if let Err(reason) = check_framing(&request, version) {
+        eprintln!("Rejecting ambiguous request: {}", reason);
+        stream.close_after_response();
+        return send_error(stream, "400 Bad Request", "Bad Request");
+    }
+    let target = match origin_form(target) {
+        Some(target) => target,
+        None => return send_error(stream, "400 Bad Request", "Bad Request"),
+    };
+    let (path, query) = target.split_once('?').unwrap_or((target, ""));
+
+    let ip = stream.peer_addr()?.ip();
+    if shared.tarpit.as_ref().is_some_and(|tarpit| tarpit.matches(target)) {
+        stream.close_after_response();
+        stream.tarpitted = Some(target.to_string());
+        return Ok(());
+    }
+
+    let exact = match query_param(query, "exact") {
+        Some("0") | Some("false") => false,
+        Some(_) => true,
+        None => args.exact,
+    };
+    // An archive would only hold the serving layer's copy of an overlaid directory
+    let archive = args.overlays.is_empty();
+    let sort = listing::Sort::from_query(query_param(query, "sort"), query_param(query, "order"));
+    let json = query_param(query, "format") == Some("json") || accepts_json(&request);
+    let mut options = ListingOptions {
+        exact,
+        asset_prefix: &args.asset_prefix,
+        upload: false,
+        archive,
+        sortable: true,
+        sort,
+        json,
+        reload: args.watch,
+        hidden: &args.hidden,
+        dir: "",
+        gallery: true,
+        searchable: true,
+        search: None,
+    };
+
+    // The prefix's endpoints take a login, and count against the rate limit,
+    // like anything else, so they're answered further on
+    let endpoint = path.strip_prefix(args.asset_prefix.as_str()).filter(|file| match *file {
+        "share" => shared.share_key.is_some(),
+        "events" => shared.watcher.is_some(),
+        "metrics" => shared.metrics.is_some(),
+        "health" | "stats" => args.admin,
+        file => file.starts_with("dlna/") && args.dlna,
+    });
+    // Embedded assets never fall through to the filesystem, so user files can't shadow them
+    if let Some(file) = path.strip_prefix(args.asset_prefix.as_str()).filter(|_| endpoint.is_none()) {
+        return assets::send_asset(stream, file);
+    }
+
+    if let Some(wait) = shared.rate_limiter.as_ref().and_then(|limiter| limiter.check(ip)) {
+        let headers = format!("Retry-After: {}\r\n", wait.as_secs() + 1);
+        return send_error_with_headers(stream, "429 Too Many Requests", "", &headers);
+    }
+
+    if let Some(remaining) = failures.locked_out(ip) {
+        let headers = format!("Retry-After: {}\r\n", remaining.as_secs() + 1);
+        return send_error_with_headers(stream, "429 Too Many Requests", "Too many failed logins", &headers);
+    }
+
+    if args.cors.is_some() && cors::is_preflight(method, &request) {
+        return cors::send_preflight(stream, &request);
+    }
+
+    // A share link's token stands in for a login, but only to read what it was made for
+    let share = shared
+        .share_key
+        .as_ref()
+        .filter(|_| method == "GET" || method == "HEAD")
+        .and_then(|key| key.find(&request, query, &decode_url_encoded(path)));
+    if let Some(cookie) = share.as_ref().and_then(Share::cookie) {
+        stream.extra_headers.push_str(&cookie);
+    }
+
+    // Verified against --tls-client-ca, so as good as a password
+    let client_names = stream.client_names();
+    // --auth gates everything but the embedded assets behind one of its passwords
+    if !args.auth.is_empty() && share.is_none() {
+        if !users::passes_gate(&args.auth, &request, &client_names) {
+            if let Some((name, _)) = users::basic_credentials(&request) {
+                failures.record(ip, Some(&name), path);
+            }
+            return send_unauthorized(stream, "Basic realm=\"Bounty\", charset=\"UTF-8\"");
+        }
+        failures.clear(ip);
+    }
+
+    // With accounts, everything but the embedded assets needs a login, and
+    // paths resolve against the account's root
+    let user = if let Some(share) = &share {
+        share.user()
+    } else if args.has_accounts() {
+        match users::login(args, &request, &client_names) {
+            Some(user) => {
+                failures.clear(ip);
+                Some(user)
+            }
+            None => {
+                // A request without credentials is just the browser learning it needs some
+                if let Some((name, _)) = users::basic_credentials(&request) {
+                    failures.record(ip, Some(&name), path);
+                }
+                return send_unauthorized(stream, "Basic realm=\"Bounty\", charset=\"UTF-8\"");
+            }
+        }
+    } else {
+        None
+    };
+    let user = user.as_ref();
+    stream.user = user.map(|user| user.name.clone());
+    // `/~<user>/` directories and mounts aren't the root, so uploads can't go there
+    let in_user_dir = args.user_dirs.is_some() && decode_url_encoded(path).starts_with("/~");
+    let in_mount = by_prefix(&args.mounts, &decode_url_encoded(path)).is_some();
+    let can_write = user.is_none_or(|user| user.can_write) && !in_user_dir && !in_mount && share.is_none();
+    options.upload = args.allow_upload && can_write;
+    let root = match (&share, user) {
+        (Some(share), _) => share.root.clone(),
+        (None, Some(user)) => user.root.clone(),
+        (None, None) => vhost_root(args, &request).clone(),
+    };
+
+    // Or a share link's bearer could make longer-lived ones for themselves, or
+    // read what the server says about itself
+    if endpoint.is_some() && share.is_some() {
+        return send_error(stream, "403 Forbidden", "Forbidden");
+    }
+    if let Some(watcher) = shared.watcher.as_ref().filter(|_| endpoint == Some("events")) {
+        return watcher.send_events(stream, &shared.shutdown);
+    }
+    if let Some(metrics) = shared.metrics.as_ref().filter(|_| endpoint == Some("metrics")) {
+        return metrics.send(stream, &shared.totals, args.worker_state.as_deref());
+    }
+    match endpoint {
+        Some("health") => return api::send_health(stream, &shared.totals, &shared.shutdown),
+        Some("stats") => return api::send_stats(stream, &shared.totals, shared.metrics.as_ref(), args.worker_state.as_deref()),
+        _ => {}
+    }
+    if let Some(key) = shared.share_key.as_ref().filter(|_| endpoint == Some("share")) {
+        if method != "GET" {
+            return send_error(stream, "405 Method Not Allowed", "Method Not Allowed");
+        }
+        return share::send_link(stream, &request, query, key, &root, user, args);
+    }
+
+    // Loopback only: it reveals filesystem paths and every header, credentials
+    // included. Behind a local reverse proxy everyone is loopback, hence the flag.
+    let echo = path.strip_prefix("/_debug/echo").filter(|rest| rest.is_empty() || rest.starts_with('/'));
+    if let Some(rest) = echo.filter(|_| args.debug_echo && ip.is_loopback()) {
+        return debug::send_echo(stream, args, &request, ip, user, &root, rest);
+    }
+
+    // Refusing with a final status (413, 405) before the body arrives is a valid
+    // answer to 100-continue; handlers that do read a body send the 100 themselves
+    if let Some(expect) = request_header(&request, "Expect") {
+        if !expect.eq_ignore_ascii_case("100-continue") {
+            return send_error(stream, "417 Expectation Failed", "Expectation Failed");
+        }
+    }
+
+    // Judge the declared length before reading any of the body
+    if let Some(length) = request_header(&request, "Content-Length") {
+        match length.parse::<u64>() {
+            Ok(length) if length > args.max_body_size => {
+                send_error(stream, "413 Payload Too Large", "Payload Too Large")?;
+                return close_lingering(stream);
+            }
+            Ok(_) => {}
+            Err(_) => return send_error(stream, "400 Bad Request", "Bad Request"),
+        }
+    }
+
+    if let Some(rest) = endpoint.and_then(|endpoint| endpoint.strip_prefix("dlna/")) {
+        return dlna::handle(stream, method, &request, received, rest, &root, args);
+    }
+
+    // Any method goes through, so this comes before the handlers for uploads and WebDAV
+    if let Some((upstream, rest)) = by_prefix(&args.proxies, path) {
+        let rest = &target[path.len() - rest.len()..];
+        return proxy::handle(stream, method, version, &request, received, target, rest, upstream, ip, args);
+    }
+
+    // Before any handler that reads or changes the tree, so hidden paths can't
+    // be written to any more than read. Handlers check paths from elsewhere in
+    // the request, like a MOVE's Destination, themselves.
+    let decoded_path = decode_url_encoded(path);
+    if args.hidden.hides(&decoded_path) {
+        return send_error(stream, "404 Not Found", "Not Found");
+    }
+
+    // Only reserved while enabled, so the URL otherwise means a `_append` directory
+    if let Some(target) = path.strip_prefix("/_append/").filter(|_| user.is_some() || args.append_token.is_some()) {
+        match (user, &args.append_token) {
+            (Some(user), _) if !user.can_write => {
+                return send_error(stream, "403 Forbidden", "Forbidden");
+            }
+            (None, Some(token)) if !has_bearer_token(&request, token) => {
+                return reject_token(stream, failures, ip, &request, path);
+            }
+            _ => {}
+        }
+        let target = decode_url_encoded(target);
+        if args.hidden.hides(&target) {
+            return send_error(stream, "404 Not Found", "Not Found");
+        }
+        let quota = user.and_then(|user| user.quota);
+        return append::handle(stream, method, &request, received, &target, &root, quota, args);
+    }
+
+    if let (Some(paste_dir), "/_paste") = (&args.paste_dir, path) {
+        return paste::handle(stream, method, &request, received, query, paste_dir, args);
+    }
+
+    // Only reserved while enabled, like /_append/
+    if let Some(store) = &args.short_links {
+        if path == "/s" || path.starts_with("/s/") {
+            return shortlink::handle(stream, method, &request, received, path, query, store, args);
+        }
+    }
+
+    if let (Some(token), "/_api/batch") = (&args.batch_token, path) {
+        if !has_bearer_token(&request, token) {
+            return reject_token(stream, failures, ip, &request, path);
+        }
+        return api::handle_batch(stream, method, &request, received, &args.root, &args.hidden);
+    }
+
+    // WebDAV clients save files with PUT, so --webdav-write takes it in as an upload
+    let dav_put = args.webdav_write && can_write && method == "PUT";
+    if dav_put || options.upload && (method == "PUT" || method == "POST") {
+        let quota = user.and_then(|user| user.quota);
+        return upload::handle(stream, method, &request, received, path, &root, quota, &args.hidden);
+    }
+
+    // The listing's delete and rename controls send these, so uploads take them too
+    if options.upload && !args.webdav_write && (method == "DELETE" || method == "MOVE") {
+        return match method {
+            "DELETE" => webdav::delete(stream, &decoded_path, &root),
+            _ => webdav::move_to(stream, &request, &decoded_path, &root, &args.hidden),
+        };
+    }
+
+    if args.webdav && ["OPTIONS", "PROPFIND", "MKCOL", "MOVE", "DELETE"].contains(&method) {
+        return webdav::handle(stream, method, &request, received, path, &root, can_write, args);
+    }
+
+    // HEAD answers with exactly what GET would, minus the body
+    if method != "GET" && method != "HEAD" {
+        send_error(stream, "405 Method Not Allowed", "Method Not Allowed")?;
+        return Ok(());
+    }
+
+    if let (Some(user), "/_api/usage") = (user, path) {
+        return api::send_usage(stream, user);
+    }
+
+    if let Some(dir) = &args.download_stats {
+        match path {
+            "/_stats/top" => return stats::send_top_page(stream, dir, query, &args.asset_prefix),
+            "/_api/stats/top" => return api::send_top_downloads(stream, dir, query),
+            _ => {}
+        }
+    }
+
+    // Describes the working tree even with --git-ref, which has no such metadata.
+    // Only reserved while enabled, so the URL otherwise means an `_api` directory.
+    if let Some(rest) = path.strip_prefix("/_api/stat/").filter(|_| args.stat_api) {
+        let rest = decode_url_encoded(rest);
+        if args.hidden.hides(&rest) {
+            return send_error(stream, "404 Not Found", "Not Found");
+        }
+        return api::send_stat(stream, &root, &rest, &args.mime_types);
+    }
+
+    // Split before decoding so refs like `release%2F1.2` can contain slashes.
+    // Only reserved while enabled, which accounts can't be.
+    if let Some(rest) = path.strip_prefix("/_git/").filter(|_| args.git_urls) {
+        let (git_ref, tree_path) = rest.split_once('/').unwrap_or((rest, ""));
+        return git::send_tree_path(stream, &args.root, &decode_url_encoded(git_ref), &decode_url_encoded(tree_path), &options, &args.mime_types);
+    }
+
+    // Mounts are looked up before anything else resolves the path, and serve
+    // their directories from disk even under --git-ref
+    let mount = by_prefix(&args.mounts, &decoded_path);
+    if let Some(git_ref) = args.git_ref.as_ref().filter(|_| mount.is_none()) {
+        return git::send_tree_path(stream, &args.root, git_ref, &decoded_path, &options, &args.mime_types);
+    }
+
+    let (layers, resource_path) = match (mount, args.user_dirs.as_deref().zip(decoded_path.strip_prefix("/~"))) {
+        (Some((root, resource_path)), _) => (vec![root.clone()], resource_path),
+        (None, Some((pattern, rest))) => match user_dir(pattern, rest) {
+            Some((root, resource_path)) => (vec![root], resource_path),
+            None => return send_error(stream, "404 Not Found", "Not Found"),
+        },
+        (None, None) => (overlay_layers(args, root), if decoded_path == "/" { "" } else { &decoded_path[1..] }),
+    };
+    let resource_path = Path::new(resource_path);
+    // The first layer holding the path serves it; a path in none of them is the root's 404
+    let layer = layers
+        .iter()
+        .position(|layer| caches.stats.metadata(&layer.join(resource_path)).is_some())
+        .unwrap_or(layers.len() - 1);
+    let root = &layers[layer];
+    let requested_path = root.join(resource_path);
+    let mut absolute_path = requested_path.clone();
+    let mut headers = String::new();
+
+    // Checked before the favicon fallback is possible, which never gets cached.
+    // Under --spa a miss serves the app instead, so it isn't needed.
+    if !args.spa && caches.not_found.contains(&requested_path) {
+        return send_error(stream, "404 Not Found", "Not Found");
+    }
+
+    let mut info = caches.stats.metadata(&absolute_path);
+    if info.is_none() {
+        let accept_language = request_header(&request, "Accept-Language");
+        if let Some((variant, language)) =
+            language::find_variant(&absolute_path, accept_language, args.default_language.as_deref())
+        {
+            headers.push_str(&format!("Content-Language: {}\r\nVary: Accept-Language\r\n", language));
+            info = caches.stats.metadata(&variant);
+            absolute_path = variant;
+        }
+    }
+
+    // A real favicon.ico in the root takes precedence over the built-in one
+    if path == "/favicon.ico" && !info.is_some_and(|info| info.is_file) {
+        return send_content(stream, assets::FAVICON, "image/x-icon", "");
+    }
+
+    let (absolute_path, info) = match info {
+        Some(info) => (absolute_path, info),
+        // Client-side routers handle every path the tree doesn't have
+        None => match args.spa.then(|| index_file(root, caches)).flatten() {
+            Some(index) => index,
+            None => {
+                caches.not_found.insert(requested_path);
+                return send_error(stream, "404 Not Found", "Not Found");
+            }
+        },
+    };
+
+    // Checked before index.html can stand in for the directory
+    let archive = query_param(query, "download").and_then(archive::Format::parse).filter(|_| options.archive);
+    if let Some(format) = archive.filter(|_| info.is_dir) {
+        if !is_path_within(&absolute_path, root)? {
+            return send_error(stream, "403 Forbidden", "Forbidden");
+        }
+        let digest_trailer = args.digest_trailers && version != "HTTP/1.0";
+        return archive::send(stream, &absolute_path, root, format, &args.hidden, &decoded_path, digest_trailer);
+    }
+    if let Some(search) = form_field(query, "q").filter(|search| !search.is_empty() && info.is_dir) {
+        if !is_path_within(&absolute_path, root)? {
+            return send_error(stream, "403 Forbidden", "Forbidden");
+        }
+        // Every layer's copy of the directory is searched, like a listing merges them
+        let dirs = directory_layers(&layers[layer..], resource_path);
+        // Results aren't a directory to upload into, archive or sort server-side
+        let options =
+            ListingOptions { dir: &decoded_path, upload: false, archive: false, gallery: false, sortable: false, search: Some(&search), ..options };
+        headers.push_str("Vary: Accept\r\n");
+        let title = decode_url_encoded(&absolute_path.display().to_string());
+        return search::send(stream, &title, &dirs, &search, &options, &headers);
+    }
+    if query_param(query, "view") == Some("gallery") && info.is_dir {
+        if !is_path_within(&absolute_path, root)? {
+            return send_error(stream, "403 Forbidden", "Forbidden");
+        }
+        let options = ListingOptions { dir: &decoded_path, ..options };
+        let title = decode_url_encoded(&absolute_path.display().to_string());
+        return gallery::send_page(stream, &title, &directory_layers(&layers[layer..], resource_path), &options, &headers);
+    }
+
+    // A directory's own index.html is served in place of its listing, though
+    // not to scripts asking for the listing itself
+    let (absolute_path, info) = match (info.is_dir && !options.json).then(|| index_file(&absolute_path, caches)).flatten() {
+        Some(index) => index,
+        None => (absolute_path, info),
+    };
+
+    if !is_path_within(&absolute_path, root)? {
+        send_error(stream, "403 Forbidden", "Forbidden")?;
+        return Ok(());
+    }
+
+    let accept_encoding = request_header(&request, "Accept-Encoding");
+    // Bodies compressed on the fly are always chunked, having no length until
+    // they're done, so HTTP/1.0 clients get identity ones
+    let can_compress = args.compress && version != "HTTP/1.0";
+    if info.is_dir {
+        // HTTP/1.0 clients can't take a chunked body
+        let transfer = match version {
+            "HTTP/1.0" => Transfer::Buffered,
+            _ if args.digest_trailers => Transfer::ChunkedWithDigest,
+            _ => Transfer::Chunked,
+        };
+        // Accept can ask for the JSON listing
+        headers.push_str(if args.compress { "Vary: Accept, Accept-Encoding\r\n" } else { "Vary: Accept\r\n" });
+        let encoding = compress::negotiate(accept_encoding.filter(|_| can_compress));
+        // Lower layers' copies of the directory fill in whatever the serving layer lacks
+        let lower = directory_layers(&layers[layer + 1..], resource_path);
+        let options = ListingOptions { dir: &decoded_path, ..options };
+        send_directory_listing(stream, &absolute_path, &lower, &options, transfer, encoding, &headers, caches)?;
+    } else if info.is_file {
+        // The rest treats the thumbnail like the file itself, validators and all;
+        // an image that can't have one is sent as it is
+        let thumbnail = query_param(query, "thumbnail").and_then(|_| gallery::thumbnail(&absolute_path, &info));
+        let (absolute_path, info) = match thumbnail.as_ref().and_then(|path| Some((path.clone(), caches.stats.metadata(path)?))) {
+            Some(thumbnail) => thumbnail,
+            None => (absolute_path, info),
+        };
+        // Markdown too, which otherwise may be rendered
+        if source::wanted(info.size, query) {
+            return source::send(stream, &absolute_path, &args.asset_prefix, args.watch, &headers);
+        }
+        if markdown::wanted(&absolute_path, info.size, query, args) {
+            return markdown::send(stream, &absolute_path, args, &headers);
+        }
+        // Pages can't reload themselves without the script, so they're read whole to add it
+        let is_html = args.mime_types.by_extension(&absolute_path).is_some_and(|mime| mime.starts_with("text/html"));
+        if args.watch && is_html && query_param(query, "download").is_none() {
+            let page = watch::inject(&fs::read(&absolute_path)?, &args.asset_prefix);
+            let headers = format!("{}Cache-Control: no-store\r\n", headers);
+            return write_response(stream, &stream.response_head("200 OK", "text/html; charset=utf-8", page.len(), &headers), &page);
+        }
+        // Downloads keep the requested name even when a language variant was picked
+        if let Some(file_name) = resource_path.file_name().filter(|_| query_param(query, "download").is_some()) {
+            headers.push_str(&content_disposition(&file_name.to_string_lossy()));
+        }
+        // Ranges count bytes of the file itself, so they're always served from it
+        let range = request_header(&request, "Range");
+        let accept_encoding = accept_encoding.filter(|_| range.is_none());
+
+        // A sibling the build compressed stands in for the file, keeping its type
+        let siblings: Vec<_> = compress::PRECOMPRESSED
+            .iter()
+            .filter(|_| args.precompressed)
+            .filter_map(|&(coding, extension)| {
+                let mut sibling = absolute_path.clone().into_os_string();
+                sibling.push(format!(".{}", extension));
+                let sibling = PathBuf::from(sibling);
+                let info = caches.stats.metadata(&sibling).filter(|info| info.is_file)?;
+                is_path_within(&sibling, root).ok()?.then_some((coding, (sibling, info)))
+            })
+            .collect();
+        let precompressed = compress::preferred(accept_encoding, siblings.iter().cloned());
+        let content_type = args.mime_types.by_extension(&absolute_path);
+        let content_type = content_type.or_else(|| precompressed.is_some().then(|| mime::sniff_file(&absolute_path)));
+        let (absolute_path, info) = match &precompressed {
+            Some((coding, (sibling, sibling_info))) => {
+                headers.push_str(&format!("Content-Encoding: {}\r\n", coding));
+                (sibling.clone(), *sibling_info)
+            }
+            None => (absolute_path, info),
+        };
+
+        let compressible = args.compress && info.size >= compress::MIN_SIZE && compress::is_compressible(&absolute_path);
+        let encoding = compress::negotiate(accept_encoding.filter(|_| compressible && can_compress && precompressed.is_none()));
+        if compressible || !siblings.is_empty() {
+            headers.push_str("Vary: Accept-Encoding\r\n");
+        }
+        // Validators come from the file actually sent, which may be a language
+        // variant. A compressed body is a different one, so it needs its own ETag.
+        let etag = info.etag().map(|etag| match encoding {
+            Some(encoding) => format!("{}-{}\"", etag.trim_end_matches('"'), encoding.name()),
+            None => etag,
+        });
+        let modified = info.modified.and_then(listing::unix_seconds);
+        if let Some(etag) = &etag {
+            headers.push_str(&format!("ETag: {}\r\n", etag));
+        }
+        if let Some(modified) = modified {
+            headers.push_str(&format!("Last-Modified: {}\r\n", listing::format_http_date(modified)));
+        }
+        if let Some(cache_control) = &args.cache_control {
+            headers.push_str(&format!("Cache-Control: {}\r\n", cache_control));
+        }
+        if is_not_modified(&request, etag.as_deref(), modified) {
+            // No body, and no Content-Length either: it would have to be the full file's
+            let response =
+                format!("HTTP/1.1 304 Not Modified\r\nConnection: {}\r\n{}{}\r\n", stream.connection(), stream.common_headers(), headers);
+            stream.write_all(response.as_bytes())?;
+            return stream.flush();
+        }
+        // The digests are of the whole file as stored, which neither a compressed body nor a range is
+        let partial = range.is_some_and(|range| !matches!(parse_range(range, info.size), ByteRange::Full));
+        if let Some(algorithm) = args.file_digest.filter(|_| encoding.is_none() && !partial) {
+            headers.push_str(&caches.checksums.header(&absolute_path, info, algorithm)?);
+        }
+        let file_cache = shared.file_cache.as_ref();
+        let sent =
+            send_file_content(stream, &absolute_path, content_type, &headers, range, encoding, args.digest_trailers, file_cache)?;
+        if let Some(dir) = args.download_stats.as_ref().filter(|_| !stream.is_head_request() && thumbnail.is_none()) {
+            stats::record(dir, &encode_url_path(&decoded_path), sent);
+        }
+    } else {
+        send_error(stream, "404 Not Found", "Not Found")?;
+    }
+
+    Ok(())
+}
+
+
+// Closing with unread request data makes the kernel send a reset, which can
+// destroy the response before the client reads it, so drain a bounded amount first
+fn close_lingering(stream: &mut Exchange) -> io::Result<()> {
+    stream.close_after_response();
+    stream.shutdown_write()?;
+    stream.set_read_timeout(Some(Duration::from_secs(1)))?;
+
+    let mut buffer = [0; 8192];
+    let mut drained = 0;
+    while drained < 1024 * 1024 {
+        match stream.read(&mut buffer) {
+            Ok(0) | Err(_) => break,
+            Ok(n) => drained += n,
+        }
+    }
+    Ok(())
+}
+
+// `received` is everything read so far, headers included. Answers an
+// `Expect: 100-continue`, so call it only once the body is known to be wanted.
+fn read_body(stream: &mut dyn Stream, request: &str, received: &[u8], length: u64) -> io::Result<Vec<u8>> {
+    let mut body = Vec::new();
+    body_reader(stream, request, received, length)?.read_to_end(&mut body)?;
+    if (body.len() as u64) < length {
+        return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "body shorter than Content-Length"));
+    }
+    Ok(body)
+}
+
+// Like read_body, for bodies too big to hold in memory. The reader ends after
+// `length` bytes, or sooner if the client stops sending.
+fn body_reader<'a>(
+    stream: &'a mut dyn Stream,
+    request: &str,
+    received: &'a [u8],
+    length: u64,
+) -> io::Result<impl Read + 'a> {
+    if request_header(request, "Expect").is_some_and(|expect| expect.eq_ignore_ascii_case("100-continue")) {
+        stream.write_all(b"HTTP/1.1 100 Continue\r\n\r\n")?;
+        stream.flush()?;
+    }
+
+    let header_end = received
+        .windows(4)
+        .position(|window| window == b"\r\n\r\n")
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "request headers didn't fit the read buffer"))?;
+
+    let start = &received[header_end + 4..];
+    let start = &start[..start.len().min(length as usize)];
+
+    Ok(start.chain(stream.take(length - start.len() as u64)))
+}
+
+fn has_bearer_token(request: &str, token: &str) -> bool {
+    let presented = match request_header(request, "Authorization").and_then(|value| value.strip_prefix("Bearer ")) {
+        Some(presented) => presented.trim(),
+        None => return false,
+    };
+
+    constant_time_eq(presented, token)
+}
+
+// Compares every byte so timing doesn't reveal how much of a secret matched
+fn constant_time_eq(a: &str, b: &str) -> bool {
+    a.len() == b.len() && a.bytes().zip(b.bytes()).fold(0, |diff, (a, b)| diff | (a ^ b)) == 0
+}
+
+fn parse_request_line(request_line: &str) -> (&str, &str, &str) {
+    let mut parts = request_line.split_whitespace();
+    let method = parts.next().unwrap_or("");
+    let path = parts.next().unwrap_or("");
+    let version = parts.next().unwrap_or("");
+    (method, path, version)
+}
+
+// Anything two HTTP hops could disagree about is refused rather than guessed at
+// (RFC 9112 sections 3, 5 and 6.3): a fronting proxy and this server must never
+// see different requests in the same bytes
+fn check_framing(request: &str, version: &str) -> Result<(), &'static str> {
+    // Only complete lines: a request too large for the read buffer ends mid-header
+    let head = match request.find("\r\n\r\n") {
+        Some(end) => &request[..end],
+        None => &request[..request.rfind('\n').unwrap_or(0)],
+    };
+    let mut lines = head.split('\n').map(|line| line.strip_suffix('\r').unwrap_or(line));
+
+    let request_line = lines.next().unwrap_or("");
+    if request_line.split(' ').count() != 3 || request_line.split(' ').any(str::is_empty) {
+        return Err("request line not separated by single spaces");
+    }
+
+    let mut lengths = Vec::new();
+    let (mut hosts, mut transfer_encoding) = (0, false);
+    for line in lines {
+        if line.starts_with([' ', '\t']) {
+            return Err("obsolete line folding");
+        }
+        let (name, value) = line.split_once(':').ok_or("header line without a colon")?;
+        if name.is_empty() || name.ends_with([' ', '\t']) {
+            return Err("whitespace before a header colon");
+        }
+        match name.to_ascii_lowercase().as_str() {
+            "content-length" => lengths.push(value.trim()),
+            "transfer-encoding" => transfer_encoding = true,
+            "host" => hosts += 1,
+            _ => {}
+        }
+    }
+
+    if lengths.iter().any(|length| length.is_empty() || !length.bytes().all(|byte| byte.is_ascii_digit())) {
+        return Err("malformed Content-Length");
+    }
+    if lengths.windows(2).any(|pair| pair[0] != pair[1]) {
+        return Err("conflicting Content-Length headers");
+    }
+    // Request bodies are only ever read by length, so a chunked one would be
+    // taken for the next request's bytes
+    if transfer_encoding {
+        return Err("Transfer-Encoding on a request");
+    }
+    if hosts > 1 || (hosts == 0 && version == "HTTP/1.1") {
+        return Err("missing or repeated Host");
+    }
+    Ok(())
+}
+
+// Reduces an absolute-form target (`http://host/path`, as sent to proxies) to
+// the path and query everything else routes on
+fn origin_form(target: &str) -> Option<&str> {
+    if target.starts_with('/') {
+        return Some(target);
+    }
+    let scheme_end = target.find("://")?;
+    if !["http", "https"].iter().any(|scheme| target[..scheme_end].eq_ignore_ascii_case(scheme)) {
+        return None;
+    }
+    let rest = &target[scheme_end + 3..];
+    match rest.find(['/', '?']) {
+        Some(start) if rest[start..].starts_with('/') => Some(&rest[start..]),
+        _ => Some("/"),
+    }
+}
+
+// Scripts ask for JSON by name; browsers list text/html, and */* means anything goes
+fn accepts_json(request: &str) -> bool {
+    let accept = match request_header(request, "Accept") {
+        Some(accept) => accept,
+        None => return false,
+    };
+    let types: Vec<&str> = accept.split(',').map(|item| item.split(';').next().unwrap_or("").trim()).collect();
+    types.iter().any(|media_type| media_type.eq_ignore_ascii_case("application/json"))
+        && !types.iter().any(|media_type| media_type.eq_ignore_ascii_case("text/html"))
+}
+
+// Header names are case-insensitive; the first occurrence wins
+fn request_header<'a>(request: &'a str, name: &str) -> Option<&'a str> {
+    request
+        .lines()
+        .skip(1)
+        .take_while(|line| !line.is_empty())
+        .filter_map(|line| line.split_once(':'))
+        .find(|(key, _)| key.trim().eq_ignore_ascii_case(name))
+        .map(|(_, value)| value.trim())
+}
+
+// A bare `?name` yields an empty value
+fn query_param<'a>(query: &'a str, name: &str) -> Option<&'a str> {
+    query
+        .split('&')
+        .map(|pair| pair.split_once('=').unwrap_or((pair, "")))
+        .find(|(key, _)| *key == name)
+        .map(|(_, value)| value)
+}
+
+// For `application/x-www-form-urlencoded` bodies, where spaces arrive as `+`
+fn form_field(form: &str, name: &str) -> Option<String> {
+    form.split('&').find_map(|pair| {
+        let (key, value) = pair.split_once('=')?;
+        (key == name).then(|| decode_url_encoded(&value.replace('+', " ")))
+    })
+}
+
+
+// `root` must already be canonical
+fn is_path_within(path: &Path, root: &Path) -> io::Result<bool> {
+    let abs_path = path.canonicalize()?;
+    Ok(abs_path.starts_with(root))
+}
+
+// Names that are safe to substitute into a directory pattern
+fn is_account_name(name: &str) -> bool {
+    !name.is_empty()
+        && !name.starts_with(['.', '-'])
+        && name.chars().all(|c| c.is_ascii_alphanumeric() || matches!(c, '.' | '_' | '-'))
+}
+
+// `/~alice/rest` under `--user-dirs /home/*/public_html` is `rest` inside
+// alice's public_html, which then acts as the root for containment checks.
+// None for names that can't be accounts or users without such a directory.
+fn user_dir<'a>(pattern: &str, rest: &'a str) -> Option<(PathBuf, &'a str)> {
+    let (user, resource_path) = rest.split_once('/').unwrap_or((rest, ""));
+    if !is_account_name(user) {
+        return None;
+    }
+
+    let root = PathBuf::from(pattern.replacen('*', user, 1)).canonicalize().ok()?;
+    root.is_dir().then_some((root, resource_path))
+}
+
+// The Host header's name, without its port, picks the root; unknown hosts and
+// requests without one get the main root
+fn vhost_root<'a>(args: &'a Args, request: &str) -> &'a PathBuf {
+    let host = match request_header(request, "Host") {
+        Some(host) if host.starts_with('[') => host.split_once(']').map_or(host, |(host, _)| &host[1..]),
+        Some(host) => host.split(':').next().unwrap_or(host),
+        None => return &args.root,
+    };
+    let host = host.trim_end_matches('.');
+    args.vhosts.iter().find(|(name, _)| name.eq_ignore_ascii_case(host)).map_or(&args.root, |(_, dir)| dir)
+}
+
+// `/docs/guide/intro.md` under `--mount /docs=./docs` is `guide/intro.md`
+// inside ./docs, which then acts as the root for containment checks. Proxies
+// look up their prefixes the same way.
+fn by_prefix<'a, 'm, T>(table: &'m [(String, T)], path: &'a str) -> Option<(&'m T, &'a str)> {
+    table.iter().find_map(|(prefix, value)| {
+        let rest = path.strip_prefix(prefix.as_str())?;
+        match rest {
+            "" => Some((value, "")),
+            _ => rest.strip_prefix('/').map(|rest| (value, rest)),
+        }
+    })
+}
+
+// Rows are written as the directory is walked so big listings start arriving
+// right away. `encoding` only applies to chunked transfers.
+#[allow(clippy::too_many_arguments)]
+fn send_directory_listing(
+    stream: &mut Exchange,
+    path: &Path,
+    lower: &[PathBuf],
+    options: &ListingOptions,
+    transfer: Transfer,
+    encoding: Option<Encoding>,
+    headers: &str,
+    caches: &mut Caches,
+) -> io::Result<()> {
+    let headers = match encoding {
+        Some(encoding) => format!("{}Content-Encoding: {}\r\n", headers, encoding.name()),
+        None => headers.to_string(),
+    };
+    let content_type = if options.json { "application/json" } else { "text/html" };
+    let headers = match transfer {
+        Transfer::Buffered => {
+            let mut body = Vec::new();
+            write_directory_listing(&mut body, path, lower, options, caches)?;
+            return write_response(stream, &stream.response_head("200 OK", content_type, body.len(), &headers), &body);
+        }
+        Transfer::Chunked => headers,
+        Transfer::ChunkedWithDigest => headers + chunked::DIGEST_TRAILER,
+    };
+    stream.write_all(stream.chunked_response_head("200 OK", content_type, &headers).as_bytes())?;
+    if stream.is_head_request() {
+        return stream.flush();
+    }
+    let mut body = match transfer {
+        Transfer::ChunkedWithDigest => ChunkedWriter::with_digest(stream),
+        _ => ChunkedWriter::new(stream),
+    };
+
+    match encoding {
+        Some(encoding) => {
+            let mut encoder = Encoder::new(encoding, body);
+            write_directory_listing(&mut encoder, path, lower, options, caches)?;
+            encoder.finish()?.finish()?;
+        }
+        None => {
+            write_directory_listing(&mut body, path, lower, options, caches)?;
+            body.finish()?;
+        }
+    }
+    Ok(())
+}
+
+// Directories with more entries than this have the rest stat'ed in parallel
+const PARALLEL_LISTING_THRESHOLD: usize = 1000;
+
+// `lower` holds the same directory in overlay layers below `path`'s, whose
+// entries are listed unless a higher layer already has one by that name
+fn write_directory_listing(
+    out: &mut impl Write,
+    path: &Path,
+    lower: &[PathBuf],
+    options: &ListingOptions,
+    caches: &mut Caches,
+) -> io::Result<()> {
+    out.write_all(listing::header(&decode_url_encoded(&path.display().to_string()), options).as_bytes())?;
+
+    // A merged listing would go stale with any layer's mtime, so only single ones are cached
+    let modified = fs::metadata(path)?.modified()?;
+    let mut rows = 0;
+    if let Some(entries) = caches.listings.get(path, modified).filter(|_| lower.is_empty()) {
+        let mut sorted: Vec<_> = entries.iter().collect();
+        if let Some(sort) = options.sort {
+            sort.apply(&mut sorted);
+        }
+        for entry in sorted {
+            write_row(out, entry, options, &mut rows)?;
+        }
+        return out.write_all(listing::footer(&gallery_options(options, entries.iter())).as_bytes());
+    }
+
+    // Rows still stream out as they're found, unless they're to be sorted; the
+    // collected copy is cached afterwards
+    let streaming = options.sort.is_none();
+    let mut collected = Vec::new();
+    let mut entries = WalkDir::new(path).max_depth(1).min_depth(1).into_iter();
+    for entry in entries.by_ref().take(PARALLEL_LISTING_THRESHOLD) {
+        let entry = entry?;
+        let info = caches.stats.metadata(entry.path());
+        let entry = listing_entry(&entry, path, info)?;
+        if streaming {
+            write_row(out, &entry, options, &mut rows)?;
+        }
+        collected.push(entry);
+    }
+
+    // Whatever is left belongs to a huge directory; rows arrive in completion order
+    stat_in_parallel(entries, |entry, info| {
+        if let Some(info) = info {
+            caches.stats.insert(entry.path().to_path_buf(), info);
+        }
+        let entry = listing_entry(&entry, path, info)?;
+        if streaming {
+            write_row(out, &entry, options, &mut rows)?;
+        }
+        collected.push(entry);
+        Ok(())
+    })?;
+
+    // Directories in several layers merge rather than conflict
+    let mut names: HashMap<String, bool> = collected.iter().map(|entry| (entry.name.clone(), entry.is_dir)).collect();
+    let mut merged = Vec::new();
+    for dir in lower {
+        for entry in WalkDir::new(dir).max_depth(1).min_depth(1) {
+            let entry = entry?;
+            let info = caches.stats.metadata(entry.path());
+            let entry_path = entry.path().to_path_buf();
+            let entry = listing_entry(&entry, dir, info)?;
+            if let Some(&higher_is_dir) = names.get(&entry.name) {
+                if !(higher_is_dir && entry.is_dir) {
+                    eprintln!("Overlay conflict: {} is hidden by a higher layer", entry_path.display());
+                }
+                continue;
+            }
+            if streaming {
+                write_row(out, &entry, options, &mut rows)?;
+            }
+            names.insert(entry.name.clone(), entry.is_dir);
+            merged.push(entry);
+        }
+    }
+
+    if let Some(sort) = options.sort {
+        let mut sorted: Vec<_> = collected.iter().chain(&merged).collect();
+        sort.apply(&mut sorted);
+        for entry in sorted {
+            write_row(out, entry, options, &mut rows)?;
+        }
+    }
+
+    let footer = listing::footer(&gallery_options(options, collected.iter().chain(&merged)));
+    if lower.is_empty() {
+        caches.listings.insert(path.to_path_buf(), modified, collected);
+    }
+    out.write_all(footer.as_bytes())
+}
+
+// Keeps the gallery link only for listings of mostly images
+fn gallery_options<'a, 'e>(options: &ListingOptions<'a>, entries: impl Iterator<Item = &'e ListingEntry>) -> ListingOptions<'a> {
+    let files = entries.filter(|entry| !entry.is_dir && !options.hidden.hides_in(options.dir, &entry.name));
+    ListingOptions { gallery: options.gallery && gallery::is_mostly_images(files.map(|entry| entry.name.as_str())), ..*options }
+}
+
+// HTML rows stand alone, but JSON ones need commas between them; `rows`
+// counts those written so far
+fn write_row(out: &mut impl Write, entry: &ListingEntry, options: &ListingOptions, rows: &mut usize) -> io::Result<()> {
+    if options.hidden.hides_in(options.dir, &entry.name) {
+        return Ok(());
+    }
+    let row = match (options.json, *rows) {
+        (false, _) => listing::entry(entry, options),
+        (true, 0) => listing::json_entry(entry),
+        (true, _) => format!(",{}", listing::json_entry(entry)),
+    };
+    *rows += 1;
+    out.write_all(row.as_bytes())
+}
+
+fn index_file(dir: &Path, caches: &mut Caches) -> Option<(PathBuf, FileInfo)> {
+    let index = dir.join("index.html");
+    let info = caches.stats.metadata(&index).filter(|info| info.is_file)?;
+    Some((index, info))
+}
+
+// The directory at `resource_path` in each of `layers` that has it
+fn directory_layers(layers: &[PathBuf], resource_path: &Path) -> Vec<PathBuf> {
+    layers
+        .iter()
+        .map(|layer| (layer.join(resource_path), layer))
+        .filter(|(dir, layer)| dir.is_dir() && is_path_within(dir, layer).unwrap_or(false))
+        .map(|(dir, _)| dir)
+        .collect()
+}
+
+// Overlays, highest first, and then the root they sit on
+fn overlay_layers(args: &Args, root: PathBuf) -> Vec<PathBuf> {
+    let mut layers = args.overlays.clone();
+    layers.push(root);
+    layers
+}
+
+fn listing_entry(entry: &DirEntry, base: &Path, info: Option<FileInfo>) -> io::Result<ListingEntry> {
+    let file_name = entry.file_name().to_string_lossy();
+
+    // Here we strip the prefix relative to the requested directory, not the current directory
+    let file_path = entry.path().strip_prefix(base)
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?
+        .display()
+        .to_string();
+
+    // Encode the file path to handle special characters (CJK characters, spaces, etc.)
+    let encoded_file_path = encode_path(&file_path);
+
+    // Symlinks are followed like the file handler does; broken links just lose their metadata
+    let is_dir = info.is_some_and(|info| info.is_dir);
+
+    Ok(ListingEntry {
+        href: encoded_file_path,
+        name: file_name.to_string(),
+        is_dir,
+        size: info.filter(|_| !is_dir).map(|info| info.size),
+        modified: info.and_then(|info| info.modified),
+    })
+}
+
+// One thread reads the directory while a pool stats entries, since stat latency
+// rather than readdir dominates on big directories (and on network filesystems)
+fn stat_in_parallel(
+    entries: walkdir::IntoIter,
+    mut handle: impl FnMut(DirEntry, Option<FileInfo>) -> io::Result<()>,
+) -> io::Result<()> {
+    let workers = thread::available_parallelism().map_or(4, |n| n.get() * 2).clamp(2, 16);
+    // Unbounded so the reader never blocks if we stop consuming early on an error
+    let (work_sender, work_receiver) = mpsc::channel();
+    let work_receiver = Mutex::new(work_receiver);
+    let (result_sender, result_receiver) = mpsc::channel();
+
+    thread::scope(|scope| {
+        scope.spawn(move || {
+            for entry in entries {
+                if work_sender.send(entry).is_err() {
+                    break;
+                }
+            }
+        });
+
+        for _ in 0..workers {
+            let result_sender = result_sender.clone();
+            let work_receiver = &work_receiver;
+            scope.spawn(move || loop {
+                let entry = match work_receiver.lock().unwrap().recv() {
+                    Ok(entry) => entry,
+                    Err(_) => break,
+                };
+                let result = entry.map(|entry| {
+                    let info = cache::stat(entry.path());
+                    (entry, info)
+                });
+                if result_sender.send(result).is_err() {
+                    break;
+                }
+            });
+        }
+        drop(result_sender);
+
+        for result in result_receiver {
+            let (entry, info) = result?;
+            handle(entry, info)?;
+        }
+        Ok(())
+    })
+}
+
+// If-None-Match takes precedence, and If-Modified-Since only counts without it
+// (RFC 9110 section 13.2.2). ETags compare weakly, ignoring any W/ prefix.
+fn is_not_modified(request: &str, etag: Option<&str>, modified: Option<u64>) -> bool {
+    if let Some(candidates) = request_header(request, "If-None-Match") {
+        let etag = match etag {
+            Some(etag) => etag.trim_start_matches("W/"),
+            None => return false,
+        };
+        return candidates
+            .split(',')
+            .map(|candidate| candidate.trim().trim_start_matches("W/"))
+            .any(|candidate| candidate == "*" || candidate == etag);
+    }
+
+    let since = request_header(request, "If-Modified-Since").and_then(listing::parse_http_date);
+    matches!((since, modified), (Some(since), Some(modified)) if modified <= since)
+}
+
+// Big enough for every signature infer knows
+const SNIFF_LENGTH: usize = 8192;
+
+// Streams the file, so memory use doesn't grow with its size. The content type
+// is sniffed unless given. `range` is the request's Range header, ignored when
+// compressing. Returns how many bytes of the file were sent.
+#[allow(clippy::too_many_arguments)]
+fn send_file_content(
+    stream: &mut Exchange,
+    path: &Path,
+    content_type: Option<String>,
+    headers: &str,
+    range: Option<&str>,
+    encoding: Option<Encoding>,
+    digest_trailer: bool,
+    file_cache: Option<&FileCache>,
+) -> io::Result<u64> {
+    let opened = match file_cache.and_then(|cache| cache.get(path)) {
+        Some(contents) => Ok((contents.len() as u64, Box::new(Cursor::new(contents)) as Box<dyn Body>)),
+        None => open_body(path, file_cache),
+    };
+    let (length, mut file) = match opened {
+        Ok(opened) => opened,
+        Err(e) => {
+            eprintln!("Error reading file {}: {:?}", path.display(), e);
+            send_error(stream, "500 Internal Server Error", "Internal Server Error")?;
+            return Ok(0);
+        }
+    };
+
+    // Sniffed from the start of the file even for ranges, since a slice from the
+    // middle has no magic bytes
+    let mut sniffed = Vec::with_capacity(SNIFF_LENGTH);
+    (&mut file).take(SNIFF_LENGTH as u64).read_to_end(&mut sniffed)?;
+    let content_type = content_type.unwrap_or_else(|| mime::sniff(&sniffed));
+    let content_type = content_type.as_str();
+
+    let headers = format!("Accept-Ranges: bytes\r\n{}", headers);
+    // Compressed on the fly, so chunked, and the trailer is the only place a digest can go
+    if let Some(encoding) = encoding {
+        let mut headers = format!("{}Content-Encoding: {}\r\n", headers, encoding.name());
+        if digest_trailer {
+            headers.push_str(chunked::DIGEST_TRAILER);
+        }
+        stream.write_all(stream.chunked_response_head("200 OK", content_type, &headers).as_bytes())?;
+        if stream.is_head_request() {
+            stream.flush()?;
+            return Ok(0);
+        }
+        file.seek(SeekFrom::Start(0))?;
+        let chunked = match digest_trailer {
+            true => ChunkedWriter::with_digest(&mut *stream),
+            false => ChunkedWriter::new(&mut *stream),
+        };
+        let mut body = Encoder::new(encoding, chunked);
+        let sent = io::copy(&mut file, &mut body)?;
+        body.finish()?.finish()?;
+        return Ok(sent);
+    }
+
+    let (status, start, end, headers) = match range.map_or(ByteRange::Full, |range| parse_range(range, length)) {
+        ByteRange::Full => ("200 OK", 0, length, headers),
+        ByteRange::Partial(first, last) => {
+            let headers = format!("{}Content-Range: bytes {}-{}/{}\r\n", headers, first, last, length);
+            ("206 Partial Content", first, last + 1, headers)
+        }
+        ByteRange::Unsatisfiable => {
+            let headers = format!("{}Content-Range: bytes */{}\r\n", headers, length);
+            send_error_with_headers(stream, "416 Range Not Satisfiable", "", &headers)?;
+            return Ok(0);
+        }
+    };
+
+    stream.write_all(stream.response_head(status, content_type, (end - start) as usize, &headers).as_bytes())?;
+    if stream.is_head_request() {
+        stream.flush()?;
+        return Ok(0);
+    }
+    stream.flush()?;
+    // A file that shrinks meanwhile just ends the body short of the promised length
+    let sent = match file.as_file().and_then(|file| stream.send_file(file, start, end - start)) {
+        Some(sent) => sent?,
+        None => {
+            file.seek(SeekFrom::Start(start))?;
+            let sent = io::copy(&mut file.take(end - start), stream)?;
+            stream.flush()?;
+            sent
+        }
+    };
+    // The client would take whatever comes next on the connection for the rest
+    if sent < end - start {
+        return Err(io::Error::new(io::ErrorKind::UnexpectedEof, format!("{} shrank while being sent", path.display())));
+    }
+    Ok(sent)
+}
+
+// What a file's body is read from: the file, or its contents in memory
+trait Body: Read + Seek {
+    fn as_file(&self) -> Option<&fs::File> {
+        None
+    }
+}
+
+impl Body for fs::File {
+    fn as_file(&self) -> Option<&fs::File> {
+        Some(self)
+    }
+}
+
+impl Body for Cursor<Arc<[u8]>> {}
+
+// Small files are read whole with --file-cache, and kept for next time
+fn open_body(path: &Path, file_cache: Option<&FileCache>) -> io::Result<(u64, Box<dyn Body>)> {
+    let mut file = fs::File::open(path)?;
+    let metadata = file.metadata()?;
+    let (cache, modified) = match (file_cache.filter(|cache| cache.admits(metadata.len())), metadata.modified()) {
+        (Some(cache), Ok(modified)) => (cache, modified),
+        _ => return Ok((metadata.len(), Box::new(file))),
+    };
+    let mut contents = Vec::with_capacity(metadata.len() as usize);
+    (&mut file).take(metadata.len()).read_to_end(&mut contents)?;
+    let contents: Arc<[u8]> = contents.into();
+    // One that shrank while being read is served as read, but not kept
+    if contents.len() as u64 == metadata.len() {
+        cache.insert(path.to_path_buf(), modified, Arc::clone(&contents));
+    }
+    Ok((contents.len() as u64, Box::new(Cursor::new(contents))))
+}
+
+enum ByteRange {
+    Full,
+    // Inclusive, like Content-Range
+    Partial(u64, u64),
+    Unsatisfiable,
+}
+
+// Only single ranges are served; multiple ranges or a header we can't make
+// sense of get the whole file, which RFC 9110 allows
+fn parse_range(value: &str, length: u64) -> ByteRange {
+    let spec = match value.trim().split_once('=') {
+        Some((unit, spec)) if unit.trim().eq_ignore_ascii_case("bytes") && !spec.contains(',') => spec.trim(),
+        _ => return ByteRange::Full,
+    };
+    let (first, last) = match spec.split_once('-') {
+        Some(bounds) => bounds,
+        None => return ByteRange::Full,
+    };
+    let is_number = |bound: &str| !bound.is_empty() && bound.bytes().all(|byte| byte.is_ascii_digit());
+
+    // `-N` is the final N bytes
+    if first.is_empty() {
+        if !is_number(last) {
+            return ByteRange::Full;
+        }
+        let suffix = last.parse().unwrap_or(u64::MAX);
+        return if suffix == 0 || length == 0 {
+            ByteRange::Unsatisfiable
+        } else {
+            ByteRange::Partial(length - suffix.min(length), length - 1)
+        };
+    }
+
+    if !is_number(first) {
+        return ByteRange::Full;
+    }
+    let start = first.parse().unwrap_or(u64::MAX);
+    let end = match last {
+        "" => u64::MAX,
+        // Past the end (or past u64) just means up to the end
+        last if is_number(last) => last.parse().unwrap_or(u64::MAX),
+        _ => return ByteRange::Full,
+    };
+    if end < start {
+        ByteRange::Full
+    } else if start >= length {
+        ByteRange::Unsatisfiable
+    } else {
+        ByteRange::Partial(start, end.min(length - 1))
+    }
+}
+
+// `headers` holds any extra CRLF-terminated header lines
+fn send_content(stream: &mut Exchange, content: &[u8], content_type: &str, headers: &str) -> io::Result<()> {
+    let content_length = content.len();
+    
+    let head = stream.response_head("200 OK", content_type, content_length, headers);
+    write_response(stream, &head, content)
+}
+
+
+fn reject_token(
+    stream: &mut Exchange,
+    failures: &AuthFailures,
+    ip: IpAddr,
+    request: &str,
+    path: &str,
+) -> io::Result<()> {
+    if request_header(request, "Authorization").is_some() {
+        failures.record(ip, None, path);
+    }
+    send_unauthorized(stream, "Bearer")
+}
+
+// `challenge` is the WWW-Authenticate value telling the client how to log in
+fn send_unauthorized(stream: &mut Exchange, challenge: &str) -> io::Result<()> {
+    let headers = format!("WWW-Authenticate: {}\r\n", challenge);
+    send_error_with_headers(stream, "401 Unauthorized", "", &headers)
+}
+
+fn send_response(stream: &mut Exchange, status: &str, content_type: &str, body: &str) -> io::Result<()> {
+    write_response(stream, &stream.response_head(status, content_type, body.len(), ""), body.as_bytes())
+}
+
+// 4xx and 5xx answers are pages: the --error-page for the status, or the
+// built-in one showing `message`
+fn send_error(stream: &mut Exchange, status: &str, message: &str) -> io::Result<()> {
+    send_error_with_headers(stream, status, message, "")
+}
+
+fn send_error_with_headers(stream: &mut Exchange, status: &str, message: &str, headers: &str) -> io::Result<()> {
+    let body = stream.error_pages.body(status, message);
+    write_response(stream, &stream.response_head(status, "text/html; charset=utf-8", body.len(), headers), &body)
+}
+
+// `head` comes from response_head; the body is left out when answering HEAD
+fn write_response(stream: &mut Exchange, head: &str, body: &[u8]) -> io::Result<()> {
+    stream.write_all(head.as_bytes())?;
+    if !stream.is_head_request() {
+        stream.write_all(body)?;
+    }
+    stream.flush()
+}
+
+// RFC 5987 attr-char: everything else in filename* must be percent-encoded
+const ATTR_CHAR: &AsciiSet = &NON_ALPHANUMERIC
+    .remove(b'!')
+    .remove(b'#')
+    .remove(b'$')
+    .remove(b'&')
+    .remove(b'+')
+    .remove(b'-')
+    .remove(b'.')
+    .remove(b'^')
+    .remove(b'_')
+    .remove(b'`')
+    .remove(b'|')
+    .remove(b'~');
+
+// RFC 6266: an ASCII `filename` for old clients plus the exact UTF-8 name in `filename*`
+fn content_disposition(file_name: &str) -> String {
+    let fallback: String = file_name
+        .chars()
+        .map(|c| if c == ' ' || (c.is_ascii_graphic() && c != '"' && c != '\\') { c } else { '_' })
+        .collect();
+    format!(
+        "Content-Disposition: attachment; filename=\"{}\"; filename*=UTF-8''{}\r\n",
+        fallback,
+        utf8_percent_encode(file_name, ATTR_CHAR)
+    )
+}
+
+fn encode_path(path: &str) -> String {
+    utf8_percent_encode(path, NON_ALPHANUMERIC).to_string()
+}
+
+// For text and double-quoted attributes, in HTML and XML alike
+fn escape_html(text: &str) -> String {
+    text.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;").replace('"', "&quot;")
+}
+
+// Like encode_path, but keeps the slashes between segments
+fn encode_url_path(path: &str) -> String {
+    path.split('/').map(encode_path).collect::<Vec<_>>().join("/")
+}
+
+fn decode_url_encoded(path: &str) -> String {
+    percent_decode(path.as_bytes()).decode_utf8_lossy().to_string()
+}
\ No newline at end of file
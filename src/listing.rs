@@ -0,0 +1,353 @@
+use std::{
+    cmp::Ordering,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use serde_json::json;
+
+use crate::{assets, escape_html, hidden::Hidden, watch};
+
+#[derive(Clone, Copy)]
+pub struct ListingOptions<'a> {
+    pub exact: bool,
+    pub asset_prefix: &'a str,
+    // Offer a form that uploads into the listed directory, and buttons that
+    // delete and rename its entries
+    pub upload: bool,
+    // Link to ZIP and tar archives of the listed directory
+    pub archive: bool,
+    // Column headings link to sorted copies of the listing
+    pub sortable: bool,
+    // None keeps the directory's own order, which lets rows stream as they're read
+    pub sort: Option<Sort>,
+    // An array of entries for scripts, in place of the page
+    pub json: bool,
+    // Include --watch's script that reloads the page on changes
+    pub reload: bool,
+    // Entries it hides are left out; `dir` is the listed directory's path from the root
+    pub hidden: &'a Hidden,
+    pub dir: &'a str,
+    // Link to the gallery view, which only pays off when most entries are images
+    pub gallery: bool,
+    // The filter can be submitted as a `?q=` search of the subdirectories
+    pub searchable: bool,
+    // Set for `?q=` results, which the page then says it is
+    pub search: Option<&'a str>,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum SortKey {
+    Name,
+    Size,
+    Mtime,
+}
+
+#[derive(Clone, Copy)]
+pub struct Sort {
+    pub key: SortKey,
+    pub descending: bool,
+}
+
+impl Sort {
+    // `?sort=name|size|mtime&order=asc|desc`, ascending unless told otherwise
+    pub fn from_query(sort: Option<&str>, order: Option<&str>) -> Option<Sort> {
+        let key = match sort? {
+            "name" => SortKey::Name,
+            "size" => SortKey::Size,
+            "mtime" => SortKey::Mtime,
+            _ => return None,
+        };
+        Some(Sort { key, descending: order == Some("desc") })
+    }
+
+    // Directories come first either way, as in file managers; equal keys fall
+    // back to the name so the order is stable across requests
+    pub fn apply(self, entries: &mut [&ListingEntry]) {
+        entries.sort_by(|a, b| {
+            let ordering = match self.key {
+                SortKey::Name => Ordering::Equal,
+                SortKey::Size => a.size.cmp(&b.size),
+                SortKey::Mtime => a.modified.cmp(&b.modified),
+            };
+            let ordering = ordering
+                .then_with(|| a.name.to_lowercase().cmp(&b.name.to_lowercase()))
+                .then_with(|| a.name.cmp(&b.name));
+            let ordering = if self.descending { ordering.reverse() } else { ordering };
+            b.is_dir.cmp(&a.is_dir).then(ordering)
+        });
+    }
+}
+
+pub struct ListingEntry {
+    // Already percent-encoded, without the trailing slash for directories
+    pub href: String,
+    pub name: String,
+    pub is_dir: bool,
+    pub size: Option<u64>,
+    pub modified: Option<SystemTime>,
+}
+
+pub fn header(title: &str, options: &ListingOptions) -> String {
+    if options.json {
+        return "[".to_string();
+    }
+    let title = escape_html(title);
+    let (title_line, value) = match options.search {
+        Some(search) => {
+            let search = escape_html(search);
+            (format!("Names containing \u{201c}{}\u{201d} under {}", search, title), search)
+        }
+        None => (format!("Directory listing for {}", title), String::new()),
+    };
+    // The filter narrows the rows on the page as it's typed in; submitted, it
+    // searches the directories below as well
+    let filter = match options.searchable {
+        true => format!(
+            "<form id=\"search\"><input id=\"filter\" name=\"q\" type=\"search\" value=\"{}\" \
+             placeholder=\"Filter (press /), Enter searches subdirectories\" autocomplete=\"off\"></form>",
+            value
+        ),
+        false => "<input id=\"filter\" type=\"search\" placeholder=\"Filter (press /)\" autocomplete=\"off\">".to_string(),
+    };
+    format!(
+        "<!DOCTYPE html><html><head><meta charset=\"utf-8\">\
+         <meta name=\"viewport\" content=\"width=device-width, initial-scale=1\">\
+         <title>{}</title><link rel=\"icon\" href=\"{}\"><link rel=\"stylesheet\" href=\"{}\"></head>\
+         <body><h1>{}</h1>{}\
+         <table><thead><tr>{}{}{}<th class=\"type\">Type</th>{}</tr></thead><tbody>",
+        title,
+        assets::url(options.asset_prefix, "favicon", "ico"),
+        assets::url(options.asset_prefix, "listing", "css"),
+        title_line,
+        filter,
+        heading("Name", "name", SortKey::Name, options),
+        heading("Size", "size", SortKey::Size, options),
+        heading("Modified", "mtime", SortKey::Mtime, options),
+        if options.upload { "<th class=\"actions\"></th>" } else { "" }
+    )
+}
+
+// The sorted column's heading links to the opposite order, the others to ascending
+fn heading(label: &str, key_name: &str, key: SortKey, options: &ListingOptions) -> String {
+    if !options.sortable {
+        return format!("<th class=\"{}\">{}</th>", key_name, label);
+    }
+    let (order, aria, arrow) = match options.sort.filter(|sort| sort.key == key) {
+        Some(sort) if sort.descending => ("asc", " aria-sort=\"descending\"", " ▼"),
+        Some(_) => ("desc", " aria-sort=\"ascending\"", " ▲"),
+        None => ("asc", "", ""),
+    };
+    format!(
+        "<th class=\"{}\"{}><a href=\"?sort={}&amp;order={}\">{}{}</a></th>",
+        key_name, aria, key_name, order, label, arrow
+    )
+}
+
+pub fn footer(options: &ListingOptions) -> String {
+    if options.json {
+        return "]\n".to_string();
+    }
+    // Posts to the listing's own URL, which is the directory
+    let form = if options.upload {
+        "<form id=\"upload\" method=\"post\" enctype=\"multipart/form-data\">\
+         <input type=\"file\" name=\"file\" multiple required><button>Upload</button></form>"
+    } else {
+        ""
+    };
+    // Relative to the listing's URL, like the form
+    let archive = if options.archive {
+        "<p id=\"download\">Download all: <a href=\"?download=zip\">ZIP</a> · <a href=\"?download=tar\">tar</a></p>"
+    } else {
+        ""
+    };
+    let gallery = if options.gallery { "<p id=\"gallery\"><a href=\"?view=gallery\">Gallery view</a></p>" } else { "" };
+    format!(
+        "</tbody></table>{}{}{}<script src=\"{}\"></script>{}</body></html>",
+        gallery,
+        archive,
+        form,
+        assets::url(options.asset_prefix, "listing", "js"),
+        if options.reload { watch::script_tag(options.asset_prefix) } else { String::new() }
+    )
+}
+
+// Raw numbers go in data attributes so client-side sorting stays numeric
+// whichever display format is chosen
+pub fn entry(entry: &ListingEntry, options: &ListingOptions) -> String {
+    // Add trailing slash for directories in the listing
+    let slash = if entry.is_dir { "/" } else { "" };
+    let mut attributes = String::new();
+
+    let size = match entry.size {
+        Some(size) => {
+            attributes.push_str(&format!(" data-size=\"{}\"", size));
+            if options.exact { format!("{} bytes", size) } else { format_size(size) }
+        }
+        None => String::new(),
+    };
+
+    let modified = match entry.modified.and_then(unix_seconds) {
+        Some(seconds) => {
+            attributes.push_str(&format!(" data-mtime=\"{}\"", seconds));
+            if options.exact { format_iso8601(seconds) } else { format_relative(seconds) }
+        }
+        None => String::new(),
+    };
+
+    // listing.js sends these as DELETE and MOVE requests for the entry's link
+    let actions = match options.upload {
+        true => "<td class=\"actions\"><button data-action=\"rename\">Rename</button>\
+                 <button data-action=\"delete\">Delete</button></td>",
+        false => "",
+    };
+    format!(
+        "<tr{}><td class=\"name\"><a href=\"{}{}\">{}{}</a></td><td class=\"size\">{}</td>\
+         <td class=\"mtime\">{}</td><td class=\"type\">{}</td>{}</tr>",
+        attributes,
+        entry.href,
+        slash,
+        escape_html(&entry.name),
+        slash,
+        size,
+        modified,
+        escape_html(&kind(entry)),
+        actions
+    )
+}
+
+// Going by extension, which is all a listing has to go on without opening files
+fn kind(entry: &ListingEntry) -> String {
+    if entry.is_dir {
+        return "Directory".to_string();
+    }
+    match entry.name.rsplit_once('.') {
+        Some((stem, extension)) if !stem.is_empty() && !extension.is_empty() => extension.to_lowercase(),
+        _ => "File".to_string(),
+    }
+}
+
+// `size` is null for directories, matching the page's empty cells
+pub fn json_entry(entry: &ListingEntry) -> String {
+    let body = json!({
+        "name": entry.name,
+        "size": entry.size,
+        "mtime": entry.modified.and_then(unix_seconds).map(format_iso8601),
+        "is_dir": entry.is_dir,
+    });
+    body.to_string()
+}
+
+pub fn unix_seconds(time: SystemTime) -> Option<u64> {
+    time.duration_since(UNIX_EPOCH).ok().map(|duration| duration.as_secs())
+}
+
+pub fn format_size(bytes: u64) -> String {
+    const UNITS: [&str; 4] = ["KiB", "MiB", "GiB", "TiB"];
+
+    if bytes < 1024 {
+        return format!("{} B", bytes);
+    }
+
+    let mut size = bytes as f64 / 1024.0;
+    let mut unit = 0;
+    while size >= 1024.0 && unit < UNITS.len() - 1 {
+        size /= 1024.0;
+        unit += 1;
+    }
+    format!("{:.1} {}", size, UNITS[unit])
+}
+
+fn format_relative(seconds: u64) -> String {
+    let now = unix_seconds(SystemTime::now()).unwrap_or(0);
+    // Timestamps in the future (clock skew, touch -d) read as just now
+    let elapsed = now.saturating_sub(seconds);
+
+    let (count, unit) = match elapsed {
+        0..=59 => return "just now".to_string(),
+        60..=3599 => (elapsed / 60, "minute"),
+        3600..=86_399 => (elapsed / 3600, "hour"),
+        86_400..=2_591_999 => (elapsed / 86_400, "day"),
+        2_592_000..=31_535_999 => (elapsed / 2_592_000, "month"),
+        _ => (elapsed / 31_536_000, "year"),
+    };
+    let plural = if count == 1 { "" } else { "s" };
+    format!("{} {}{} ago", count, unit, plural)
+}
+
+pub fn format_iso8601(seconds: u64) -> String {
+    let (year, month, day) = civil_from_days((seconds / 86_400) as i64);
+    let time = seconds % 86_400;
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}Z",
+        year,
+        month,
+        day,
+        time / 3600,
+        time / 60 % 60,
+        time % 60
+    )
+}
+
+const MONTHS: [&str; 12] = ["Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec"];
+// Starting from 1970-01-01, a Thursday
+const WEEKDAYS: [&str; 7] = ["Thu", "Fri", "Sat", "Sun", "Mon", "Tue", "Wed"];
+
+// IMF-fixdate, the form HTTP headers use: `Sun, 06 Nov 1994 08:49:37 GMT`
+pub fn format_http_date(seconds: u64) -> String {
+    let days = seconds / 86_400;
+    let (year, month, day) = civil_from_days(days as i64);
+    let time = seconds % 86_400;
+    format!(
+        "{}, {:02} {} {:04} {:02}:{:02}:{:02} GMT",
+        WEEKDAYS[(days % 7) as usize],
+        day,
+        MONTHS[month as usize - 1],
+        year,
+        time / 3600,
+        time / 60 % 60,
+        time % 60
+    )
+}
+
+// Only IMF-fixdate, which every current client sends; anything else reads as
+// no date at all and so gets a full response
+pub fn parse_http_date(value: &str) -> Option<u64> {
+    let mut fields = value.split_whitespace().skip(1);
+    let day: u32 = fields.next()?.parse().ok()?;
+    let month = fields.next()?;
+    let month = MONTHS.iter().position(|name| *name == month)? as u32 + 1;
+    let year: i64 = fields.next()?.parse().ok()?;
+    let mut time = fields.next()?.split(':').map(|part| part.parse::<u64>().ok());
+    let (hour, minute, second) = (time.next()??, time.next()??, time.next()??);
+    if fields.next() != Some("GMT") || day == 0 || day > 31 || hour > 23 || minute > 59 || second > 60 {
+        return None;
+    }
+
+    let days = u64::try_from(days_from_civil(year, month, day)).ok()?;
+    Some(days * 86_400 + hour * 3600 + minute * 60 + second)
+}
+
+// The inverse of civil_from_days
+pub fn days_from_civil(year: i64, month: u32, day: u32) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let yoe = year.rem_euclid(400);
+    let mp = if month > 2 { month - 3 } else { month + 9 } as i64;
+    let doy = (153 * mp + 2) / 5 + day as i64 - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    era * 146_097 + doe - 719_468
+}
+
+// Days since 1970-01-01 to a proleptic Gregorian date, after Howard Hinnant's algorithm
+pub fn civil_from_days(days: i64) -> (i64, u32, u32) {
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = (doy - (153 * mp + 2) / 5 + 1) as u32;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 } as u32;
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+    (year, month, day)
+}
@@ -0,0 +1,55 @@
+use std::{fs, io, path::Path};
+
+use pulldown_cmark::{html, Options, Parser};
+
+use crate::{args::Args, assets, escape_html, query_param, send_error, Exchange, watch, write_response};
+
+// Rendering holds the whole document and its HTML in memory; bigger files go out as they are
+const MAX_RENDERED_SIZE: u64 = 4 * 1024 * 1024;
+
+// `?render=1` shows a .md file as a page; with --render-markdown that's the
+// default and `?render=0` gets the file itself. Downloads are never rendered.
+pub fn wanted(path: &Path, size: u64, query: &str, args: &Args) -> bool {
+    let is_markdown = path
+        .extension()
+        .and_then(|extension| extension.to_str())
+        .is_some_and(|extension| extension.eq_ignore_ascii_case("md") || extension.eq_ignore_ascii_case("markdown"));
+    let render = match query_param(query, "render") {
+        Some("1") => true,
+        Some("0") => false,
+        _ => args.render_markdown,
+    };
+    is_markdown && render && size <= MAX_RENDERED_SIZE && query_param(query, "download").is_none()
+}
+
+// `headers` holds the file's own, like Content-Language
+pub fn send(stream: &mut Exchange, path: &Path, args: &Args, headers: &str) -> io::Result<()> {
+    let asset_prefix = args.asset_prefix.as_str();
+    let source = match fs::read(path) {
+        Ok(source) => String::from_utf8_lossy(&source).into_owned(),
+        Err(e) => {
+            eprintln!("Error reading {}: {:?}", path.display(), e);
+            return send_error(stream, "500 Internal Server Error", "Internal Server Error");
+        }
+    };
+
+    let title = path.file_name().map_or(String::new(), |name| name.to_string_lossy().to_string());
+    let mut body = format!(
+        "<!DOCTYPE html><html><head><meta charset=\"utf-8\">\
+         <meta name=\"viewport\" content=\"width=device-width, initial-scale=1\">\
+         <title>{}</title><link rel=\"icon\" href=\"{}\"><link rel=\"stylesheet\" href=\"{}\"></head><body>\n",
+        escape_html(&title),
+        assets::url(asset_prefix, "favicon", "ico"),
+        assets::url(asset_prefix, "markdown", "css")
+    );
+    let options = Options::ENABLE_TABLES | Options::ENABLE_STRIKETHROUGH | Options::ENABLE_TASKLISTS | Options::ENABLE_FOOTNOTES;
+    html::push_html(&mut body, Parser::new_ext(&source, options));
+    body.push_str("<p><a href=\"?render=0\">View source</a></p>");
+    if args.watch {
+        body.push_str(&watch::script_tag(asset_prefix));
+    }
+    body.push_str("</body></html>\n");
+
+    let head = stream.response_head("200 OK", "text/html; charset=utf-8", body.len(), headers);
+    write_response(stream, &head, body.as_bytes())
+}
@@ -0,0 +1,243 @@
+use std::{
+    io,
+    net::{IpAddr, Ipv4Addr, SocketAddr, SocketAddrV4, UdpSocket},
+    thread,
+    time::Duration,
+};
+
+use socket2::{Domain, Protocol, Socket, Type};
+
+use crate::{args::Args, banner};
+
+const GROUP: Ipv4Addr = Ipv4Addr::new(224, 0, 0, 251);
+const PORT: u16 = 5353;
+const HOST: &str = "bounty.local";
+const INSTANCE: &str = "Bounty";
+const TTL: u32 = 120;
+
+const TYPE_A: u16 = 1;
+const TYPE_PTR: u16 = 12;
+const TYPE_TXT: u16 = 16;
+const TYPE_AAAA: u16 = 28;
+const TYPE_SRV: u16 = 33;
+const TYPE_ANY: u16 = 255;
+const CLASS_IN: u16 = 1;
+// On records only this host answers for, so caches drop what they had for them
+const CACHE_FLUSH: u16 = 0x8000;
+
+#[derive(Clone)]
+struct Record {
+    name: String,
+    kind: u16,
+    unique: bool,
+    data: Vec<u8>,
+}
+
+// `--mdns` answers multicast DNS (RFC 6762) for `bounty.local` and announces
+// an `_http._tcp` (or `_https._tcp`) service there (RFC 6763), so Bonjour and
+// Avahi browsers list it. Over IPv4 only, though the answers carry the IPv6
+// addresses too. Names aren't probed first, so two servers advertising at
+// once both claim `bounty.local`. The advertisement is withdrawn when the
+// returned value is dropped.
+pub struct Advertisement {
+    socket: UdpSocket,
+    records: Vec<Record>,
+}
+
+pub fn advertise(args: &Args, addresses: &[SocketAddr]) -> io::Result<Advertisement> {
+    let reachable: Vec<SocketAddr> =
+        banner::reachable(addresses).into_iter().filter(|address| !address.ip().is_loopback()).collect();
+    let port = match reachable.first() {
+        Some(address) => address.port(),
+        None => {
+            let message = "--mdns has nothing to advertise when listening on loopback only; try --bind 0.0.0.0";
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, message));
+        }
+    };
+    let records = records(args, &reachable, port);
+
+    // Other responders, Avahi's or the system's, share the port
+    let socket = Socket::new(Domain::IPV4, Type::DGRAM, Some(Protocol::UDP))?;
+    socket.set_reuse_address(true)?;
+    #[cfg(unix)]
+    socket.set_reuse_port(true)?;
+    socket.bind(&SocketAddr::from((Ipv4Addr::UNSPECIFIED, PORT)).into())?;
+    socket.join_multicast_v4(&GROUP, &Ipv4Addr::UNSPECIFIED)?;
+    socket.set_multicast_ttl_v4(255)?;
+    let socket: UdpSocket = socket.into();
+
+    let advertisement = Advertisement { socket, records };
+    let announcement = response(0, &[], &advertisement.records.iter().collect::<Vec<_>>(), &[], TTL);
+    let responder = advertisement.socket.try_clone()?;
+    let records = advertisement.records.clone();
+    thread::spawn(move || {
+        // Sent twice a second apart, in case the first one is lost
+        for _ in 0..2 {
+            let _ = responder.send_to(&announcement, SocketAddrV4::new(GROUP, PORT));
+            thread::sleep(Duration::from_secs(1));
+        }
+        respond(&responder, &records);
+    });
+    println!("Advertising {}://{}:{}/ over mDNS", args.scheme(), HOST, port);
+    Ok(advertisement)
+}
+
+impl Drop for Advertisement {
+    fn drop(&mut self) {
+        let goodbye = response(0, &[], &self.records.iter().collect::<Vec<_>>(), &[], 0);
+        let _ = self.socket.send_to(&goodbye, SocketAddrV4::new(GROUP, PORT));
+    }
+}
+
+fn records(args: &Args, reachable: &[SocketAddr], port: u16) -> Vec<Record> {
+    let service = format!("_{}._tcp.local", args.scheme());
+    let instance = format!("{}.{}", INSTANCE, service);
+
+    let mut srv = vec![0, 0, 0, 0];
+    srv.extend_from_slice(&port.to_be_bytes());
+    srv.extend(encode_name(HOST));
+    let mut records = vec![
+        Record { name: "_services._dns-sd._udp.local".to_string(), kind: TYPE_PTR, unique: false, data: encode_name(&service) },
+        Record { name: service, kind: TYPE_PTR, unique: false, data: encode_name(&instance) },
+        Record { name: instance.clone(), kind: TYPE_SRV, unique: true, data: srv },
+        Record { name: instance, kind: TYPE_TXT, unique: true, data: b"\x06path=/".to_vec() },
+    ];
+    let mut ips: Vec<IpAddr> = reachable.iter().map(SocketAddr::ip).collect();
+    ips.dedup();
+    for ip in ips {
+        let (kind, data) = match ip {
+            IpAddr::V4(ip) => (TYPE_A, ip.octets().to_vec()),
+            IpAddr::V6(ip) => (TYPE_AAAA, ip.octets().to_vec()),
+        };
+        records.push(Record { name: HOST.to_string(), kind, unique: true, data });
+    }
+    records
+}
+
+// Answers the questions about our names; the rest of the records go along
+// as additional ones, which saves a browser asking for each in turn
+fn respond(socket: &UdpSocket, records: &[Record]) {
+    let mut buffer = [0; 9000];
+    loop {
+        let (length, from) = match socket.recv_from(&mut buffer) {
+            Ok(received) => received,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(e) => {
+                eprintln!("Error reading mDNS queries, no longer answering them: {}", e);
+                return;
+            }
+        };
+        let packet = &buffer[..length];
+        let (id, questions) = match parse_query(packet) {
+            Some(query) => query,
+            None => continue,
+        };
+        let answers: Vec<&Record> = records
+            .iter()
+            .filter(|record| {
+                questions.iter().any(|(name, kind)| {
+                    name.eq_ignore_ascii_case(&record.name) && (*kind == record.kind || *kind == TYPE_ANY)
+                })
+            })
+            .collect();
+        if answers.is_empty() {
+            continue;
+        }
+        let additional: Vec<&Record> = records
+            .iter()
+            .filter(|record| record.kind != TYPE_PTR && !answers.iter().any(|answer| std::ptr::eq(*answer, *record)))
+            .collect();
+
+        // A resolver that isn't mDNS-aware asks from another port and expects
+        // a plain DNS reply, questions and all, sent back to it
+        let result = if from.port() == PORT {
+            socket.send_to(&response(0, &[], &answers, &additional, TTL), SocketAddrV4::new(GROUP, PORT))
+        } else {
+            socket.send_to(&response(id, &questions, &answers, &additional, TTL.min(10)), from)
+        };
+        if let Err(e) = result {
+            eprintln!("Error answering an mDNS query from {}: {}", from, e);
+        }
+    }
+}
+
+// The ID and questions of a query, or None for responses and malformed packets
+fn parse_query(packet: &[u8]) -> Option<(u16, Vec<(String, u16)>)> {
+    let word = |at: usize| packet.get(at..at + 2).map(|bytes| u16::from_be_bytes([bytes[0], bytes[1]]));
+    let id = word(0)?;
+    if word(2)? & 0x8000 != 0 {
+        return None;
+    }
+    let mut questions = Vec::new();
+    let mut at = 12;
+    for _ in 0..word(4)? {
+        let (name, end) = read_name(packet, at)?;
+        questions.push((name, word(end)?));
+        at = end + 4;
+    }
+    Some((id, questions))
+}
+
+// A possibly compressed name starting at `at`, and where what follows it starts
+fn read_name(packet: &[u8], mut at: usize) -> Option<(String, usize)> {
+    let mut labels = Vec::new();
+    let mut end = None;
+    // Enough for any name that fits in a packet, without looping forever on a bad one
+    for _ in 0..128 {
+        let length = *packet.get(at)? as usize;
+        match length {
+            0 => {
+                let name = labels.join(".");
+                return Some((name, end.unwrap_or(at + 1)));
+            }
+            _ if length & 0xc0 == 0xc0 => {
+                let pointer = (length & 0x3f) << 8 | *packet.get(at + 1)? as usize;
+                end.get_or_insert(at + 2);
+                at = pointer;
+            }
+            _ if length < 64 => {
+                let label = packet.get(at + 1..at + 1 + length)?;
+                labels.push(String::from_utf8_lossy(label).into_owned());
+                at += 1 + length;
+            }
+            _ => return None,
+        }
+    }
+    None
+}
+
+fn encode_name(name: &str) -> Vec<u8> {
+    let mut encoded = Vec::new();
+    for label in name.split('.') {
+        encoded.push(label.len() as u8);
+        encoded.extend_from_slice(label.as_bytes());
+    }
+    encoded.push(0);
+    encoded
+}
+
+fn response(id: u16, questions: &[(String, u16)], answers: &[&Record], additional: &[&Record], ttl: u32) -> Vec<u8> {
+    let mut packet = Vec::new();
+    packet.extend_from_slice(&id.to_be_bytes());
+    // A response, and authoritative
+    packet.extend_from_slice(&0x8400u16.to_be_bytes());
+    for count in [questions.len(), answers.len(), 0, additional.len()] {
+        packet.extend_from_slice(&(count as u16).to_be_bytes());
+    }
+    for (name, kind) in questions {
+        packet.extend(encode_name(name));
+        packet.extend_from_slice(&kind.to_be_bytes());
+        packet.extend_from_slice(&CLASS_IN.to_be_bytes());
+    }
+    for record in answers.iter().chain(additional) {
+        packet.extend(encode_name(&record.name));
+        packet.extend_from_slice(&record.kind.to_be_bytes());
+        // Replies to plain DNS resolvers, the ones echoing questions, mustn't set it
+        let class = if record.unique && questions.is_empty() { CLASS_IN | CACHE_FLUSH } else { CLASS_IN };
+        packet.extend_from_slice(&class.to_be_bytes());
+        packet.extend_from_slice(&ttl.to_be_bytes());
+        packet.extend_from_slice(&(record.data.len() as u16).to_be_bytes());
+        packet.extend_from_slice(&record.data);
+    }
+    packet
+}
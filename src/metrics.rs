@@ -0,0 +1,238 @@
+use std::{
+    collections::BTreeMap,
+    fmt::Write as _,
+    fs, io,
+    path::Path,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Mutex,
+    },
+    thread,
+    time::Duration,
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{access_log::Recorder, shutdown::Totals, Exchange, write_response};
+
+// Where the supervisor keeps what exited children served, next to the
+// `worker-<n>.json` files running ones keep up to date
+const RETIRED: &str = "retired.json";
+const PUBLISH_INTERVAL: Duration = Duration::from_secs(1);
+
+static RETIRING: Mutex<()> = Mutex::new(());
+
+// Upper bounds of the latency histogram's buckets, in seconds
+const BUCKETS: [f64; 11] = [0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0];
+
+// `--metrics` serves `<asset prefix>metrics` in Prometheus' text format. With
+// --processes each child publishes its numbers once a second, and whichever
+// child answers adds up all of them, so the totals are the whole server's.
+pub struct Metrics {
+    statuses: Mutex<BTreeMap<u16, u64>>,
+    body_bytes: AtomicU64,
+    // Per bucket rather than cumulative, with one more for slower requests
+    durations: [AtomicU64; BUCKETS.len() + 1],
+    duration_micros: AtomicU64,
+}
+
+impl Metrics {
+    pub fn new() -> Metrics {
+        Metrics {
+            statuses: Mutex::new(BTreeMap::new()),
+            body_bytes: AtomicU64::new(0),
+            durations: Default::default(),
+            duration_micros: AtomicU64::new(0),
+        }
+    }
+
+    // Responses the client left before are in the request total only
+    pub fn record(&self, response: &Recorder, duration: Duration) {
+        let status = match response.status() {
+            Some(status) => status,
+            None => return,
+        };
+        *self.statuses.lock().unwrap_or_else(|e| e.into_inner()).entry(status).or_insert(0) += 1;
+        self.body_bytes.fetch_add(response.body_bytes(), Ordering::Relaxed);
+        let seconds = duration.as_secs_f64();
+        let bucket = BUCKETS.iter().position(|&bound| seconds <= bound).unwrap_or(BUCKETS.len());
+        self.durations[bucket].fetch_add(1, Ordering::Relaxed);
+        self.duration_micros.fetch_add(duration.as_micros() as u64, Ordering::Relaxed);
+    }
+
+    // The process's numbers, joined by its siblings' when `worker_state` is
+    // the file the supervisor gave it
+    pub fn send(&self, stream: &mut Exchange, totals: &Totals, worker_state: Option<&Path>) -> io::Result<()> {
+        let snapshot = Snapshot::gather(totals, Some(self), worker_state);
+        let mut body = String::new();
+        let family = |body: &mut String, name: &str, kind: &str, help: &str| {
+            let _ = write!(body, "# HELP {} {}\n# TYPE {} {}\n", name, help, name, kind);
+        };
+
+        family(&mut body, "bounty_requests_total", "counter", "Requests read, answered or not.");
+        let _ = writeln!(body, "bounty_requests_total {}", snapshot.requests);
+        family(&mut body, "bounty_connections_total", "counter", "Connections accepted.");
+        let _ = writeln!(body, "bounty_connections_total {}", snapshot.connections);
+        family(&mut body, "bounty_open_connections", "gauge", "Connections accepted and not yet finished.");
+        let _ = writeln!(body, "bounty_open_connections {}", snapshot.open);
+        family(&mut body, "bounty_processes", "gauge", "Worker processes running.");
+        let _ = writeln!(body, "bounty_processes {}", snapshot.processes);
+        family(&mut body, "bounty_uptime_seconds", "gauge", "Seconds since the answering process started.");
+        let _ = writeln!(body, "bounty_uptime_seconds {}", totals.uptime().as_secs());
+
+        family(&mut body, "bounty_responses_total", "counter", "Responses sent, by status.");
+        for (status, count) in &snapshot.statuses {
+            let _ = writeln!(body, "bounty_responses_total{{status=\"{}\"}} {}", status, count);
+        }
+        family(&mut body, "bounty_response_bytes_total", "counter", "Response body bytes sent.");
+        let _ = writeln!(body, "bounty_response_bytes_total {}", snapshot.body_bytes);
+
+        family(&mut body, "bounty_request_duration_seconds", "histogram", "Time from a request's head to the end of its response.");
+        let mut cumulative = 0;
+        for (index, count) in snapshot.durations.iter().enumerate() {
+            cumulative += count;
+            let bound = BUCKETS.get(index).map_or("+Inf".to_string(), |bound| bound.to_string());
+            let _ = writeln!(body, "bounty_request_duration_seconds_bucket{{le=\"{}\"}} {}", bound, cumulative);
+        }
+        let seconds = snapshot.duration_micros as f64 / 1_000_000.0;
+        let _ = writeln!(body, "bounty_request_duration_seconds_sum {}", seconds);
+        let _ = writeln!(body, "bounty_request_duration_seconds_count {}", cumulative);
+
+        let head = stream.response_head("200 OK", "text/plain; version=0.0.4; charset=utf-8", body.len(), "Cache-Control: no-store\r\n");
+        write_response(stream, &head, body.as_bytes())
+    }
+}
+
+// What one process, or several added up, has served so far
+#[derive(Serialize, Deserialize, Default)]
+pub struct Snapshot {
+    pub requests: u64,
+    pub connections: u64,
+    pub open: u64,
+    pub processes: u64,
+    pub statuses: BTreeMap<u16, u64>,
+    pub body_bytes: u64,
+    pub durations: Vec<u64>,
+    pub duration_micros: u64,
+}
+
+// Exited children's numbers, and which worker files they came from so
+// nothing is counted twice while a file is on its way out
+#[derive(Serialize, Deserialize, Default)]
+struct Retired {
+    snapshot: Snapshot,
+    folded: Vec<String>,
+}
+
+impl Snapshot {
+    fn of(totals: &Totals, metrics: Option<&Metrics>) -> Snapshot {
+        let mut snapshot = Snapshot {
+            requests: totals.requests.load(Ordering::Relaxed),
+            connections: totals.connections.load(Ordering::Relaxed),
+            open: totals.open.load(Ordering::Relaxed),
+            processes: 1,
+            ..Snapshot::default()
+        };
+        if let Some(metrics) = metrics {
+            snapshot.statuses = metrics.statuses.lock().unwrap_or_else(|e| e.into_inner()).clone();
+            snapshot.body_bytes = metrics.body_bytes.load(Ordering::Relaxed);
+            snapshot.durations = metrics.durations.iter().map(|count| count.load(Ordering::Relaxed)).collect();
+            snapshot.duration_micros = metrics.duration_micros.load(Ordering::Relaxed);
+        }
+        snapshot
+    }
+
+    // This process's numbers, plus the rest of the supervisor's children's
+    // when `worker_state` is set
+    pub fn gather(totals: &Totals, metrics: Option<&Metrics>, worker_state: Option<&Path>) -> Snapshot {
+        let mut snapshot = Snapshot::of(totals, metrics);
+        let (own, dir) = match worker_state.and_then(|file| Some((file.file_name()?.to_str()?, file.parent()?))) {
+            Some(state) => state,
+            None => return snapshot,
+        };
+        // Siblings first: one retired meanwhile is then either read here or
+        // listed as folded in, never neither
+        let siblings: Vec<(String, Snapshot)> = fs::read_dir(dir)
+            .into_iter()
+            .flatten()
+            .filter_map(|entry| entry.ok()?.file_name().into_string().ok())
+            .filter(|name| name.starts_with("worker-") && name != own)
+            .filter_map(|name| Some((name.clone(), read_json(&dir.join(name))?)))
+            .collect();
+        let retired: Retired = read_json(&dir.join(RETIRED)).unwrap_or_default();
+        snapshot.add(&retired.snapshot);
+        for (_, sibling) in siblings.iter().filter(|(name, _)| !retired.folded.contains(name)) {
+            snapshot.add(sibling);
+        }
+        snapshot
+    }
+
+    fn add(&mut self, other: &Snapshot) {
+        self.requests += other.requests;
+        self.connections += other.connections;
+        self.open += other.open;
+        self.processes += other.processes;
+        for (status, count) in &other.statuses {
+            *self.statuses.entry(*status).or_insert(0) += count;
+        }
+        self.body_bytes += other.body_bytes;
+        if self.durations.len() < other.durations.len() {
+            self.durations.resize(other.durations.len(), 0);
+        }
+        for (total, count) in self.durations.iter_mut().zip(&other.durations) {
+            *total += count;
+        }
+        self.duration_micros += other.duration_micros;
+    }
+}
+
+fn read_json<T: for<'de> Deserialize<'de>>(path: &Path) -> Option<T> {
+    serde_json::from_slice(&fs::read(path).ok()?).ok()
+}
+
+// Written whole and renamed into place, so readers never see half a file
+fn write_json(path: &Path, value: &impl Serialize) -> io::Result<()> {
+    let partial = path.with_extension("partial");
+    fs::write(&partial, serde_json::to_vec(value)?)?;
+    fs::rename(&partial, path)
+}
+
+// A supervised child's side: keeps `file` up to date until the process has
+// stopped serving and its last connection is done
+pub fn publish(file: &Path, totals: &Totals, metrics: Option<&Metrics>) {
+    loop {
+        let serving = totals.serving.load(Ordering::SeqCst) || totals.open.load(Ordering::SeqCst) > 0;
+        if let Err(e) = write_json(file, &Snapshot::of(totals, metrics)) {
+            eprintln!("Error publishing metrics to {}: {}", file.display(), e);
+        }
+        if !serving {
+            return;
+        }
+        thread::sleep(PUBLISH_INTERVAL);
+    }
+}
+
+// The supervisor's side, once a child has exited: its last numbers join the
+// retired ones, and its file goes
+pub fn retire(file: &Path) -> io::Result<()> {
+    let (name, dir) = match (file.file_name().and_then(|name| name.to_str()), file.parent()) {
+        (Some(name), Some(dir)) => (name, dir),
+        _ => return Ok(()),
+    };
+    // Children exiting together mustn't both start from the same old file
+    let _retiring = RETIRING.lock().unwrap_or_else(|e| e.into_inner());
+    let mut retired: Retired = read_json(&dir.join(RETIRED)).unwrap_or_default();
+    if let Some(mut last) = read_json::<Snapshot>(file) {
+        last.open = 0;
+        last.processes = 0;
+        retired.snapshot.add(&last);
+        // Files already gone can't be listed any more; names are never reused
+        retired.folded.retain(|folded| dir.join(folded).exists());
+        retired.folded.push(name.to_string());
+        write_json(&dir.join(RETIRED), &retired)?;
+    }
+    match fs::remove_file(file) {
+        Err(e) if e.kind() != io::ErrorKind::NotFound => Err(e),
+        _ => Ok(()),
+    }
+}
@@ -0,0 +1,144 @@
+use std::{collections::HashMap, fs, io, path::Path};
+
+// Types for extensions whose files have no magic bytes to sniff, or whose
+// magic bytes say less than the extension does (a .docx sniffs as a ZIP)
+const BUILT_IN: &[(&str, &str)] = &[
+    ("html", "text/html"),
+    ("htm", "text/html"),
+    ("css", "text/css"),
+    ("js", "text/javascript"),
+    ("mjs", "text/javascript"),
+    ("json", "application/json"),
+    ("map", "application/json"),
+    ("webmanifest", "application/manifest+json"),
+    ("xml", "application/xml"),
+    ("xhtml", "application/xhtml+xml"),
+    ("rss", "application/rss+xml"),
+    ("atom", "application/atom+xml"),
+    ("svg", "image/svg+xml"),
+    ("txt", "text/plain"),
+    ("text", "text/plain"),
+    ("log", "text/plain"),
+    ("md", "text/markdown"),
+    ("markdown", "text/markdown"),
+    ("csv", "text/csv"),
+    ("tsv", "text/tab-separated-values"),
+    ("ics", "text/calendar"),
+    ("vtt", "text/vtt"),
+    ("toml", "application/toml"),
+    ("yaml", "application/yaml"),
+    ("yml", "application/yaml"),
+    ("wasm", "application/wasm"),
+    ("pdf", "application/pdf"),
+    ("zip", "application/zip"),
+    ("gz", "application/gzip"),
+    ("tar", "application/x-tar"),
+    ("7z", "application/x-7z-compressed"),
+    ("png", "image/png"),
+    ("jpg", "image/jpeg"),
+    ("jpeg", "image/jpeg"),
+    ("gif", "image/gif"),
+    ("webp", "image/webp"),
+    ("avif", "image/avif"),
+    ("ico", "image/x-icon"),
+    ("bmp", "image/bmp"),
+    ("woff", "font/woff"),
+    ("woff2", "font/woff2"),
+    ("ttf", "font/ttf"),
+    ("otf", "font/otf"),
+    ("mp3", "audio/mpeg"),
+    ("ogg", "audio/ogg"),
+    ("opus", "audio/opus"),
+    ("wav", "audio/wav"),
+    ("flac", "audio/flac"),
+    ("mp4", "video/mp4"),
+    ("webm", "video/webm"),
+    ("mkv", "video/x-matroska"),
+    ("mov", "video/quicktime"),
+    ("docx", "application/vnd.openxmlformats-officedocument.wordprocessingml.document"),
+    ("xlsx", "application/vnd.openxmlformats-officedocument.spreadsheetml.sheet"),
+    ("pptx", "application/vnd.openxmlformats-officedocument.presentationml.presentation"),
+    ("odt", "application/vnd.oasis.opendocument.text"),
+    ("epub", "application/epub+zip"),
+];
+
+// Served for whatever neither the extension nor the content identifies
+const FALLBACK: &str = "application/octet-stream";
+
+// Content types by file extension; `--mime-types FILE` adds to and overrides
+// the built-in table, and files with extensions in neither are sniffed
+pub struct MimeTypes {
+    overrides: HashMap<String, String>,
+}
+
+impl MimeTypes {
+    pub fn new() -> MimeTypes {
+        MimeTypes { overrides: HashMap::new() }
+    }
+
+    // The mime.types format of Apache and /etc: a type, then its extensions.
+    // Blank lines and `#` comments are skipped.
+    pub fn load(path: &Path) -> io::Result<MimeTypes> {
+        let mut overrides = HashMap::new();
+        for (index, line) in fs::read_to_string(path)?.lines().enumerate() {
+            let line = line.split('#').next().unwrap_or("");
+            let mut words = line.split_whitespace();
+            let content_type = match words.next() {
+                Some(content_type) => content_type,
+                None => continue,
+            };
+            if !content_type.contains('/') {
+                let message = format!("{}:{}: {:?} is not a type like text/plain", path.display(), index + 1, content_type);
+                return Err(io::Error::new(io::ErrorKind::InvalidData, message));
+            }
+            for extension in words {
+                let extension = extension.trim_start_matches('.').to_ascii_lowercase();
+                overrides.insert(extension, content_type.to_string());
+            }
+        }
+        Ok(MimeTypes { overrides })
+    }
+
+    pub fn override_count(&self) -> usize {
+        self.overrides.len()
+    }
+
+    // None when the extension is unknown, leaving it to sniffing
+    pub fn by_extension(&self, path: &Path) -> Option<String> {
+        let extension = path.extension()?.to_str()?.to_ascii_lowercase();
+        let content_type = match self.overrides.get(&extension) {
+            Some(content_type) => content_type.as_str(),
+            None => BUILT_IN.iter().find(|(known, _)| *known == extension).map(|(_, content_type)| *content_type)?,
+        };
+        Some(with_charset(content_type))
+    }
+
+    // `content` is the start of the file, for when the extension says nothing
+    pub fn for_file(&self, path: &Path, content: &[u8]) -> String {
+        self.by_extension(path).unwrap_or_else(|| sniff(content))
+    }
+}
+
+pub fn sniff(content: &[u8]) -> String {
+    with_charset(infer::get(content).map_or(FALLBACK, |mime| mime.mime_type()))
+}
+
+// Only reads the first few kilobytes
+pub fn sniff_file(path: &Path) -> String {
+    with_charset(infer::get_from_path(path).ok().flatten().map_or(FALLBACK, |mime| mime.mime_type()))
+}
+
+// Text is assumed to be UTF-8 rather than left to browsers to guess at
+fn with_charset(content_type: &str) -> String {
+    let essence = content_type.split(';').next().unwrap_or("").trim();
+    let textual = essence.starts_with("text/")
+        || essence.ends_with("+xml")
+        || essence.ends_with("+json")
+        || ["application/json", "application/xml", "application/javascript", "application/toml", "application/yaml"]
+            .contains(&essence);
+    if textual && !content_type.contains("charset=") {
+        format!("{}; charset=utf-8", content_type)
+    } else {
+        content_type.to_string()
+    }
+}
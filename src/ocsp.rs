@@ -0,0 +1,177 @@
+use std::{
+    io::{self, Read, Write},
+    ops::Range,
+};
+
+use ring::digest::{digest, SHA1_FOR_LEGACY_USE_ONLY};
+
+use crate::{der, listing, proxy};
+
+// 1.3.6.1.5.5.7.1.1
+const AUTHORITY_INFO_ACCESS: &[u8] = &[0x2b, 0x06, 0x01, 0x05, 0x05, 0x07, 0x01, 0x01];
+// 1.3.6.1.5.5.7.48.1, the access method naming a responder
+const OCSP: &[u8] = &[0x2b, 0x06, 0x01, 0x05, 0x05, 0x07, 0x30, 0x01];
+// 1.3.6.1.5.5.7.48.1.1
+const BASIC_RESPONSE: &[u8] = &[0x2b, 0x06, 0x01, 0x05, 0x05, 0x07, 0x30, 0x01, 0x01];
+// 1.3.14.3.2.26. Responders all take SHA-1 CertIDs, and not all take anything else.
+const SHA1: &[u8] = &[0x2b, 0x0e, 0x03, 0x02, 0x1a];
+
+// Anything bigger isn't a response for one certificate
+const MAX_RESPONSE: u64 = 64 * 1024;
+
+// Asking a responder about one certificate
+pub struct Query {
+    pub responder: String,
+    request: Vec<u8>,
+    serial: Vec<u8>,
+}
+
+impl Query {
+    // `responder` stands in for the one the certificate names, if any
+    pub fn new(certificate: &[u8], issuer: &[u8], responder: Option<&str>) -> Result<Query, String> {
+        let fields = der::certificate_fields(certificate).ok_or("unreadable certificate")?;
+        let issuer_fields = der::certificate_fields(issuer).ok_or("unreadable issuer certificate")?;
+        let responder = match responder {
+            Some(responder) => responder.to_string(),
+            None => named_responder(&fields).ok_or("the certificate names no OCSP responder")?,
+        };
+
+        // The serial, signature algorithm and issuer come first; the issuer's
+        // key is the bit string in its subjectPublicKeyInfo, the sixth field
+        let serial = fields.first().filter(|serial| serial.tag == der::INTEGER).ok_or("unreadable serial")?;
+        let issuer_name = fields.get(2).filter(|name| name.tag == der::SEQUENCE).ok_or("unreadable issuer")?;
+        let key = issuer_fields
+            .get(5)
+            .and_then(|info| der::elements(info.contents).nth(1))
+            .filter(|key| key.tag == der::BIT_STRING && !key.contents.is_empty())
+            .ok_or("unreadable issuer key")?;
+        let sha1 = |data: &[u8]| der::encode(der::OCTET_STRING, digest(&SHA1_FOR_LEGACY_USE_ONLY, data).as_ref());
+        let algorithm = der::encode(der::SEQUENCE, &[der::encode(der::OID, SHA1), vec![0x05, 0x00]].concat());
+        let cert_id = [
+            algorithm,
+            sha1(&der::encode(der::SEQUENCE, issuer_name.contents)),
+            // Past the count of unused bits
+            sha1(&key.contents[1..]),
+            der::encode(der::INTEGER, serial.contents),
+        ]
+        .concat();
+        // CertID in a Request, in the requestList, in the TBSRequest, in the OCSPRequest
+        let request = (0..5).fold(cert_id, |inner, _| der::encode(der::SEQUENCE, &inner));
+        Ok(Query { responder, request, serial: serial.contents.to_vec() })
+    }
+
+    // Responders speak plain HTTP; what they say is signed, so nothing's lost
+    pub fn fetch(&self) -> io::Result<Vec<u8>> {
+        let invalid = |message: String| io::Error::new(io::ErrorKind::InvalidData, message);
+        let upstream = proxy::Upstream::parse(&self.responder)
+            .ok_or_else(|| invalid("the responder isn't an http:// URL".to_string()))?;
+        let mut stream = proxy::connect(upstream.authority())?;
+        write!(
+            stream,
+            "POST {} HTTP/1.0\r\nHost: {}\r\nContent-Type: application/ocsp-request\r\nContent-Length: {}\r\n\r\n",
+            upstream.path(),
+            upstream.authority(),
+            self.request.len()
+        )?;
+        stream.write_all(&self.request)?;
+
+        let mut response = Vec::new();
+        stream.take(MAX_RESPONSE).read_to_end(&mut response)?;
+        let end = response
+            .windows(4)
+            .position(|window| window == b"\r\n\r\n")
+            .ok_or_else(|| invalid("malformed HTTP response".to_string()))?;
+        let head = String::from_utf8_lossy(&response[..end]).into_owned();
+        let status_line = head.lines().next().unwrap_or("");
+        if status_line.split(' ').nth(1) != Some("200") {
+            return Err(invalid(format!("the responder answered {}", status_line)));
+        }
+        Ok(response.split_off(end + 4))
+    }
+
+    // When a response is due for replacing, by its nextUpdate if it has one.
+    // Refusals and certificates in anything but good standing aren't stapled,
+    // and neither is a response that's out of date `now`.
+    pub fn check(&self, response: &[u8], now: u64) -> Result<Option<u64>, String> {
+        let malformed = || "malformed OCSP response".to_string();
+        let response = der::read(&mut &response[..]).filter(|response| response.tag == der::SEQUENCE).ok_or_else(malformed)?;
+        let mut parts = der::elements(response.contents);
+        match parts.next() {
+            Some(status) if status.tag == der::ENUMERATED && status.contents == [0] => {}
+            Some(status) if status.tag == der::ENUMERATED => {
+                return Err(format!("the responder refused, status {}", status.contents.first().copied().unwrap_or(0)))
+            }
+            _ => return Err(malformed()),
+        }
+        let bytes = parts
+            .next()
+            .filter(|bytes| bytes.tag == 0xa0)
+            .and_then(|bytes| der::read(&mut &bytes.contents[..]))
+            .ok_or_else(malformed)?;
+        let mut bytes = der::elements(bytes.contents);
+        bytes.next().filter(|kind| kind.tag == der::OID && kind.contents == BASIC_RESPONSE).ok_or("not a basic OCSP response")?;
+        let basic = bytes
+            .next()
+            .filter(|basic| basic.tag == der::OCTET_STRING)
+            .and_then(|basic| der::read(&mut &basic.contents[..]))
+            .ok_or_else(malformed)?;
+        let data = der::read(&mut &basic.contents[..]).ok_or_else(malformed)?;
+        // Past the optional version, the responder's ID and the production time
+        let responses = der::elements(data.contents).find(|field| field.tag == der::SEQUENCE).ok_or_else(malformed)?;
+
+        for single in der::elements(responses.contents) {
+            let mut fields = der::elements(single.contents);
+            let cert_id = fields.next().ok_or_else(malformed)?;
+            if der::elements(cert_id.contents).nth(3).is_none_or(|serial| serial.contents != self.serial) {
+                continue;
+            }
+            match fields.next().map(|status| status.tag) {
+                Some(0x80) => {}
+                Some(0xa1) => return Err("the certificate is revoked".to_string()),
+                _ => return Err("the responder doesn't know the certificate".to_string()),
+            }
+            // thisUpdate, then the optional nextUpdate
+            fields.next();
+            let next_update = fields
+                .next()
+                .filter(|next| next.tag == 0xa0)
+                .and_then(|next| der::read(&mut &next.contents[..]))
+                .filter(|time| time.tag == der::GENERALIZED_TIME)
+                .and_then(|time| generalized_time(time.contents));
+            if next_update.is_some_and(|next| next <= now) {
+                return Err("the response is out of date".to_string());
+            }
+            return Ok(next_update);
+        }
+        Err("the response says nothing about the certificate".to_string())
+    }
+}
+
+// The first OCSP responder in the authorityInfoAccess extension
+fn named_responder(fields: &[der::Element]) -> Option<String> {
+    let mut access = der::extension(fields, AUTHORITY_INFO_ACCESS)?;
+    let descriptions = der::read(&mut access)?;
+    der::elements(descriptions.contents).find_map(|description| {
+        let mut parts = der::elements(description.contents);
+        parts.next().filter(|method| method.tag == der::OID && method.contents == OCSP)?;
+        // [6], a uniformResourceIdentifier
+        let location = parts.next().filter(|location| location.tag == 0x86)?;
+        String::from_utf8(location.contents.to_vec()).ok()
+    })
+}
+
+// `20261014045451Z`, as seconds since the epoch; fractions of a second are dropped
+fn generalized_time(value: &[u8]) -> Option<u64> {
+    let value = std::str::from_utf8(value).ok()?;
+    if !value.ends_with('Z') || !value.get(..14)?.bytes().all(|byte| byte.is_ascii_digit()) {
+        return None;
+    }
+    let number = |range: Range<usize>| value[range].parse::<u32>().ok();
+    let (year, month, day) = (number(0..4)?, number(4..6)?, number(6..8)?);
+    let (hour, minute, second) = (number(8..10)? as u64, number(10..12)? as u64, number(12..14)? as u64);
+    if !(1..=12).contains(&month) || day == 0 || day > 31 || hour > 23 || minute > 59 || second > 60 {
+        return None;
+    }
+    let days = u64::try_from(listing::days_from_civil(year as i64, month, day)).ok()?;
+    Some(days * 86_400 + hour * 3600 + minute * 60 + second)
+}
@@ -0,0 +1,128 @@
+use std::{
+    ffi::{c_char, c_int, c_void, CString},
+    path::PathBuf,
+    ptr,
+    sync::mpsc,
+    thread,
+    time::Duration,
+};
+
+use crate::{is_account_name, users::User};
+
+const PAM_SUCCESS: c_int = 0;
+const PAM_BUF_ERR: c_int = 5;
+const PAM_CONV_ERR: c_int = 19;
+const PAM_PROMPT_ECHO_OFF: c_int = 1;
+const PAM_PROMPT_ECHO_ON: c_int = 2;
+
+// Modules can stall (delays after failures, unreachable network backends), and
+// a login that takes longer than this is treated as failed
+const LOGIN_TIMEOUT: Duration = Duration::from_secs(10);
+
+#[repr(C)]
+struct PamMessage {
+    msg_style: c_int,
+    msg: *const c_char,
+}
+
+#[repr(C)]
+struct PamResponse {
+    resp: *mut c_char,
+    resp_retcode: c_int,
+}
+
+#[repr(C)]
+struct PamConv {
+    conv: extern "C" fn(c_int, *mut *const PamMessage, *mut *mut PamResponse, *mut c_void) -> c_int,
+    appdata_ptr: *mut c_void,
+}
+
+#[link(name = "pam")]
+extern "C" {
+    fn pam_start(service: *const c_char, user: *const c_char, conv: *const PamConv, handle: *mut *mut c_void) -> c_int;
+    fn pam_authenticate(handle: *mut c_void, flags: c_int) -> c_int;
+    fn pam_acct_mgmt(handle: *mut c_void, flags: c_int) -> c_int;
+    fn pam_end(handle: *mut c_void, status: c_int) -> c_int;
+}
+
+extern "C" {
+    fn calloc(count: usize, size: usize) -> *mut c_void;
+    fn strdup(s: *const c_char) -> *mut c_char;
+}
+
+// System accounts are read-only and unlimited; their root comes from the
+// `--pam-root` pattern and has to exist
+pub fn login(service: &str, root_pattern: &str, name: &str, password: &str) -> Option<User> {
+    if !is_account_name(name) {
+        return None;
+    }
+    let root = PathBuf::from(root_pattern.replacen('*', name, 1)).canonicalize().ok()?;
+    if !root.is_dir() {
+        return None;
+    }
+
+    // The conversation runs on its own thread so a hung module costs that
+    // thread rather than the worker, which gives up after LOGIN_TIMEOUT
+    let (service, user, password) =
+        (CString::new(service).ok()?, CString::new(name).ok()?, CString::new(password).ok()?);
+    let (sender, receiver) = mpsc::channel();
+    thread::spawn(move || {
+        let _ = sender.send(authenticate(&service, &user, &password));
+    });
+    if !receiver.recv_timeout(LOGIN_TIMEOUT).unwrap_or(false) {
+        return None;
+    }
+
+    Some(User { name: name.to_string(), root, can_write: false, quota: None })
+}
+
+fn authenticate(service: &CString, user: &CString, password: &CString) -> bool {
+    let conv = PamConv { conv: converse, appdata_ptr: password.as_ptr() as *mut c_void };
+    let mut handle = ptr::null_mut();
+
+    // SAFETY: every pointer handed to PAM outlives the handle, which is ended
+    // before returning
+    unsafe {
+        let mut status = pam_start(service.as_ptr(), user.as_ptr(), &conv, &mut handle);
+        if status != PAM_SUCCESS {
+            return false;
+        }
+        status = pam_authenticate(handle, 0);
+        // Expired or locked accounts pass authentication but not this
+        if status == PAM_SUCCESS {
+            status = pam_acct_mgmt(handle, 0);
+        }
+        pam_end(handle, status);
+        status == PAM_SUCCESS
+    }
+}
+
+// Answers every prompt with the password. PAM frees the responses, so they are
+// allocated with the C allocator.
+extern "C" fn converse(
+    count: c_int,
+    messages: *mut *const PamMessage,
+    responses: *mut *mut PamResponse,
+    password: *mut c_void,
+) -> c_int {
+    if count <= 0 || messages.is_null() || responses.is_null() {
+        return PAM_CONV_ERR;
+    }
+
+    // SAFETY: Linux-PAM passes an array of `count` message pointers, and
+    // `password` is the NUL-terminated string set up in authenticate
+    unsafe {
+        let replies = calloc(count as usize, std::mem::size_of::<PamResponse>()) as *mut PamResponse;
+        if replies.is_null() {
+            return PAM_BUF_ERR;
+        }
+        for i in 0..count as usize {
+            let message = &**messages.add(i);
+            if matches!(message.msg_style, PAM_PROMPT_ECHO_OFF | PAM_PROMPT_ECHO_ON) {
+                (*replies.add(i)).resp = strdup(password as *const c_char);
+            }
+        }
+        *responses = replies;
+    }
+    PAM_SUCCESS
+}
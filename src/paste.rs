@@ -0,0 +1,188 @@
+use std::{
+    collections::hash_map::RandomState,
+    fs::{self, OpenOptions},
+    hash::{BuildHasher, Hasher},
+    io::{self, ErrorKind, Write},
+    path::{Path, PathBuf},
+    thread,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use crate::{
+    args::Args, encode_url_path, form_field, query_param, read_body, request_header, send_error,
+    Exchange, write_response,
+};
+
+const MAX_PASTE_SIZE: u64 = 1024 * 1024;
+const NAME_LENGTH: usize = 6;
+const NAME_ALPHABET: &[u8] = b"abcdefghijkmnpqrstuvwxyz23456789";
+
+// Expired pastes linger at most this long before being removed
+const SWEEP_INTERVAL: Duration = Duration::from_secs(30);
+
+const FORM: &str = r#"<!DOCTYPE html>
+<html>
+<head><meta charset="utf-8"><meta name="viewport" content="width=device-width"><title>Paste</title></head>
+<body>
+<form method="post" action="/_paste">
+<p><textarea name="text" rows="20" cols="80" autofocus required></textarea></p>
+<p><label>Expires <select name="expires">
+<option value="">never</option><option value="1h">in an hour</option><option value="1d">in a day</option><option value="7d">in a week</option>
+</select></label> <button>Paste</button></p>
+</form>
+</body>
+</html>
+"#;
+
+// `GET /_paste` is the form, and HEAD its head; `POST /_paste` stores the
+// text, either a raw body (curl --data-binary @file) or the form's fields. An
+// `expires` query parameter or form field takes a duration like 30m, 1h or 7d.
+pub fn handle(
+    stream: &mut Exchange,
+    method: &str,
+    request: &str,
+    received: &[u8],
+    query: &str,
+    paste_dir: &str,
+    args: &Args,
+) -> io::Result<()> {
+    match method {
+        "GET" | "HEAD" => {
+            let head = stream.response_head("200 OK", "text/html; charset=utf-8", FORM.len(), "");
+            return write_response(stream, &head, FORM.as_bytes());
+        }
+        "POST" => {}
+        _ => return send_error(stream, "405 Method Not Allowed", "Method Not Allowed"),
+    }
+
+    let length = match request_header(request, "Content-Length").and_then(|length| length.parse::<u64>().ok()) {
+        Some(length) if length > MAX_PASTE_SIZE => {
+            return send_error(stream, "413 Payload Too Large", "Payload Too Large")
+        }
+        Some(length) => length,
+        None => return send_error(stream, "411 Length Required", "Length Required"),
+    };
+    let body = match read_body(stream, request, received, length) {
+        Ok(body) => body,
+        Err(e) => {
+            eprintln!("Error reading paste body: {:?}", e);
+            return send_error(stream, "400 Bad Request", "Bad Request");
+        }
+    };
+
+    // curl labels every --data-binary body as a form, so only a `text` field makes it one
+    let is_urlencoded = request_header(request, "Content-Type")
+        .is_some_and(|content_type| content_type.starts_with("application/x-www-form-urlencoded"));
+    let form = is_urlencoded.then(|| String::from_utf8_lossy(&body).to_string());
+    let form_text = form.as_deref().and_then(|form| form_field(form, "text"));
+    let is_form = form_text.is_some();
+    let form_expires = form.as_deref().filter(|_| is_form).and_then(|form| form_field(form, "expires"));
+    let text = form_text.map_or(body, String::into_bytes);
+
+    let expires = match form_expires.as_deref().or_else(|| query_param(query, "expires")).filter(|e| !e.is_empty()) {
+        Some(expires) => match parse_duration(expires) {
+            Some(duration) => Some(SystemTime::now() + duration),
+            None => return send_error(stream, "400 Bad Request", "Bad expires duration"),
+        },
+        None => None,
+    };
+
+    let dir = args.root.join(paste_dir);
+    fs::create_dir_all(&dir)?;
+    let name = store(&dir, &text, expires)?;
+
+    let address = args.address().to_string();
+    let host = request_header(request, "Host").unwrap_or(&address);
+    let location = format!("/{}/{}", encode_url_path(paste_dir), name);
+    let url = format!("{}://{}{}\n", args.scheme(), host, location);
+    let headers = format!("Location: {}\r\n", location);
+
+    // Browsers go look at the paste; scripts get its URL as the body
+    let status = if is_form { "303 See Other" } else { "201 Created" };
+    let response = stream.response_head(status, "text/plain; charset=utf-8", url.len(), &headers) + &url;
+    stream.write_all(response.as_bytes())?;
+    stream.flush()
+}
+
+// Removes expired pastes every SWEEP_INTERVAL for as long as the process runs
+pub fn spawn_sweeper(dir: PathBuf) {
+    thread::spawn(move || loop {
+        sweep(&dir);
+        thread::sleep(SWEEP_INTERVAL);
+    });
+}
+
+fn store(dir: &Path, text: &[u8], expires: Option<SystemTime>) -> io::Result<String> {
+    loop {
+        let name = format!("{}.txt", random_name());
+        let path = dir.join(&name);
+        // create_new fails instead of overwriting when the name is already taken
+        let mut file = match OpenOptions::new().write(true).create_new(true).open(&path) {
+            Ok(file) => file,
+            Err(e) if e.kind() == ErrorKind::AlreadyExists => continue,
+            Err(e) => return Err(e),
+        };
+
+        if let Some(expires) = expires {
+            let seconds = expires.duration_since(UNIX_EPOCH).map_or(0, |since| since.as_secs());
+            fs::write(expiry_path(&path), seconds.to_string())?;
+        }
+        file.write_all(text)?;
+        return Ok(name);
+    }
+}
+
+// A paste's expiry lives beside it as `.<name>.expires`, holding a Unix timestamp
+fn expiry_path(path: &Path) -> PathBuf {
+    let name = path.file_name().unwrap_or_default().to_string_lossy();
+    path.with_file_name(format!(".{}.expires", name))
+}
+
+fn sweep(dir: &Path) {
+    let entries = match fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(_) => return,
+    };
+    let now = SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |since| since.as_secs());
+
+    for entry in entries.flatten() {
+        let file_name = entry.file_name().to_string_lossy().to_string();
+        let paste = match file_name.strip_prefix('.').and_then(|name| name.strip_suffix(".expires")) {
+            Some(paste) => dir.join(paste),
+            None => continue,
+        };
+        let expires = fs::read_to_string(entry.path()).ok().and_then(|expires| expires.trim().parse::<u64>().ok());
+        if expires.is_some_and(|expires| expires <= now) {
+            let _ = fs::remove_file(&paste);
+            let _ = fs::remove_file(entry.path());
+        }
+    }
+}
+
+// Not meant to be unguessable, only short and unlikely to collide
+pub fn random_name() -> String {
+    let mut hasher = RandomState::new().build_hasher();
+    hasher.write_u128(SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |since| since.as_nanos()));
+    let mut bits = hasher.finish();
+
+    (0..NAME_LENGTH)
+        .map(|_| {
+            let c = NAME_ALPHABET[(bits % NAME_ALPHABET.len() as u64) as usize];
+            bits /= NAME_ALPHABET.len() as u64;
+            c as char
+        })
+        .collect()
+}
+
+pub fn parse_duration(value: &str) -> Option<Duration> {
+    let unit = value.chars().last()?;
+    let number = &value[..value.len() - unit.len_utf8()];
+    let multiplier = match unit {
+        's' => 1,
+        'm' => 60,
+        'h' => 3600,
+        'd' => 86_400,
+        _ => return None,
+    };
+    Some(Duration::from_secs(number.parse::<u64>().ok()?.checked_mul(multiplier)?))
+}
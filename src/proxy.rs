@@ -0,0 +1,255 @@
+use std::{
+    io::{self, BufReader, Read, Write},
+    net::{IpAddr, TcpStream, ToSocketAddrs},
+    time::Duration,
+};
+
+use crate::{
+    args::Args,
+    body_reader,
+    chunked::{ChunkedReader, ChunkedWriter},
+    request_header, send_error, Exchange,
+};
+
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(10);
+// How long the upstream may go quiet, before its response or partway through it
+const READ_TIMEOUT: Duration = Duration::from_secs(60);
+const MAX_HEAD_SIZE: usize = 64 * 1024;
+
+// Meaningful for a single connection only (RFC 9110 section 7.6.1), so never
+// passed along; Upgrade goes too, which is why WebSockets can't be proxied
+const HOP_BY_HOP: [&str; 9] = [
+    "connection",
+    "keep-alive",
+    "proxy-connection",
+    "proxy-authorization",
+    "te",
+    "trailer",
+    "transfer-encoding",
+    "upgrade",
+    "expect",
+];
+
+// `--proxy /api=http://localhost:3000` forwards /api and everything under it
+// there, as it was asked for. A URL with a path stands in for the prefix:
+// with `http://localhost:3000/v1`, `/api/users` is forwarded as `/v1/users`.
+// Only plain HTTP upstreams, since this is for development servers.
+pub struct Upstream {
+    // `host:port` to connect to, which is also the Host header sent
+    authority: String,
+    base: Option<String>,
+}
+
+impl Upstream {
+    pub fn parse(url: &str) -> Option<Upstream> {
+        let rest = url.strip_prefix("http://")?;
+        let (authority, base) = match rest.find('/') {
+            Some(slash) => (&rest[..slash], Some(rest[slash..].trim_end_matches('/').to_string())),
+            None => (rest, None),
+        };
+        if authority.is_empty() || authority.contains(['@', '?', '#']) {
+            return None;
+        }
+        let authority = match authority.rsplit_once(':') {
+            Some((_, port)) if port.parse::<u16>().is_ok() => authority.to_string(),
+            _ => format!("{}:80", authority),
+        };
+        Some(Upstream { authority, base })
+    }
+
+    pub fn authority(&self) -> &str {
+        &self.authority
+    }
+
+    // The URL's own path, for requests to the upstream itself
+    pub fn path(&self) -> &str {
+        self.base.as_deref().filter(|base| !base.is_empty()).unwrap_or("/")
+    }
+
+    pub fn url(&self) -> String {
+        format!("http://{}{}", self.authority, self.base.as_deref().unwrap_or(""))
+    }
+
+    // `rest` is what followed the prefix in `target`, without its leading slash
+    fn target(&self, target: &str, rest: &str) -> String {
+        match &self.base {
+            Some(base) => format!("{}/{}", base, rest),
+            None => target.to_string(),
+        }
+    }
+}
+
+// Relays the request upstream and its response back, streaming both bodies.
+// `rest` is the path after the prefix, still encoded, with any query.
+#[allow(clippy::too_many_arguments)]
+pub fn handle(
+    stream: &mut Exchange,
+    method: &str,
+    version: &str,
+    request: &str,
+    received: &[u8],
+    target: &str,
+    rest: &str,
+    upstream: &Upstream,
+    ip: IpAddr,
+    args: &Args,
+) -> io::Result<()> {
+    let mut upstream_stream = match connect(&upstream.authority) {
+        Ok(upstream_stream) => upstream_stream,
+        Err(e) => {
+            eprintln!("Error connecting to {}: {}", upstream.url(), e);
+            return send_error(stream, "502 Bad Gateway", "The upstream server isn't answering");
+        }
+    };
+
+    let head = forwarded_head(method, request, &upstream.target(target, rest), &upstream.authority, ip, args);
+    let length = request_header(request, "Content-Length").and_then(|length| length.parse::<u64>().ok()).unwrap_or(0);
+    let sent = upstream_stream.write_all(head.as_bytes()).and_then(|_| {
+        if length > 0 {
+            io::copy(&mut body_reader(stream, request, received, length)?, &mut upstream_stream)?;
+        }
+        upstream_stream.flush()
+    });
+    if let Err(e) = sent {
+        eprintln!("Error forwarding to {}: {}", upstream.url(), e);
+        stream.close_after_response();
+        return send_error(stream, "502 Bad Gateway", "The upstream server went away");
+    }
+
+    // Interim responses go through as they are; the final one has its
+    // connection headers replaced with this connection's
+    let mut pending = Vec::new();
+    loop {
+        let head = match read_head(&mut upstream_stream, &mut pending) {
+            Ok(head) => head,
+            Err(e) if matches!(e.kind(), io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut) => {
+                return send_error(stream, "504 Gateway Timeout", "The upstream server took too long");
+            }
+            Err(e) => {
+                eprintln!("Bad response from {}: {}", upstream.url(), e);
+                return send_error(stream, "502 Bad Gateway", "The upstream server sent a bad response");
+            }
+        };
+        let status = head.split(' ').nth(1).and_then(|status| status.parse::<u16>().ok()).unwrap_or(0);
+        if (100..200).contains(&status) {
+            stream.write_all(head.as_bytes())?;
+            continue;
+        }
+
+        // The upstream closes once it's done, so whatever it sends is the body.
+        // HTTP/1.0 clients can't take chunks: a chunked body is decoded for
+        // them and ends with the connection. HTTP/1.1 ones get an unframed
+        // body chunked, so the connection can carry on.
+        let chunked = request_header(&head, "Transfer-Encoding").is_some_and(|coding| coding.eq_ignore_ascii_case("chunked"));
+        let bodiless = status == 204 || status == 304 || method == "HEAD";
+        let mut body = io::Cursor::new(pending).chain(&mut upstream_stream);
+        if bodiless || (!chunked && request_header(&head, "Content-Length").is_some()) {
+            stream.write_all(relayed_head(&head, stream.connection(), false).as_bytes())?;
+            io::copy(&mut body, stream)?;
+        } else if chunked && version == "HTTP/1.0" {
+            stream.close_after_response();
+            stream.write_all(relayed_head(&head, stream.connection(), false).as_bytes())?;
+            io::copy(&mut ChunkedReader::new(BufReader::new(body)), stream)?;
+        } else if chunked {
+            stream.write_all(relayed_head(&head, stream.connection(), true).as_bytes())?;
+            io::copy(&mut body, stream)?;
+        } else if version == "HTTP/1.0" {
+            stream.close_after_response();
+            stream.write_all(relayed_head(&head, stream.connection(), false).as_bytes())?;
+            io::copy(&mut body, stream)?;
+        } else {
+            stream.write_all(relayed_head(&head, stream.connection(), true).as_bytes())?;
+            let mut writer = ChunkedWriter::new(&mut *stream);
+            io::copy(&mut body, &mut writer)?;
+            writer.finish()?;
+        }
+        return stream.flush();
+    }
+}
+
+pub fn connect(authority: &str) -> io::Result<TcpStream> {
+    let mut last_error = io::Error::new(io::ErrorKind::NotFound, "no addresses");
+    for address in authority.to_socket_addrs()? {
+        match TcpStream::connect_timeout(&address, CONNECT_TIMEOUT) {
+            Ok(stream) => {
+                stream.set_read_timeout(Some(READ_TIMEOUT))?;
+                stream.set_write_timeout(Some(READ_TIMEOUT))?;
+                return Ok(stream);
+            }
+            Err(e) => last_error = e,
+        }
+    }
+    Err(last_error)
+}
+
+// The client's headers, minus hop-by-hop ones and credentials meant for this
+// server, with the X-Forwarded-* set an upstream expects from a proxy
+fn forwarded_head(method: &str, request: &str, target: &str, authority: &str, ip: IpAddr, args: &Args) -> String {
+    let named_in_connection: Vec<String> = request_header(request, "Connection")
+        .unwrap_or("")
+        .split(',')
+        .map(|name| name.trim().to_ascii_lowercase())
+        .collect();
+    let ours = !args.auth.is_empty() || args.has_accounts();
+
+    let mut head = format!("{} {} HTTP/1.1\r\nHost: {}\r\n", method, target, authority);
+    let mut forwarded_for = ip.to_string();
+    for (name, value) in request.lines().skip(1).take_while(|line| !line.is_empty()).filter_map(|line| line.split_once(':')) {
+        let lower = name.trim().to_ascii_lowercase();
+        match lower.as_str() {
+            "host" => head.push_str(&format!("X-Forwarded-Host: {}\r\n", value.trim())),
+            "x-forwarded-for" => forwarded_for = format!("{}, {}", value.trim(), forwarded_for),
+            "x-forwarded-host" | "x-forwarded-proto" => {}
+            "authorization" if ours => {}
+            _ if HOP_BY_HOP.contains(&lower.as_str()) || named_in_connection.contains(&lower) => {}
+            _ => head.push_str(&format!("{}:{}\r\n", name, value)),
+        }
+    }
+    let proto = if args.tls_cert.is_some() { "https" } else { "http" };
+    head.push_str(&format!("X-Forwarded-For: {}\r\nX-Forwarded-Proto: {}\r\nConnection: close\r\n\r\n", forwarded_for, proto));
+    head
+}
+
+// `connection` is our own Connection header's value, which replaces upstream's.
+// The framing is ours too: chunked when `chunked`, and otherwise upstream's
+// Content-Length, unless a chunked upstream body made that meaningless.
+fn relayed_head(head: &str, connection: &str, chunked: bool) -> String {
+    let upstream_chunked = request_header(head, "Transfer-Encoding").is_some();
+    let mut lines = head.split("\r\n");
+    let mut relayed = format!("{}\r\n", lines.next().unwrap_or(""));
+    for line in lines.filter(|line| !line.is_empty()) {
+        let name = line.split_once(':').map_or(line, |(name, _)| name).trim().to_ascii_lowercase();
+        let framing = name == "transfer-encoding" || (name == "content-length" && (chunked || upstream_chunked));
+        if name != "connection" && name != "keep-alive" && !framing {
+            relayed.push_str(line);
+            relayed.push_str("\r\n");
+        }
+    }
+    if chunked {
+        relayed.push_str("Transfer-Encoding: chunked\r\n");
+    }
+    relayed.push_str(&format!("Connection: {}\r\n\r\n", connection));
+    relayed
+}
+
+// Leaves whatever was read past the head in `pending`
+fn read_head(upstream: &mut TcpStream, pending: &mut Vec<u8>) -> io::Result<String> {
+    let mut buffer = [0; 8192];
+    loop {
+        if let Some(end) = pending.windows(4).position(|window| window == b"\r\n\r\n").map(|end| end + 4) {
+            let head = String::from_utf8_lossy(&pending[..end]).into_owned();
+            pending.drain(..end);
+            if !head.starts_with("HTTP/1.") {
+                return Err(io::Error::new(io::ErrorKind::InvalidData, "not an HTTP/1 response"));
+            }
+            return Ok(head);
+        }
+        if pending.len() > MAX_HEAD_SIZE {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "response head too large"));
+        }
+        match upstream.read(&mut buffer)? {
+            0 => return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "closed before responding")),
+            n => pending.extend_from_slice(&buffer[..n]),
+        }
+    }
+}
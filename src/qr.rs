@@ -0,0 +1,368 @@
+// A QR code encoder, just enough for a URL: byte mode at error correction
+// level L, versions 1 to 9 (up to 230 bytes), whose lengths fit 8 bits.
+// Follows ISO/IEC 18004.
+
+// Per version, indexed from 1
+const ECC_CODEWORDS_PER_BLOCK: [usize; 10] = [0, 7, 10, 15, 20, 26, 18, 20, 24, 30];
+const ERROR_CORRECTION_BLOCKS: [usize; 10] = [0, 1, 1, 1, 1, 1, 2, 2, 2, 2];
+const MAX_VERSION: usize = 9;
+
+// Modules of a square symbol, true for dark
+pub struct Code {
+    size: usize,
+    modules: Vec<bool>,
+    // Finder, timing, alignment and format modules, which masks leave alone
+    reserved: Vec<bool>,
+}
+
+impl Code {
+    // None when `data` doesn't fit the largest version supported
+    pub fn encode(data: &[u8]) -> Option<Code> {
+        let version = (1..=MAX_VERSION).find(|&version| 4 + 8 + data.len() * 8 <= data_codewords(version) * 8)?;
+        let capacity = data_codewords(version);
+
+        // Mode indicator, length, the bytes, then terminator and padding
+        let mut bits = Vec::new();
+        push_bits(&mut bits, 0b0100, 4);
+        push_bits(&mut bits, data.len() as u32, 8);
+        for &byte in data {
+            push_bits(&mut bits, byte as u32, 8);
+        }
+        let terminator = (capacity * 8 - bits.len()).min(4);
+        push_bits(&mut bits, 0, terminator);
+        let padding = (8 - bits.len() % 8) % 8;
+        push_bits(&mut bits, 0, padding);
+        let mut codewords: Vec<u8> =
+            bits.chunks(8).map(|byte| byte.iter().fold(0, |value, &bit| value << 1 | bit as u8)).collect();
+        for pad in [0xEC, 0x11].iter().cycle() {
+            if codewords.len() >= capacity {
+                break;
+            }
+            codewords.push(*pad);
+        }
+
+        let size = version * 4 + 17;
+        let mut code = Code { size, modules: vec![false; size * size], reserved: vec![false; size * size] };
+        code.draw_function_patterns(version);
+        code.draw_codewords(&interleave(version, &codewords));
+
+        // The mask that leaves the fewest confusing patterns
+        let mask = (0..8)
+            .min_by_key(|&mask| {
+                code.apply_mask(mask);
+                code.draw_format_bits(mask);
+                let penalty = code.penalty();
+                code.apply_mask(mask);
+                penalty
+            })
+            .unwrap_or(0);
+        code.apply_mask(mask);
+        code.draw_format_bits(mask);
+        Some(code)
+    }
+
+    pub fn size(&self) -> usize {
+        self.size
+    }
+
+    // Outside the symbol is the light quiet zone
+    pub fn is_dark(&self, x: isize, y: isize) -> bool {
+        let size = self.size as isize;
+        (0..size).contains(&x) && (0..size).contains(&y) && self.modules[y as usize * self.size + x as usize]
+    }
+
+    fn set(&mut self, x: usize, y: usize, dark: bool) {
+        self.modules[y * self.size + x] = dark;
+        self.reserved[y * self.size + x] = true;
+    }
+
+    fn draw_function_patterns(&mut self, version: usize) {
+        let size = self.size;
+        for i in 0..size {
+            self.set(6, i, i % 2 == 0);
+            self.set(i, 6, i % 2 == 0);
+        }
+        for (x, y) in [(3, 3), (size - 4, 3), (3, size - 4)] {
+            self.draw_finder(x as isize, y as isize);
+        }
+
+        let positions = alignment_positions(version);
+        for &x in &positions {
+            for &y in &positions {
+                // Not over the finders
+                let corner = x == 6 && (y == 6 || y == size - 7) || x == size - 7 && y == 6;
+                if !corner {
+                    self.draw_alignment(x, y);
+                }
+            }
+        }
+
+        // Reserved now, written once the mask is known
+        self.draw_format_bits(0);
+        if version >= 7 {
+            let bits = (version as u32) << 12 | bch_remainder(version as u32, 0x1F25, 12);
+            for i in 0..18 {
+                let dark = bits >> i & 1 == 1;
+                let (a, b) = (size - 11 + i % 3, i / 3);
+                self.set(a, b, dark);
+                self.set(b, a, dark);
+            }
+        }
+    }
+
+    // The 7x7 target with its light separator, clipped at the edges
+    fn draw_finder(&mut self, x: isize, y: isize) {
+        for dy in -4..=4_isize {
+            for dx in -4..=4_isize {
+                let (px, py) = (x + dx, y + dy);
+                if (0..self.size as isize).contains(&px) && (0..self.size as isize).contains(&py) {
+                    let distance = dx.abs().max(dy.abs());
+                    self.set(px as usize, py as usize, distance != 2 && distance != 4);
+                }
+            }
+        }
+    }
+
+    fn draw_alignment(&mut self, x: usize, y: usize) {
+        for dy in -2..=2_isize {
+            for dx in -2..=2_isize {
+                let dark = dx.abs().max(dy.abs()) != 1;
+                self.set((x as isize + dx) as usize, (y as isize + dy) as usize, dark);
+            }
+        }
+    }
+
+    fn draw_format_bits(&mut self, mask: u32) {
+        // Level L is 01
+        let data = 0b01 << 3 | mask;
+        let bits = (data << 10 | bch_remainder(data, 0x537, 10)) ^ 0x5412;
+        let bit = |i: usize| bits >> i & 1 == 1;
+        let size = self.size;
+
+        for i in 0..=5 {
+            self.set(8, i, bit(i));
+        }
+        self.set(8, 7, bit(6));
+        self.set(8, 8, bit(7));
+        self.set(7, 8, bit(8));
+        for i in 9..15 {
+            self.set(14 - i, 8, bit(i));
+        }
+
+        for i in 0..8 {
+            self.set(size - 1 - i, 8, bit(i));
+        }
+        for i in 8..15 {
+            self.set(8, size - 15 + i, bit(i));
+        }
+        self.set(8, size - 8, true);
+    }
+
+    // Two columns at a time from the bottom right, snaking up and down and
+    // skipping the vertical timing pattern
+    fn draw_codewords(&mut self, codewords: &[u8]) {
+        let size = self.size;
+        let mut index = 0;
+        let mut right = size as isize - 1;
+        while right >= 1 {
+            if right == 6 {
+                right = 5;
+            }
+            for vertical in 0..size {
+                for offset in 0..2 {
+                    let x = right as usize - offset;
+                    let upward = (right + 1) & 2 == 0;
+                    let y = if upward { size - 1 - vertical } else { vertical };
+                    if !self.reserved[y * size + x] && index < codewords.len() * 8 {
+                        self.modules[y * size + x] = codewords[index / 8] >> (7 - index % 8) & 1 == 1;
+                        index += 1;
+                    }
+                }
+            }
+            right -= 2;
+        }
+    }
+
+    // Its own inverse, which is how encode tries each one
+    fn apply_mask(&mut self, mask: u32) {
+        for y in 0..self.size {
+            for x in 0..self.size {
+                let flip = match mask {
+                    0 => (x + y) % 2 == 0,
+                    1 => y % 2 == 0,
+                    2 => x % 3 == 0,
+                    3 => (x + y) % 3 == 0,
+                    4 => (x / 3 + y / 2) % 2 == 0,
+                    5 => x * y % 2 + x * y % 3 == 0,
+                    6 => (x * y % 2 + x * y % 3) % 2 == 0,
+                    _ => ((x + y) % 2 + x * y % 3) % 2 == 0,
+                };
+                if flip && !self.reserved[y * self.size + x] {
+                    self.modules[y * self.size + x] ^= true;
+                }
+            }
+        }
+    }
+
+    // The standard's four rules: runs, 2x2 blocks, finder lookalikes and balance
+    fn penalty(&self) -> usize {
+        let size = self.size;
+        let mut penalty = 0;
+        for transpose in [false, true] {
+            for a in 0..size {
+                let line: Vec<bool> = (0..size)
+                    .map(|b| if transpose { self.modules[b * size + a] } else { self.modules[a * size + b] })
+                    .collect();
+                let mut run = 1;
+                for i in 1..=size {
+                    if i < size && line[i] == line[i - 1] {
+                        run += 1;
+                        continue;
+                    }
+                    if run >= 5 {
+                        penalty += run - 2;
+                    }
+                    run = 1;
+                }
+                let finder = [true, false, true, true, true, false, true];
+                for i in 0..size.saturating_sub(6) {
+                    if line[i..i + 7] == finder {
+                        let light_before = i >= 4 && line[i - 4..i].iter().all(|&dark| !dark);
+                        let light_after = i + 11 <= size && line[i + 7..i + 11].iter().all(|&dark| !dark);
+                        if light_before || light_after {
+                            penalty += 40;
+                        }
+                    }
+                }
+            }
+        }
+        for y in 0..size - 1 {
+            for x in 0..size - 1 {
+                let color = self.modules[y * size + x];
+                if [(x + 1, y), (x, y + 1), (x + 1, y + 1)].iter().all(|&(x, y)| self.modules[y * size + x] == color) {
+                    penalty += 3;
+                }
+            }
+        }
+        let dark = self.modules.iter().filter(|&&dark| dark).count();
+        let total = size * size;
+        penalty + (dark * 20).abs_diff(total * 10) / total * 10
+    }
+}
+
+fn push_bits(bits: &mut Vec<bool>, value: u32, count: usize) {
+    bits.extend((0..count).rev().map(|i| value >> i & 1 == 1));
+}
+
+fn bch_remainder(data: u32, generator: u32, degree: u32) -> u32 {
+    let mut remainder = data << degree;
+    for bit in (degree..=degree + 5).rev() {
+        if remainder >> bit & 1 == 1 {
+            remainder ^= generator << (bit - degree);
+        }
+    }
+    remainder
+}
+
+// Modules left for data once the function patterns are drawn, in bits
+fn raw_data_modules(version: usize) -> usize {
+    let mut modules = (16 * version + 128) * version + 64;
+    if version >= 2 {
+        let alignments = version / 7 + 2;
+        modules -= (25 * alignments - 10) * alignments - 55;
+        if version >= 7 {
+            modules -= 36;
+        }
+    }
+    modules
+}
+
+fn data_codewords(version: usize) -> usize {
+    raw_data_modules(version) / 8 - ECC_CODEWORDS_PER_BLOCK[version] * ERROR_CORRECTION_BLOCKS[version]
+}
+
+fn alignment_positions(version: usize) -> Vec<usize> {
+    if version == 1 {
+        return Vec::new();
+    }
+    let count = version / 7 + 2;
+    let size = version * 4 + 17;
+    let step = (version * 4 + count * 2 + 1) / (count * 2 - 2) * 2;
+    let mut positions: Vec<usize> = (0..count - 1).map(|i| size - 7 - i * step).collect();
+    positions.push(6);
+    positions.reverse();
+    positions
+}
+
+// Splits the data into blocks, appends each block's error correction, and
+// interleaves them: data bytes column by column, then error correction bytes
+fn interleave(version: usize, data: &[u8]) -> Vec<u8> {
+    let blocks = ERROR_CORRECTION_BLOCKS[version];
+    let ecc_length = ECC_CODEWORDS_PER_BLOCK[version];
+    let raw_codewords = raw_data_modules(version) / 8;
+    let short_blocks = blocks - raw_codewords % blocks;
+    let short_length = raw_codewords / blocks - ecc_length;
+
+    let divisor = rs_divisor(ecc_length);
+    let mut split = Vec::new();
+    let mut start = 0;
+    for block in 0..blocks {
+        let length = short_length + usize::from(block >= short_blocks);
+        let data = &data[start..start + length];
+        split.push((data.to_vec(), rs_remainder(data, &divisor)));
+        start += length;
+    }
+
+    let mut result = Vec::with_capacity(raw_codewords);
+    for i in 0..=short_length {
+        for (data, _) in &split {
+            if let Some(&byte) = data.get(i) {
+                result.push(byte);
+            }
+        }
+    }
+    for i in 0..ecc_length {
+        for (_, ecc) in &split {
+            result.push(ecc[i]);
+        }
+    }
+    result
+}
+
+// Reed-Solomon over GF(256) with the polynomial 0x11D
+fn gf_multiply(x: u8, y: u8) -> u8 {
+    let mut product: u32 = 0;
+    for i in (0..8).rev() {
+        product = (product << 1) ^ ((product >> 7) * 0x11D);
+        product ^= ((y as u32 >> i) & 1) * x as u32;
+    }
+    product as u8
+}
+
+fn rs_divisor(degree: usize) -> Vec<u8> {
+    let mut divisor = vec![0; degree];
+    divisor[degree - 1] = 1;
+    let mut root = 1;
+    for _ in 0..degree {
+        for j in 0..degree {
+            divisor[j] = gf_multiply(divisor[j], root);
+            if j + 1 < degree {
+                divisor[j] ^= divisor[j + 1];
+            }
+        }
+        root = gf_multiply(root, 0x02);
+    }
+    divisor
+}
+
+fn rs_remainder(data: &[u8], divisor: &[u8]) -> Vec<u8> {
+    let mut remainder = vec![0; divisor.len()];
+    for &byte in data {
+        let factor = byte ^ remainder.remove(0);
+        remainder.push(0);
+        for (slot, &coefficient) in remainder.iter_mut().zip(divisor) {
+            *slot ^= gf_multiply(coefficient, factor);
+        }
+    }
+    remainder
+}
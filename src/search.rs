@@ -0,0 +1,74 @@
+use std::{
+    collections::HashSet,
+    fs, io,
+    path::PathBuf,
+};
+
+use walkdir::WalkDir;
+
+use crate::{
+    encode_url_path,
+    listing::{self, ListingEntry, ListingOptions},
+    Exchange,
+    write_response,
+};
+
+// Plenty to pick from; a query matching more than this wants narrowing
+const MAX_RESULTS: usize = 1000;
+
+// `?q=` on a directory lists what's below it with the query in its name,
+// ignoring case, as a listing of relative paths. `dirs` holds the directory
+// in each overlay layer, highest first, and a path is listed once from the
+// highest. Symlinks aren't followed, so the walk stays within the tree.
+pub fn send(
+    stream: &mut Exchange,
+    title: &str,
+    dirs: &[PathBuf],
+    search: &str,
+    options: &ListingOptions,
+    headers: &str,
+) -> io::Result<()> {
+    let needle = search.to_lowercase();
+    let mut seen = HashSet::new();
+    let mut results = Vec::new();
+    'layers: for dir in dirs {
+        let walker = WalkDir::new(dir).min_depth(1).sort_by_file_name().into_iter().filter_entry(|entry| {
+            let relative = entry.path().strip_prefix(dir).unwrap_or(entry.path()).to_string_lossy();
+            !options.hidden.hides(&format!("{}/{}", options.dir, relative))
+        });
+        // Unreadable directories are left out rather than failing the search
+        for entry in walker.filter_map(Result::ok) {
+            let name = entry.file_name().to_string_lossy();
+            if !name.to_lowercase().contains(&needle) {
+                continue;
+            }
+            let relative = entry.path().strip_prefix(dir).unwrap_or(entry.path()).to_string_lossy().into_owned();
+            if !seen.insert(relative.clone()) {
+                continue;
+            }
+            let info = fs::metadata(entry.path()).ok();
+            let is_dir = info.as_ref().is_some_and(|info| info.is_dir());
+            results.push(ListingEntry {
+                href: encode_url_path(&relative),
+                name: relative,
+                is_dir,
+                size: info.as_ref().filter(|_| !is_dir).map(|info| info.len()),
+                modified: info.and_then(|info| info.modified().ok()),
+            });
+            if results.len() == MAX_RESULTS {
+                break 'layers;
+            }
+        }
+    }
+
+    let mut body = listing::header(title, options);
+    let rows: Vec<String> = match options.json {
+        true => results.iter().map(listing::json_entry).collect(),
+        false => results.iter().map(|entry| listing::entry(entry, options)).collect(),
+    };
+    body.push_str(&rows.join(if options.json { "," } else { "" }));
+    body.push_str(&listing::footer(options));
+
+    let content_type = if options.json { "application/json" } else { "text/html" };
+    write_response(stream, &stream.response_head("200 OK", content_type, body.len(), headers), body.as_bytes())
+}
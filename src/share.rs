@@ -0,0 +1,210 @@
+use std::{
+    fs::{self, OpenOptions},
+    io::{self, Write},
+    path::{Path, PathBuf},
+    process,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use ring::{
+    hmac,
+    rand::{SecureRandom, SystemRandom},
+};
+
+use crate::{
+    args::Args, decode_url_encoded, encode_url_path, paste, query_param, request_header, send_error,
+    Exchange, users::User, write_response,
+};
+
+const COOKIE: &str = "bounty-share";
+const DEFAULT_LIFETIME: Duration = Duration::from_secs(86_400);
+const MAX_LIFETIME: Duration = Duration::from_secs(365 * 86_400);
+
+// `--share-key FILE` signs share links with the key in FILE, made the first
+// time it's needed. Keeping it in a file lets links outlive restarts and work
+// with every worker process; replacing it revokes every link made so far.
+pub struct ShareKey(hmac::Key);
+
+// What a valid token lets its bearer read: `path`, which ends in `/` for a
+// directory and everything below it, as resolved against `root`. `user` is
+// whoever made it, for servers with accounts.
+pub struct Share {
+    pub path: String,
+    pub root: PathBuf,
+    pub user: Option<String>,
+    expires: u64,
+    // Whether the token came with the URL, rather than in a cookie
+    from_query: bool,
+    token: String,
+}
+
+impl ShareKey {
+    pub fn load(path: &Path) -> io::Result<ShareKey> {
+        let encoded = match fs::read_to_string(path) {
+            Ok(encoded) => encoded,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => create(path)?,
+            Err(e) => return Err(e),
+        };
+        let bytes = URL_SAFE_NO_PAD.decode(encoded.trim()).ok().filter(|bytes| bytes.len() >= 32).ok_or_else(|| {
+            io::Error::new(io::ErrorKind::InvalidData, format!("{} doesn't hold a share key", path.display()))
+        })?;
+        Ok(ShareKey(hmac::Key::new(hmac::HMAC_SHA256, &bytes)))
+    }
+
+    fn mint(&self, path: &str, root: &str, user: Option<&str>, expires: u64) -> String {
+        let payload = format!("{}\0{}\0{}\0{}", expires, user.unwrap_or(""), root, path);
+        let tag = hmac::sign(&self.0, payload.as_bytes());
+        format!("{}.{}", URL_SAFE_NO_PAD.encode(&payload), URL_SAFE_NO_PAD.encode(tag.as_ref()))
+    }
+
+    fn verify(&self, token: &str, from_query: bool) -> Option<Share> {
+        let (payload, tag) = token.split_once('.')?;
+        let payload = URL_SAFE_NO_PAD.decode(payload).ok()?;
+        hmac::verify(&self.0, &payload, &URL_SAFE_NO_PAD.decode(tag).ok()?).ok()?;
+        let payload = String::from_utf8(payload).ok()?;
+        let mut fields = payload.splitn(4, '\0');
+        let expires = fields.next()?.parse::<u64>().ok()?;
+        let user = fields.next()?;
+        let (root, path) = (fields.next()?, fields.next()?);
+        if expires <= now() {
+            return None;
+        }
+        Some(Share {
+            path: path.to_string(),
+            root: PathBuf::from(root),
+            user: (!user.is_empty()).then(|| user.to_string()),
+            expires,
+            from_query,
+            token: token.to_string(),
+        })
+    }
+
+    // The share a GET or HEAD of `path` (decoded) is let in by: one whose
+    // token is the `?token=` parameter, or a cookie a directory share set
+    pub fn find(&self, request: &str, query: &str, path: &str) -> Option<Share> {
+        // A share of a directory is no way out of it
+        if path.split('/').any(|segment| segment == ".." || segment == ".") {
+            return None;
+        }
+        let cookies = request_header(request, "Cookie").unwrap_or("").split(';').filter_map(|cookie| {
+            let (name, value) = cookie.trim().split_once('=')?;
+            (name == COOKIE).then_some((value, false))
+        });
+        query_param(query, "token")
+            .map(|token| (token, true))
+            .into_iter()
+            .chain(cookies)
+            .filter_map(|(token, from_query)| self.verify(token, from_query))
+            .find(|share| share.covers(path))
+    }
+}
+
+impl Share {
+    fn covers(&self, path: &str) -> bool {
+        match self.path.strip_suffix('/') {
+            // `/docs` is only ever redirected to `/docs/`
+            Some(dir) => path.starts_with(&self.path) || path == dir,
+            None => path == self.path,
+        }
+    }
+
+    // Who gets to read as the share's maker, and nothing more
+    pub fn user(&self) -> Option<User> {
+        let name = self.user.clone()?;
+        Some(User { name, root: self.root.clone(), can_write: false, quota: None })
+    }
+
+    // Links in a shared directory's listing don't carry the token, so the
+    // first visit leaves it in a cookie for the directory's URLs
+    pub fn cookie(&self) -> Option<String> {
+        if !self.from_query || !self.path.ends_with('/') {
+            return None;
+        }
+        Some(format!(
+            "Set-Cookie: {}={}; Path={}; Max-Age={}; HttpOnly; SameSite=Lax\r\n",
+            COOKIE,
+            self.token,
+            encode_url_path(&self.path),
+            self.expires.saturating_sub(now())
+        ))
+    }
+}
+
+// `<asset prefix>share?path=/some/file&expires=7d` answers with a link that
+// lets anyone read that file, or that directory and all below it, until it
+// expires (a day from now by default). `root` is what the path is resolved
+// against, which with accounts is the maker's own.
+pub fn send_link(
+    stream: &mut Exchange,
+    request: &str,
+    query: &str,
+    key: &ShareKey,
+    root: &Path,
+    user: Option<&User>,
+    args: &Args,
+) -> io::Result<()> {
+    let path = match query_param(query, "path").map(|path| decode_url_encoded(&path.replace('+', " "))) {
+        Some(path) if path.starts_with('/') && !path.contains('\0') => path,
+        _ => return send_error(stream, "400 Bad Request", "Expected ?path=/some/path"),
+    };
+    let lifetime = match query_param(query, "expires") {
+        Some(expires) => match paste::parse_duration(expires).filter(|lifetime| *lifetime <= MAX_LIFETIME) {
+            Some(lifetime) => lifetime,
+            None => return send_error(stream, "400 Bad Request", "Bad expires duration"),
+        },
+        None => DEFAULT_LIFETIME,
+    };
+
+    let relative = path.trim_matches('/');
+    if relative.split('/').any(|segment| segment == ".." || segment == ".") || args.hidden.hides(&path) {
+        return send_error(stream, "404 Not Found", "Not Found");
+    }
+    let resolved = root.join(relative);
+    let is_dir = match resolved.canonicalize() {
+        Ok(canonical) if canonical.starts_with(root) => canonical.is_dir(),
+        _ => return send_error(stream, "404 Not Found", "Not Found"),
+    };
+    let root_name = match root.to_str() {
+        Some(root_name) => root_name,
+        None => return send_error(stream, "500 Internal Server Error", "The root's path can't go in a link"),
+    };
+    let path = match (relative, is_dir) {
+        ("", _) => "/".to_string(),
+        (_, true) => format!("/{}/", relative),
+        (_, false) => format!("/{}", relative),
+    };
+
+    let expires = now() + lifetime.as_secs();
+    let token = key.mint(&path, root_name, user.map(|user| user.name.as_str()), expires);
+    let address = args.address().to_string();
+    let host = request_header(request, "Host").unwrap_or(&address);
+    let url = format!("{}://{}{}?token={}\n", args.scheme(), host, encode_url_path(&path), token);
+    write_response(stream, &stream.response_head("200 OK", "text/plain; charset=utf-8", url.len(), ""), url.as_bytes())
+}
+
+// Written aside and linked into place, which fails if another process got
+// there first; then its key is the one to use
+fn create(path: &Path) -> io::Result<String> {
+    let mut bytes = [0; 32];
+    SystemRandom::new().fill(&mut bytes).map_err(|_| io::Error::other("no randomness for a share key"))?;
+    let encoded = URL_SAFE_NO_PAD.encode(bytes);
+
+    let partial = path.with_extension(format!("partial-{}", process::id()));
+    let mut options = OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+    #[cfg(unix)]
+    std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+    let written = options.open(&partial).and_then(|mut file| file.write_all(format!("{}\n", encoded).as_bytes()));
+    let linked = written.and_then(|_| fs::hard_link(&partial, path));
+    let _ = fs::remove_file(&partial);
+    match linked {
+        Ok(()) => Ok(encoded),
+        Err(e) if e.kind() == io::ErrorKind::AlreadyExists => fs::read_to_string(path),
+        Err(e) => Err(e),
+    }
+}
+
+fn now() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |since| since.as_secs())
+}
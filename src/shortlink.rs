@@ -0,0 +1,124 @@
+use std::{
+    fs::{self, OpenOptions},
+    io::{self, ErrorKind, Write},
+    path::Path,
+};
+
+use crate::{
+    args::Args, encode_url_path, form_field, is_path_within, paste, read_body, request_header,
+    send_error, Exchange,
+};
+
+// Only ever holds one form field
+const MAX_FORM_SIZE: u64 = 8192;
+
+const FORM: &str = r#"<!DOCTYPE html>
+<html>
+<head><meta charset="utf-8"><meta name="viewport" content="width=device-width"><title>Short link</title></head>
+<body>
+<form method="post" action="/s">
+<p><input name="path" size="80" placeholder="/some/deeply/nested/file.txt" autofocus required> <button>Shorten</button></p>
+</form>
+</body>
+</html>
+"#;
+
+// `GET /s/<code>` redirects to the aliased path. `POST /s?path=<path>` (or the
+// form at `GET /s`) mints an alias, reusing the existing one for a known path.
+// The store is a text file of `<code> <path>` lines, read on every request so
+// all workers and processes see new links at once.
+#[allow(clippy::too_many_arguments)]
+pub fn handle(
+    stream: &mut Exchange,
+    method: &str,
+    request: &str,
+    received: &[u8],
+    path: &str,
+    query: &str,
+    store: &Path,
+    args: &Args,
+) -> io::Result<()> {
+    match (method, path.strip_prefix("/s/")) {
+        ("GET", Some(code)) => redirect(stream, code, store),
+        ("GET", None) => {
+            let response = stream.response_head("200 OK", "text/html; charset=utf-8", FORM.len(), "") + FORM;
+            stream.write_all(response.as_bytes())?;
+            stream.flush()
+        }
+        ("POST", None) => mint(stream, request, received, query, store, args),
+        _ => send_error(stream, "405 Method Not Allowed", "Method Not Allowed"),
+    }
+}
+
+fn redirect(stream: &mut Exchange, code: &str, store: &Path) -> io::Result<()> {
+    let target = match read_store(store)?.into_iter().find(|(known, _)| known == code) {
+        Some((_, target)) => target,
+        None => return send_error(stream, "404 Not Found", "Not Found"),
+    };
+
+    let headers = format!("Location: {}\r\n", encode_url_path(&target));
+    let response = stream.response_head("302 Found", "text/html", 0, &headers);
+    stream.write_all(response.as_bytes())?;
+    stream.flush()
+}
+
+fn mint(stream: &mut Exchange, request: &str, received: &[u8], query: &str, store: &Path, args: &Args) -> io::Result<()> {
+    let target = match form_field(query, "path") {
+        Some(target) => target,
+        None => {
+            let length = match request_header(request, "Content-Length").and_then(|length| length.parse::<u64>().ok()) {
+                Some(length) if length <= MAX_FORM_SIZE => length,
+                _ => return send_error(stream, "400 Bad Request", "Missing path"),
+            };
+            let body = read_body(stream, request, received, length)?;
+            match form_field(&String::from_utf8_lossy(&body), "path") {
+                Some(target) => target,
+                None => return send_error(stream, "400 Bad Request", "Missing path"),
+            }
+        }
+    };
+    let target = format!("/{}", target.trim_start_matches('/'));
+
+    // Only real paths get aliases, which also bounds how large the store can grow
+    let resource_path = target.trim_start_matches('/');
+    let absolute_path = args.root.join(resource_path);
+    if target.contains('\n') || !absolute_path.exists() || !is_path_within(&absolute_path, &args.root)? {
+        return send_error(stream, "404 Not Found", "Not Found");
+    }
+
+    let links = read_store(store)?;
+    let code = match links.iter().find(|(_, known)| *known == target) {
+        Some((code, _)) => code.clone(),
+        None => {
+            let mut code = paste::random_name();
+            while links.iter().any(|(known, _)| *known == code) {
+                code = paste::random_name();
+            }
+            // One write per line keeps concurrent mints from interleaving
+            let mut file = OpenOptions::new().create(true).append(true).open(store)?;
+            file.write_all(format!("{} {}\n", code, target).as_bytes())?;
+            code
+        }
+    };
+
+    let address = args.address().to_string();
+    let host = request_header(request, "Host").unwrap_or(&address);
+    let url = format!("{}://{}/s/{}\n", args.scheme(), host, code);
+    let headers = format!("Location: /s/{}\r\n", code);
+    let response = stream.response_head("201 Created", "text/plain; charset=utf-8", url.len(), &headers) + &url;
+    stream.write_all(response.as_bytes())?;
+    stream.flush()
+}
+
+fn read_store(store: &Path) -> io::Result<Vec<(String, String)>> {
+    let content = match fs::read_to_string(store) {
+        Ok(content) => content,
+        Err(e) if e.kind() == ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(e),
+    };
+    Ok(content
+        .lines()
+        .filter_map(|line| line.split_once(' '))
+        .map(|(code, target)| (code.to_string(), target.to_string()))
+        .collect())
+}
@@ -0,0 +1,237 @@
+use std::{
+    collections::HashMap,
+    io,
+    net::{Shutdown, TcpListener, TcpStream},
+    sync::{
+        atomic::{AtomicBool, AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+    thread,
+    time::{Duration, Instant},
+};
+
+use socket2::SockRef;
+
+const POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+static REQUESTED: AtomicBool = AtomicBool::new(false);
+static RELOAD: AtomicBool = AtomicBool::new(false);
+
+// Ctrl-C or SIGTERM stops new connections from being accepted and lets those
+// already open finish, for up to `--shutdown-timeout`. A second Ctrl-C exits
+// at once.
+#[cfg(unix)]
+pub fn install() {
+    use std::ffi::c_int;
+
+    const SIGINT: c_int = 2;
+    const SIGTERM: c_int = 15;
+
+    extern "C" {
+        fn signal(signum: c_int, handler: extern "C" fn(c_int)) -> usize;
+        fn _exit(status: c_int) -> !;
+    }
+
+    // Only async-signal-safe work in here: the flag, or leaving outright
+    extern "C" fn handle(_signum: c_int) {
+        if REQUESTED.swap(true, Ordering::SeqCst) {
+            unsafe { _exit(130) }
+        }
+    }
+
+    unsafe {
+        signal(SIGINT, handle);
+        signal(SIGTERM, handle);
+    }
+}
+
+#[cfg(not(unix))]
+pub fn install() {}
+
+pub fn requested() -> bool {
+    REQUESTED.load(Ordering::SeqCst)
+}
+
+// SIGHUP asks a supervisor to replace its children, one at a time. The
+// children only note it, so a hangup sent to the whole process group doesn't
+// take them down under their supervisor.
+#[cfg(unix)]
+pub fn install_reload() {
+    use std::ffi::c_int;
+
+    const SIGHUP: c_int = 1;
+
+    extern "C" {
+        fn signal(signum: c_int, handler: extern "C" fn(c_int)) -> usize;
+    }
+
+    extern "C" fn handle(_signum: c_int) {
+        RELOAD.store(true, Ordering::SeqCst);
+    }
+
+    unsafe {
+        signal(SIGHUP, handle);
+    }
+}
+
+#[cfg(not(unix))]
+pub fn install_reload() {}
+
+// Whether a reload was asked for since the last call
+pub fn take_reload() -> bool {
+    RELOAD.swap(false, Ordering::SeqCst)
+}
+
+// Asks one Server to drain, leaving any other in the process serving. `run`'s
+// server also drains on the signals `install` catches.
+#[derive(Clone)]
+pub struct ShutdownHandle {
+    requested: Arc<AtomicBool>,
+    signals: bool,
+}
+
+impl ShutdownHandle {
+    pub(crate) fn new(signals: bool) -> ShutdownHandle {
+        ShutdownHandle { requested: Arc::new(AtomicBool::new(false)), signals }
+    }
+
+    pub fn request(&self) {
+        self.requested.store(true, Ordering::SeqCst);
+    }
+
+    pub fn requested(&self) -> bool {
+        self.requested.load(Ordering::SeqCst) || (self.signals && requested())
+    }
+}
+
+// The sockets of a Server's open connections, so those still open at the
+// deadline can be cut off rather than waited on
+pub struct OpenConnections {
+    next: AtomicU64,
+    sockets: Mutex<HashMap<u64, TcpStream>>,
+    // Past the deadline, so connections still queued are cut off as they're picked up
+    closed: AtomicBool,
+}
+
+// Forgets its connection when dropped
+pub struct Registration<'a> {
+    connections: &'a OpenConnections,
+    id: u64,
+}
+
+impl OpenConnections {
+    pub fn new() -> OpenConnections {
+        OpenConnections { next: AtomicU64::new(0), sockets: Mutex::new(HashMap::new()), closed: AtomicBool::new(false) }
+    }
+
+    pub fn register(&self, stream: &TcpStream) -> io::Result<Registration<'_>> {
+        let id = self.next.fetch_add(1, Ordering::Relaxed);
+        let mut sockets = self.sockets.lock().unwrap_or_else(|e| e.into_inner());
+        if self.closed.load(Ordering::SeqCst) {
+            stream.shutdown(Shutdown::Both)?;
+        }
+        sockets.insert(id, stream.try_clone()?);
+        Ok(Registration { connections: self, id })
+    }
+
+    fn close_all(&self) {
+        let sockets = self.sockets.lock().unwrap_or_else(|e| e.into_inner());
+        self.closed.store(true, Ordering::SeqCst);
+        for socket in sockets.values() {
+            let _ = socket.shutdown(Shutdown::Both);
+        }
+    }
+}
+
+impl Drop for Registration<'_> {
+    fn drop(&mut self) {
+        self.connections.sockets.lock().unwrap_or_else(|e| e.into_inner()).remove(&self.id);
+    }
+}
+
+// Asks a supervised child to drain too, when the signal only reached its supervisor
+#[cfg(unix)]
+pub fn forward(pid: u32) {
+    use std::ffi::c_int;
+
+    extern "C" {
+        fn kill(pid: c_int, signum: c_int) -> c_int;
+    }
+    unsafe {
+        kill(pid as c_int, 15);
+    }
+}
+
+#[cfg(not(unix))]
+pub fn forward(_pid: u32) {}
+
+// What a process has served, for the summary it prints on the way out
+pub struct Totals {
+    started: Instant,
+    pub connections: AtomicU64,
+    pub requests: AtomicU64,
+    // Accepted but not yet finished, counting those still queued for a handler
+    pub open: AtomicU64,
+    pub serving: AtomicBool,
+}
+
+impl Totals {
+    pub fn new() -> Totals {
+        Totals {
+            started: Instant::now(),
+            connections: AtomicU64::new(0),
+            requests: AtomicU64::new(0),
+            open: AtomicU64::new(0),
+            serving: AtomicBool::new(true),
+        }
+    }
+
+    pub fn uptime(&self) -> Duration {
+        self.started.elapsed()
+    }
+
+    pub fn print_summary(&self) {
+        println!(
+            "Served {} requests over {} connections in {}s",
+            self.requests.load(Ordering::Relaxed),
+            self.connections.load(Ordering::Relaxed),
+            self.started.elapsed().as_secs()
+        );
+    }
+}
+
+// Runs alongside the acceptors until they stop. Once shutdown is requested it
+// wakes them by shutting their listeners (which fails a pending accept on
+// Linux; elsewhere they notice at the next connection), then gives open
+// connections until the deadline before cutting them off and failing.
+pub fn watch(
+    listeners: &[TcpListener],
+    totals: &Totals,
+    connections: &OpenConnections,
+    handle: &ShutdownHandle,
+    timeout: Duration,
+) -> io::Result<()> {
+    while !handle.requested() {
+        if !totals.serving.load(Ordering::SeqCst) {
+            return Ok(());
+        }
+        thread::sleep(POLL_INTERVAL);
+    }
+
+    let open = totals.open.load(Ordering::SeqCst);
+    println!("Shutting down; waiting up to {}s for {} open connection(s)", timeout.as_secs(), open);
+    for listener in listeners {
+        let _ = SockRef::from(listener).shutdown(Shutdown::Both);
+    }
+
+    let deadline = Instant::now() + timeout;
+    while totals.open.load(Ordering::SeqCst) > 0 || totals.serving.load(Ordering::SeqCst) {
+        if Instant::now() >= deadline {
+            let open = totals.open.load(Ordering::SeqCst);
+            connections.close_all();
+            return Err(io::Error::new(io::ErrorKind::TimedOut, format!("gave up on {} connection(s) still open", open)));
+        }
+        thread::sleep(POLL_INTERVAL);
+    }
+    Ok(())
+}
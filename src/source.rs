@@ -0,0 +1,378 @@
+use std::{fs, io, path::Path};
+
+use crate::{assets, encode_path, escape_html, query_param, send_error, Exchange, watch, write_response};
+
+// Highlighting holds the file and its much bigger HTML in memory; larger files go out as they are
+const MAX_RENDERED_SIZE: u64 = 2 * 1024 * 1024;
+
+// What the highlighter knows of a language: enough to pick out comments,
+// strings, numbers and keywords, which is most of what makes code readable
+struct Language {
+    line_comments: &'static [&'static str],
+    block_comment: Option<(&'static str, &'static str)>,
+    quotes: &'static str,
+    // `'` opens a character literal only when one closes it right after, so Rust lifetimes stay plain
+    char_literals: bool,
+    triple_quotes: bool,
+    keywords: &'static [&'static str],
+    // Keywords in any case, like SQL's
+    ignore_case: bool,
+}
+
+const PLAIN: Language = Language {
+    line_comments: &[],
+    block_comment: None,
+    quotes: "",
+    char_literals: false,
+    triple_quotes: false,
+    keywords: &[],
+    ignore_case: false,
+};
+
+const RUST: Language = Language {
+    line_comments: &["//"],
+    block_comment: Some(("/*", "*/")),
+    quotes: "\"",
+    char_literals: true,
+    triple_quotes: false,
+    keywords: &[
+        "as", "async", "await", "break", "const", "continue", "crate", "dyn", "else", "enum", "extern", "false", "fn", "for",
+        "if", "impl", "in", "let", "loop", "match", "mod", "move", "mut", "pub", "ref", "return", "self", "Self", "static",
+        "struct", "super", "trait", "true", "type", "unsafe", "use", "where", "while",
+    ],
+    ignore_case: false,
+};
+
+const C: Language = Language {
+    line_comments: &["//"],
+    block_comment: Some(("/*", "*/")),
+    quotes: "\"'",
+    char_literals: false,
+    triple_quotes: false,
+    keywords: &[
+        "auto", "bool", "break", "case", "char", "class", "const", "continue", "default", "delete", "do", "double", "else",
+        "enum", "extern", "false", "float", "for", "goto", "if", "inline", "int", "long", "namespace", "new", "nullptr",
+        "private", "protected", "public", "return", "short", "signed", "sizeof", "static", "struct", "switch", "template",
+        "this", "true", "typedef", "union", "unsigned", "using", "virtual", "void", "volatile", "while",
+    ],
+    ignore_case: false,
+};
+
+const JAVASCRIPT: Language = Language {
+    line_comments: &["//"],
+    block_comment: Some(("/*", "*/")),
+    quotes: "\"'`",
+    char_literals: false,
+    triple_quotes: false,
+    keywords: &[
+        "async", "await", "break", "case", "catch", "class", "const", "continue", "default", "delete", "do", "else", "export",
+        "extends", "false", "finally", "for", "from", "function", "if", "import", "in", "instanceof", "interface", "let",
+        "new", "null", "of", "return", "static", "super", "switch", "this", "throw", "true", "try", "type", "typeof",
+        "undefined", "var", "void", "while", "yield",
+    ],
+    ignore_case: false,
+};
+
+const GO: Language = Language {
+    line_comments: &["//"],
+    block_comment: Some(("/*", "*/")),
+    quotes: "\"'`",
+    char_literals: false,
+    triple_quotes: false,
+    keywords: &[
+        "break", "case", "chan", "const", "continue", "default", "defer", "else", "fallthrough", "false", "for", "func",
+        "go", "goto", "if", "import", "interface", "map", "nil", "package", "range", "return", "select", "struct", "switch",
+        "true", "type", "var",
+    ],
+    ignore_case: false,
+};
+
+const JAVA: Language = Language {
+    line_comments: &["//"],
+    block_comment: Some(("/*", "*/")),
+    quotes: "\"'",
+    char_literals: false,
+    triple_quotes: false,
+    keywords: &[
+        "abstract", "boolean", "break", "case", "catch", "class", "continue", "default", "do", "double", "else", "enum",
+        "extends", "false", "final", "finally", "float", "for", "fun", "if", "implements", "import", "int", "interface",
+        "long", "new", "null", "object", "override", "package", "private", "protected", "public", "return", "static",
+        "super", "switch", "this", "throw", "throws", "true", "try", "val", "var", "void", "when", "while",
+    ],
+    ignore_case: false,
+};
+
+const PYTHON: Language = Language {
+    line_comments: &["#"],
+    block_comment: None,
+    quotes: "\"'",
+    char_literals: false,
+    triple_quotes: true,
+    keywords: &[
+        "and", "as", "assert", "async", "await", "break", "class", "continue", "def", "del", "elif", "else", "except",
+        "False", "finally", "for", "from", "global", "if", "import", "in", "is", "lambda", "None", "nonlocal", "not", "or",
+        "pass", "raise", "return", "True", "try", "while", "with", "yield",
+    ],
+    ignore_case: false,
+};
+
+const RUBY: Language = Language {
+    line_comments: &["#"],
+    block_comment: None,
+    quotes: "\"'",
+    char_literals: false,
+    triple_quotes: false,
+    keywords: &[
+        "begin", "break", "case", "class", "def", "do", "else", "elsif", "end", "ensure", "false", "for", "if", "in",
+        "module", "next", "nil", "not", "or", "and", "require", "rescue", "return", "self", "then", "true", "unless",
+        "until", "when", "while", "yield",
+    ],
+    ignore_case: false,
+};
+
+const SHELL: Language = Language {
+    line_comments: &["#"],
+    block_comment: None,
+    quotes: "\"'",
+    char_literals: false,
+    triple_quotes: false,
+    keywords: &[
+        "case", "do", "done", "elif", "else", "esac", "export", "fi", "for", "function", "if", "in", "local", "return",
+        "then", "until", "while",
+    ],
+    ignore_case: false,
+};
+
+// TOML, YAML, INI and the like
+const CONFIG: Language = Language {
+    line_comments: &["#", ";"],
+    block_comment: None,
+    quotes: "\"'",
+    char_literals: false,
+    triple_quotes: false,
+    keywords: &["true", "false", "null", "yes", "no", "on", "off"],
+    ignore_case: false,
+};
+
+const JSON: Language = Language {
+    line_comments: &[],
+    block_comment: None,
+    quotes: "\"",
+    char_literals: false,
+    triple_quotes: false,
+    keywords: &["true", "false", "null"],
+    ignore_case: false,
+};
+
+const CSS: Language = Language {
+    line_comments: &[],
+    block_comment: Some(("/*", "*/")),
+    quotes: "\"'",
+    char_literals: false,
+    triple_quotes: false,
+    keywords: &["!important", "@media", "@import", "@font-face", "@keyframes"],
+    ignore_case: false,
+};
+
+const MARKUP: Language = Language {
+    line_comments: &[],
+    block_comment: Some(("<!--", "-->")),
+    quotes: "\"",
+    char_literals: false,
+    triple_quotes: false,
+    keywords: &[],
+    ignore_case: false,
+};
+
+const SQL: Language = Language {
+    line_comments: &["--"],
+    block_comment: Some(("/*", "*/")),
+    quotes: "'\"",
+    char_literals: false,
+    triple_quotes: false,
+    keywords: &[
+        "and", "as", "by", "create", "delete", "from", "group", "having", "in", "index", "insert", "into", "is", "join",
+        "key", "left", "limit", "not", "null", "on", "or", "order", "primary", "select", "set", "table", "update", "values",
+        "where",
+    ],
+    ignore_case: true,
+};
+
+fn language(path: &Path) -> &'static Language {
+    let name = path.file_name().and_then(|name| name.to_str()).unwrap_or("");
+    if ["Makefile", "Dockerfile", "Containerfile"].contains(&name) {
+        return &SHELL;
+    }
+    let extension = path.extension().and_then(|extension| extension.to_str()).unwrap_or("").to_ascii_lowercase();
+    match extension.as_str() {
+        "rs" => &RUST,
+        "c" | "h" | "cc" | "cpp" | "cxx" | "hpp" | "hh" | "m" | "cs" | "swift" | "zig" => &C,
+        "js" | "mjs" | "cjs" | "jsx" | "ts" | "tsx" => &JAVASCRIPT,
+        "go" => &GO,
+        "java" | "kt" | "kts" | "scala" | "groovy" | "gradle" => &JAVA,
+        "py" | "pyi" => &PYTHON,
+        "rb" => &RUBY,
+        "sh" | "bash" | "zsh" | "fish" | "mk" => &SHELL,
+        "toml" | "yaml" | "yml" | "ini" | "cfg" | "conf" | "properties" | "env" => &CONFIG,
+        "json" => &JSON,
+        "css" | "scss" | "less" => &CSS,
+        "html" | "htm" | "xml" | "svg" | "vue" => &MARKUP,
+        "sql" => &SQL,
+        _ => &PLAIN,
+    }
+}
+
+// `?view=source` shows a text file as a page with line numbers and highlighting
+// going by its extension; the plain URL stays the file itself
+pub fn wanted(size: u64, query: &str) -> bool {
+    query_param(query, "view") == Some("source") && size <= MAX_RENDERED_SIZE && query_param(query, "download").is_none()
+}
+
+// `headers` holds the file's own, like Content-Language
+pub fn send(stream: &mut Exchange, path: &Path, asset_prefix: &str, reload: bool, headers: &str) -> io::Result<()> {
+    let source = match fs::read(path) {
+        Ok(source) => source,
+        Err(e) => {
+            eprintln!("Error reading {}: {:?}", path.display(), e);
+            return send_error(stream, "500 Internal Server Error", "Internal Server Error");
+        }
+    };
+    // Zero bytes don't turn up in text
+    if source.contains(&0) {
+        return send_error(stream, "415 Unsupported Media Type", "Not a text file");
+    }
+    let source = String::from_utf8_lossy(&source);
+
+    let name = path.file_name().map_or(String::new(), |name| name.to_string_lossy().to_string());
+    let mut body = format!(
+        "<!DOCTYPE html><html><head><meta charset=\"utf-8\">\
+         <meta name=\"viewport\" content=\"width=device-width, initial-scale=1\">\
+         <title>{}</title><link rel=\"icon\" href=\"{}\"><link rel=\"stylesheet\" href=\"{}\"></head><body>\
+         <header><h1>{}</h1><a href=\"{}\">Raw</a> <a href=\"?download\">Download</a></header><table>",
+        escape_html(&name),
+        assets::url(asset_prefix, "favicon", "ico"),
+        assets::url(asset_prefix, "source", "css"),
+        escape_html(&name),
+        encode_path(&name)
+    );
+
+    // Spans are closed at the end of each line and reopened on the next, so
+    // every row stands alone
+    let mut number = 1;
+    let mut line = String::new();
+    for (class, text) in highlight(&source, language(path)) {
+        for (index, piece) in text.split('\n').enumerate() {
+            if index > 0 {
+                push_line(&mut body, number, &line);
+                line.clear();
+                number += 1;
+            }
+            let piece = escape_html(piece.strip_suffix('\r').unwrap_or(piece));
+            match class {
+                Some(class) if !piece.is_empty() => line.push_str(&format!("<span class=\"{}\">{}</span>", class, piece)),
+                _ => line.push_str(&piece),
+            }
+        }
+    }
+    // A final newline ends the last line rather than starting one
+    if !line.is_empty() || !source.ends_with('\n') {
+        push_line(&mut body, number, &line);
+    }
+    body.push_str("</table>");
+    if reload {
+        body.push_str(&watch::script_tag(asset_prefix));
+    }
+    body.push_str("</body></html>\n");
+
+    let head = stream.response_head("200 OK", "text/html; charset=utf-8", body.len(), headers);
+    write_response(stream, &head, body.as_bytes())
+}
+
+// Rows link to themselves, so `#L12` can be passed around in a review
+fn push_line(body: &mut String, number: usize, line: &str) {
+    body.push_str(&format!("<tr id=\"L{}\"><td><a href=\"#L{}\">{}</a></td><td>{}</td></tr>", number, number, number, line));
+}
+
+// The source cut into runs, each with its class: `c` comments, `s`
+// strings, `n` numbers, `k` keywords, and None for the rest
+fn highlight<'a>(source: &'a str, language: &Language) -> Vec<(Option<&'static str>, &'a str)> {
+    let mut runs = Vec::new();
+    let mut plain_start = 0;
+    let mut at = 0;
+    let bytes = source.as_bytes();
+    while at < source.len() {
+        let rest = &source[at..];
+        let run = if language.line_comments.iter().any(|comment| rest.starts_with(comment)) {
+            Some(("c", rest.find('\n').unwrap_or(rest.len())))
+        } else if let Some((open, close)) = language.block_comment.filter(|(open, _)| rest.starts_with(open)) {
+            Some(("c", rest[open.len()..].find(close).map_or(rest.len(), |end| open.len() + end + close.len())))
+        } else if let Some(quote) = rest.chars().next().filter(|c| language.quotes.contains(*c)) {
+            Some(("s", string_length(rest, quote, language.triple_quotes)))
+        } else if language.char_literals && rest.starts_with('\'') {
+            char_literal_length(rest).map(|length| ("s", length))
+        } else {
+            let previous = at.checked_sub(1).map(|before| bytes[before]);
+            let starts_word = !previous.is_some_and(|byte| byte.is_ascii_alphanumeric() || byte == b'_');
+            let word = rest.find(|c: char| !(c.is_ascii_alphanumeric() || c == '_' || c == '!' || c == '@')).unwrap_or(rest.len());
+            if !starts_word || word == 0 {
+                None
+            } else if rest.as_bytes()[0].is_ascii_digit() {
+                let number = rest.find(|c: char| !(c.is_ascii_alphanumeric() || c == '_' || c == '.')).unwrap_or(rest.len());
+                Some(("n", number))
+            } else if language.keywords.iter().any(|keyword| match language.ignore_case {
+                true => keyword.eq_ignore_ascii_case(&rest[..word]),
+                false => *keyword == &rest[..word],
+            }) {
+                Some(("k", word))
+            } else {
+                // Skipping the whole word keeps its tail from starting a number or keyword
+                at += word;
+                continue;
+            }
+        };
+        match run {
+            Some((class, length)) => {
+                if plain_start < at {
+                    runs.push((None, &source[plain_start..at]));
+                }
+                runs.push((Some(class), &source[at..at + length]));
+                at += length;
+                plain_start = at;
+            }
+            None => at += rest.chars().next().map_or(1, char::len_utf8),
+        }
+    }
+    if plain_start < source.len() {
+        runs.push((None, &source[plain_start..]));
+    }
+    runs
+}
+
+// Up to and including the closing quote, skipping escaped ones; an
+// unclosed string runs to the end of its line
+fn string_length(rest: &str, quote: char, triple_quotes: bool) -> usize {
+    let triple: String = [quote; 3].iter().collect();
+    if triple_quotes && rest.starts_with(&triple) {
+        return rest[3..].find(&triple).map_or(rest.len(), |end| end + 6);
+    }
+    let mut escaped = false;
+    for (index, c) in rest.char_indices().skip(1) {
+        match c {
+            _ if escaped => escaped = false,
+            '\\' => escaped = true,
+            '\n' if quote != '`' => return index,
+            _ if c == quote => return index + 1,
+            _ => {}
+        }
+    }
+    rest.len()
+}
+
+fn char_literal_length(rest: &str) -> Option<usize> {
+    let mut chars = rest.char_indices().skip(1);
+    match chars.next()? {
+        (_, '\\') => rest[2..].find('\'').map(|end| end + 3).filter(|&length| length <= 12),
+        (_, '\'') | (_, '\n') => None,
+        (_, _) => chars.next().filter(|(_, c)| *c == '\'').map(|(index, _)| index + 1),
+    }
+}
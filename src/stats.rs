@@ -0,0 +1,164 @@
+use std::{
+    collections::HashMap,
+    fs::{self, OpenOptions},
+    io::{self, ErrorKind, Write},
+    path::Path,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use serde_json::{json, Value};
+
+use crate::{assets, decode_url_encoded, escape_html, listing, query_param, Exchange, write_response};
+
+const DAY: u64 = 86_400;
+
+// Report windows, in days; the longest also bounds how long records are kept
+const WINDOWS: &[(&str, u64)] = &[("24h", 1), ("7d", 7), ("30d", 30)];
+const DEFAULT_LIMIT: usize = 20;
+
+struct Downloads {
+    path: String,
+    count: u64,
+    bytes: u64,
+}
+
+// Appends one `<unix seconds> <bytes> <path>` line to the day's file in `dir`.
+// Appends that small are atomic, so every worker and process can share the
+// directory without locking. `path` is percent-encoded and so has no spaces.
+pub fn record(dir: &Path, path: &str, bytes: u64) {
+    let now = unix_now();
+    let line = format!("{} {} {}\n", now, bytes, path);
+    let result = fs::create_dir_all(dir).and_then(|_| {
+        let mut file = OpenOptions::new().create(true).append(true).open(dir.join(day_file(now)))?;
+        file.write_all(line.as_bytes())
+    });
+    // Losing a record isn't worth failing the download over
+    if let Err(e) = result {
+        eprintln!("Error recording download of {}: {:?}", path, e);
+    }
+}
+
+// `GET /_stats/top`: the most downloaded files in each window, as a page
+pub fn send_top_page(stream: &mut Exchange, dir: &Path, query: &str, asset_prefix: &str) -> io::Result<()> {
+    let windows = top(dir, limit(query))?;
+
+    let mut body = format!(
+        "<!DOCTYPE html><html><head><meta charset=\"utf-8\">\
+         <meta name=\"viewport\" content=\"width=device-width, initial-scale=1\">\
+         <title>Top downloads</title><link rel=\"icon\" href=\"{}\"><link rel=\"stylesheet\" href=\"{}\"></head>\
+         <body><h1>Top downloads</h1>",
+        assets::url(asset_prefix, "favicon", "ico"),
+        assets::url(asset_prefix, "listing", "css")
+    );
+    for (name, downloads) in &windows {
+        body.push_str(&format!("<h2>Last {}</h2><ul>", name));
+        if downloads.is_empty() {
+            body.push_str("<li>No downloads</li>");
+        }
+        for entry in downloads {
+            body.push_str(&format!(
+                "<li><a href=\"{}\"><span class=\"name\">{}</span><span class=\"meta\">{} download(s) · {}</span></a></li>",
+                entry.path,
+                escape_html(&decode_url_encoded(&entry.path)),
+                entry.count,
+                listing::format_size(entry.bytes)
+            ));
+        }
+        body.push_str("</ul>");
+    }
+    body.push_str("</body></html>");
+
+    let head = stream.response_head("200 OK", "text/html; charset=utf-8", body.len(), "Cache-Control: no-store\r\n");
+    write_response(stream, &head, body.as_bytes())
+}
+
+// The same report as JSON, keyed by window
+pub fn top_json(dir: &Path, query: &str) -> io::Result<Value> {
+    let windows = top(dir, limit(query))?;
+    let report: serde_json::Map<String, Value> = windows
+        .into_iter()
+        .map(|(name, downloads)| {
+            let downloads = downloads
+                .iter()
+                .map(|entry| json!({ "path": entry.path, "downloads": entry.count, "bytes": entry.bytes }))
+                .collect();
+            (name.to_string(), Value::Array(downloads))
+        })
+        .collect();
+    Ok(Value::Object(report))
+}
+
+// `?limit=N` caps each window's list
+fn limit(query: &str) -> usize {
+    query_param(query, "limit")
+        .and_then(|limit| limit.parse().ok())
+        .filter(|limit| *limit > 0)
+        .unwrap_or(DEFAULT_LIMIT)
+}
+
+// Reads the last 30 days of records, dropping older day files along the way
+fn top(dir: &Path, limit: usize) -> io::Result<Vec<(&'static str, Vec<Downloads>)>> {
+    let now = unix_now();
+    let longest = WINDOWS.iter().map(|(_, days)| days).max().copied().unwrap_or(0);
+    // Day files sort by name, so comparing names compares dates
+    let oldest_kept = day_file(now.saturating_sub(longest * DAY));
+
+    let mut totals: Vec<HashMap<String, (u64, u64)>> = vec![HashMap::new(); WINDOWS.len()];
+    let entries = match fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == ErrorKind::NotFound => return Ok(WINDOWS.iter().map(|(name, _)| (*name, Vec::new())).collect()),
+        Err(e) => return Err(e),
+    };
+    for entry in entries {
+        let entry = entry?;
+        let name = entry.file_name().to_string_lossy().to_string();
+        if !is_day_file(&name) {
+            continue;
+        }
+        if name < oldest_kept {
+            let _ = fs::remove_file(entry.path());
+            continue;
+        }
+
+        for line in fs::read_to_string(entry.path())?.lines() {
+            let mut fields = line.splitn(3, ' ');
+            let (Some(time), Some(bytes), Some(path)) = (fields.next(), fields.next(), fields.next()) else { continue };
+            let (Ok(time), Ok(bytes)) = (time.parse::<u64>(), bytes.parse::<u64>()) else { continue };
+
+            let age = now.saturating_sub(time);
+            for (window, (_, days)) in totals.iter_mut().zip(WINDOWS) {
+                if age < days * DAY {
+                    let total = window.entry(path.to_string()).or_insert((0, 0));
+                    total.0 += 1;
+                    total.1 += bytes;
+                }
+            }
+        }
+    }
+
+    Ok(WINDOWS
+        .iter()
+        .zip(totals)
+        .map(|((name, _), totals)| {
+            let mut downloads: Vec<Downloads> =
+                totals.into_iter().map(|(path, (count, bytes))| Downloads { path, count, bytes }).collect();
+            // Ties go alphabetically so the report doesn't reshuffle between loads
+            downloads.sort_by(|a, b| b.count.cmp(&a.count).then_with(|| a.path.cmp(&b.path)));
+            downloads.truncate(limit);
+            (*name, downloads)
+        })
+        .collect())
+}
+
+// `YYYY-MM-DD`, in UTC
+fn day_file(seconds: u64) -> String {
+    listing::format_iso8601(seconds)[..10].to_string()
+}
+
+fn is_day_file(name: &str) -> bool {
+    name.len() == 10 && name.bytes().enumerate().all(|(i, byte)| if i == 4 || i == 7 { byte == b'-' } else { byte.is_ascii_digit() })
+}
+
+fn unix_now() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |since| since.as_secs())
+}
@@ -0,0 +1,48 @@
+use std::{
+    fs::File,
+    io::{self, Read, Write},
+    net::{Shutdown, SocketAddr, TcpStream},
+    time::Duration,
+};
+
+// What request handling needs from a connection, so plain TCP and TLS
+// connections are served by the same code
+pub trait Stream: Read + Write + Send {
+    fn peer_addr(&self) -> io::Result<SocketAddr>;
+    fn set_read_timeout(&self, timeout: Option<Duration>) -> io::Result<()>;
+    fn set_write_timeout(&self, timeout: Option<Duration>) -> io::Result<()>;
+    // Tells the client nothing more is coming while still reading from it
+    fn shutdown_write(&mut self) -> io::Result<()>;
+    // The names on the client's certificate, with --tls-client-ca
+    fn client_names(&self) -> Vec<String> {
+        Vec::new()
+    }
+    // Sends part of a file some faster way than copying it through write, or
+    // returns None when there's no such way and the caller should copy it
+    fn send_file(&mut self, _file: &File, _offset: u64, _length: u64) -> Option<io::Result<u64>> {
+        None
+    }
+}
+
+impl Stream for TcpStream {
+    fn peer_addr(&self) -> io::Result<SocketAddr> {
+        TcpStream::peer_addr(self)
+    }
+
+    fn set_read_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        TcpStream::set_read_timeout(self, timeout)
+    }
+
+    fn set_write_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        TcpStream::set_write_timeout(self, timeout)
+    }
+
+    fn shutdown_write(&mut self) -> io::Result<()> {
+        self.shutdown(Shutdown::Write)
+    }
+
+    #[cfg(all(feature = "io-uring", target_os = "linux"))]
+    fn send_file(&mut self, file: &File, offset: u64, length: u64) -> Option<io::Result<u64>> {
+        crate::uring::send_file(self, file, offset, length)
+    }
+}
@@ -0,0 +1,225 @@
+use std::{
+    env, fs,
+    io::{self, BufRead, BufReader, Read},
+    path::PathBuf,
+    process::{self, Command, ExitStatus, Stdio},
+    sync::{
+        atomic::{AtomicBool, AtomicU64, Ordering},
+        mpsc::{self, Sender},
+        Mutex,
+    },
+    thread::{self, Scope},
+    time::{Duration, Instant},
+};
+
+use crate::{metrics, shutdown};
+
+// Children that die sooner than this after starting are probably failing at
+// startup (port taken, bad root), so back off instead of respawning in a loop
+const MIN_UPTIME: Duration = Duration::from_secs(5);
+const MAX_BACKOFF: Duration = Duration::from_secs(30);
+const POLL_INTERVAL: Duration = Duration::from_millis(100);
+// What a child prints once its listeners are bound
+const READY: &str = "Serving with";
+
+struct Supervisor {
+    exe: PathBuf,
+    args: Vec<String>,
+    // The children's metrics files, which they read each other's from
+    state: PathBuf,
+    next_file: AtomicU64,
+    next_generation: AtomicU64,
+    // By worker id, less one
+    slots: Mutex<Vec<Slot>>,
+}
+
+// Which supervising thread keeps a worker running, and the child it's on.
+// A reload hands the slot to a new thread once its child is ready.
+struct Slot {
+    generation: u64,
+    pid: Option<u32>,
+}
+
+// Runs the server as `processes` child processes sharing the port through
+// SO_REUSEPORT, so a panic or leak in one only takes that process down.
+// SIGHUP replaces them one at a time with the binary and config as they are
+// now, each old child draining once its successor is serving.
+pub fn run(processes: usize) -> io::Result<()> {
+    let mut child_args = Vec::new();
+    let mut args = env::args().skip(1);
+    while let Some(arg) = args.next() {
+        if arg == "--processes" {
+            args.next();
+        } else {
+            child_args.push(arg);
+        }
+    }
+    child_args.push("--supervised".to_string());
+
+    let state = env::temp_dir().join(format!("bounty-supervisor-{}", process::id()));
+    fs::create_dir_all(&state)?;
+    let supervisor = Supervisor {
+        exe: env::current_exe()?,
+        args: child_args,
+        state,
+        next_file: AtomicU64::new(1),
+        next_generation: AtomicU64::new(processes as u64 + 1),
+        slots: Mutex::new((1..=processes as u64).map(|generation| Slot { generation, pid: None }).collect()),
+    };
+
+    println!("Supervising {} worker processes", processes);
+    thread::scope(|scope| {
+        for id in 1..=processes {
+            let supervisor = &supervisor;
+            scope.spawn(move || supervisor.supervise(id, id as u64, None));
+        }
+        while !shutdown::requested() {
+            if shutdown::take_reload() {
+                supervisor.reload(scope, processes);
+            }
+            thread::sleep(POLL_INTERVAL);
+        }
+    });
+    let _ = fs::remove_dir_all(&supervisor.state);
+    Ok(())
+}
+
+impl Supervisor {
+    // Stops at the first successor that doesn't come up, leaving the rest of
+    // the old children serving
+    fn reload<'scope>(&'scope self, scope: &'scope Scope<'scope, '_>, processes: usize) {
+        println!("Reloading {} worker processes", processes);
+        for id in 1..=processes {
+            let generation = self.next_generation.fetch_add(1, Ordering::Relaxed);
+            let (ready, started) = mpsc::channel();
+            scope.spawn(move || self.supervise(id, generation, Some(ready)));
+            let pid = match started.recv() {
+                Ok(pid) => pid,
+                Err(_) => {
+                    if !shutdown::requested() {
+                        eprintln!("[worker {}] replacement didn't start serving; reload abandoned", id);
+                    }
+                    return;
+                }
+            };
+            let mut slots = self.slots.lock().unwrap_or_else(|e| e.into_inner());
+            let previous = slots[id - 1].pid.replace(pid);
+            slots[id - 1].generation = generation;
+            drop(slots);
+            if let Some(previous) = previous {
+                shutdown::forward(previous);
+            }
+        }
+        println!("Reloaded {} worker processes", processes);
+    }
+
+    // `ready` is set for a reload's successor, which doesn't own its slot
+    // until it has said so, and isn't restarted if it dies before then
+    fn supervise(&self, id: usize, generation: u64, mut ready: Option<Sender<u32>>) {
+        let mut backoff = Duration::from_secs(1);
+
+        loop {
+            let started = Instant::now();
+            match self.run_child(id, generation, ready.take()) {
+                Ok(status) => eprintln!("[worker {}] exited with {}", id, status),
+                Err(e) => eprintln!("[worker {}] failed to start: {:?}", id, e),
+            }
+            if shutdown::requested() || !self.owns(id, generation) {
+                return;
+            }
+
+            if started.elapsed() >= MIN_UPTIME {
+                backoff = Duration::from_secs(1);
+            } else {
+                backoff = (backoff * 2).min(MAX_BACKOFF);
+            }
+            eprintln!("[worker {}] restarting in {}s", id, backoff.as_secs());
+            thread::sleep(backoff);
+            // Replaced by a reload while backing off
+            if !self.owns(id, generation) {
+                return;
+            }
+        }
+    }
+
+    fn owns(&self, id: usize, generation: u64) -> bool {
+        self.slots.lock().unwrap_or_else(|e| e.into_inner())[id - 1].generation == generation
+    }
+
+    fn run_child(&self, id: usize, generation: u64, ready: Option<Sender<u32>>) -> io::Result<ExitStatus> {
+        let file = self.state.join(format!("worker-{}.json", self.next_file.fetch_add(1, Ordering::Relaxed)));
+        let mut child = Command::new(&self.exe)
+            .args(&self.args)
+            .arg("--worker-state")
+            .arg(&file)
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()?;
+
+        let stdout = child.stdout.take();
+        let stderr = child.stderr.take();
+        let pid = child.id();
+        // A reload may have taken the slot since this thread last looked
+        let mut replaced = false;
+        if ready.is_none() {
+            let mut slots = self.slots.lock().unwrap_or_else(|e| e.into_inner());
+            match slots[id - 1].generation == generation {
+                true => slots[id - 1].pid = Some(pid),
+                false => replaced = true,
+            }
+        }
+        if replaced {
+            shutdown::forward(pid);
+        }
+
+        let exited = AtomicBool::new(false);
+        let status = thread::scope(|scope| {
+            if let Some(stdout) = stdout {
+                scope.spawn(move || forward(id, stdout, false, ready.map(|ready| (ready, pid))));
+            }
+            if let Some(stderr) = stderr {
+                scope.spawn(move || forward(id, stderr, true, None));
+            }
+            // A Ctrl-C reaches the children itself, but a SIGTERM sent to the supervisor alone doesn't
+            let exited = &exited;
+            scope.spawn(move || {
+                while !exited.load(Ordering::SeqCst) {
+                    if shutdown::requested() {
+                        shutdown::forward(pid);
+                        return;
+                    }
+                    thread::sleep(POLL_INTERVAL);
+                }
+            });
+            let status = child.wait();
+            exited.store(true, Ordering::SeqCst);
+            status
+        });
+        // What it served still counts towards the server's totals
+        if let Err(e) = metrics::retire(&file) {
+            eprintln!("[worker {}] failed to keep its metrics: {}", id, e);
+        }
+        status
+    }
+}
+
+// Prefixes each line so interleaved output from several workers stays
+// attributable, and tells a reload once its child is serving
+fn forward(id: usize, output: impl Read, is_stderr: bool, mut ready: Option<(Sender<u32>, u32)>) {
+    for line in BufReader::new(output).lines() {
+        let line = match line {
+            Ok(line) => line,
+            Err(_) => break,
+        };
+        if is_stderr {
+            eprintln!("[worker {}] {}", id, line);
+        } else {
+            println!("[worker {}] {}", id, line);
+        }
+        if line.starts_with(READY) {
+            if let Some((ready, pid)) = ready.take() {
+                let _ = ready.send(pid);
+            }
+        }
+    }
+}
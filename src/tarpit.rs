@@ -0,0 +1,111 @@
+use std::{
+    collections::HashMap,
+    fs,
+    io::{self, Write},
+    net::IpAddr,
+    sync::{Arc, Mutex},
+    thread,
+    time::{Duration, Instant},
+};
+
+use crate::{args::Args, stream::Stream};
+
+// Paths nothing legitimate asks a static file server for
+const DEFAULT_PATTERNS: &[&str] = &[
+    "wp-login.php",
+    "/wp-admin",
+    "xmlrpc.php",
+    "/.env",
+    "/.git/config",
+    "phpmyadmin",
+    "/cgi-bin/",
+    "/vendor/phpunit",
+    "/.aws/credentials",
+    "/actuator/",
+    "/boaform/",
+];
+
+const DRIP_INTERVAL: Duration = Duration::from_secs(10);
+const MAX_TRAP_TIME: Duration = Duration::from_secs(30 * 60);
+
+// Traps beyond these limits are closed at once: the point is spending the
+// scanner's time, not our threads
+const MAX_TRAPS: usize = 256;
+const MAX_TRAPS_PER_ADDRESS: usize = 4;
+
+// Answers scanner probes with a response head that never finishes, one bogus
+// header line every DRIP_INTERVAL, on a thread of its own so workers stay free
+pub struct Tarpit {
+    patterns: Vec<String>,
+    active: Arc<Mutex<HashMap<IpAddr, usize>>>,
+}
+
+impl Tarpit {
+    pub fn new(args: &Args) -> io::Result<Option<Tarpit>> {
+        if !args.tarpit {
+            return Ok(None);
+        }
+
+        // One case-insensitive substring per line; `#` starts a comment
+        let patterns = match &args.tarpit_patterns {
+            Some(path) => fs::read_to_string(path)?
+                .lines()
+                .map(|line| line.split('#').next().unwrap_or("").trim().to_ascii_lowercase())
+                .filter(|line| !line.is_empty())
+                .collect(),
+            None => DEFAULT_PATTERNS.iter().map(|pattern| pattern.to_string()).collect(),
+        };
+        Ok(Some(Tarpit { patterns, active: Arc::new(Mutex::new(HashMap::new())) }))
+    }
+
+    pub fn matches(&self, path: &str) -> bool {
+        let path = path.to_ascii_lowercase();
+        self.patterns.iter().any(|pattern| path.contains(pattern.as_str()))
+    }
+
+    pub fn trap(&self, stream: Box<dyn Stream>, ip: IpAddr, path: &str) {
+        {
+            let mut active = self.active.lock().unwrap_or_else(|e| e.into_inner());
+            let total: usize = active.values().sum();
+            let for_address = active.entry(ip).or_insert(0);
+            if total >= MAX_TRAPS || *for_address >= MAX_TRAPS_PER_ADDRESS {
+                eprintln!("Tarpit full, dropping {} asking for {}", ip, path);
+                if *for_address == 0 {
+                    active.remove(&ip);
+                }
+                return;
+            }
+            *for_address += 1;
+        }
+        eprintln!("Tarpitting {} asking for {}", ip, path);
+
+        let active = Arc::clone(&self.active);
+        thread::spawn(move || {
+            let started = Instant::now();
+            let _ = drip(stream);
+            eprintln!("Released {} from the tarpit after {}s", ip, started.elapsed().as_secs());
+
+            let mut active = active.lock().unwrap_or_else(|e| e.into_inner());
+            if let Some(count) = active.get_mut(&ip) {
+                *count -= 1;
+                if *count == 0 {
+                    active.remove(&ip);
+                }
+            }
+        });
+    }
+}
+
+fn drip(mut stream: Box<dyn Stream>) -> io::Result<()> {
+    stream.set_write_timeout(Some(DRIP_INTERVAL))?;
+    stream.write_all(b"HTTP/1.1 200 OK\r\n")?;
+
+    let started = Instant::now();
+    let mut line = 0u32;
+    while started.elapsed() < MAX_TRAP_TIME {
+        thread::sleep(DRIP_INTERVAL);
+        line = line.wrapping_mul(1_103_515_245).wrapping_add(12_345);
+        stream.write_all(format!("X-{:x}: {:x}\r\n", line >> 16, line).as_bytes())?;
+    }
+    Ok(())
+}
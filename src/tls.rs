@@ -0,0 +1,268 @@
+use std::{
+    io::{self, Write},
+    mem,
+    net::{Shutdown, SocketAddr, TcpStream},
+    path::{Path, PathBuf},
+    sync::{Arc, RwLock},
+    thread,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use rustls::{
+    crypto::{ring::Ticketer, CryptoProvider},
+    pki_types::{pem::PemObject, CertificateDer, PrivateKeyDer},
+    server::{danger::ClientCertVerifier, ClientHello, NoServerSessionStorage, ResolvesServerCert, WebPkiClientVerifier},
+    sign::CertifiedKey,
+    version, RootCertStore, ServerConfig, ServerConnection, StreamOwned, SupportedProtocolVersion,
+};
+
+use crate::{der, ocsp, stream::Stream};
+
+const OCSP_REFRESH: Duration = Duration::from_secs(3600);
+const OCSP_RETRY: Duration = Duration::from_secs(300);
+
+pub type TlsStream = StreamOwned<ServerConnection, TcpStream>;
+
+// How handshakes go, past the certificate. Left alone, it's rustls's safe
+// defaults: TLS 1.2 and 1.3, its cipher suites in its order, and resumption
+// through a session cache rather than tickets.
+#[derive(Clone, Default)]
+pub struct TlsOptions {
+    // `--tls-min-version 1.3` turns TLS 1.2 away
+    pub tls13_only: bool,
+    // `--tls-ciphers`: rustls's names for the suites allowed, most preferred first
+    pub ciphers: Vec<String>,
+    // `--tls-alpn`: the protocols offered, when not just http/1.1
+    pub alpn: Option<Vec<String>>,
+    // `--tls-session-tickets`: resumption through tickets under rotating keys,
+    // so it works across handler threads and needs no cache
+    pub session_tickets: bool,
+    // `--no-tls-resumption`: every connection gets a full handshake
+    pub no_resumption: bool,
+    // `--tls-client-ca`: clients must present a certificate this CA signed
+    pub client_ca: Option<PathBuf>,
+    // `--tls-ocsp`: staple the responder's latest word on the certificate
+    pub ocsp: bool,
+    // `--tls-ocsp-responder`: where to ask, if not where the certificate says
+    pub ocsp_responder: Option<String>,
+}
+
+// Only HTTP/1.x is spoken, so offering h2 would break every client that took it
+pub const ALPN_PROTOCOLS: [&str; 2] = ["http/1.1", "http/1.0"];
+
+// For --tls-ciphers, as rustls names them: TLS13_AES_128_GCM_SHA256 and the like
+pub fn cipher_suite_names() -> Vec<&'static str> {
+    rustls::crypto::ring::default_provider().cipher_suites.iter().filter_map(|suite| suite.suite().as_str()).collect()
+}
+
+// `--tls-cert` holds the certificate chain, leaf first, and `--tls-key` its
+// private key, both PEM as certbot and mkcert write them
+pub fn load_config(cert: &Path, key: &Path, options: &TlsOptions) -> io::Result<Arc<ServerConfig>> {
+    let invalid = |path: &Path, e: &dyn std::fmt::Display| {
+        io::Error::new(io::ErrorKind::InvalidData, format!("{}: {}", path.display(), e))
+    };
+
+    let chain = read_chain(cert)?;
+    let key_der = PrivateKeyDer::from_pem_file(key).map_err(|e| invalid(key, &e))?;
+
+    let mut provider = rustls::crypto::ring::default_provider();
+    if !options.ciphers.is_empty() {
+        let available = mem::take(&mut provider.cipher_suites);
+        provider.cipher_suites = options
+            .ciphers
+            .iter()
+            .filter_map(|name| available.iter().find(|suite| suite.suite().as_str() == Some(name.as_str())).copied())
+            .collect();
+    }
+    let versions: &[&SupportedProtocolVersion] = match options.tls13_only {
+        true => &[&version::TLS13],
+        false => &[&version::TLS13, &version::TLS12],
+    };
+    let policy_error = |e: rustls::Error| io::Error::new(io::ErrorKind::InvalidInput, format!("TLS settings: {}", e));
+    let provider = Arc::new(provider);
+    let builder = ServerConfig::builder_with_provider(Arc::clone(&provider))
+        .with_protocol_versions(versions)
+        .map_err(policy_error)?;
+    let builder = match &options.client_ca {
+        Some(ca) => builder.with_client_cert_verifier(client_verifier(ca, Arc::clone(&provider))?),
+        None => builder.with_no_client_auth(),
+    };
+    let mut config = if options.ocsp {
+        let query = ocsp_query(cert, &chain, options)?;
+        let signing_key = provider.key_provider.load_private_key(key_der).map_err(|e| invalid(key, &e))?;
+        let certified = CertifiedKey::new(chain, signing_key);
+        certified.keys_match().map_err(|e| invalid(cert, &e))?;
+        let stapler = Arc::new(Stapler(RwLock::new(Arc::new(certified))));
+        let refreshed = Arc::clone(&stapler);
+        thread::spawn(move || keep_stapled(&refreshed, &query));
+        builder.with_cert_resolver(stapler)
+    } else {
+        builder.with_single_cert(chain, key_der).map_err(|e| invalid(cert, &e))?
+    };
+    config.alpn_protocols = match &options.alpn {
+        Some(protocols) => protocols.iter().map(|protocol| protocol.as_bytes().to_vec()).collect(),
+        // Saying so keeps browsers from trying h2
+        None => vec![b"http/1.1".to_vec()],
+    };
+    if options.session_tickets {
+        config.ticketer = Ticketer::new().map_err(policy_error)?;
+    }
+    if options.no_resumption {
+        config.session_storage = Arc::new(NoServerSessionStorage {});
+        config.send_tls13_tickets = 0;
+    }
+    Ok(Arc::new(config))
+}
+
+fn read_chain(cert: &Path) -> io::Result<Vec<CertificateDer<'static>>> {
+    let invalid = |e: &dyn std::fmt::Display| io::Error::new(io::ErrorKind::InvalidData, format!("{}: {}", cert.display(), e));
+    let chain = CertificateDer::pem_file_iter(cert)
+        .and_then(|certs| certs.collect::<Result<Vec<_>, _>>())
+        .map_err(|e| invalid(&e))?;
+    if chain.is_empty() {
+        return Err(invalid(&"no certificates found"));
+    }
+    Ok(chain)
+}
+
+// The issuer's certificate has to follow the leaf in --tls-cert, since the
+// responder is asked about the pair, as fullchain.pem has it
+fn ocsp_query(cert: &Path, chain: &[CertificateDer], options: &TlsOptions) -> io::Result<ocsp::Query> {
+    let issuer = chain.get(1).ok_or_else(|| {
+        io::Error::new(io::ErrorKind::InvalidData, format!("{}: OCSP stapling needs the issuer's certificate after the leaf", cert.display()))
+    })?;
+    ocsp::Query::new(&chain[0], issuer, options.ocsp_responder.as_deref())
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, format!("{}: {}", cert.display(), e)))
+}
+
+// Where --tls-ocsp will ask, for --dry-run
+pub fn ocsp_responder(cert: &Path, options: &TlsOptions) -> io::Result<String> {
+    Ok(ocsp_query(cert, &read_chain(cert)?, options)?.responder)
+}
+
+// Hands every handshake the certificate with the latest response stapled on
+#[derive(Debug)]
+struct Stapler(RwLock<Arc<CertifiedKey>>);
+
+impl Stapler {
+    fn staple(&self, response: Option<Vec<u8>>) {
+        let mut current = self.0.write().unwrap_or_else(|e| e.into_inner());
+        let mut stapled = CertifiedKey::clone(&current);
+        stapled.ocsp = response;
+        *current = Arc::new(stapled);
+    }
+}
+
+impl ResolvesServerCert for Stapler {
+    fn resolve(&self, _: ClientHello) -> Option<Arc<CertifiedKey>> {
+        Some(Arc::clone(&self.0.read().unwrap_or_else(|e| e.into_inner())))
+    }
+}
+
+// Fetches a response at once, then again halfway to each one's nextUpdate,
+// or hourly if it has none. Failed fetches are retried every few minutes,
+// and what's stapled stays until it goes out of date.
+fn keep_stapled(stapler: &Stapler, query: &ocsp::Query) {
+    let mut expires = None;
+    loop {
+        let now = SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |since| since.as_secs());
+        let fetched = query.fetch().map_err(|e| e.to_string()).and_then(|response| Ok((query.check(&response, now)?, response)));
+        let wait = match fetched {
+            Ok((next_update, response)) => {
+                stapler.staple(Some(response));
+                expires = next_update;
+                next_update.map_or(OCSP_REFRESH, |next| Duration::from_secs((next - now) / 2)).max(OCSP_RETRY)
+            }
+            Err(e) => {
+                eprintln!("OCSP stapling: {}: {}", query.responder, e);
+                if expires.is_some_and(|expires| expires <= now + OCSP_RETRY.as_secs()) {
+                    stapler.staple(None);
+                    expires = None;
+                }
+                OCSP_RETRY
+            }
+        };
+        thread::sleep(wait);
+    }
+}
+
+// Every certificate in `ca` is trusted to sign client certificates. Those
+// must be meant for client authentication, and rustls checks the expiry.
+fn client_verifier(ca: &Path, provider: Arc<CryptoProvider>) -> io::Result<Arc<dyn ClientCertVerifier>> {
+    let invalid = |e: &dyn std::fmt::Display| io::Error::new(io::ErrorKind::InvalidData, format!("{}: {}", ca.display(), e));
+    let mut roots = RootCertStore::empty();
+    for certificate in CertificateDer::pem_file_iter(ca).map_err(|e| invalid(&e))? {
+        roots.add(certificate.map_err(|e| invalid(&e))?).map_err(|e| invalid(&e))?;
+    }
+    if roots.is_empty() {
+        return Err(invalid(&"no certificates found"));
+    }
+    WebPkiClientVerifier::builder_with_provider(Arc::new(roots), provider).build().map_err(|e| invalid(&e))
+}
+
+// The names a client certificate goes by: its subject's common names, then
+// the DNS names and email addresses among its subjectAltNames
+fn certificate_names(certificate: &[u8]) -> Vec<String> {
+    let fields = match der::certificate_fields(certificate) {
+        Some(fields) => fields,
+        None => return Vec::new(),
+    };
+    let text = |value: der::Element| std::str::from_utf8(value.contents).ok().map(String::from);
+    // After the serial, signature algorithm, issuer and validity
+    let mut names: Vec<String> = fields
+        .get(4)
+        .into_iter()
+        .flat_map(|subject| der::elements(subject.contents))
+        .filter(|attributes| attributes.tag == der::SET)
+        .flat_map(|attributes| der::elements(attributes.contents))
+        .filter_map(|attribute| {
+            let mut parts = der::elements(attribute.contents);
+            parts.next().filter(|oid| oid.tag == der::OID && oid.contents == der::COMMON_NAME)?;
+            parts.next()
+        })
+        .filter_map(text)
+        .collect();
+    if let Some(mut alt_names) = der::extension(&fields, der::SUBJECT_ALT_NAME) {
+        let list = der::read(&mut alt_names);
+        // [1] is an rfc822Name and [2] a dNSName, both IA5String
+        names.extend(
+            list.into_iter()
+                .flat_map(|list| der::elements(list.contents))
+                .filter(|name| name.tag == 0x81 || name.tag == 0x82)
+                .filter_map(text),
+        );
+    }
+    names
+}
+
+// The handshake happens on the first read or write, on the handler thread
+pub fn accept(config: &Arc<ServerConfig>, stream: TcpStream) -> io::Result<TlsStream> {
+    let connection = ServerConnection::new(Arc::clone(config)).map_err(io::Error::other)?;
+    Ok(StreamOwned::new(connection, stream))
+}
+
+impl Stream for TlsStream {
+    fn peer_addr(&self) -> io::Result<SocketAddr> {
+        self.sock.peer_addr()
+    }
+
+    fn set_read_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        self.sock.set_read_timeout(timeout)
+    }
+
+    fn set_write_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        self.sock.set_write_timeout(timeout)
+    }
+
+    // Only verified certificates get this far; a failed check ends the handshake
+    fn client_names(&self) -> Vec<String> {
+        self.conn.peer_certificates().and_then(|chain| chain.first()).map_or_else(Vec::new, |leaf| certificate_names(leaf))
+    }
+
+    // close_notify first, so the client can tell the end from a truncation
+    fn shutdown_write(&mut self) -> io::Result<()> {
+        self.conn.send_close_notify();
+        self.flush()?;
+        self.sock.shutdown(Shutdown::Write)
+    }
+}
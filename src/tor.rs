@@ -0,0 +1,98 @@
+use std::{
+    fs,
+    io::{self, BufRead, BufReader, Write},
+    net::{SocketAddr, TcpStream},
+    path::Path,
+};
+
+// Asks a running Tor, through its control port, to publish an onion service
+// forwarding port 80 to this server at `address`. The service lasts as long as the returned
+// control connection stays open, so the caller keeps it for the process's
+// lifetime. With `key_file`, the service key is kept there so the .onion
+// address survives restarts; otherwise every run gets a new address.
+pub fn publish(
+    control: &str,
+    password: Option<&str>,
+    key_file: Option<&Path>,
+    address: SocketAddr,
+) -> io::Result<TcpStream> {
+    let mut stream = TcpStream::connect(control)?;
+    let mut reader = BufReader::new(stream.try_clone()?);
+
+    let info = command(&mut stream, &mut reader, "PROTOCOLINFO 1")?;
+    let auth = info.iter().find_map(|line| line.strip_prefix("AUTH ")).unwrap_or("");
+    let methods = auth.split_whitespace().find_map(|field| field.strip_prefix("METHODS=")).unwrap_or("");
+    let methods: Vec<&str> = methods.split(',').collect();
+
+    let authenticate = if let Some(password) = password {
+        format!("AUTHENTICATE \"{}\"", password.replace('\\', "\\\\").replace('"', "\\\""))
+    } else if methods.contains(&"NULL") {
+        "AUTHENTICATE".to_string()
+    } else if methods.contains(&"COOKIE") {
+        let cookie_file = auth
+            .split_whitespace()
+            .find_map(|field| field.strip_prefix("COOKIEFILE="))
+            .map(|file| file.trim_matches('"'))
+            .ok_or_else(|| tor_error("Tor offers cookie authentication but no cookie file"))?;
+        let cookie = fs::read(cookie_file)?;
+        format!("AUTHENTICATE {}", cookie.iter().map(|byte| format!("{:02x}", byte)).collect::<String>())
+    } else {
+        return Err(tor_error(&format!("no supported control port authentication among {:?}; try --tor-password", methods)));
+    };
+    command(&mut stream, &mut reader, &authenticate)?;
+
+    let existing_key = match key_file {
+        Some(path) if path.exists() => Some(fs::read_to_string(path)?.trim().to_string()),
+        _ => None,
+    };
+    let key = existing_key.as_deref().unwrap_or("NEW:ED25519-V3");
+    let flags = if key_file.is_some() { "" } else { " Flags=DiscardPK" };
+    let reply = command(&mut stream, &mut reader, &format!("ADD_ONION {} Port=80,{}{}", key, address, flags))?;
+
+    let service_id = reply
+        .iter()
+        .find_map(|line| line.strip_prefix("ServiceID="))
+        .ok_or_else(|| tor_error("ADD_ONION reply without a ServiceID"))?;
+    if let (Some(path), None) = (key_file, &existing_key) {
+        if let Some(private_key) = reply.iter().find_map(|line| line.strip_prefix("PrivateKey=")) {
+            fs::write(path, format!("{}\n", private_key))?;
+        }
+    }
+
+    println!("Onion service at http://{}.onion", service_id);
+    Ok(stream)
+}
+
+// Sends one command and collects the reply's lines without their status
+// prefix, failing unless the status is 250
+fn command(stream: &mut TcpStream, reader: &mut impl BufRead, command: &str) -> io::Result<Vec<String>> {
+    stream.write_all(format!("{}\r\n", command).as_bytes())?;
+
+    let mut lines = Vec::new();
+    loop {
+        let mut line = String::new();
+        if reader.read_line(&mut line)? == 0 {
+            return Err(tor_error("control connection closed"));
+        }
+        let line = line.trim_end();
+        if line.len() < 4 {
+            return Err(tor_error(&format!("malformed reply: {}", line)));
+        }
+
+        let (status, rest) = line.split_at(3);
+        if status != "250" {
+            // Keep secrets out of the message: only the command's first word
+            let name = command.split_whitespace().next().unwrap_or("");
+            return Err(tor_error(&format!("{} failed: {}", name, line)));
+        }
+        lines.push(rest[1..].to_string());
+        // `250-` continues the reply, `250 ` ends it
+        if rest.starts_with(' ') {
+            return Ok(lines);
+        }
+    }
+}
+
+fn tor_error(message: &str) -> io::Error {
+    io::Error::other(format!("Tor: {}", message))
+}
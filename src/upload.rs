@@ -0,0 +1,313 @@
+use std::{
+    fs::{self, File, OpenOptions},
+    io::{self, Read, Write},
+    path::{Component, Path, PathBuf},
+    process,
+    sync::atomic::{AtomicU64, Ordering},
+};
+
+use crate::{
+    append, body_reader, decode_url_encoded, hidden::Hidden, is_path_within, request_header, send_error, send_response, Exchange,
+    users,
+};
+
+// Longest line accepted among a part's headers, and the most of them
+const MAX_PART_HEADER_LINE: usize = 8192;
+const MAX_PART_HEADERS: usize = 16;
+
+// Tells apart the temporary files of uploads running at the same time
+static UPLOADS: AtomicU64 = AtomicU64::new(0);
+
+// `PUT /<path>` stores the body as that file, replacing any existing one, and
+// `POST /<dir>/` with a multipart/form-data body stores each file in it under
+// its own name. Directories are never created. The caller has already checked
+// that the client may write under `root`, and that `path` isn't hidden.
+#[allow(clippy::too_many_arguments)]
+pub fn handle(
+    stream: &mut Exchange,
+    method: &str,
+    request: &str,
+    received: &[u8],
+    path: &str,
+    root: &Path,
+    quota: Option<u64>,
+    hidden: &Hidden,
+) -> io::Result<()> {
+    // Request bodies are never chunked, so there is always a length
+    let length = match request_header(request, "Content-Length").and_then(|length| length.parse::<u64>().ok()) {
+        Some(length) => length,
+        None => return send_error(stream, "411 Length Required", "Length Required"),
+    };
+    if quota.is_some_and(|quota| users::disk_usage(root).saturating_add(length) > quota) {
+        return send_error(stream, "507 Insufficient Storage", "Insufficient Storage");
+    }
+
+    let target = decode_url_encoded(path);
+    match method {
+        "PUT" => put(stream, request, received, &target, root, length),
+        _ => match request_header(request, "Content-Type").and_then(boundary) {
+            Some(boundary) => post(stream, request, received, path, &target, root, length, &boundary, hidden),
+            None => send_error(stream, "415 Unsupported Media Type", "Expected multipart/form-data"),
+        },
+    }
+}
+
+fn put(stream: &mut Exchange, request: &str, received: &[u8], target: &str, root: &Path, length: u64) -> io::Result<()> {
+    let path = match append::resolve(target.trim_start_matches('/'), root) {
+        Ok(path) => path,
+        Err(status) => return send_error(stream, status, &status[4..]),
+    };
+
+    // Written beside the destination and renamed, so readers never see half an upload
+    let partial = partial_path(&path);
+    let result = File::create(&partial).and_then(|mut file| {
+        let copied = io::copy(&mut body_reader(stream, request, received, length)?, &mut file)?;
+        if copied < length {
+            return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "body shorter than Content-Length"));
+        }
+        file.sync_all()
+    });
+    if let Err(e) = result {
+        let _ = fs::remove_file(&partial);
+        eprintln!("Error receiving upload of {}: {:?}", path.display(), e);
+        return send_error(stream, "400 Bad Request", "Bad Request");
+    }
+
+    let created = !path.exists();
+    fs::rename(&partial, &path)?;
+    if created {
+        send_response(stream, "201 Created", "text/html", "")
+    } else {
+        send_response(stream, "204 No Content", "text/html", "")
+    }
+}
+
+// Files whose names are already taken or hidden are skipped and reported, the
+// rest kept. `path` is the request's, still percent-encoded, and `target` the
+// decoded one.
+#[allow(clippy::too_many_arguments)]
+fn post(
+    stream: &mut Exchange,
+    request: &str,
+    received: &[u8],
+    path: &str,
+    target: &str,
+    root: &Path,
+    length: u64,
+    boundary: &str,
+    hidden: &Hidden,
+) -> io::Result<()> {
+    let dir = root.join(target.trim_start_matches('/'));
+    if !dir.is_dir() {
+        return send_error(stream, "404 Not Found", "Not Found");
+    }
+    if !is_path_within(&dir, root)? {
+        return send_error(stream, "403 Forbidden", "Forbidden");
+    }
+
+    let parts = Multipart::new(body_reader(stream, request, received, length)?, boundary);
+    let (skipped, refused) = match store_files(parts, &dir, target, hidden) {
+        Ok(outcome) => outcome,
+        Err(e) => {
+            eprintln!("Error receiving upload into {}: {:?}", dir.display(), e);
+            return send_error(stream, "400 Bad Request", "Bad Request");
+        }
+    };
+
+    if !refused.is_empty() {
+        let body = format!("Names that can't be uploaded: {}", refused.join(", "));
+        return send_error(stream, "403 Forbidden", &body);
+    }
+    if !skipped.is_empty() {
+        let body = format!("Already exists: {}", skipped.join(", "));
+        return send_error(stream, "409 Conflict", &body);
+    }
+    // Back to the listing, which now shows the new files
+    let response = stream.response_head("303 See Other", "text/html", 0, &format!("Location: {}\r\n", path));
+    stream.write_all(response.as_bytes())?;
+    stream.flush()
+}
+
+// Returns the names that were skipped because they exist, and then those
+// refused because they'd be hidden in `target`
+fn store_files(mut parts: Multipart<impl Read>, dir: &Path, target: &str, hidden: &Hidden) -> io::Result<(Vec<String>, Vec<String>)> {
+    let (mut skipped, mut refused) = (Vec::new(), Vec::new());
+    while let Some(file_name) = parts.next_part()? {
+        let name = match file_name.as_deref().and_then(plain_name) {
+            Some(name) => name,
+            // Ordinary form fields, and file inputs left empty
+            None => continue,
+        };
+        if hidden.hides_in(target, name) {
+            refused.push(name.to_string());
+            continue;
+        }
+
+        // create_new refuses existing entries, symlinks included, in one step
+        let path = dir.join(name);
+        let mut file = match OpenOptions::new().write(true).create_new(true).open(&path) {
+            Ok(file) => file,
+            Err(e) if e.kind() == io::ErrorKind::AlreadyExists => {
+                skipped.push(name.to_string());
+                continue;
+            }
+            Err(e) => return Err(e),
+        };
+        if let Err(e) = parts.copy_part(&mut file).and_then(|_| file.sync_all()) {
+            let _ = fs::remove_file(&path);
+            return Err(e);
+        }
+    }
+    Ok((skipped, refused))
+}
+
+fn partial_path(path: &Path) -> PathBuf {
+    let name = path.file_name().unwrap_or_default().to_string_lossy();
+    let id = UPLOADS.fetch_add(1, Ordering::Relaxed);
+    path.with_file_name(format!(".{}.upload-{}-{}", name, process::id(), id))
+}
+
+fn boundary(content_type: &str) -> Option<String> {
+    let mut params = content_type.split(';');
+    if !params.next()?.trim().eq_ignore_ascii_case("multipart/form-data") {
+        return None;
+    }
+    params
+        .filter_map(|param| param.split_once('='))
+        .find(|(name, _)| name.trim().eq_ignore_ascii_case("boundary"))
+        .map(|(_, value)| value.trim().trim_matches('"').to_string())
+        .filter(|boundary| !boundary.is_empty() && boundary.len() <= 70)
+}
+
+// Browsers send bare names, but some older ones send the whole client-side path
+fn plain_name(file_name: &str) -> Option<&str> {
+    let name = file_name.rsplit(['/', '\\']).next()?;
+    let mut components = Path::new(name).components();
+    match (components.next(), components.next()) {
+        (Some(Component::Normal(_)), None) if !name.contains('\0') => Some(name),
+        _ => None,
+    }
+}
+
+// Reads a multipart body (RFC 7578) part by part without holding any part in memory
+struct Multipart<R: Read> {
+    reader: R,
+    buffer: Vec<u8>,
+    // CRLF, two dashes and the boundary: what ends every part
+    delimiter: Vec<u8>,
+    finished: bool,
+}
+
+impl<R: Read> Multipart<R> {
+    fn new(reader: R, boundary: &str) -> Multipart<R> {
+        // The first delimiter has no CRLF before it, so start as if one had been read
+        Multipart { reader, buffer: b"\r\n".to_vec(), delimiter: format!("\r\n--{}", boundary).into_bytes(), finished: false }
+    }
+
+    // Moves to the next part and returns the filename its headers give, if any;
+    // None once the closing delimiter has been read
+    fn next_part(&mut self) -> io::Result<Option<Option<String>>> {
+        if self.finished {
+            return Ok(None);
+        }
+        // Skips the preamble before the first part, or what's left of the last one
+        self.copy_part(&mut io::sink())?;
+        self.buffer.drain(..self.delimiter.len());
+
+        while self.buffer.len() < 2 {
+            self.fill()?;
+        }
+        if self.buffer.starts_with(b"--") {
+            self.finished = true;
+            return Ok(None);
+        }
+        self.read_line()?;
+
+        let mut file_name = None;
+        for _ in 0..MAX_PART_HEADERS {
+            let line = self.read_line()?;
+            if line.is_empty() {
+                return Ok(Some(file_name));
+            }
+            let line = String::from_utf8_lossy(&line);
+            if let Some((name, value)) = line.split_once(':') {
+                if name.trim().eq_ignore_ascii_case("Content-Disposition") {
+                    file_name = disposition_param(value, "filename");
+                }
+            }
+        }
+        Err(io::Error::new(io::ErrorKind::InvalidData, "too many part headers"))
+    }
+
+    // Copies the current part's content up to the delimiter that ends it,
+    // which is left in the buffer for next_part
+    fn copy_part(&mut self, out: &mut impl Write) -> io::Result<()> {
+        loop {
+            if let Some(end) = find(&self.buffer, &self.delimiter) {
+                out.write_all(&self.buffer[..end])?;
+                self.buffer.drain(..end);
+                return Ok(());
+            }
+            // Whatever can't be the start of a delimiter is content
+            let keep = self.delimiter.len() - 1;
+            if self.buffer.len() > keep {
+                let content = self.buffer.len() - keep;
+                out.write_all(&self.buffer[..content])?;
+                self.buffer.drain(..content);
+            }
+            self.fill()?;
+        }
+    }
+
+    fn read_line(&mut self) -> io::Result<Vec<u8>> {
+        loop {
+            if let Some(end) = find(&self.buffer, b"\r\n") {
+                let line = self.buffer[..end].to_vec();
+                self.buffer.drain(..end + 2);
+                return Ok(line);
+            }
+            if self.buffer.len() > MAX_PART_HEADER_LINE {
+                return Err(io::Error::new(io::ErrorKind::InvalidData, "part header line too long"));
+            }
+            self.fill()?;
+        }
+    }
+
+    fn fill(&mut self) -> io::Result<()> {
+        let mut chunk = [0; 16 * 1024];
+        let read = self.reader.read(&mut chunk)?;
+        if read == 0 {
+            return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "multipart body ended early"));
+        }
+        self.buffer.extend_from_slice(&chunk[..read]);
+        Ok(())
+    }
+}
+
+// `form-data; name="file"; filename="a.txt"`; quoted values may contain `;`
+fn disposition_param(value: &str, wanted: &str) -> Option<String> {
+    let mut rest = value;
+    while let Some(start) = rest.find(';') {
+        rest = rest[start + 1..].trim_start();
+        let (name, after) = rest.split_once('=')?;
+        let (param, remainder) = match after.strip_prefix('"') {
+            Some(quoted) => {
+                let end = quoted.find('"')?;
+                (quoted[..end].to_string(), &quoted[end + 1..])
+            }
+            None => {
+                let end = after.find(';').unwrap_or(after.len());
+                (after[..end].trim().to_string(), &after[end..])
+            }
+        };
+        if name.trim().eq_ignore_ascii_case(wanted) {
+            return Some(param);
+        }
+        rest = remainder;
+    }
+    None
+}
+
+fn find(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    haystack.windows(needle.len()).position(|window| window == needle)
+}
@@ -0,0 +1,351 @@
+// `--io-uring`, with the io-uring feature on Linux: file bodies go out through
+// a ring per handler thread, which reads the next piece of the file while the
+// last one is being sent, and waits for both with one system call. Kernels or
+// sandboxes without io_uring get the ordinary copy, as do TLS connections and
+// bodies served from --file-cache.
+
+use std::{
+    cell::RefCell,
+    fs::File,
+    io, mem,
+    net::TcpStream,
+    os::fd::{AsRawFd, RawFd},
+    ptr,
+    sync::atomic::{AtomicBool, AtomicU32, Ordering},
+    time::Duration,
+};
+
+const ENTRIES: u32 = 4;
+const CHUNK_SIZE: usize = 128 * 1024;
+
+const IORING_OFF_SQ_RING: libc::off_t = 0;
+const IORING_OFF_CQ_RING: libc::off_t = 0x8000000;
+const IORING_OFF_SQES: libc::off_t = 0x10000000;
+const IORING_ENTER_GETEVENTS: libc::c_uint = 1;
+const IORING_OP_LINK_TIMEOUT: u8 = 15;
+const IORING_OP_READ: u8 = 22;
+const IORING_OP_SEND: u8 = 26;
+const IOSQE_IO_LINK: u8 = 1 << 2;
+
+// What each completion was for
+const SEND: u64 = 1;
+const TIMEOUT: u64 = 2;
+const READ: u64 = 3;
+
+// Set once setting a ring up has failed, so no thread tries again
+static UNAVAILABLE: AtomicBool = AtomicBool::new(false);
+
+thread_local! {
+    static RING: RefCell<Option<Ring>> = const { RefCell::new(None) };
+}
+
+// `length` bytes of `file` from `offset` onto `socket`, or None when there's
+// no ring to do it with. A file that shrinks ends the body early, as with
+// io::copy.
+pub fn send_file(socket: &TcpStream, file: &File, offset: u64, length: u64) -> Option<io::Result<u64>> {
+    if UNAVAILABLE.load(Ordering::Relaxed) {
+        return None;
+    }
+    RING.with(|ring| {
+        let mut ring = ring.borrow_mut();
+        if ring.is_none() {
+            match Ring::new() {
+                Ok(new) => *ring = Some(new),
+                Err(e) => {
+                    if !UNAVAILABLE.swap(true, Ordering::Relaxed) {
+                        eprintln!("io_uring unavailable, sending files the ordinary way: {}", e);
+                    }
+                    return None;
+                }
+            }
+        }
+        let timeout = socket.write_timeout().ok().flatten();
+        ring.as_mut().map(|ring| ring.send_file(socket.as_raw_fd(), file.as_raw_fd(), offset, length, timeout))
+    })
+}
+
+#[repr(C)]
+#[derive(Default)]
+struct SqringOffsets {
+    head: u32,
+    tail: u32,
+    ring_mask: u32,
+    ring_entries: u32,
+    flags: u32,
+    dropped: u32,
+    array: u32,
+    resv1: u32,
+    user_addr: u64,
+}
+
+#[repr(C)]
+#[derive(Default)]
+struct CqringOffsets {
+    head: u32,
+    tail: u32,
+    ring_mask: u32,
+    ring_entries: u32,
+    overflow: u32,
+    cqes: u32,
+    flags: u32,
+    resv1: u32,
+    user_addr: u64,
+}
+
+#[repr(C)]
+#[derive(Default)]
+struct Params {
+    sq_entries: u32,
+    cq_entries: u32,
+    flags: u32,
+    sq_thread_cpu: u32,
+    sq_thread_idle: u32,
+    features: u32,
+    wq_fd: u32,
+    resv: [u32; 3],
+    sq_off: SqringOffsets,
+    cq_off: CqringOffsets,
+}
+
+#[repr(C)]
+#[derive(Default, Clone, Copy)]
+struct Sqe {
+    opcode: u8,
+    flags: u8,
+    ioprio: u16,
+    fd: i32,
+    off: u64,
+    addr: u64,
+    len: u32,
+    op_flags: u32,
+    user_data: u64,
+    buf_index: u16,
+    personality: u16,
+    splice_fd_in: i32,
+    addr3: u64,
+    pad: u64,
+}
+
+#[repr(C)]
+struct Cqe {
+    user_data: u64,
+    res: i32,
+    flags: u32,
+}
+
+#[repr(C)]
+struct Timespec {
+    tv_sec: i64,
+    tv_nsec: i64,
+}
+
+// One mapping of the kernel's, unmapped on drop
+struct Mapping {
+    address: *mut u8,
+    length: usize,
+}
+
+impl Mapping {
+    fn new(fd: RawFd, length: usize, offset: libc::off_t) -> io::Result<Mapping> {
+        let address = unsafe {
+            libc::mmap(
+                ptr::null_mut(),
+                length,
+                libc::PROT_READ | libc::PROT_WRITE,
+                libc::MAP_SHARED | libc::MAP_POPULATE,
+                fd,
+                offset,
+            )
+        };
+        if address == libc::MAP_FAILED {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(Mapping { address: address.cast(), length })
+    }
+
+    fn at<T>(&self, offset: u32) -> *mut T {
+        unsafe { self.address.add(offset as usize).cast() }
+    }
+}
+
+impl Drop for Mapping {
+    fn drop(&mut self) {
+        unsafe {
+            libc::munmap(self.address.cast(), self.length);
+        }
+    }
+}
+
+struct Ring {
+    fd: RawFd,
+    sq: Mapping,
+    cq: Mapping,
+    sqes: Mapping,
+    sq_off: SqringOffsets,
+    cq_off: CqringOffsets,
+    // Two, so one can be read into while the other is sent
+    buffers: [Box<[u8]>; 2],
+    timeout: Timespec,
+}
+
+impl Ring {
+    fn new() -> io::Result<Ring> {
+        let mut params = Params::default();
+        let fd = unsafe { libc::syscall(libc::SYS_io_uring_setup, ENTRIES, &mut params as *mut Params) };
+        if fd < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        let fd = fd as RawFd;
+        let mappings = (|| {
+            let sq_length = params.sq_off.array as usize + params.sq_entries as usize * mem::size_of::<u32>();
+            let cq_length = params.cq_off.cqes as usize + params.cq_entries as usize * mem::size_of::<Cqe>();
+            let sq = Mapping::new(fd, sq_length, IORING_OFF_SQ_RING)?;
+            let cq = Mapping::new(fd, cq_length, IORING_OFF_CQ_RING)?;
+            let sqes = Mapping::new(fd, params.sq_entries as usize * mem::size_of::<Sqe>(), IORING_OFF_SQES)?;
+            Ok((sq, cq, sqes))
+        })();
+        let (sq, cq, sqes) = match mappings {
+            Ok(mappings) => mappings,
+            Err(e) => {
+                unsafe { libc::close(fd) };
+                return Err(e);
+            }
+        };
+        Ok(Ring {
+            fd,
+            sq,
+            cq,
+            sqes,
+            sq_off: params.sq_off,
+            cq_off: params.cq_off,
+            buffers: [vec![0; CHUNK_SIZE].into_boxed_slice(), vec![0; CHUNK_SIZE].into_boxed_slice()],
+            timeout: Timespec { tv_sec: 0, tv_nsec: 0 },
+        })
+    }
+
+    fn send_file(&mut self, socket: RawFd, file: RawFd, mut offset: u64, length: u64, timeout: Option<Duration>) -> io::Result<u64> {
+        if let Some(timeout) = timeout {
+            self.timeout = Timespec { tv_sec: timeout.as_secs() as i64, tv_nsec: timeout.subsec_nanos() as i64 };
+        }
+        let mut unread = length;
+        let mut sent = 0;
+        // The buffer being sent, how far into it, and how much it holds
+        let (mut current, mut start, mut end) = (0, 0, 0);
+        // How much the other buffer holds, once it's been read into
+        let mut next = None;
+
+        loop {
+            let read_next = next.is_none() && unread > 0;
+            let sending = start < end;
+            if !sending && !read_next {
+                return Ok(sent);
+            }
+            if sending {
+                let piece = &self.buffers[current][start..end];
+                let (address, len) = (piece.as_ptr() as u64, piece.len() as u32);
+                let linked = if timeout.is_some() { IOSQE_IO_LINK } else { 0 };
+                self.push(Sqe { opcode: IORING_OP_SEND, flags: linked, fd: socket, addr: address, len, op_flags: libc::MSG_NOSIGNAL as u32, user_data: SEND, ..Sqe::default() });
+                if timeout.is_some() {
+                    let address = &self.timeout as *const Timespec as u64;
+                    self.push(Sqe { opcode: IORING_OP_LINK_TIMEOUT, fd: -1, addr: address, len: 1, user_data: TIMEOUT, ..Sqe::default() });
+                }
+            }
+            if read_next {
+                let buffer = &mut self.buffers[1 - current];
+                let (address, len) = (buffer.as_mut_ptr() as u64, buffer.len().min(usize::try_from(unread).unwrap_or(usize::MAX)) as u32);
+                self.push(Sqe { opcode: IORING_OP_READ, fd: file, off: offset, addr: address, len, user_data: READ, ..Sqe::default() });
+            }
+
+            let expected = sending as u32 * (1 + timeout.is_some() as u32) + read_next as u32;
+            let (mut send_result, mut read_result) = (None, None);
+            self.submit_and_wait(expected, |user_data, result| match user_data {
+                SEND => send_result = Some(result),
+                READ => read_result = Some(result),
+                _ => {}
+            })?;
+
+            if let Some(result) = read_result {
+                let read = result.map_err(io::Error::from_raw_os_error)? as u64;
+                offset += read;
+                // Shrunk since it was opened: what's left to send is all there is
+                unread = if read == 0 { 0 } else { unread - read };
+                next = Some(read as usize);
+            }
+            if let Some(result) = send_result {
+                match result {
+                    Ok(0) => return Err(io::ErrorKind::WriteZero.into()),
+                    Ok(written) => {
+                        start += written as usize;
+                        sent += written as u64;
+                    }
+                    Err(libc::ECANCELED) => return Err(io::Error::new(io::ErrorKind::TimedOut, "sending timed out")),
+                    Err(e) => return Err(io::Error::from_raw_os_error(e)),
+                }
+            }
+            if start == end {
+                if let Some(read) = next.take() {
+                    current = 1 - current;
+                    (start, end) = (0, read);
+                }
+            }
+        }
+    }
+
+    fn push(&mut self, sqe: Sqe) {
+        let head = unsafe { &*self.sq.at::<AtomicU32>(self.sq_off.head) };
+        let tail = unsafe { &*self.sq.at::<AtomicU32>(self.sq_off.tail) };
+        let mask = unsafe { *self.sq.at::<u32>(self.sq_off.ring_mask) };
+        let position = tail.load(Ordering::Relaxed);
+        // Every round waits for all it submitted, so the ring never fills
+        debug_assert!(position.wrapping_sub(head.load(Ordering::Acquire)) < ENTRIES);
+        let index = position & mask;
+        unsafe {
+            *self.sqes.at::<Sqe>(0).add(index as usize) = sqe;
+            *self.sq.at::<u32>(self.sq_off.array).add(index as usize) = index;
+        }
+        tail.store(position.wrapping_add(1), Ordering::Release);
+    }
+
+    // Every entry pushed is submitted, and all `count` completions are in
+    // before this returns, so no buffer is still the kernel's afterwards
+    fn submit_and_wait(&mut self, count: u32, mut complete: impl FnMut(u64, Result<u32, i32>)) -> io::Result<()> {
+        let mut to_submit = count;
+        let mut remaining = count;
+        while remaining > 0 {
+            let submitted = unsafe {
+                libc::syscall(libc::SYS_io_uring_enter, self.fd, to_submit, 1 as libc::c_uint, IORING_ENTER_GETEVENTS, ptr::null::<u8>(), 0usize)
+            };
+            if submitted < 0 {
+                let e = io::Error::last_os_error();
+                if e.kind() == io::ErrorKind::Interrupted {
+                    continue;
+                }
+                // Nothing can be known to be done with the buffers, so this ring is done for
+                UNAVAILABLE.store(true, Ordering::Relaxed);
+                return Err(e);
+            }
+            to_submit -= (submitted as u32).min(to_submit);
+
+            let head = unsafe { &*self.cq.at::<AtomicU32>(self.cq_off.head) };
+            let tail = unsafe { &*self.cq.at::<AtomicU32>(self.cq_off.tail) };
+            let mask = unsafe { *self.cq.at::<u32>(self.cq_off.ring_mask) };
+            let mut position = head.load(Ordering::Relaxed);
+            while position != tail.load(Ordering::Acquire) && remaining > 0 {
+                let cqe = unsafe { &*self.cq.at::<Cqe>(self.cq_off.cqes).add((position & mask) as usize) };
+                complete(cqe.user_data, if cqe.res < 0 { Err(-cqe.res) } else { Ok(cqe.res as u32) });
+                position = position.wrapping_add(1);
+                remaining -= 1;
+            }
+            head.store(position, Ordering::Release);
+        }
+        Ok(())
+    }
+}
+
+impl Drop for Ring {
+    fn drop(&mut self) {
+        unsafe {
+            libc::close(self.fd);
+        }
+    }
+}
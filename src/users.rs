@@ -0,0 +1,191 @@
+use std::{
+    fs, io,
+    path::{Path, PathBuf},
+};
+
+use base64::{engine::general_purpose::STANDARD, Engine};
+use sha2::{Digest, Sha256};
+use walkdir::WalkDir;
+
+use crate::{
+    args::{self, Args},
+    constant_time_eq, request_header,
+};
+
+// One account per line, fields separated by whitespace, `#` starting a comment:
+//
+//     alice  sha-256:<salt>:<hex digest>  /srv/alice  rw  10G
+//     bob    sha-256:<salt>:<hex digest>  /srv/shared ro
+//
+// The digest is SHA-256 over the salt followed by the password, as printed by
+// `printf '%s' "$salt$password" | sha256sum`. The quota is optional and only
+// constrains writes.
+pub struct Users {
+    accounts: Vec<Account>,
+}
+
+struct Account {
+    user: User,
+    salt: String,
+    digest: String,
+}
+
+// Whoever a request authenticated as, from the users file, a system login or LDAP
+#[derive(Clone)]
+pub struct User {
+    pub name: String,
+    // Canonical, so it can be compared against canonicalized request paths
+    pub root: PathBuf,
+    pub can_write: bool,
+    pub quota: Option<u64>,
+}
+
+impl Users {
+    pub fn load(path: &Path) -> io::Result<Users> {
+        let content = fs::read_to_string(path)?;
+        let accounts = content
+            .lines()
+            .enumerate()
+            .map(|(index, line)| (index + 1, line.split('#').next().unwrap_or("").trim()))
+            .filter(|(_, line)| !line.is_empty())
+            .map(|(number, line)| {
+                parse_account(line).map_err(|message| {
+                    io::Error::new(io::ErrorKind::InvalidData, format!("{}:{}: {}", path.display(), number, message))
+                })
+            })
+            .collect::<io::Result<Vec<_>>>()?;
+        Ok(Users { accounts })
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &User> {
+        self.accounts.iter().map(|account| &account.user)
+    }
+
+    // The account a client certificate names with its CN or a subjectAltName.
+    // The CA vouched for the certificate, so no password is asked for.
+    fn certified(&self, names: &[String]) -> Option<&User> {
+        names.iter().find_map(|name| self.iter().find(|user| &user.name == name))
+    }
+
+    fn authenticate(&self, name: &str, password: &str) -> Option<&User> {
+        let account = self.accounts.iter().find(|account| account.user.name == name)?;
+        let digest = hex(&Sha256::digest(format!("{}{}", account.salt, password)));
+        constant_time_eq(&digest, &account.digest).then_some(&account.user)
+    }
+}
+
+// `--auth user:password`, or a line of an `--auth-file` in the same form. Unlike
+// accounts these only gate access, and everyone let in sees the same root. The
+// password is kept as a digest so comparing takes the same time whatever its length.
+pub struct Credential {
+    name: String,
+    digest: String,
+}
+
+impl Credential {
+    pub fn parse(pair: &str) -> Result<Credential, String> {
+        match pair.split_once(':') {
+            Some((name, password)) if !name.is_empty() && !password.is_empty() => {
+                Ok(Credential { name: name.to_string(), digest: hex(&Sha256::digest(password)) })
+            }
+            _ => Err("expected user:password".to_string()),
+        }
+    }
+
+    // One pair per line; blank lines and `#` comments are skipped, but a `#`
+    // elsewhere is part of the password
+    pub fn load(path: &Path) -> io::Result<Vec<Credential>> {
+        fs::read_to_string(path)?
+            .lines()
+            .enumerate()
+            .filter(|(_, line)| !line.trim().is_empty() && !line.trim_start().starts_with('#'))
+            .map(|(index, line)| {
+                Credential::parse(line).map_err(|message| {
+                    io::Error::new(io::ErrorKind::InvalidData, format!("{}:{}: {}", path.display(), index + 1, message))
+                })
+            })
+            .collect()
+    }
+}
+
+// Whether the request's `Authorization: Basic` header matches one of the
+// pairs, or the client's certificate names one of their users
+pub fn passes_gate(credentials: &[Credential], request: &str, client_names: &[String]) -> bool {
+    if credentials.iter().any(|credential| client_names.contains(&credential.name)) {
+        return true;
+    }
+    let (name, password) = match basic_credentials(request) {
+        Some(credentials) => credentials,
+        None => return false,
+    };
+    let digest = hex(&Sha256::digest(password));
+    credentials.iter().any(|credential| credential.name == name && constant_time_eq(&digest, &credential.digest))
+}
+
+// The account the client's certificate names, or else the user named by a
+// valid `Authorization: Basic` header, checked against the users file first
+// and then, if enabled, the system's PAM stack and the LDAP directory
+pub fn login(args: &Args, request: &str, client_names: &[String]) -> Option<User> {
+    if let Some(user) = args.users.as_ref().and_then(|users| users.certified(client_names)) {
+        return Some(user.clone());
+    }
+    let (name, password) = basic_credentials(request)?;
+    let (name, password) = (name.as_str(), password.as_str());
+
+    if let Some(user) = args.users.as_ref().and_then(|users| users.authenticate(name, password)) {
+        return Some(user.clone());
+    }
+
+    #[cfg(feature = "pam")]
+    if let Some(user) = args.pam_service.as_ref().and_then(|service| crate::pam::login(service, &args.pam_root, name, password)) {
+        return Some(user);
+    }
+    args.ldap.as_ref().and_then(|server| crate::ldap::login(args, server, name, password))
+}
+
+// Name and password from an `Authorization: Basic` header
+pub fn basic_credentials(request: &str) -> Option<(String, String)> {
+    let credentials = request_header(request, "Authorization")?.strip_prefix("Basic ")?;
+    let credentials = String::from_utf8(STANDARD.decode(credentials.trim()).ok()?).ok()?;
+    let (name, password) = credentials.split_once(':')?;
+    Some((name.to_string(), password.to_string()))
+}
+
+fn parse_account(line: &str) -> Result<Account, String> {
+    let fields: Vec<&str> = line.split_whitespace().collect();
+    let (name, password, root, access) = match fields[..] {
+        [name, password, root, access] | [name, password, root, access, _] => (name, password, root, access),
+        _ => return Err("expected name, password, root, ro or rw, and an optional quota".to_string()),
+    };
+
+    let (salt, digest) = match password.strip_prefix("sha-256:").and_then(|rest| rest.split_once(':')) {
+        Some((salt, digest)) if digest.len() == 64 => (salt, digest.to_ascii_lowercase()),
+        _ => return Err("password must be sha-256:<salt>:<hex digest>".to_string()),
+    };
+    let root = Path::new(root).canonicalize().map_err(|e| format!("root {}: {}", root, e))?;
+    let can_write = match access {
+        "ro" => false,
+        "rw" => true,
+        other => return Err(format!("access must be ro or rw, not {}", other)),
+    };
+    let quota = fields.get(4).map(|quota| args::parse_size(quota)).transpose().map_err(|e| e.to_string())?;
+
+    let user = User { name: name.to_string(), root, can_write, quota };
+    Ok(Account { user, salt: salt.to_string(), digest })
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+// Bytes stored under a root. Walks the whole tree, which is fine for the
+// occasional write or usage query but is why nothing keeps it up to date.
+pub fn disk_usage(root: &Path) -> u64 {
+    WalkDir::new(root)
+        .into_iter()
+        .filter_map(Result::ok)
+        .filter_map(|entry| entry.metadata().ok())
+        .filter(|metadata| metadata.is_file())
+        .map(|metadata| metadata.len())
+        .sum()
+}
@@ -0,0 +1,125 @@
+use std::{
+    collections::hash_map::DefaultHasher,
+    hash::{Hash, Hasher},
+    io::{self, Write},
+    path::Path,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+    thread,
+    time::{Duration, Instant},
+};
+
+use walkdir::WalkDir;
+
+use crate::{assets, shutdown::ShutdownHandle, Exchange};
+
+// How often the tree is walked for changes, and the event streams look for them
+const POLL_INTERVAL: Duration = Duration::from_millis(500);
+// Comments sent on idle event streams, which is how closed tabs are noticed
+const PING_INTERVAL: Duration = Duration::from_secs(15);
+
+// `--watch`: a development mode where pages reload themselves when anything
+// under the root changes. The tree is polled rather than watched through the
+// OS, so it works the same everywhere, at the cost of walking it twice a second.
+pub struct Watcher {
+    generation: AtomicU64,
+}
+
+impl Watcher {
+    pub fn start(root: &Path) -> Arc<Watcher> {
+        let watcher = Arc::new(Watcher { generation: AtomicU64::new(0) });
+        let root = root.to_path_buf();
+        let polled = Arc::clone(&watcher);
+        thread::spawn(move || {
+            let mut last = fingerprint(&root);
+            loop {
+                thread::sleep(POLL_INTERVAL);
+                let current = fingerprint(&root);
+                if current != last {
+                    last = current;
+                    polled.generation.fetch_add(1, Ordering::SeqCst);
+                }
+            }
+        });
+        watcher
+    }
+
+    // Bumped whenever the tree changes
+    pub fn generation(&self) -> u64 {
+        self.generation.load(Ordering::SeqCst)
+    }
+
+    // `GET <asset prefix>events`: a Server-Sent Events stream with a `change`
+    // event when the tree next changes. Each open page holds a handler thread
+    // meanwhile, so --threads bounds how many tabs can be watching.
+    pub fn send_events(&self, stream: &mut Exchange, shutdown: &ShutdownHandle) -> io::Result<()> {
+        // The body never ends, so the connection can't carry another request
+        stream.close_after_response();
+        let head = format!(
+            "HTTP/1.1 200 OK\r\nContent-Type: text/event-stream\r\nCache-Control: no-store\r\nConnection: close\r\n{}\r\n",
+            stream.common_headers()
+        );
+        stream.write_all(head.as_bytes())?;
+        if stream.is_head_request() {
+            return stream.flush();
+        }
+
+        let seen = self.generation.load(Ordering::SeqCst);
+        let result = (|| -> io::Result<()> {
+            stream.write_all(b"retry: 1000\n\n")?;
+            stream.flush()?;
+            let mut pinged = Instant::now();
+            while !shutdown.requested() {
+                thread::sleep(POLL_INTERVAL);
+                let generation = self.generation.load(Ordering::SeqCst);
+                if generation != seen {
+                    stream.write_all(format!("event: change\ndata: {}\n\n", generation).as_bytes())?;
+                    return stream.flush();
+                }
+                if pinged.elapsed() >= PING_INTERVAL {
+                    stream.write_all(b": ping\n\n")?;
+                    stream.flush()?;
+                    pinged = Instant::now();
+                }
+            }
+            Ok(())
+        })();
+        // A closed tab is how these streams normally end
+        match result {
+            Err(e) if matches!(e.kind(), io::ErrorKind::BrokenPipe | io::ErrorKind::ConnectionReset) => Ok(()),
+            result => result,
+        }
+    }
+}
+
+pub fn script_tag(asset_prefix: &str) -> String {
+    format!("<script src=\"{}\"></script>", assets::url(asset_prefix, "reload", "js"))
+}
+
+// Puts the reload script before the page's closing </body> tag, or at the end without one
+pub fn inject(html: &[u8], asset_prefix: &str) -> Vec<u8> {
+    let tag = script_tag(asset_prefix);
+    let lower = html.to_ascii_lowercase();
+    let at = lower.windows(7).rposition(|window| window == b"</body>").unwrap_or(html.len());
+    let mut injected = Vec::with_capacity(html.len() + tag.len());
+    injected.extend_from_slice(&html[..at]);
+    injected.extend_from_slice(tag.as_bytes());
+    injected.extend_from_slice(&html[at..]);
+    injected
+}
+
+// Changes whenever a file or directory under the root is added, removed,
+// resized or touched
+fn fingerprint(root: &Path) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    for entry in WalkDir::new(root).follow_links(true).sort_by_file_name().into_iter().filter_map(Result::ok) {
+        entry.path().hash(&mut hasher);
+        if let Ok(metadata) = entry.metadata() {
+            metadata.len().hash(&mut hasher);
+            metadata.modified().ok().hash(&mut hasher);
+        }
+    }
+    hasher.finish()
+}
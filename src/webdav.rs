@@ -0,0 +1,244 @@
+use std::{
+    fs, io,
+    path::{Path, PathBuf},
+};
+
+use crate::{
+    api, args::Args, cache::FileInfo, hidden::Hidden, decode_url_encoded, encode_path, encode_url_path, escape_html, is_path_within, listing,
+    origin_form, read_body, request_header, send_error, send_response, Exchange, write_response,
+};
+
+// PROPFIND bodies name the properties wanted; every response carries all of
+// ours anyway, so bodies are read only to be discarded
+const MAX_BODY: u64 = 64 * 1024;
+
+// `--webdav`: enough of WebDAV class 1 (RFC 4918) for Finder and Explorer to
+// mount the root, read-only unless `--webdav-write` adds MKCOL, MOVE and
+// DELETE. PUT is left to uploads, which take DELETE and MOVE too. `path` is
+// the request's, still percent-encoded; `writable` says whether this client
+// may change anything.
+#[allow(clippy::too_many_arguments)]
+pub fn handle(
+    stream: &mut Exchange,
+    method: &str,
+    request: &str,
+    received: &[u8],
+    path: &str,
+    root: &Path,
+    writable: bool,
+    args: &Args,
+) -> io::Result<()> {
+    let length = request_header(request, "Content-Length").and_then(|length| length.parse::<u64>().ok()).unwrap_or(0);
+    if length > MAX_BODY {
+        return send_error(stream, "413 Payload Too Large", "Payload Too Large");
+    }
+    if length > 0 {
+        // MKCOL bodies would describe the new collection, which isn't supported (RFC 4918 section 9.3)
+        if method == "MKCOL" {
+            return send_error(stream, "415 Unsupported Media Type", "Unsupported Media Type");
+        }
+        if let Err(e) = read_body(stream, request, received, length) {
+            eprintln!("Error reading {} body: {:?}", method, e);
+            return send_error(stream, "400 Bad Request", "Bad Request");
+        }
+    }
+
+    let target = decode_url_encoded(path);
+    match method {
+        "OPTIONS" => send_options(stream, args),
+        "PROPFIND" => propfind(stream, request, &target, root, &args.hidden),
+        _ if !args.webdav_write => send_error(stream, "405 Method Not Allowed", "Method Not Allowed"),
+        _ if !writable => send_error(stream, "403 Forbidden", "Forbidden"),
+        "MKCOL" => mkcol(stream, &target, root),
+        "DELETE" => delete(stream, &target, root),
+        _ => move_to(stream, request, &target, root, &args.hidden),
+    }
+}
+
+fn send_options(stream: &mut Exchange, args: &Args) -> io::Result<()> {
+    let allow = if args.webdav_write {
+        "OPTIONS, GET, HEAD, PUT, PROPFIND, MKCOL, MOVE, DELETE"
+    } else {
+        "OPTIONS, GET, HEAD, PROPFIND"
+    };
+    // Explorer only treats the server as WebDAV with the MS-Author-Via header
+    let headers = format!("DAV: 1\r\nAllow: {}\r\nMS-Author-Via: DAV\r\n", allow);
+    write_response(stream, &stream.response_head("200 OK", "text/html", 0, &headers), b"")
+}
+
+// Depth 0 describes the resource itself and depth 1 adds a collection's
+// members. Infinite depth could walk the whole tree, so it's refused, as RFC
+// 4918 section 9.1 allows.
+fn propfind(stream: &mut Exchange, request: &str, target: &str, root: &Path, hidden: &Hidden) -> io::Result<()> {
+    let depth = match request_header(request, "Depth").unwrap_or("infinity") {
+        "0" => 0,
+        "1" => 1,
+        _ => return send_error(stream, "403 Forbidden", "Depth infinity is not supported"),
+    };
+
+    let path = root.join(target.trim_start_matches('/'));
+    let metadata = match fs::metadata(&path).ok().filter(|_| !hidden.hides(target)) {
+        Some(metadata) => metadata,
+        None => return send_error(stream, "404 Not Found", "Not Found"),
+    };
+    if !is_path_within(&path, root)? {
+        return send_error(stream, "403 Forbidden", "Forbidden");
+    }
+
+    let mut href = encode_url_path(target);
+    if metadata.is_dir() && !href.ends_with('/') {
+        href.push('/');
+    }
+    let name = path.file_name().map_or(String::new(), |name| name.to_string_lossy().to_string());
+    let mut body = String::from("<?xml version=\"1.0\" encoding=\"utf-8\"?><D:multistatus xmlns:D=\"DAV:\">");
+    body.push_str(&response_element(&href, &name, &metadata));
+
+    if depth == 1 && metadata.is_dir() {
+        for entry in fs::read_dir(&path)? {
+            let entry = entry?;
+            // Followed like everywhere else; broken links are left out
+            let metadata = match fs::metadata(entry.path()) {
+                Ok(metadata) => metadata,
+                Err(_) => continue,
+            };
+            let name = entry.file_name().to_string_lossy().to_string();
+            if hidden.hides_in(target, &name) {
+                continue;
+            }
+            let slash = if metadata.is_dir() { "/" } else { "" };
+            let entry_href = format!("{}{}{}", href, encode_path(&name), slash);
+            body.push_str(&response_element(&entry_href, &name, &metadata));
+        }
+    }
+
+    body.push_str("</D:multistatus>");
+    let head = stream.response_head("207 Multi-Status", "application/xml; charset=utf-8", body.len(), "");
+    write_response(stream, &head, body.as_bytes())
+}
+
+fn response_element(href: &str, name: &str, metadata: &fs::Metadata) -> String {
+    let info = FileInfo {
+        is_dir: metadata.is_dir(),
+        is_file: metadata.is_file(),
+        size: metadata.len(),
+        modified: metadata.modified().ok(),
+    };
+
+    let mut props = format!("<D:displayname>{}</D:displayname>", escape_html(name));
+    if info.is_dir {
+        props.push_str("<D:resourcetype><D:collection/></D:resourcetype>");
+    } else {
+        props.push_str(&format!("<D:resourcetype/><D:getcontentlength>{}</D:getcontentlength>", info.size));
+        if let Some(etag) = info.etag() {
+            props.push_str(&format!("<D:getetag>{}</D:getetag>", escape_html(&etag)));
+        }
+    }
+    if let Some(modified) = info.modified.and_then(listing::unix_seconds) {
+        props.push_str(&format!("<D:getlastmodified>{}</D:getlastmodified>", listing::format_http_date(modified)));
+    }
+
+    format!(
+        "<D:response><D:href>{}</D:href><D:propstat><D:prop>{}</D:prop>\
+         <D:status>HTTP/1.1 200 OK</D:status></D:propstat></D:response>",
+        href, props
+    )
+}
+
+fn mkcol(stream: &mut Exchange, target: &str, root: &Path) -> io::Result<()> {
+    // A missing parent is a conflict rather than something to create (RFC 4918 section 9.3.1)
+    let path = match api::resolve(target, root) {
+        Ok(path) => path,
+        Err(_) if target.trim_matches('/').is_empty() => {
+            return send_error(stream, "405 Method Not Allowed", "Method Not Allowed")
+        }
+        Err(_) => return send_error(stream, "409 Conflict", "Conflict"),
+    };
+    if fs::symlink_metadata(&path).is_ok() {
+        return send_error(stream, "405 Method Not Allowed", "Method Not Allowed");
+    }
+
+    match fs::create_dir(&path) {
+        Ok(()) => send_response(stream, "201 Created", "text/html", ""),
+        Err(e) => failed(stream, "MKCOL", &path, e),
+    }
+}
+
+pub fn delete(stream: &mut Exchange, target: &str, root: &Path) -> io::Result<()> {
+    let path = match resolve_existing(target, root) {
+        Ok(path) => path,
+        Err(status) => return send_error(stream, status, &status[4..]),
+    };
+
+    match remove(&path) {
+        Ok(()) => send_response(stream, "204 No Content", "text/html", ""),
+        Err(e) => failed(stream, "DELETE", &path, e),
+    }
+}
+
+// The Destination header holds an absolute URL, normally on this same server.
+// The caller has checked `target` isn't hidden, but not the destination.
+pub fn move_to(stream: &mut Exchange, request: &str, target: &str, root: &Path, hidden: &Hidden) -> io::Result<()> {
+    let from = match resolve_existing(target, root) {
+        Ok(from) => from,
+        Err(status) => return send_error(stream, status, &status[4..]),
+    };
+    let destination = match request_header(request, "Destination").and_then(origin_form) {
+        Some(destination) => decode_url_encoded(destination.split('?').next().unwrap_or("")),
+        None => return send_error(stream, "400 Bad Request", "Missing Destination"),
+    };
+    if hidden.hides(&destination) {
+        return send_error(stream, "403 Forbidden", "Forbidden");
+    }
+    let to = match api::resolve(&destination, root) {
+        Ok(to) => to,
+        Err(_) => return send_error(stream, "409 Conflict", "Conflict"),
+    };
+    if to == from {
+        return send_error(stream, "403 Forbidden", "Forbidden");
+    }
+
+    // Overwriting is the default; `Overwrite: F` asks to fail instead
+    let exists = fs::symlink_metadata(&to).is_ok();
+    if exists {
+        if request_header(request, "Overwrite").is_some_and(|overwrite| overwrite.eq_ignore_ascii_case("F")) {
+            return send_error(stream, "412 Precondition Failed", "Precondition Failed");
+        }
+        if let Err(e) = remove(&to) {
+            return failed(stream, "MOVE", &to, e);
+        }
+    }
+
+    match fs::rename(&from, &to) {
+        Ok(()) if exists => send_response(stream, "204 No Content", "text/html", ""),
+        Ok(()) => send_response(stream, "201 Created", "text/html", ""),
+        Err(e) => failed(stream, "MOVE", &from, e),
+    }
+}
+
+// Like api::resolve, but the entry must exist and the root itself is off limits.
+// Errors are the status line to answer with.
+fn resolve_existing(target: &str, root: &Path) -> Result<PathBuf, &'static str> {
+    if target.trim_matches('/').is_empty() {
+        return Err("403 Forbidden");
+    }
+    let path = api::resolve(target, root).map_err(|_| "404 Not Found")?;
+    fs::symlink_metadata(&path).map_err(|_| "404 Not Found")?;
+    Ok(path)
+}
+
+// Symlinks are removed themselves, never what they point at
+fn remove(path: &Path) -> io::Result<()> {
+    if fs::symlink_metadata(path)?.is_dir() {
+        fs::remove_dir_all(path)
+    } else {
+        fs::remove_file(path)
+    }
+}
+
+fn failed(stream: &mut Exchange, method: &str, path: &Path, error: io::Error) -> io::Result<()> {
+    eprintln!("Error in {} of {}: {:?}", method, path.display(), error);
+    match error.kind() {
+        io::ErrorKind::PermissionDenied => send_error(stream, "403 Forbidden", "Forbidden"),
+        _ => send_error(stream, "409 Conflict", "Conflict"),
+    }
+}
@@ -0,0 +1,78 @@
+// The subcommands, run as the built executable
+use std::{
+    fs,
+    path::PathBuf,
+    process::{Command, Output},
+};
+
+fn bounty(args: &[&str]) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_Bounty")).args(args).output().unwrap()
+}
+
+fn scratch_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("bounty-cli-{}-{}", name, std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+#[test]
+fn init_writes_a_config_that_checks_out() {
+    let dir = scratch_dir("init");
+    let config = dir.join("bounty.toml");
+    let config = config.to_str().unwrap();
+    let output = bounty(&["init", "--with-auth", "--with-uploads", config]);
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    assert!(String::from_utf8_lossy(&output.stdout).contains("Log in as admin with password"));
+    let text = fs::read_to_string(config).unwrap();
+    assert!(text.contains("\nallow-upload = true\n"));
+    assert!(text.contains("\nauth = [\"admin:"));
+
+    let output = bounty(&["check", config]);
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+
+    // An existing file is left alone
+    assert!(!bounty(&["init", config]).status.success());
+    assert_eq!(fs::read_to_string(config).unwrap(), text);
+}
+
+#[test]
+fn check_points_at_problems() {
+    let dir = scratch_dir("check");
+    let config = dir.join("bounty.toml");
+    fs::write(&config, "root = \".\"\n\n[features]\ncompres = true\n").unwrap();
+    let output = bounty(&["check", config.to_str().unwrap()]);
+    assert!(!output.status.success());
+    let errors = String::from_utf8_lossy(&output.stderr);
+    assert!(errors.contains("bounty.toml:4:1: unknown key `compres`; did you mean `compress`?"), "{}", errors);
+
+    fs::write(&config, "root = \".\"\n\n[limits]\nrate-limit = -1\n").unwrap();
+    let errors = String::from_utf8_lossy(&bounty(&["check", config.to_str().unwrap()]).stderr).into_owned();
+    assert!(errors.contains("bounty.toml:4:1: --rate-limit"), "{}", errors);
+
+    fs::write(&config, "root = \".\"\n\n[tls]\ncert = \"missing.pem\"\nkey = \"missing.pem\"\n").unwrap();
+    let errors = String::from_utf8_lossy(&bounty(&["check", config.to_str().unwrap()]).stderr).into_owned();
+    assert!(errors.contains("TLS setup failed"), "{}", errors);
+}
+
+#[test]
+fn command_line_flags_win_over_the_file() {
+    let dir = scratch_dir("precedence");
+    let config = dir.join("bounty.toml");
+    let text = "root = \".\"\nport = 8001\n\n[features]\ncompress = true\nno-admin = true\n\n[cache]\nstat-cache-ttl = 7\n";
+    fs::write(&config, text).unwrap();
+    let config = config.to_str().unwrap();
+
+    let output = bounty(&["--config", config, "--dry-run"]);
+    let report = String::from_utf8_lossy(&output.stdout);
+    assert!(report.contains("on the fly gzip, deflate"), "{}", report);
+    assert!(report.contains("stats 7s"), "{}", report);
+    assert!(!report.contains("/_bounty/health"), "{}", report);
+
+    let output = bounty(&["--config", config, "--dry-run", "--port", "8002", "--no-compress", "--admin", "--stat-cache-ttl", "3"]);
+    let report = String::from_utf8_lossy(&output.stdout);
+    assert!(report.contains("127.0.0.1:8002"), "{}", report);
+    assert!(report.contains("on the fly off"), "{}", report);
+    assert!(report.contains("stats 3s"), "{}", report);
+    assert!(report.contains("/_bounty/health"), "{}", report);
+}
@@ -0,0 +1,1094 @@
+// Requests over real sockets to a server on an ephemeral port, serving
+// tests/fixtures/root. tests/fixtures/outside.txt sits just outside it, for
+// the traversal checks.
+use std::{
+    fs,
+    io::{Read, Write},
+    net::{SocketAddr, TcpListener, TcpStream},
+    path::{Path, PathBuf},
+    process::Command,
+    sync::Arc,
+    thread,
+    time::Duration,
+};
+
+use bounty::{args::Args, Server};
+
+fn fixture_root() -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/root")
+}
+
+fn start(flags: &[&str]) -> SocketAddr {
+    start_in(&fixture_root(), flags)
+}
+
+// The server runs on its own thread until the test process exits
+fn start_in(root: &Path, flags: &[&str]) -> SocketAddr {
+    let flags = [root.display().to_string()].into_iter().chain(flags.iter().map(|flag| flag.to_string()));
+    let server = Server::new(Args::from_flags(flags).unwrap()).unwrap();
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let address = listener.local_addr().unwrap();
+    thread::spawn(move || server.serve(listener));
+    address
+}
+
+struct Response {
+    status: u16,
+    headers: Vec<(String, String)>,
+    body: Vec<u8>,
+}
+
+impl Response {
+    fn header(&self, name: &str) -> Option<&str> {
+        self.headers.iter().find(|(key, _)| key.eq_ignore_ascii_case(name)).map(|(_, value)| value.as_str())
+    }
+
+    fn text(&self) -> String {
+        String::from_utf8_lossy(&self.body).into_owned()
+    }
+}
+
+// Sends `request` exactly as given, so tests can send what no well-behaved
+// client would, and reads until the server closes the connection
+fn send(address: SocketAddr, request: &str) -> Response {
+    let mut stream = TcpStream::connect(address).unwrap();
+    stream.set_read_timeout(Some(Duration::from_secs(10))).unwrap();
+    stream.write_all(request.as_bytes()).unwrap();
+    let mut raw = Vec::new();
+    stream.read_to_end(&mut raw).unwrap();
+    parse(&raw)
+}
+
+fn get(address: SocketAddr, target: &str) -> Response {
+    request(address, "GET", target, &[])
+}
+
+fn request(address: SocketAddr, method: &str, target: &str, headers: &[(&str, &str)]) -> Response {
+    let headers: String = headers.iter().map(|(name, value)| format!("{}: {}\r\n", name, value)).collect();
+    send(address, &format!("{} {} HTTP/1.1\r\nHost: localhost\r\n{}Connection: close\r\n\r\n", method, target, headers))
+}
+
+fn parse(raw: &[u8]) -> Response {
+    let end = raw.windows(4).position(|window| window == b"\r\n\r\n").expect("no end to the response head");
+    let head = String::from_utf8(raw[..end].to_vec()).unwrap();
+    let mut lines = head.split("\r\n");
+    let status = lines.next().unwrap().split(' ').nth(1).unwrap().parse().unwrap();
+    let headers: Vec<(String, String)> = lines
+        .map(|line| line.split_once(':').unwrap())
+        .map(|(name, value)| (name.to_string(), value.trim().to_string()))
+        .collect();
+    let mut response = Response { status, headers, body: raw[end + 4..].to_vec() };
+    if response.header("Transfer-Encoding") == Some("chunked") {
+        response.body = dechunk(&response.body);
+    }
+    response
+}
+
+fn dechunk(mut chunked: &[u8]) -> Vec<u8> {
+    let mut body = Vec::new();
+    loop {
+        let line_end = chunked.windows(2).position(|window| window == b"\r\n").unwrap();
+        let size = std::str::from_utf8(&chunked[..line_end]).unwrap().split(';').next().unwrap();
+        let size = usize::from_str_radix(size.trim(), 16).unwrap();
+        if size == 0 {
+            return body;
+        }
+        body.extend_from_slice(&chunked[line_end + 2..line_end + 2 + size]);
+        chunked = &chunked[line_end + 2 + size + 2..];
+    }
+}
+
+// The hrefs of a listing's rows, as the page has them
+fn listing_links(page: &str) -> Vec<String> {
+    page.split("<td class=\"name\"><a href=\"")
+        .skip(1)
+        .map(|rest| rest.split('"').next().unwrap().to_string())
+        .collect()
+}
+
+#[test]
+fn serves_a_file_with_its_headers() {
+    let address = start(&[]);
+    let response = get(address, "/hello.txt");
+    assert_eq!(response.status, 200);
+    assert_eq!(response.text(), "hello, world\n");
+    assert_eq!(response.header("Content-Type"), Some("text/plain; charset=utf-8"));
+    assert_eq!(response.header("Content-Length"), Some("13"));
+    assert_eq!(response.header("Accept-Ranges"), Some("bytes"));
+    assert_eq!(response.header("Connection"), Some("close"));
+    assert!(response.header("ETag").is_some());
+    assert!(response.header("Last-Modified").is_some());
+}
+
+#[test]
+fn head_answers_without_a_body() {
+    let address = start(&[]);
+    let response = request(address, "HEAD", "/hello.txt", &[]);
+    assert_eq!(response.status, 200);
+    assert_eq!(response.header("Content-Length"), Some("13"));
+    assert!(response.body.is_empty());
+}
+
+#[test]
+fn a_matching_etag_answers_304() {
+    let address = start(&[]);
+    let etag = get(address, "/hello.txt").header("ETag").unwrap().to_string();
+    let response = request(address, "GET", "/hello.txt", &[("If-None-Match", &etag)]);
+    assert_eq!(response.status, 304);
+    assert!(response.body.is_empty());
+
+    let response = request(address, "GET", "/hello.txt", &[("If-None-Match", "\"something-else\"")]);
+    assert_eq!(response.status, 200);
+}
+
+#[test]
+fn ranges_answer_206_or_416() {
+    let address = start(&[]);
+    let response = request(address, "GET", "/hello.txt", &[("Range", "bytes=0-4")]);
+    assert_eq!(response.status, 206);
+    assert_eq!(response.text(), "hello");
+    assert_eq!(response.header("Content-Range"), Some("bytes 0-4/13"));
+
+    let response = request(address, "GET", "/hello.txt", &[("Range", "bytes=-6")]);
+    assert_eq!(response.status, 206);
+    assert_eq!(response.text(), "world\n");
+
+    let response = request(address, "GET", "/hello.txt", &[("Range", "bytes=100-")]);
+    assert_eq!(response.status, 416);
+    assert_eq!(response.header("Content-Range"), Some("bytes */13"));
+}
+
+#[test]
+fn file_digests_are_left_off_ranges() {
+    let address = start(&["--file-digest", "md5"]);
+    let whole = get(address, "/hello.txt");
+    assert!(whole.header("Content-MD5").is_some());
+
+    let part = request(address, "GET", "/hello.txt", &[("Range", "bytes=0-4")]);
+    assert_eq!(part.status, 206);
+    assert_eq!(part.header("Content-MD5"), None);
+    assert_eq!(part.header("Repr-Digest"), None);
+}
+
+#[test]
+fn missing_files_are_404() {
+    let address = start(&[]);
+    assert_eq!(get(address, "/no-such-file.txt").status, 404);
+    assert_eq!(get(address, "/dir/no-such-file.txt").status, 404);
+}
+
+#[test]
+fn percent_encoded_names_round_trip() {
+    let address = start(&[]);
+    let listing = get(address, "/");
+    assert_eq!(listing.status, 200);
+    let links = listing_links(&listing.text());
+    for name in ["hello.txt", "with space.txt", "日本語.txt", "100%.txt", "dir"] {
+        let encoded: String = name
+            .bytes()
+            .map(|byte| match byte {
+                b'a'..=b'z' | b'A'..=b'Z' | b'0'..=b'9' => (byte as char).to_string(),
+                _ => format!("%{:02X}", byte),
+            })
+            .collect();
+        assert!(links.iter().any(|link| link.trim_end_matches('/') == encoded), "{} not listed as {}", name, encoded);
+    }
+
+    // Every link the listing gives leads back to the file it names
+    for link in links.iter().filter(|link| !link.ends_with('/')) {
+        let response = get(address, &format!("/{}", link));
+        assert_eq!(response.status, 200, "following {}", link);
+        let name = percent_decode(link);
+        assert_eq!(response.body, fs::read(fixture_root().join(&name)).unwrap(), "body of {}", name);
+    }
+
+    // Clients differ in what they escape; these all name the same files
+    assert_eq!(get(address, "/with%20space.txt").text(), "spaced\n");
+    assert_eq!(get(address, "/%68ello.txt").text(), "hello, world\n");
+    assert_eq!(get(address, "/%E6%97%A5%E6%9C%AC%E8%AA%9E.txt").text(), "nihongo\n");
+    assert_eq!(get(address, "/100%25.txt").text(), "percent\n");
+    assert_eq!(get(address, "/dir/nested.txt").text(), "nested\n");
+    assert_eq!(get(address, "/dir%2Fnested.txt").text(), "nested\n");
+}
+
+fn percent_decode(encoded: &str) -> String {
+    let bytes = encoded.as_bytes();
+    let mut decoded = Vec::new();
+    let mut index = 0;
+    while index < bytes.len() {
+        if bytes[index] == b'%' {
+            decoded.push(u8::from_str_radix(&encoded[index + 1..index + 3], 16).unwrap());
+            index += 3;
+        } else {
+            decoded.push(bytes[index]);
+            index += 1;
+        }
+    }
+    String::from_utf8(decoded).unwrap()
+}
+
+#[test]
+fn traversal_out_of_the_root_is_refused() {
+    let address = start(&[]);
+    let attempts = [
+        "/../outside.txt",
+        "/dir/../../outside.txt",
+        "/%2e%2e/outside.txt",
+        "/%2E%2E%2Foutside.txt",
+        "/dir/..%2F..%2Foutside.txt",
+        "/dir/%2e%2e/%2e%2e/outside.txt",
+        "/..%5Coutside.txt",
+        "//../outside.txt",
+    ];
+    for attempt in attempts {
+        let response = get(address, attempt);
+        assert!(matches!(response.status, 400 | 403 | 404), "{} answered {}", attempt, response.status);
+        assert!(!response.text().contains("outside the root"), "{} leaked the file", attempt);
+    }
+}
+
+#[test]
+fn dot_segments_within_the_root_resolve() {
+    let address = start(&[]);
+    let response = get(address, "/dir/../hello.txt");
+    assert_eq!(response.status, 200);
+    assert_eq!(response.text(), "hello, world\n");
+}
+
+#[test]
+fn dotfiles_are_hidden_when_asked() {
+    let address = start(&[]);
+    assert_eq!(get(address, "/.secret").status, 200);
+
+    let address = start(&["--hide-dotfiles"]);
+    assert_eq!(get(address, "/.secret").status, 404);
+    assert_eq!(get(address, "/%2Esecret").status, 404);
+    assert!(!listing_links(&get(address, "/").text()).iter().any(|link| link.contains("secret")));
+}
+
+#[test]
+fn listings_come_as_json_on_request() {
+    let address = start(&[]);
+    let response = get(address, "/dir/?format=json");
+    assert_eq!(response.status, 200);
+    assert_eq!(response.header("Content-Type"), Some("application/json"));
+    let entries: serde_json::Value = serde_json::from_slice(&response.body).unwrap();
+    assert_eq!(entries[0]["name"], "nested.txt");
+    assert_eq!(entries[0]["size"], 7);
+    assert_eq!(entries[0]["is_dir"], false);
+}
+
+#[test]
+fn malformed_requests_are_refused() {
+    let address = start(&[]);
+    assert_eq!(send(address, "GET / HTTP/2.0\r\nHost: localhost\r\n\r\n").status, 505);
+    assert_eq!(send(address, "GET hello.txt HTTP/1.1\r\nHost: localhost\r\n\r\n").status, 400);
+    // Both framings at once is how requests get smuggled past proxies
+    let smuggled = "POST / HTTP/1.1\r\nHost: localhost\r\nContent-Length: 4\r\nTransfer-Encoding: chunked\r\n\r\n0\r\n\r\n";
+    assert_eq!(send(address, smuggled).status, 400);
+}
+
+#[test]
+fn oversized_request_heads_are_refused() {
+    let address = start(&["--max-uri-length", "64", "--max-headers", "4"]);
+    let long = format!("/{}", "a".repeat(100));
+    assert_eq!(get(address, &long).status, 414);
+
+    let headers = [("A", "1"), ("B", "2"), ("C", "3"), ("D", "4")];
+    assert_eq!(request(address, "GET", "/hello.txt", &headers).status, 431);
+    assert_eq!(request(address, "GET", "/hello.txt", &headers[..1]).status, 200);
+}
+
+#[test]
+fn writes_are_refused_without_uploads() {
+    let address = start(&[]);
+    for method in ["PUT", "DELETE", "MOVE", "PATCH"] {
+        assert_eq!(request(address, method, "/hello.txt", &[]).status, 405, "{}", method);
+    }
+    assert_eq!(get(address, "/hello.txt").text(), "hello, world\n");
+}
+
+#[test]
+fn configured_headers_reach_every_response() {
+    let address = start(&["--secure-headers", "--header", "X-Test: yes"]);
+    for target in ["/hello.txt", "/", "/no-such-file.txt"] {
+        let response = get(address, target);
+        assert_eq!(response.header("X-Test"), Some("yes"), "{}", target);
+        assert_eq!(response.header("X-Content-Type-Options"), Some("nosniff"), "{}", target);
+        assert!(response.header("Content-Security-Policy").is_some(), "{}", target);
+    }
+}
+
+#[test]
+fn a_request_head_that_trickles_in_gets_408() {
+    let address = start(&["--header-timeout", "1"]);
+    let mut stream = TcpStream::connect(address).unwrap();
+    stream.set_read_timeout(Some(Duration::from_secs(10))).unwrap();
+    stream.write_all(b"GET /hello.txt HTTP/1.1\r\n").unwrap();
+    thread::sleep(Duration::from_millis(600));
+    stream.write_all(b"Host: localhost\r\n").unwrap();
+    let mut raw = Vec::new();
+    stream.read_to_end(&mut raw).unwrap();
+    assert_eq!(parse(&raw).status, 408);
+}
+
+#[test]
+fn kept_alive_connections_answer_each_request() {
+    let address = start(&[]);
+    let mut stream = TcpStream::connect(address).unwrap();
+    stream.set_read_timeout(Some(Duration::from_secs(10))).unwrap();
+    stream
+        .write_all(
+            b"GET /hello.txt HTTP/1.1\r\nHost: localhost\r\n\r\n\
+              GET /dir/nested.txt HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n",
+        )
+        .unwrap();
+    let mut raw = Vec::new();
+    stream.read_to_end(&mut raw).unwrap();
+
+    let first = parse(&raw);
+    assert_eq!(first.header("Connection"), Some("keep-alive"));
+    let length: usize = first.header("Content-Length").unwrap().parse().unwrap();
+    assert_eq!(&first.body[..length], b"hello, world\n");
+    let second = parse(&first.body[length..]);
+    assert_eq!(second.status, 200);
+    assert_eq!(second.text(), "nested\n");
+}
+
+// An empty directory of its own for a test that writes, removed first in case
+// an earlier run left it behind
+fn scratch_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("bounty-{}-{}", name, std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+// A repository whose served root is its `public` directory, with a secret
+// committed beside it
+fn git_fixture() -> PathBuf {
+    let repo = scratch_dir("git");
+    fs::create_dir_all(repo.join("public")).unwrap();
+    fs::write(repo.join("secret.key"), "top secret\n").unwrap();
+    fs::write(repo.join("public/inner.txt"), "committed\n").unwrap();
+    let git = |args: &[&str]| {
+        let status = Command::new("git")
+            .args(["-c", "user.name=test", "-c", "user.email=test@example.com"])
+            .args(args)
+            .current_dir(&repo)
+            .output()
+            .unwrap()
+            .status;
+        assert!(status.success(), "git {:?}", args);
+    };
+    git(&["init", "-q"]);
+    git(&["add", "."]);
+    git(&["commit", "-q", "-m", "fixture"]);
+    repo
+}
+
+#[test]
+fn git_urls_stay_below_the_root() {
+    let repo = git_fixture();
+    let root = repo.join("public");
+
+    // Nothing is reserved without the flag
+    let address = start_in(&root, &[]);
+    assert_eq!(get(address, "/_git/HEAD/inner.txt").status, 404);
+
+    let address = start_in(&root, &["--git-urls"]);
+    assert_eq!(get(address, "/_git/HEAD/inner.txt").text(), "committed\n");
+    let listing = get(address, "/_git/HEAD/").text();
+    assert_eq!(listing_links(&listing), ["inner%2Etxt"]);
+
+    let tree = Command::new("git").args(["rev-parse", "HEAD^{tree}"]).current_dir(&repo).output().unwrap().stdout;
+    let tree = String::from_utf8(tree).unwrap();
+    let attempts = [
+        "/_git/HEAD/secret.key".to_string(),
+        "/_git/HEAD/../secret.key".to_string(),
+        "/_git/HEAD/%2E%2E/secret.key".to_string(),
+        "/_git/HEAD:secret.key/".to_string(),
+        "/_git/HEAD%3A..%2Fsecret.key/".to_string(),
+        format!("/_git/{}/secret.key", tree.trim()),
+    ];
+    for attempt in &attempts {
+        let response = get(address, attempt);
+        assert!(matches!(response.status, 403 | 404), "{} answered {}", attempt, response.status);
+        assert!(!response.text().contains("top secret"), "{} leaked the file", attempt);
+    }
+    let _ = fs::remove_dir_all(&repo);
+}
+
+#[test]
+fn uploaded_names_are_escaped_in_listings() {
+    let root = scratch_dir("escaping");
+    fs::create_dir(root.join("<i>dir")).unwrap();
+    let address = start_in(&root, &["--allow-upload"]);
+
+    let name = "/%3Cimg%20src%3Dx%20onerror%3Dalert(1)%3E.txt";
+    let upload = send(address, &format!("PUT {} HTTP/1.1\r\nHost: localhost\r\nContent-Length: 2\r\n\r\nhi", name));
+    assert_eq!(upload.status, 201);
+
+    let listing = get(address, "/").text();
+    assert!(!listing.contains("<img"), "{}", listing);
+    assert!(listing.contains("&lt;img src=x onerror=alert(1)&gt;.txt"));
+    let listing = get(address, "/%3Ci%3Edir/").text();
+    assert!(!listing.contains("<i>"), "{}", listing);
+    assert!(listing.contains("&lt;i&gt;dir"));
+    let _ = fs::remove_dir_all(&root);
+}
+
+// Hidden paths are as out of reach to writes as to reads, whichever handler
+// would do the writing
+#[test]
+fn hidden_paths_cant_be_written() {
+    let root = scratch_dir("hidden-writes");
+    fs::write(root.join(".bountyignore"), "secret*\n").unwrap();
+    fs::write(root.join("visible.txt"), "visible\n").unwrap();
+    let hiding = ["--hide-dotfiles", "--bountyignore"];
+    let put = |address, target: &str| {
+        send(address, &format!("PUT {} HTTP/1.1\r\nHost: localhost\r\nContent-Length: 2\r\n\r\nhi", target)).status
+    };
+
+    let address = start_in(&root, &[&hiding[..], &["--allow-upload"]].concat());
+    assert_eq!(put(address, "/.hidden"), 404);
+    assert_eq!(put(address, "/secret.txt"), 404);
+    assert_eq!(put(address, "/.bountyignore"), 404);
+    assert_eq!(request(address, "DELETE", "/.bountyignore", &[]).status, 404);
+    let destination = [("Destination", "http://localhost/.hidden")];
+    assert_eq!(request(address, "MOVE", "/visible.txt", &destination).status, 403);
+    let destination = [("Destination", "http://localhost/secret.txt")];
+    assert_eq!(request(address, "MOVE", "/visible.txt", &destination).status, 403);
+
+    let body = "--b\r\nContent-Disposition: form-data; name=\"file\"; filename=\".hidden\"\r\n\r\nhi\r\n--b--\r\n";
+    let post = format!(
+        "POST / HTTP/1.1\r\nHost: localhost\r\nContent-Type: multipart/form-data; boundary=b\r\nContent-Length: {}\r\n\r\n{}",
+        body.len(),
+        body
+    );
+    assert_eq!(send(address, &post).status, 403);
+
+    let address = start_in(&root, &[&hiding[..], &["--webdav-write"]].concat());
+    assert_eq!(request(address, "MKCOL", "/.hidden", &[]).status, 404);
+    assert_eq!(request(address, "DELETE", "/.bountyignore", &[]).status, 404);
+    assert_eq!(put(address, "/.bountyignore"), 404);
+    let destination = [("Destination", "http://localhost/.bountyignore")];
+    assert_eq!(request(address, "MOVE", "/visible.txt", &destination).status, 403);
+
+    let address = start_in(&root, &[&hiding[..], &["--batch-token", "token"]].concat());
+    let body = r#"[{"op":"delete","path":".bountyignore"},{"op":"copy","from":"visible.txt","to":"secret.txt"}]"#;
+    let batch = format!(
+        "POST /_api/batch HTTP/1.1\r\nHost: localhost\r\nAuthorization: Bearer token\r\nContent-Length: {}\r\n\r\n{}",
+        body.len(),
+        body
+    );
+    let results: serde_json::Value = serde_json::from_slice(&send(address, &batch).body).unwrap();
+    assert_eq!(results[0]["ok"], false);
+    assert_eq!(results[1]["ok"], false);
+
+    let mut left: Vec<_> = fs::read_dir(&root).unwrap().map(|entry| entry.unwrap().file_name()).collect();
+    left.sort();
+    assert_eq!(left, [".bountyignore", "visible.txt"]);
+    assert_eq!(fs::read_to_string(root.join(".bountyignore")).unwrap(), "secret*\n");
+    let _ = fs::remove_dir_all(&root);
+}
+
+#[cfg(unix)]
+#[test]
+fn appends_stay_inside_the_root() {
+    let root = scratch_dir("append-root");
+    let outside = scratch_dir("append-outside");
+    std::os::unix::fs::symlink(outside.join("created.log"), root.join("dangling.log")).unwrap();
+    let address = start_in(&root, &["--append-token", "token"]);
+    let append = |target: &str, length: usize| {
+        let head = format!(
+            "POST {} HTTP/1.1\r\nHost: localhost\r\nAuthorization: Bearer token\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+            target, length
+        );
+        send(address, &(head + &"x".repeat(length.min(16)))).status
+    };
+
+    assert_eq!(append("/_append/dangling.log", 2), 403);
+    assert!(!outside.join("created.log").exists());
+    // Refused on its length, before any of it is read
+    assert_eq!(append("/_append/big.log", 64 * 1024 * 1024), 413);
+    assert_eq!(append("/_append/fine.log", 2), 201);
+    assert_eq!(fs::read_to_string(root.join("fine.log")).unwrap(), "xx");
+    let _ = fs::remove_dir_all(&root);
+    let _ = fs::remove_dir_all(&outside);
+}
+
+#[test]
+fn dot_segments_dont_get_around_anchored_patterns() {
+    let root = scratch_dir("ignore-dots");
+    fs::create_dir_all(root.join("secret")).unwrap();
+    fs::create_dir_all(root.join("pub")).unwrap();
+    fs::write(root.join("secret/config"), "hidden\n").unwrap();
+    fs::write(root.join(".bountyignore"), "secret/config\n").unwrap();
+    let address = start_in(&root, &["--bountyignore"]);
+    for target in ["/secret/config", "/pub/../secret/config", "/secret/./config", "/pub/%2e%2e/secret/config"] {
+        assert_eq!(get(address, target).status, 404, "{}", target);
+    }
+    let _ = fs::remove_dir_all(&root);
+}
+
+#[test]
+fn the_stat_api_only_takes_its_urls_when_enabled() {
+    let root = scratch_dir("stat-api");
+    fs::create_dir_all(root.join("_api/stat")).unwrap();
+    fs::write(root.join("_api/stat/notes.txt"), "mine\n").unwrap();
+
+    assert_eq!(get(start_in(&root, &[]), "/_api/stat/notes.txt").text(), "mine\n");
+
+    let response = get(start_in(&root, &["--stat-api"]), "/_api/stat/_api/stat/notes.txt");
+    assert_eq!(response.status, 200);
+    assert_eq!(response.header("Content-Type"), Some("application/json"));
+    assert!(response.text().contains("\"size\":5"), "{}", response.text());
+}
+
+#[test]
+fn servers_keep_their_own_error_pages() {
+    let pages = scratch_dir("error-pages");
+    fs::write(pages.join("first.html"), "first's 404").unwrap();
+    fs::write(pages.join("second.html"), "second's 404").unwrap();
+    let first = start(&["--error-page", &format!("404={}", pages.join("first.html").display())]);
+    let second = start(&["--error-page", &format!("404={}", pages.join("second.html").display())]);
+
+    assert_eq!(get(first, "/missing").text(), "first's 404");
+    assert_eq!(get(second, "/missing").text(), "second's 404");
+}
+
+#[test]
+fn a_shutdown_gives_up_on_connections_past_the_deadline() {
+    let flags = [fixture_root().display().to_string(), "--shutdown-timeout".to_string(), "1".to_string()];
+    let server = Server::new(Args::from_flags(flags).unwrap()).unwrap();
+    let handle = server.shutdown_handle();
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let address = listener.local_addr().unwrap();
+    let serving = thread::spawn(move || server.serve(listener));
+    let bystander = start(&[]);
+
+    // Connected but silent, so it's still open when the deadline comes
+    let mut idle = TcpStream::connect(address).unwrap();
+    idle.set_read_timeout(Some(Duration::from_secs(10))).unwrap();
+    thread::sleep(Duration::from_millis(200));
+    handle.request();
+
+    let error = serving.join().unwrap().unwrap_err();
+    assert_eq!(error.kind(), std::io::ErrorKind::TimedOut);
+    assert_eq!(idle.read(&mut [0; 16]).unwrap(), 0);
+    // Only the one server was asked to stop
+    assert_eq!(get(bystander, "/hello.txt").status, 200);
+}
+
+// Several of the ring's buffers' worth, and not a whole number of them
+#[cfg(feature = "io-uring")]
+#[test]
+fn io_uring_sends_whole_files_and_ranges() {
+    let root = scratch_dir("io-uring");
+    let contents: Vec<u8> = (0..1_000_003u32).map(|i| (i * 7 % 251) as u8).collect();
+    fs::write(root.join("big.bin"), &contents).unwrap();
+    let address = start_in(&root, &["--io-uring"]);
+
+    let response = get(address, "/big.bin");
+    assert_eq!(response.status, 200);
+    assert!(response.body == contents);
+
+    let response = request(address, "GET", "/big.bin", &[("Range", "bytes=131000-400000")]);
+    assert_eq!(response.status, 206);
+    assert!(response.body == contents[131000..=400000]);
+}
+
+// An upstream answering each connection with `response`, whatever it's asked
+fn fake_upstream(response: &'static str) -> SocketAddr {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let address = listener.local_addr().unwrap();
+    thread::spawn(move || {
+        for mut stream in listener.incoming().map_while(Result::ok) {
+            let mut head = Vec::new();
+            let mut byte = [0; 1];
+            while !head.ends_with(b"\r\n\r\n") && stream.read(&mut byte).unwrap_or(0) == 1 {
+                head.push(byte[0]);
+            }
+            let _ = stream.write_all(response.as_bytes());
+        }
+    });
+    address
+}
+
+#[test]
+fn proxied_bodies_are_framed_for_the_client() {
+    let upstream = fake_upstream("HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n\r\n5\r\nhello\r\n6\r\n world\r\n0\r\n\r\n");
+    let address = start(&["--proxy", &format!("/api=http://{}", upstream)]);
+    let response = send(address, "GET /api/greeting HTTP/1.0\r\n\r\n");
+    assert_eq!(response.status, 200);
+    assert_eq!(response.header("Transfer-Encoding"), None);
+    assert_eq!(response.header("Connection"), Some("close"));
+    assert_eq!(response.body, b"hello world");
+
+    // Ended by the upstream closing, which a keep-alive connection can't rely on
+    let upstream = fake_upstream("HTTP/1.1 200 OK\r\nContent-Type: text/plain\r\n\r\nuntil the end");
+    let address = start(&["--proxy", &format!("/api=http://{}", upstream)]);
+    let mut stream = TcpStream::connect(address).unwrap();
+    stream.set_read_timeout(Some(Duration::from_secs(10))).unwrap();
+    stream.write_all(b"GET /api/stream HTTP/1.1\r\nHost: localhost\r\n\r\n").unwrap();
+    let mut raw = Vec::new();
+    let mut buffer = [0; 4096];
+    while !raw.ends_with(b"0\r\n\r\n") {
+        let read = stream.read(&mut buffer).unwrap();
+        assert!(read > 0, "closed before the last chunk");
+        raw.extend_from_slice(&buffer[..read]);
+    }
+    let response = parse(&raw);
+    assert_eq!(response.header("Transfer-Encoding"), Some("chunked"));
+    assert_eq!(response.header("Connection"), Some("keep-alive"));
+    assert_eq!(response.body, b"until the end");
+}
+
+// A directory with alice and bob in it, alice also in cn=writers. Like most
+// directories it takes an empty password as an anonymous bind.
+fn fake_directory() -> SocketAddr {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let address = listener.local_addr().unwrap();
+    thread::spawn(move || {
+        for mut stream in listener.incoming().flatten() {
+            let mut dn = String::new();
+            loop {
+                // Every message in these tests is short enough for one length byte
+                let mut head = [0; 2];
+                if stream.read_exact(&mut head).is_err() {
+                    break;
+                }
+                let mut message = vec![0; head[1] as usize];
+                stream.read_exact(&mut message).unwrap();
+                let (id, op) = (message[2], &message[3..]);
+                let reply = |tag: u8, code: u8| der(0x30, &[&der(0x02, &[&[id]]), &der(tag, &[&[0x0a, 1, code, 0x04, 0, 0x04, 0]])]);
+                match op[0] {
+                    0x60 => {
+                        let name_length = op[6] as usize;
+                        dn = String::from_utf8(op[7..7 + name_length].to_vec()).unwrap();
+                        let password = &op[7 + name_length + 2..];
+                        let known = matches!(
+                            (dn.as_str(), password),
+                            ("uid=alice,ou=people,dc=example", b"wonderland") | ("uid=bob,ou=people,dc=example", b"builder") | (_, b"")
+                        );
+                        stream.write_all(&reply(0x61, if known { 0 } else { 49 })).unwrap();
+                    }
+                    0x63 => {
+                        let searched = String::from_utf8_lossy(op);
+                        if searched.contains("cn=writers,dc=example") && dn.starts_with("uid=alice,") && searched.contains(&dn) {
+                            let entry = der(0x64, &[&der(0x04, &[b"cn=writers,dc=example"]), &der(0x30, &[])]);
+                            stream.write_all(&der(0x30, &[&der(0x02, &[&[id]]), &entry])).unwrap();
+                        }
+                        stream.write_all(&reply(0x65, 0)).unwrap();
+                    }
+                    _ => break,
+                }
+            }
+        }
+    });
+    address
+}
+
+#[test]
+fn ldap_accounts_log_in_by_binding() {
+    let directory = fake_directory();
+    let address = start(&[
+        "--ldap", &format!("ldap://{}", directory),
+        "--ldap-dn", "uid=*,ou=people,dc=example",
+        "--ldap-write-group", "cn=writers,dc=example",
+    ]);
+    let login = |name: &str, password: &str| {
+        let credentials = base64::Engine::encode(&base64::engine::general_purpose::STANDARD, format!("{}:{}", name, password));
+        request(address, "GET", "/_api/usage", &[("Authorization", &format!("Basic {}", credentials))])
+    };
+
+    let alice = login("alice", "wonderland");
+    assert_eq!(alice.status, 200);
+    assert!(alice.text().contains("\"writable\":true"), "{}", alice.text());
+    let bob = login("bob", "builder");
+    assert_eq!(bob.status, 200);
+    assert!(bob.text().contains("\"writable\":false"), "{}", bob.text());
+
+    assert_eq!(login("alice", "builder").status, 401);
+    assert_eq!(login("alice", "").status, 401);
+    // Would otherwise put a comma of its own into the DN
+    assert_eq!(login("bob,ou=people,dc=example", "builder").status, 401);
+}
+
+#[test]
+fn status_endpoints_need_a_login_like_everything_else() {
+    let address = start(&["--auth", "admin:secret", "--metrics"]);
+    assert_eq!(get(address, "/_bounty/listing.css").status, 200);
+    for endpoint in ["/_bounty/stats", "/_bounty/health", "/_bounty/metrics"] {
+        assert_eq!(get(address, endpoint).status, 401, "{}", endpoint);
+        let response = request(address, "GET", endpoint, &[("Authorization", "Basic YWRtaW46c2VjcmV0")]);
+        assert_eq!(response.status, 200, "{}", endpoint);
+    }
+}
+
+#[test]
+fn supervised_children_report_the_whole_servers_numbers() {
+    let state = scratch_dir("worker-state");
+    fs::write(state.join("worker-2.json"), r#"{"requests":5,"connections":4,"open":1,"processes":1,"statuses":{"200":5},"body_bytes":50,"durations":[5],"duration_micros":10}"#).unwrap();
+    // worker-3 has exited and been folded in, but its file is still on its way out
+    fs::write(state.join("worker-3.json"), r#"{"requests":100,"connections":100,"open":0,"processes":1,"statuses":{},"body_bytes":0,"durations":[],"duration_micros":0}"#).unwrap();
+    fs::write(state.join("retired.json"), r#"{"snapshot":{"requests":7,"connections":7,"open":0,"processes":0,"statuses":{"404":7},"body_bytes":0,"durations":[7],"duration_micros":20},"folded":["worker-3.json"]}"#).unwrap();
+    let own = state.join("worker-1.json").display().to_string();
+    let address = start(&["--metrics", "--worker-state", &own]);
+
+    assert_eq!(get(address, "/hello.txt").status, 200);
+    let metrics = get(address, "/_bounty/metrics").text();
+    // The metrics request itself is counted, the response not yet
+    assert!(metrics.contains("\nbounty_requests_total 14\n"), "{}", metrics);
+    assert!(metrics.contains("\nbounty_processes 2\n"), "{}", metrics);
+    assert!(metrics.contains("bounty_responses_total{status=\"200\"} 6\n"), "{}", metrics);
+    assert!(metrics.contains("bounty_responses_total{status=\"404\"} 7\n"), "{}", metrics);
+
+    // And this one publishes its own for the others
+    thread::sleep(Duration::from_millis(1500));
+    let published = fs::read_to_string(&own).unwrap();
+    assert!(published.contains(r#""statuses":{"200":2}"#), "{}", published);
+}
+
+#[test]
+fn head_on_pastes_answers_like_get() {
+    let root = scratch_dir("paste");
+    let address = start_in(&root, &["--paste-dir", "pastes"]);
+    let form = get(address, "/_paste");
+    let response = request(address, "HEAD", "/_paste", &[]);
+    assert_eq!(response.status, 200);
+    assert_eq!(response.header("Content-Length"), form.header("Content-Length"));
+    assert!(response.body.is_empty());
+
+    let created = send(
+        address,
+        "POST /_paste HTTP/1.1\r\nHost: localhost\r\nContent-Length: 5\r\nConnection: close\r\n\r\nhello",
+    );
+    assert_eq!(created.status, 201);
+    let location = created.header("Location").unwrap();
+    let response = request(address, "HEAD", location, &[]);
+    assert_eq!(response.status, 200);
+    assert_eq!(response.header("Content-Length"), Some("5"));
+    assert!(response.body.is_empty());
+}
+
+// The trailer a chunked response ended with, and its body
+fn trailed(address: SocketAddr, target: &str, headers: &str) -> (String, Vec<u8>) {
+    let mut stream = TcpStream::connect(address).unwrap();
+    stream.set_read_timeout(Some(Duration::from_secs(10))).unwrap();
+    write!(stream, "GET {} HTTP/1.1\r\nHost: localhost\r\n{}Connection: close\r\n\r\n", target, headers).unwrap();
+    let mut raw = Vec::new();
+    stream.read_to_end(&mut raw).unwrap();
+    let response = parse(&raw);
+    assert_eq!(response.header("Trailer"), Some("Repr-Digest"));
+    let end = raw.windows(5).rposition(|window| window == b"\r\n0\r\n").expect("no last chunk");
+    let trailer = String::from_utf8(raw[end + 5..].to_vec()).unwrap();
+    (trailer.trim_end().to_string(), response.body)
+}
+
+#[test]
+fn downloads_stream_with_digest_trailers() {
+    use base64::{engine::general_purpose::STANDARD, Engine};
+    use sha2::{Digest, Sha256};
+
+    let root = scratch_dir("trailers");
+    fs::write(root.join("big.txt"), "compress me\n".repeat(200)).unwrap();
+    let address = start_in(&root, &["--digest-trailers", "--compress"]);
+    for (target, headers) in [("/?download=tar", ""), ("/?download=zip", ""), ("/big.txt", "Accept-Encoding: gzip\r\n")] {
+        let (trailer, body) = trailed(address, target, headers);
+        assert_eq!(trailer, format!("Repr-Digest: sha-256=:{}:", STANDARD.encode(Sha256::digest(&body))), "{}", target);
+    }
+}
+
+#[test]
+fn watched_changes_show_up_past_the_caches() {
+    let root = scratch_dir("watch-caches");
+    fs::write(root.join("notes.txt"), "a").unwrap();
+    let ttls = ["--not-found-ttl", "600", "--stat-cache-ttl", "600", "--listing-cache-ttl", "600"];
+    let address = start_in(&root, &[&["--watch", "--threads", "1"][..], &ttls].concat());
+    assert_eq!(get(address, "/new.txt").status, 404);
+    assert_eq!(get(address, "/notes.txt").header("Content-Length"), Some("1"));
+
+    fs::write(root.join("new.txt"), "new").unwrap();
+    fs::write(root.join("notes.txt"), "abc").unwrap();
+    // The watcher polls twice a second
+    thread::sleep(Duration::from_millis(1500));
+    assert_eq!(get(address, "/new.txt").text(), "new");
+    assert_eq!(get(address, "/notes.txt").text(), "abc");
+}
+
+fn tls_fixture(name: &str) -> String {
+    Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/tls").join(name).display().to_string()
+}
+
+// A client trusting the fixture CA, limited to `versions`, offering `alpn`
+// and presenting the certificate of `identity` if there is one
+fn tls_client(
+    versions: &[&'static rustls::SupportedProtocolVersion],
+    alpn: &[&str],
+    identity: Option<&str>,
+) -> Arc<rustls::ClientConfig> {
+    use rustls::pki_types::{pem::PemObject, CertificateDer, PrivateKeyDer};
+    let mut roots = rustls::RootCertStore::empty();
+    roots.add(CertificateDer::from_pem_file(tls_fixture("ca.pem")).unwrap()).unwrap();
+    let builder = rustls::ClientConfig::builder_with_provider(Arc::new(rustls::crypto::ring::default_provider()))
+        .with_protocol_versions(versions)
+        .unwrap()
+        .with_root_certificates(roots);
+    let mut config = match identity {
+        Some(name) => {
+            let chain = CertificateDer::pem_file_iter(tls_fixture(&format!("{}.pem", name))).unwrap();
+            let key = PrivateKeyDer::from_pem_file(tls_fixture(&format!("{}.key", name))).unwrap();
+            builder.with_client_auth_cert(chain.map(Result::unwrap).collect(), key).unwrap()
+        }
+        None => builder.with_no_client_auth(),
+    };
+    config.alpn_protocols = alpn.iter().map(|protocol| protocol.as_bytes().to_vec()).collect();
+    Arc::new(config)
+}
+
+// GET `target` over TLS, with the connection kept for what was negotiated
+fn tls_get(
+    address: SocketAddr,
+    config: Arc<rustls::ClientConfig>,
+    target: &str,
+) -> std::io::Result<(Response, rustls::ClientConnection)> {
+    let connection = rustls::ClientConnection::new(config, "localhost".try_into().unwrap()).unwrap();
+    let socket = TcpStream::connect(address)?;
+    socket.set_read_timeout(Some(Duration::from_secs(10)))?;
+    let mut stream = rustls::StreamOwned::new(connection, socket);
+    write!(stream, "GET {} HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n", target)?;
+    let mut raw = Vec::new();
+    stream.read_to_end(&mut raw)?;
+    Ok((parse(&raw), stream.conn))
+}
+
+#[test]
+fn tls_settings_shape_the_handshake() {
+    let (cert, key) = (tls_fixture("server.pem"), tls_fixture("server.key"));
+    let address = start(&["--tls-cert", &cert, "--tls-key", &key, "--tls-min-version", "1.3"]);
+
+    let (response, connection) =
+        tls_get(address, tls_client(&[&rustls::version::TLS13], &["h2", "http/1.1"], None), "/hello.txt").unwrap();
+    assert_eq!(response.text(), "hello, world\n");
+    assert_eq!(connection.protocol_version(), Some(rustls::ProtocolVersion::TLSv1_3));
+    assert_eq!(connection.alpn_protocol(), Some(&b"http/1.1"[..]));
+
+    assert!(tls_get(address, tls_client(&[&rustls::version::TLS12], &[], None), "/hello.txt").is_err());
+}
+
+#[test]
+fn client_certificates_log_in_as_their_account() {
+    use sha2::{Digest, Sha256};
+    let dir = scratch_dir("client-certs");
+    let home = dir.join("alice");
+    fs::create_dir(&home).unwrap();
+    fs::write(home.join("mine.txt"), "alice's\n").unwrap();
+    let digest: String = Sha256::digest("saltsecret").iter().map(|byte| format!("{:02x}", byte)).collect();
+    fs::write(dir.join("users"), format!("alice sha-256:salt:{} {} ro\n", digest, home.display())).unwrap();
+    let log = dir.join("access.log");
+
+    let (cert, key, ca) = (tls_fixture("server.pem"), tls_fixture("server.key"), tls_fixture("ca.pem"));
+    let users = dir.join("users").display().to_string();
+    let address = start(&[
+        "--tls-cert", &cert, "--tls-key", &key, "--tls-client-ca", &ca,
+        "--users", &users, "--access-log", &log.display().to_string(),
+    ]);
+
+    let versions = &[&rustls::version::TLS13, &rustls::version::TLS12];
+    let (response, _) = tls_get(address, tls_client(versions, &[], Some("alice")), "/mine.txt").unwrap();
+    assert_eq!(response.status, 200);
+    assert_eq!(response.text(), "alice's\n");
+    assert!(fs::read_to_string(&log).unwrap().contains(" - alice ["));
+
+    assert!(tls_get(address, tls_client(versions, &[], None), "/mine.txt").is_err());
+}
+
+fn der(tag: u8, parts: &[&[u8]]) -> Vec<u8> {
+    let contents = parts.concat();
+    let mut encoded = vec![tag];
+    match contents.len() {
+        length @ 0..=0x7f => encoded.push(length as u8),
+        length => encoded.extend([0x82, (length >> 8) as u8, length as u8]),
+    }
+    encoded.extend(contents);
+    encoded
+}
+
+// A good response for the server fixture's serial, valid until 2099. It goes
+// unsigned: the server only checks it's the right certificate's and current,
+// and rustls clients leave it to the application.
+fn ocsp_response() -> Vec<u8> {
+    let time = |value: &str| der(0x18, &[value.as_bytes()]);
+    let sha1 = der(0x30, &[&der(0x06, &[&[0x2b, 0x0e, 0x03, 0x02, 0x1a]]), &[0x05, 0x00]]);
+    let cert_id = der(0x30, &[&sha1, &der(0x04, &[&[0; 20]]), &der(0x04, &[&[0; 20]]), &der(0x02, &[&[0x03, 0xe9]])]);
+    let single = der(0x30, &[&cert_id, &[0x80, 0x00], &time("20260101000000Z"), &der(0xa0, &[&time("20991231000000Z")])]);
+    let data = der(0x30, &[&der(0xa2, &[&der(0x04, &[&[0; 20]])]), &time("20260101000000Z"), &der(0x30, &[&single])]);
+    let basic = der(0x30, &[&data, &der(0x30, &[&der(0x06, &[&[0x2b, 0x65, 0x70]])]), &der(0x03, &[&[0x00]])]);
+    let kind = der(0x06, &[&[0x2b, 0x06, 0x01, 0x05, 0x05, 0x07, 0x30, 0x01, 0x01]]);
+    der(0x30, &[&der(0x0a, &[&[0x00]]), &der(0xa0, &[&der(0x30, &[&kind, &der(0x04, &[&basic])])])])
+}
+
+// Answers every POST with `response`, once it has the whole request
+fn fake_ocsp_responder(response: Vec<u8>) -> SocketAddr {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let address = listener.local_addr().unwrap();
+    thread::spawn(move || {
+        for mut stream in listener.incoming().map(Result::unwrap) {
+            let mut raw = Vec::new();
+            let mut buffer = [0; 4096];
+            loop {
+                let read = stream.read(&mut buffer).unwrap();
+                raw.extend_from_slice(&buffer[..read]);
+                let text = String::from_utf8_lossy(&raw).into_owned();
+                let Some((head, _)) = text.split_once("\r\n\r\n") else { continue };
+                let length = head.lines().find_map(|line| line.strip_prefix("Content-Length: ")).unwrap();
+                if raw.len() - head.len() - 4 >= length.parse().unwrap() {
+                    break;
+                }
+            }
+            assert!(raw.starts_with(b"POST / HTTP/1.0\r\n"));
+            // The CertID's serial
+            assert!(raw.windows(4).any(|window| window == [0x02, 0x02, 0x03, 0xe9]));
+            let head = format!("HTTP/1.0 200 OK\r\nContent-Type: application/ocsp-response\r\nContent-Length: {}\r\n\r\n", response.len());
+            stream.write_all(&[head.as_bytes(), &response].concat()).unwrap();
+        }
+    });
+    address
+}
+
+// Keeps whatever the server staples, checking the certificate as usual
+#[derive(Debug)]
+struct StapleKeeper {
+    verifier: Arc<rustls::client::WebPkiServerVerifier>,
+    stapled: std::sync::Mutex<Vec<u8>>,
+}
+
+impl rustls::client::danger::ServerCertVerifier for StapleKeeper {
+    fn verify_server_cert(
+        &self,
+        end_entity: &rustls::pki_types::CertificateDer,
+        intermediates: &[rustls::pki_types::CertificateDer],
+        server_name: &rustls::pki_types::ServerName,
+        ocsp_response: &[u8],
+        now: rustls::pki_types::UnixTime,
+    ) -> Result<rustls::client::danger::ServerCertVerified, rustls::Error> {
+        *self.stapled.lock().unwrap() = ocsp_response.to_vec();
+        self.verifier.verify_server_cert(end_entity, intermediates, server_name, ocsp_response, now)
+    }
+
+    fn verify_tls12_signature(
+        &self,
+        message: &[u8],
+        cert: &rustls::pki_types::CertificateDer,
+        dss: &rustls::DigitallySignedStruct,
+    ) -> Result<rustls::client::danger::HandshakeSignatureValid, rustls::Error> {
+        self.verifier.verify_tls12_signature(message, cert, dss)
+    }
+
+    fn verify_tls13_signature(
+        &self,
+        message: &[u8],
+        cert: &rustls::pki_types::CertificateDer,
+        dss: &rustls::DigitallySignedStruct,
+    ) -> Result<rustls::client::danger::HandshakeSignatureValid, rustls::Error> {
+        self.verifier.verify_tls13_signature(message, cert, dss)
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<rustls::SignatureScheme> {
+        self.verifier.supported_verify_schemes()
+    }
+}
+
+#[test]
+fn ocsp_responses_get_stapled() {
+    use rustls::pki_types::{pem::PemObject, CertificateDer};
+    let response = ocsp_response();
+    let responder = format!("http://{}/", fake_ocsp_responder(response.clone()));
+    let (cert, key) = (tls_fixture("server.pem"), tls_fixture("server.key"));
+    let address = start(&["--tls-cert", &cert, "--tls-key", &key, "--tls-ocsp-responder", &responder]);
+
+    let provider = Arc::new(rustls::crypto::ring::default_provider());
+    let mut roots = rustls::RootCertStore::empty();
+    roots.add(CertificateDer::from_pem_file(tls_fixture("ca.pem")).unwrap()).unwrap();
+    let verifier = rustls::client::WebPkiServerVerifier::builder_with_provider(Arc::new(roots), Arc::clone(&provider))
+        .build()
+        .unwrap();
+    let keeper = Arc::new(StapleKeeper { verifier, stapled: Default::default() });
+    let config = rustls::ClientConfig::builder_with_provider(provider)
+        .with_safe_default_protocol_versions()
+        .unwrap()
+        .dangerous()
+        .with_custom_certificate_verifier(Arc::clone(&keeper) as Arc<_>)
+        .with_no_client_auth();
+    let config = Arc::new(config);
+
+    // The first fetch happens in the background, so early handshakes go without
+    for _ in 0..50 {
+        let (reply, _) = tls_get(address, Arc::clone(&config), "/hello.txt").unwrap();
+        assert_eq!(reply.status, 200);
+        if !keeper.stapled.lock().unwrap().is_empty() {
+            break;
+        }
+        thread::sleep(Duration::from_millis(100));
+    }
+    assert_eq!(*keeper.stapled.lock().unwrap(), response);
+}
+
+fn soap(address: SocketAddr, control: &str, service: &str, action: &str, arguments: &str) -> Response {
+    let body = format!(
+        "<?xml version=\"1.0\"?><s:Envelope xmlns:s=\"http://schemas.xmlsoap.org/soap/envelope/\"><s:Body>\
+         <u:{0} xmlns:u=\"{1}\">{2}</u:{0}></s:Body></s:Envelope>",
+        action, service, arguments
+    );
+    send(
+        address,
+        &format!(
+            "POST {} HTTP/1.1\r\nHost: localhost\r\nSOAPAction: \"{}#{}\"\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+            control,
+            service,
+            action,
+            body.len(),
+            body
+        ),
+    )
+}
+
+#[test]
+fn dlna_browses_folders_and_media() {
+    let dir = scratch_dir("dlna");
+    fs::create_dir(dir.join("Shows")).unwrap();
+    fs::write(dir.join("Shows/pilot.mkv"), "").unwrap();
+    fs::write(dir.join("movie.mp4"), "not really").unwrap();
+    fs::write(dir.join("notes.txt"), "").unwrap();
+    let address = start_in(&dir, &["--dlna"]);
+
+    let device = get(address, "/_bounty/dlna/device.xml");
+    assert!(device.text().contains("<deviceType>urn:schemas-upnp-org:device:MediaServer:1</deviceType>"));
+    assert!(device.text().contains("<controlURL>/_bounty/dlna/content-directory</controlURL>"));
+
+    let service = "urn:schemas-upnp-org:service:ContentDirectory:1";
+    let browse = |id: &str| {
+        let arguments = format!(
+            "<ObjectID>{}</ObjectID><BrowseFlag>BrowseDirectChildren</BrowseFlag><Filter>*</Filter>\
+             <StartingIndex>0</StartingIndex><RequestedCount>0</RequestedCount><SortCriteria></SortCriteria>",
+            id
+        );
+        soap(address, "/_bounty/dlna/content-directory", service, "Browse", &arguments)
+    };
+    let top = browse("0");
+    assert_eq!(top.status, 200);
+    // The DIDL-Lite goes escaped inside <Result>
+    let result = top.text();
+    assert!(result.contains("&lt;container id=&quot;/Shows&quot; parentID=&quot;0&quot;"));
+    assert!(result.contains("&lt;item id=&quot;/movie.mp4&quot; parentID=&quot;0&quot;"));
+    assert!(result.contains("http-get:*:video/mp4:DLNA.ORG_OP=01&quot; size=&quot;10&quot;&gt;http://localhost/movie%2Emp4&lt;/res&gt;"));
+    assert!(!result.contains("notes.txt"));
+    assert!(result.contains("<NumberReturned>2</NumberReturned><TotalMatches>2</TotalMatches>"));
+
+    let shows = browse("/Shows").text();
+    assert!(shows.contains("&lt;item id=&quot;/Shows/pilot.mkv&quot; parentID=&quot;/Shows&quot;"));
+    assert_eq!(browse("/../outside").status, 500);
+    assert_eq!(get(address, "/movie.mp4").text(), "not really");
+}